@@ -0,0 +1,290 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Metallic-roughness shading with the Cook-Torrance GGX BRDF, lit by the punctual lights of
+// `render::LightBuffer`, shadowed by the maps and cubes of `render::ShadowMaps`, and by the
+// environment of `render::GpuEnvironment` with the split sum approximation.
+// Compiled twice: frag.spv, and frag_mask.spv with ALPHA_MASK defined for masked materials.
+// Keeping discard out of the other variant lets them use early depth tests
+
+const float PI = 3.14159265359;
+const uint DIRECTIONAL = 0u;
+const uint POINT = 1u;
+const uint SPOT = 2u;
+
+layout (std140, set = 0, binding = 0) uniform Camera {
+    mat4 view;
+    mat4 projection;
+    mat4 view_projection;
+    vec4 position;
+} camera;
+
+// `render::GpuLight`
+struct Light {
+    vec3 position;
+    float range;
+    vec3 direction;
+    uint kind;
+    vec3 radiance;
+    float spot_scale;
+    float spot_offset;
+    uint shadow;
+    uint shadow_count;
+};
+
+layout (std430, set = 0, binding = 1) readonly buffer Lights {
+    vec3 ambient;
+    uint light_count;
+    float environment_intensity;
+    float prefiltered_max_lod;
+    Light lights[];
+};
+
+// `render::GpuShadow`
+struct Shadow {
+    mat4 view_projection;
+    vec4 atlas_rect;
+    float split_depth;
+    float normal_offset;
+    float filter_radius;
+    float atlas_texel;
+};
+
+layout (std430, set = 0, binding = 2) readonly buffer Shadows {
+    Shadow shadows[];
+};
+
+layout (set = 0, binding = 3) uniform texture2D shadow_atlas;
+layout (set = 0, binding = 4) uniform samplerShadow shadow_sampler;
+layout (set = 0, binding = 5) uniform textureCubeArray point_shadow_cubes;
+
+// `render::GpuPointShadow`, sampling the cube of the same index
+struct PointShadow {
+    float depth_scale;
+    float depth_offset;
+    float normal_offset;
+    float filter_radius;
+};
+
+layout (std430, set = 0, binding = 6) readonly buffer PointShadows {
+    PointShadow point_shadows[];
+};
+
+// Diffuse irradiance divided by PI, and radiance prefiltered for the roughness along the levels
+layout (set = 0, binding = 7) uniform textureCube irradiance_cube;
+layout (set = 0, binding = 8) uniform textureCube prefiltered_cube;
+// Scale and bias of F0 by n_dot_v and roughness
+layout (set = 0, binding = 9) uniform texture2D brdf_lut;
+layout (set = 0, binding = 10) uniform sampler environment_sampler;
+
+// Material set of `render::GpuMaterials`
+layout (std140, set = 1, binding = 0) uniform Material {
+    vec4 base_color;
+    vec3 emissive;
+    float metallic;
+    float roughness;
+    float normal_scale;
+    float occlusion_strength;
+    float alpha_cutoff;
+    uint uv1_mask;
+} material;
+
+layout (set = 1, binding = 1) uniform texture2D base_color_texture;
+layout (set = 1, binding = 2) uniform texture2D metallic_roughness_texture;
+layout (set = 1, binding = 3) uniform texture2D normal_texture;
+layout (set = 1, binding = 4) uniform texture2D occlusion_texture;
+layout (set = 1, binding = 5) uniform texture2D emissive_texture;
+layout (set = 1, binding = 6) uniform sampler base_color_sampler;
+layout (set = 1, binding = 7) uniform sampler metallic_roughness_sampler;
+layout (set = 1, binding = 8) uniform sampler normal_sampler;
+layout (set = 1, binding = 9) uniform sampler occlusion_sampler;
+layout (set = 1, binding = 10) uniform sampler emissive_sampler;
+
+layout (location = 0) in vec3 i_world_position;
+layout (location = 1) in vec3 i_normal;
+layout (location = 2) in vec4 i_tangent;
+layout (location = 3) in vec2 i_uv;
+layout (location = 4) in vec2 i_uv1;
+layout (location = 5) in vec4 i_color;
+layout (location = 0) out vec4 o_color;
+
+// UV set sampled by the texture of a slot
+vec2 slot_uv(uint slot) {
+    return (material.uv1_mask & (1u << slot)) != 0u ? i_uv1 : i_uv;
+}
+
+// Shading normal from the normal map, flipped on back faces of double sided materials
+vec3 surface_normal() {
+    vec3 normal = normalize(i_normal);
+    vec3 tangent = i_tangent.xyz - normal * dot(normal, i_tangent.xyz);
+    if (dot(tangent, tangent) > 1e-8) {
+        tangent = normalize(tangent);
+        vec3 bitangent = cross(normal, tangent) * i_tangent.w;
+        vec3 mapped = texture(sampler2D(normal_texture, normal_sampler), slot_uv(2u)).xyz * 2.0 - 1.0;
+        mapped.xy *= material.normal_scale;
+        normal = normalize(mat3(tangent, bitangent, normal) * mapped);
+    }
+    return gl_FrontFacing ? normal : -normal;
+}
+
+float distribution_ggx(float n_dot_h, float alpha) {
+    float alpha2 = alpha * alpha;
+    float denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * denominator * denominator);
+}
+
+// Height correlated Smith masking-shadowing, divided by 4 n_dot_l n_dot_v
+float visibility_smith_ggx(float n_dot_v, float n_dot_l, float alpha) {
+    float alpha2 = alpha * alpha;
+    float view = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - alpha2) + alpha2);
+    float light = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - alpha2) + alpha2);
+    return 0.5 / max(view + light, 1e-5);
+}
+
+vec3 fresnel_schlick(vec3 f0, float v_dot_h) {
+    return f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+}
+
+// Windowed inverse square falloff, as recommended by KHR_lights_punctual
+float distance_attenuation(float distance, float range) {
+    float attenuation = 1.0 / max(distance * distance, 1e-4);
+    if (range > 0.0) {
+        float ratio = distance / range;
+        float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+        attenuation *= window * window;
+    }
+    return attenuation;
+}
+
+// Fraction of a point light reaching the fragment, filtered over a 5x5 grid of directions around
+// the one to the fragment. Depth compares against the distance along the cube face's axis
+float point_shadow_factor(Light light, vec3 normal) {
+    PointShadow shadow = point_shadows[light.shadow];
+    vec3 to_fragment = i_world_position - light.position;
+    float distance = length(to_fragment);
+    to_fragment += normal * shadow.normal_offset * distance;
+    vec3 axis_distances = abs(to_fragment);
+    float major_axis = max(axis_distances.x, max(axis_distances.y, axis_distances.z));
+    float reference = shadow.depth_scale + shadow.depth_offset / max(major_axis, 1e-4);
+    vec3 direction = to_fragment / max(length(to_fragment), 1e-4);
+    vec3 tangent = normalize(cross(direction, abs(direction.y) < 0.99 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0)));
+    vec3 bitangent = cross(direction, tangent);
+    float step_size = shadow.filter_radius * major_axis * 0.5;
+    float lit = 0.0;
+    for (int y = -2; y <= 2; y++) {
+        for (int x = -2; x <= 2; x++) {
+            vec3 sample_direction = to_fragment + (tangent * float(x) + bitangent * float(y)) * step_size;
+            lit += texture(samplerCubeArrayShadow(point_shadow_cubes, shadow_sampler), vec4(sample_direction, float(light.shadow)), reference);
+        }
+    }
+    return lit / 25.0;
+}
+
+// Fraction of the light reaching the fragment, filtered over a 5x5 grid of comparisons
+float shadow_factor(Light light, vec3 normal, float view_depth) {
+    if (light.shadow_count == 0u) {
+        return 1.0;
+    }
+    if (light.kind == POINT) {
+        return point_shadow_factor(light, normal);
+    }
+    uint index = light.shadow;
+    uint last = light.shadow + light.shadow_count - 1u;
+    while (index < last && view_depth > shadows[index].split_depth) {
+        index++;
+    }
+    Shadow shadow = shadows[index];
+    if (view_depth > shadow.split_depth) {
+        return 1.0;
+    }
+    float normal_offset = shadow.normal_offset;
+    if (light.kind == SPOT) {
+        normal_offset *= distance(light.position, i_world_position);
+    }
+    vec4 clip = shadow.view_projection * vec4(i_world_position + normal * normal_offset, 1.0);
+    vec3 ndc = clip.xyz / clip.w;
+    vec2 uv = ndc.xy * 0.5 + 0.5;
+    if (clip.w <= 0.0 || any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || ndc.z > 1.0) {
+        return 1.0;
+    }
+    vec2 atlas_uv = shadow.atlas_rect.xy + uv * shadow.atlas_rect.zw;
+    // Samples stay inside the map's tile of the atlas
+    vec2 min_uv = shadow.atlas_rect.xy + vec2(shadow.atlas_texel * 0.5);
+    vec2 max_uv = shadow.atlas_rect.xy + shadow.atlas_rect.zw - vec2(shadow.atlas_texel * 0.5);
+    float step_size = shadow.filter_radius * 0.5;
+    float lit = 0.0;
+    for (int y = -2; y <= 2; y++) {
+        for (int x = -2; x <= 2; x++) {
+            vec2 sample_uv = clamp(atlas_uv + vec2(x, y) * step_size, min_uv, max_uv);
+            lit += textureLod(sampler2DShadow(shadow_atlas, shadow_sampler), vec3(sample_uv, ndc.z), 0.0);
+        }
+    }
+    return lit / 25.0;
+}
+
+void main() {
+    vec4 base_color = material.base_color * i_color
+        * texture(sampler2D(base_color_texture, base_color_sampler), slot_uv(0u));
+#ifdef ALPHA_MASK
+    if (base_color.a < material.alpha_cutoff) {
+        discard;
+    }
+    base_color.a = 1.0;
+#endif
+    vec4 metallic_roughness = texture(sampler2D(metallic_roughness_texture, metallic_roughness_sampler), slot_uv(1u));
+    float metallic = clamp(material.metallic * metallic_roughness.b, 0.0, 1.0);
+    // Perceptual roughness, clamped so highlights of smooth surfaces don't vanish
+    float roughness = clamp(material.roughness * metallic_roughness.g, 0.045, 1.0);
+    float alpha = roughness * roughness;
+    float occlusion = mix(1.0, texture(sampler2D(occlusion_texture, occlusion_sampler), slot_uv(3u)).r, material.occlusion_strength);
+    vec3 emissive = material.emissive
+        * texture(sampler2D(emissive_texture, emissive_sampler), slot_uv(4u)).rgb;
+
+    vec3 diffuse_color = base_color.rgb * (1.0 - metallic);
+    vec3 f0 = mix(vec3(0.04), base_color.rgb, metallic);
+    vec3 normal = surface_normal();
+    vec3 view = normalize(camera.position.xyz - i_world_position);
+    float n_dot_v = max(dot(normal, view), 1e-4);
+    vec3 geometric_normal = gl_FrontFacing ? normalize(i_normal) : -normalize(i_normal);
+    float view_depth = -(camera.view * vec4(i_world_position, 1.0)).z;
+
+    vec3 color = vec3(0.0);
+    for (uint i = 0u; i < light_count; i++) {
+        Light light = lights[i];
+        vec3 to_light = -light.direction;
+        vec3 radiance = light.radiance;
+        if (light.kind != DIRECTIONAL) {
+            vec3 offset = light.position - i_world_position;
+            float distance = length(offset);
+            to_light = offset / max(distance, 1e-4);
+            radiance *= distance_attenuation(distance, light.range);
+        }
+        if (light.kind == SPOT) {
+            float cone = clamp(dot(light.direction, -to_light) * light.spot_scale + light.spot_offset, 0.0, 1.0);
+            radiance *= cone * cone;
+        }
+        float n_dot_l = dot(normal, to_light);
+        if (n_dot_l <= 0.0) {
+            continue;
+        }
+        radiance *= shadow_factor(light, geometric_normal, view_depth);
+        vec3 halfway = normalize(view + to_light);
+        float n_dot_h = max(dot(normal, halfway), 0.0);
+        float v_dot_h = max(dot(view, halfway), 0.0);
+        vec3 fresnel = fresnel_schlick(f0, v_dot_h);
+        vec3 specular = fresnel * distribution_ggx(n_dot_h, alpha) * visibility_smith_ggx(n_dot_v, n_dot_l, alpha);
+        vec3 diffuse = (1.0 - fresnel) * diffuse_color / PI;
+        color += (diffuse + specular) * radiance * n_dot_l;
+    }
+    // The flat ambient light lights diffuse and specular alike
+    vec3 indirect = ambient * (diffuse_color + f0);
+    vec3 irradiance = textureLod(samplerCube(irradiance_cube, environment_sampler), normal, 0.0).rgb;
+    vec3 reflected = reflect(-view, normal);
+    vec3 prefiltered = textureLod(samplerCube(prefiltered_cube, environment_sampler), reflected, roughness * prefiltered_max_lod).rgb;
+    vec2 scale_bias = textureLod(sampler2D(brdf_lut, environment_sampler), vec2(n_dot_v, roughness), 0.0).rg;
+    indirect += (irradiance * diffuse_color + prefiltered * (f0 * scale_bias.x + scale_bias.y)) * environment_intensity;
+    color += indirect * occlusion;
+    color += emissive;
+    o_color = vec4(color, base_color.a);
+}
@@ -0,0 +1,42 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Attributes of `asset::Vertex`
+layout (location = 0) in vec3 position;
+layout (location = 1) in vec3 normal;
+layout (location = 2) in vec4 tangent;
+layout (location = 3) in vec2 uv;
+layout (location = 4) in vec2 uv1;
+layout (location = 5) in vec4 color;
+
+// `render::ObjectConstants`
+layout (push_constant) uniform Object {
+    mat4 model;
+    mat3 normal_matrix;
+    uint material;
+} object;
+
+layout (std140, set = 0, binding = 0) uniform Camera {
+    mat4 view;
+    mat4 projection;
+    mat4 view_projection;
+    vec4 position;
+} camera;
+
+layout (location = 0) out vec3 o_world_position;
+layout (location = 1) out vec3 o_normal;
+layout (location = 2) out vec4 o_tangent;
+layout (location = 3) out vec2 o_uv;
+layout (location = 4) out vec2 o_uv1;
+layout (location = 5) out vec4 o_color;
+void main() {
+    vec4 world_position = object.model * vec4(position, 1.0);
+    o_world_position = world_position.xyz;
+    o_normal = object.normal_matrix * normal;
+    o_tangent = vec4(mat3(object.model) * tangent.xyz, tangent.w);
+    o_uv = uv;
+    o_uv1 = uv1;
+    o_color = color;
+    gl_Position = camera.view_projection * world_position;
+}
@@ -0,0 +1,35 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Upsamples the top level of the bloom chain over the scene color with a 3x3 tent filter.
+// Blended with the blend constant as alpha, the scene color becomes a mix of the scene and
+// its bloom, conserving energy
+
+layout (set = 0, binding = 0) uniform texture2D source;
+layout (set = 0, binding = 1) uniform sampler linear_sampler;
+
+// `render::bloom::BloomConstants`
+layout (push_constant) uniform Constants {
+    float radius;
+    uint karis_average;
+    float scale;
+} constants;
+
+layout (location = 0) in vec2 i_uv;
+
+layout (location = 0) out vec4 o_color;
+
+vec3 tap(vec2 uv, vec2 offset, vec2 texel_size) {
+    return textureLod(sampler2D(source, linear_sampler), uv + offset * texel_size, 0.0).rgb;
+}
+
+void main() {
+    vec2 texel_size = constants.radius / vec2(textureSize(source, 0));
+    vec3 blurred = tap(i_uv, vec2(0.0, 0.0), texel_size) * 4.0;
+    blurred += (tap(i_uv, vec2(0.0, -1.0), texel_size) + tap(i_uv, vec2(-1.0, 0.0), texel_size)
+        + tap(i_uv, vec2(1.0, 0.0), texel_size) + tap(i_uv, vec2(0.0, 1.0), texel_size)) * 2.0;
+    blurred += tap(i_uv, vec2(-1.0, -1.0), texel_size) + tap(i_uv, vec2(1.0, -1.0), texel_size)
+        + tap(i_uv, vec2(-1.0, 1.0), texel_size) + tap(i_uv, vec2(1.0, 1.0), texel_size);
+    o_color = vec4(blurred / 16.0 * constants.scale, 1.0);
+}
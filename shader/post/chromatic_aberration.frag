@@ -0,0 +1,29 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Splits red and blue apart toward the corners, like a lens focusing them differently
+// params0: x the split at the corners in units of the screen
+
+layout (set = 0, binding = 0) uniform texture2D source;
+layout (set = 0, binding = 1) uniform sampler linear_sampler;
+
+// `render::post_stack::PostConstants`
+layout (push_constant) uniform Constants {
+    vec4 params0;
+    vec4 params1;
+    vec2 texel_size;
+    uint frame;
+} post;
+
+layout (location = 0) in vec2 i_uv;
+
+layout (location = 0) out vec4 o_color;
+
+void main() {
+    vec2 offset = (i_uv - 0.5) * post.params0.x;
+    float red = textureLod(sampler2D(source, linear_sampler), i_uv - offset, 0.0).r;
+    float green = textureLod(sampler2D(source, linear_sampler), i_uv, 0.0).g;
+    float blue = textureLod(sampler2D(source, linear_sampler), i_uv + offset, 0.0).b;
+    o_color = vec4(red, green, blue, 1.0);
+}
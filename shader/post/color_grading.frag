@@ -0,0 +1,44 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Grades the exposed color with a 3D lookup table of log encoded colors, so it covers HDR colors.
+// The encoding maps the stops around middle grey to 0 to 1, see `render::ColorLut`
+// params0: x the stops on either side of middle grey, y the size of the table, z the mix of
+// the graded color from 0 to 1
+
+layout (set = 0, binding = 0) uniform texture2D source;
+layout (set = 0, binding = 1) uniform sampler linear_sampler;
+layout (set = 0, binding = 2) uniform texture3D lut;
+
+// `render::tonemap::GpuExposure`, the exposure of the frame
+layout (std430, set = 0, binding = 3) readonly buffer Exposure {
+    float luminance;
+    float scale;
+} exposure;
+
+// `render::post_stack::PostConstants`
+layout (push_constant) uniform Constants {
+    vec4 params0;
+    vec4 params1;
+    vec2 texel_size;
+    uint frame;
+} post;
+
+layout (location = 0) in vec2 i_uv;
+
+layout (location = 0) out vec4 o_color;
+
+const float MIDDLE_GREY = 0.18;
+
+void main() {
+    vec3 color = textureLod(sampler2D(source, linear_sampler), i_uv, 0.0).rgb * exposure.scale;
+    float stops = post.params0.x;
+    float size = post.params0.y;
+    vec3 encoded = clamp(log2(max(color, vec3(1e-6)) / MIDDLE_GREY) / (2.0 * stops) + 0.5, 0.0, 1.0);
+    // Texel centers, so the ends of the encoding land on the first and last texels
+    vec3 coordinates = encoded * ((size - 1.0) / size) + 0.5 / size;
+    vec3 graded_encoded = textureLod(sampler3D(lut, linear_sampler), coordinates, 0.0).rgb;
+    vec3 graded = MIDDLE_GREY * exp2((graded_encoded - 0.5) * (2.0 * stops));
+    o_color = vec4(mix(color, graded, post.params0.z) / exposure.scale, 1.0);
+}
@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Copies the source, for a stack of a single pass which can't read and write the scene color
+
+layout (set = 0, binding = 0) uniform texture2D source;
+layout (set = 0, binding = 1) uniform sampler linear_sampler;
+
+layout (location = 0) in vec2 i_uv;
+
+layout (location = 0) out vec4 o_color;
+
+void main() {
+    o_color = textureLod(sampler2D(source, linear_sampler), i_uv, 0.0);
+}
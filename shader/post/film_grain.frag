@@ -0,0 +1,36 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Scales the color by noise changing every frame, so the grain is relative to the brightness
+// params0: x the intensity of the grain
+
+layout (set = 0, binding = 0) uniform texture2D source;
+layout (set = 0, binding = 1) uniform sampler linear_sampler;
+
+// `render::post_stack::PostConstants`
+layout (push_constant) uniform Constants {
+    vec4 params0;
+    vec4 params1;
+    vec2 texel_size;
+    uint frame;
+} post;
+
+layout (location = 0) in vec2 i_uv;
+
+layout (location = 0) out vec4 o_color;
+
+// PCG hash of "Hash Functions for GPU Rendering", Jarzynski and Olano
+uint pcg(uint v) {
+    uint state = v * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+void main() {
+    vec3 color = textureLod(sampler2D(source, linear_sampler), i_uv, 0.0).rgb;
+    uvec2 pixel = uvec2(gl_FragCoord.xy);
+    uint hash = pcg(pixel.x + pcg(pixel.y + pcg(post.frame)));
+    float noise = float(hash) / 4294967295.0 - 0.5;
+    o_color = vec4(max(color * (1.0 + noise * post.params0.x), vec3(0.0)), 1.0);
+}
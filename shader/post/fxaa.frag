@@ -0,0 +1,63 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// FXAA 3.11 console, blurring along edges found from the luma of the 4 diagonal neighbors.
+// The exposed HDR color is compressed to the range of 0 to 1 for the luma, like after tonemapping
+// params0: x the longest blur in texels, y the blur reduction by luma, z the least reduction
+
+layout (set = 0, binding = 0) uniform texture2D source;
+layout (set = 0, binding = 1) uniform sampler linear_sampler;
+
+// `render::tonemap::GpuExposure`, the exposure of the frame
+layout (std430, set = 0, binding = 3) readonly buffer Exposure {
+    float luminance;
+    float scale;
+} exposure;
+
+// `render::post_stack::PostConstants`
+layout (push_constant) uniform Constants {
+    vec4 params0;
+    vec4 params1;
+    vec2 texel_size;
+    uint frame;
+} post;
+
+layout (location = 0) in vec2 i_uv;
+
+layout (location = 0) out vec4 o_color;
+
+vec3 tap(vec2 offset) {
+    return textureLod(sampler2D(source, linear_sampler), i_uv + offset * post.texel_size, 0.0).rgb * exposure.scale;
+}
+
+float luma(vec3 color) {
+    vec3 compressed = color / (1.0 + max(color.r, max(color.g, color.b)));
+    return dot(compressed, vec3(0.299, 0.587, 0.114));
+}
+
+void main() {
+    float span_max = post.params0.x;
+    float reduce_mul = post.params0.y;
+    float reduce_min = post.params0.z;
+    vec3 middle = tap(vec2(0.0, 0.0));
+    float luma_nw = luma(tap(vec2(-0.5, -0.5)));
+    float luma_ne = luma(tap(vec2(0.5, -0.5)));
+    float luma_sw = luma(tap(vec2(-0.5, 0.5)));
+    float luma_se = luma(tap(vec2(0.5, 0.5)));
+    float luma_m = luma(middle);
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 direction = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    float reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul, reduce_min);
+    float inverse_min = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * inverse_min, vec2(-span_max), vec2(span_max));
+
+    vec3 inner = 0.5 * (tap(direction * (1.0 / 3.0 - 0.5)) + tap(direction * (2.0 / 3.0 - 0.5)));
+    vec3 outer = inner * 0.5 + 0.25 * (tap(direction * -0.5) + tap(direction * 0.5));
+    float luma_outer = luma(outer);
+    // The outer taps crossed another edge
+    vec3 color = luma_outer < luma_min || luma_outer > luma_max ? inner : outer;
+    o_color = vec4(color / exposure.scale, 1.0);
+}
@@ -0,0 +1,37 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Sharpens by subtracting the 4 neighbors, clamped to their range so edges don'"'"'t ring
+// params0: x the strength, 0 keeps the source
+
+layout (set = 0, binding = 0) uniform texture2D source;
+layout (set = 0, binding = 1) uniform sampler linear_sampler;
+
+// `render::post_stack::PostConstants`
+layout (push_constant) uniform Constants {
+    vec4 params0;
+    vec4 params1;
+    vec2 texel_size;
+    uint frame;
+} post;
+
+layout (location = 0) in vec2 i_uv;
+
+layout (location = 0) out vec4 o_color;
+
+vec3 tap(vec2 offset) {
+    return textureLod(sampler2D(source, linear_sampler), i_uv + offset * post.texel_size, 0.0).rgb;
+}
+
+void main() {
+    vec3 middle = tap(vec2(0.0, 0.0));
+    vec3 north = tap(vec2(0.0, -1.0));
+    vec3 west = tap(vec2(-1.0, 0.0));
+    vec3 east = tap(vec2(1.0, 0.0));
+    vec3 south = tap(vec2(0.0, 1.0));
+    float strength = post.params0.x;
+    vec3 sharpened = middle * (1.0 + 4.0 * strength) - (north + west + east + south) * strength;
+    // Edges ring below black next to bright highlights
+    o_color = vec4(max(sharpened, vec3(0.0)), 1.0);
+}
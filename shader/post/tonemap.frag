@@ -0,0 +1,157 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Exposes the HDR scene color and maps it to the display. SDR output goes through the selected
+// operator and is sRGB encoded, unless the output format does on write. HDR output keeps the
+// color up to paper white, rolls highlights off toward the peak and is encoded with the ST 2084
+// perceptual quantizer in Rec. 2020, or as scRGB where 1 is 80 nits
+
+const uint REINHARD = 0u;
+const uint ACES = 1u;
+const uint AGX = 2u;
+
+// `render::tonemap::Output`
+const uint SDR = 0u;
+const uint SDR_SRGB = 1u;
+const uint HDR10 = 2u;
+const uint SCRGB = 3u;
+
+layout (set = 0, binding = 0) uniform texture2D scene_color;
+
+// `render::tonemap::GpuExposure`
+layout (std430, set = 0, binding = 1) readonly buffer Exposure {
+    float luminance;
+    float scale;
+    uint histogram[256];
+} exposure;
+
+// `render::tonemap::TonemapConstants`
+layout (push_constant) uniform Constants {
+    uint operator;
+    uint output_encoding;
+    uint auto_exposure;
+    float manual_exposure;
+    float paper_white_nits;
+    float peak_nits;
+} constants;
+
+layout (location = 0) in vec2 i_uv;
+
+layout (location = 0) out vec4 o_color;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+vec3 reinhard(vec3 color) {
+    return color / (1.0 + luminance(color));
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms, in linear sRGB
+vec3 aces(vec3 color) {
+    mat3 input_matrix = mat3(
+        0.59719, 0.07600, 0.02840,
+        0.35458, 0.90834, 0.13383,
+        0.04823, 0.01566, 0.83777
+    );
+    mat3 output_matrix = mat3(
+        1.60475, -0.10208, -0.00327,
+        -0.53108, 1.10813, -0.07276,
+        -0.07367, -0.00605, 1.07602
+    );
+    vec3 v = input_matrix * color;
+    vec3 a = v * (v + 0.0245786) - 0.000090537;
+    vec3 b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(output_matrix * (a / b), 0.0, 1.0);
+}
+
+// Polynomial fit of the default AgX contrast curve over the normalized log2 range
+vec3 agx_contrast(vec3 x) {
+    vec3 x2 = x * x;
+    vec3 x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+// Troy Sobotka's AgX with the base look, back to linear sRGB
+vec3 agx(vec3 color) {
+    mat3 inset = mat3(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104
+    );
+    mat3 outset = mat3(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116
+    );
+    float min_ev = -12.47393;
+    float max_ev = 4.026069;
+    vec3 v = clamp(log2(max(inset * color, vec3(1e-10))), min_ev, max_ev);
+    v = agx_contrast((v - min_ev) / (max_ev - min_ev));
+    v = outset * v;
+    return pow(clamp(v, 0.0, 1.0), vec3(2.2));
+}
+
+vec3 srgb_encode(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, vec3(lessThanEqual(color, vec3(0.0031308))));
+}
+
+// Compresses luminance above 1 so it approaches `white` instead of clipping
+vec3 highlight_rolloff(vec3 color, float white) {
+    float l = luminance(color);
+    if (l <= 1.0) {
+        return min(color, vec3(white));
+    }
+    float excess = l - 1.0;
+    float rolled = 1.0 + excess / (1.0 + excess / max(white - 1.0, 1e-4));
+    return min(color * (rolled / l), vec3(white));
+}
+
+vec3 pq_encode(vec3 nits) {
+    float m1 = 0.1593017578125;
+    float m2 = 78.84375;
+    float c1 = 0.8359375;
+    float c2 = 18.8515625;
+    float c3 = 18.6875;
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+vec3 rec709_to_rec2020(vec3 color) {
+    mat3 conversion = mat3(
+        0.6274, 0.0691, 0.0164,
+        0.3293, 0.9195, 0.0880,
+        0.0433, 0.0114, 0.8956
+    );
+    return conversion * color;
+}
+
+void main() {
+    vec3 color = texelFetch(scene_color, ivec2(gl_FragCoord.xy), 0).rgb;
+    color *= constants.auto_exposure != 0u ? exposure.scale : constants.manual_exposure;
+    if (constants.output_encoding == HDR10 || constants.output_encoding == SCRGB) {
+        color = highlight_rolloff(max(color, vec3(0.0)), constants.peak_nits / constants.paper_white_nits);
+        vec3 nits = color * constants.paper_white_nits;
+        if (constants.output_encoding == HDR10) {
+            o_color = vec4(pq_encode(rec709_to_rec2020(nits)), 1.0);
+        } else {
+            o_color = vec4(nits / 80.0, 1.0);
+        }
+        return;
+    }
+    if (constants.operator == ACES) {
+        color = aces(color);
+    } else if (constants.operator == AGX) {
+        color = agx(color);
+    } else {
+        color = reinhard(color);
+    }
+    color = clamp(color, 0.0, 1.0);
+    if (constants.output_encoding == SDR_SRGB) {
+        color = srgb_encode(color);
+    }
+    o_color = vec4(color, 1.0);
+}
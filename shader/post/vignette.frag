@@ -0,0 +1,30 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Darkens the corners, by distance from the center in units of the screen height
+// params0: x the darkening at the corners from 0 to 1, y the distance it starts at, z the
+// distance it spreads over
+
+layout (set = 0, binding = 0) uniform texture2D source;
+layout (set = 0, binding = 1) uniform sampler linear_sampler;
+
+// `render::post_stack::PostConstants`
+layout (push_constant) uniform Constants {
+    vec4 params0;
+    vec4 params1;
+    vec2 texel_size;
+    uint frame;
+} post;
+
+layout (location = 0) in vec2 i_uv;
+
+layout (location = 0) out vec4 o_color;
+
+void main() {
+    vec3 color = textureLod(sampler2D(source, linear_sampler), i_uv, 0.0).rgb;
+    float aspect = post.texel_size.y / post.texel_size.x;
+    float from_center = length((i_uv - 0.5) * vec2(aspect, 1.0));
+    float edge = smoothstep(post.params0.y, post.params0.y + max(post.params0.z, 1e-4), from_center);
+    o_color = vec4(color * (1.0 - post.params0.x * edge), 1.0);
+}
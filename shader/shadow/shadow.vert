@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Depth of `asset::Vertex` positions, seen from a light of `render::ShadowMaps`
+
+layout (location = 0) in vec3 position;
+
+// `render::ShadowConstants`
+layout (push_constant) uniform Shadow {
+    mat4 clip_from_model;
+} shadow;
+
+void main() {
+    gl_Position = shadow.clip_from_model * vec4(position, 1.0);
+}
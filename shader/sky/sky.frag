@@ -0,0 +1,120 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Background of `scene::Sky`: the environment cube of `render::GpuEnvironment`, or single
+// scattering of sunlight in the atmosphere. The scattering is marched the same way by
+// shader/ibl/atmosphere_to_cube.comp
+
+const float PI = 3.14159265359;
+const uint ENVIRONMENT = 1u;
+const uint ATMOSPHERE = 2u;
+const int VIEW_STEPS = 16;
+const int SUN_STEPS = 8;
+
+// `render::GpuAtmosphere`
+struct Atmosphere {
+    vec3 sun_direction;
+    float sun_intensity;
+    vec3 rayleigh_scattering;
+    float rayleigh_scale_height;
+    float mie_scattering;
+    float mie_scale_height;
+    float mie_anisotropy;
+    float planet_radius;
+    float atmosphere_radius;
+    float observer_altitude;
+};
+
+// `render::GpuSky`
+layout (std140, set = 0, binding = 11) uniform Sky {
+    mat4 world_from_clip;
+    Atmosphere atmosphere;
+    uint mode;
+    float intensity;
+    float depth;
+} sky;
+
+layout (set = 0, binding = 10) uniform sampler environment_sampler;
+layout (set = 0, binding = 12) uniform textureCube environment_cube;
+
+layout (location = 0) in vec2 i_clip_position;
+
+layout (location = 0) out vec4 o_color;
+
+// Distances along the ray to where it enters and leaves a sphere around the origin, x > y when it
+// misses. The sphere's origin is far away, so the distance to it is kept out of the squares
+vec2 ray_sphere(vec3 origin, vec3 direction, float radius) {
+    float distance = length(origin);
+    float b = dot(origin, direction);
+    float c = (distance - radius) * (distance + radius);
+    float discriminant = b * b - c;
+    if (discriminant < 0.0) {
+        return vec2(1e20, -1e20);
+    }
+    float root = sqrt(discriminant);
+    return vec2(-b - root, -b + root);
+}
+
+// Densities of air molecules and aerosols at the position, relative to sea level
+vec2 density(vec3 position, Atmosphere atmosphere) {
+    float altitude = length(position) - atmosphere.planet_radius;
+    return exp(-altitude / vec2(atmosphere.rayleigh_scale_height, atmosphere.mie_scale_height));
+}
+
+// Sunlight scattered toward the origin from along the direction
+vec3 atmosphere_radiance(vec3 direction, Atmosphere atmosphere) {
+    vec3 origin = vec3(0.0, atmosphere.planet_radius + atmosphere.observer_altitude, 0.0);
+    vec2 air = ray_sphere(origin, direction, atmosphere.atmosphere_radius);
+    if (air.x > air.y || air.y < 0.0) {
+        return vec3(0.0);
+    }
+    float start = max(air.x, 0.0);
+    float end = air.y;
+    vec2 ground = ray_sphere(origin, direction, atmosphere.planet_radius);
+    if (ground.x <= ground.y && ground.x > 0.0) {
+        end = min(end, ground.x);
+    }
+
+    float mu = dot(direction, atmosphere.sun_direction);
+    float g = atmosphere.mie_anisotropy;
+    float rayleigh_phase = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+    float mie_phase = 3.0 / (8.0 * PI) * (1.0 - g * g) * (1.0 + mu * mu)
+        / (pow(1.0 + g * g - 2.0 * mu * g, 1.5) * (2.0 + g * g));
+
+    float step_size = (end - start) / float(VIEW_STEPS);
+    vec2 view_depth = vec2(0.0);
+    vec3 rayleigh = vec3(0.0);
+    vec3 mie = vec3(0.0);
+    for (int i = 0; i < VIEW_STEPS; i++) {
+        vec3 position = origin + direction * (start + step_size * (float(i) + 0.5));
+        vec2 step_density = density(position, atmosphere) * step_size;
+        view_depth += step_density;
+
+        // Sunlight dims through the air toward the sun, and vanishes into the rising density
+        // when the planet is in the way
+        float sun_step = ray_sphere(position, atmosphere.sun_direction, atmosphere.atmosphere_radius).y / float(SUN_STEPS);
+        vec2 sun_depth = vec2(0.0);
+        for (int j = 0; j < SUN_STEPS; j++) {
+            sun_depth += density(position + atmosphere.sun_direction * (sun_step * (float(j) + 0.5)), atmosphere) * sun_step;
+        }
+        vec2 depth = view_depth + sun_depth;
+        vec3 transmittance = exp(-(atmosphere.rayleigh_scattering * depth.x + atmosphere.mie_scattering * depth.y));
+        rayleigh += step_density.x * transmittance;
+        mie += step_density.y * transmittance;
+    }
+    return atmosphere.sun_intensity
+        * (rayleigh_phase * atmosphere.rayleigh_scattering * rayleigh + mie_phase * atmosphere.mie_scattering * mie);
+}
+
+void main() {
+    vec4 point = sky.world_from_clip * vec4(i_clip_position, 0.5, 1.0);
+    vec3 direction = normalize(point.xyz / point.w);
+    vec3 color = vec3(0.0);
+    if (sky.mode == ENVIRONMENT) {
+        color = textureLod(samplerCube(environment_cube, environment_sampler), direction, 0.0).rgb;
+    } else if (sky.mode == ATMOSPHERE) {
+        color = atmosphere_radiance(direction, sky.atmosphere);
+    }
+    o_color = vec4(color * sky.intensity, 1.0);
+}
@@ -0,0 +1,37 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Triangle covering the screen at the far depth, drawn after opaque objects so only the
+// background passes the depth test
+
+// `render::GpuAtmosphere`
+struct Atmosphere {
+    vec3 sun_direction;
+    float sun_intensity;
+    vec3 rayleigh_scattering;
+    float rayleigh_scale_height;
+    float mie_scattering;
+    float mie_scale_height;
+    float mie_anisotropy;
+    float planet_radius;
+    float atmosphere_radius;
+    float observer_altitude;
+};
+
+// `render::GpuSky`, in the frame set of `render::ForwardRenderer`
+layout (std140, set = 0, binding = 11) uniform Sky {
+    mat4 world_from_clip;
+    Atmosphere atmosphere;
+    uint mode;
+    float intensity;
+    float depth;
+} sky;
+
+layout (location = 0) out vec2 o_clip_position;
+
+void main() {
+    vec2 position = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2)) * 2.0 - 1.0;
+    o_clip_position = position;
+    gl_Position = vec4(position, sky.depth, 1.0);
+}
@@ -1,13 +1,16 @@
-#version 400
+#version 450
 #extension GL_ARB_separate_shader_objects : enable
 #extension GL_ARB_shading_language_420pack : enable
 
-layout (location = 0) in vec4 pos;
-layout (location = 1) in vec4 color;
+// Locations of `asset::Vertex`
+layout (location = 0) in vec3 position;
+layout (location = 3) in vec2 uv;
 
 
 layout (location = 0) out vec4 o_color;
 void main() {
-    o_color = color;
-    gl_Position = pos;
+    // Red at the apex, green at the bottom left and blue at the bottom right corner
+    o_color = vec4(1.0 - uv.y, (1.0 - uv.x) * uv.y, uv.x * uv.y, 1.0);
+    // The mesh has +Y up, Vulkan clip space has it down
+    gl_Position = vec4(position.x, -position.y, 0.0, 1.0);
 }
@@ -0,0 +1,626 @@
+use std::fmt;
+use std::path::Path;
+
+use ash::vk;
+use cgmath::{Quaternion, Rad};
+use gltf::camera::Projection as GltfProjection;
+use gltf::image::Format;
+use gltf::khr_lights_punctual::Kind;
+use gltf::mesh::Mode;
+use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+use crate::ecs::{Entity, World};
+use crate::platform::gpu::vulkan_mesh::Indices;
+use crate::scene::{
+   set_parent, Camera, DirectionalLight, MeshInstance, Name, Orthographic, Perspective, PointLight, SpotLight,
+   Transform,
+};
+
+use super::{assets_mut, AlphaMode, Handle, Image, Material, MeshData, SamplerDesc, Texture, TextureSlot, Vertex};
+
+#[derive(Debug)]
+pub enum GltfError {
+   /// Reading or parsing the file, its buffers or its images failed
+   Import(gltf::Error),
+   /// The file has no scene to spawn
+   NoScene,
+}
+
+impl fmt::Display for GltfError {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         GltfError::Import(err) => write!(f, "failed to import glTF: {}", err),
+         GltfError::NoScene => write!(f, "glTF file has no scene"),
+      }
+   }
+}
+
+impl std::error::Error for GltfError {}
+
+impl From<gltf::Error> for GltfError {
+   fn from(err: gltf::Error) -> Self {
+      GltfError::Import(err)
+   }
+}
+
+/// Entities and assets created by `load_gltf`
+#[derive(Clone, Debug)]
+pub struct GltfScene {
+   /// Entity the root nodes of the scene are attached to, move it to place the whole scene
+   pub root: Entity,
+   /// Drawable primitives of each glTF mesh, by mesh index
+   pub meshes: Vec<Vec<MeshInstance>>,
+   /// By material index
+   pub materials: Vec<Handle<Material>>,
+   /// Entities with cameras, in the order of the nodes
+   pub cameras: Vec<Entity>,
+}
+
+/// Loads a `.gltf` or `.glb` file with its external and embedded buffers and images, and spawns
+/// its default scene into the world. Assets go into the `Assets` resources, which are created
+/// if missing.
+///
+/// Nodes become entities with a `Transform` and the hierarchy of `set_parent`. Cameras,
+/// `KHR_lights_punctual` lights and meshes become components of the node entities, nodes with
+/// several primitives get a child entity per primitive. Point and line primitives are skipped.
+pub fn load_gltf(world: &mut World, path: impl AsRef<Path>) -> Result<GltfScene, GltfError> {
+   let path = path.as_ref();
+   let (document, buffers, images) = gltf::import(path)?;
+   let file_name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned());
+   spawn_document(world, &document, &buffers, &images, file_name)
+}
+
+/// `load_gltf` for a `.glb` or `.gltf` in memory. All buffers and images must be embedded
+pub fn load_gltf_from_slice(world: &mut World, bytes: &[u8]) -> Result<GltfScene, GltfError> {
+   let (document, buffers, images) = gltf::import_slice(bytes)?;
+   spawn_document(world, &document, &buffers, &images, None)
+}
+
+fn spawn_document(
+   world: &mut World,
+   document: &gltf::Document,
+   buffers: &[gltf::buffer::Data],
+   images: &[gltf::image::Data],
+   file_name: Option<String>,
+) -> Result<GltfScene, GltfError> {
+   let scene = document
+      .default_scene()
+      .or_else(|| document.scenes().next())
+      .ok_or(GltfError::NoScene)?;
+
+   let image_handles: Vec<Handle<Image>> = {
+      let mut assets = assets_mut::<Image>(world);
+      images.iter().map(|image| assets.add(convert_image(image))).collect()
+   };
+   let texture_handles: Vec<Handle<Texture>> = {
+      let mut assets = assets_mut::<Texture>(world);
+      document
+         .textures()
+         .map(|texture| assets.add(Texture {
+            image: image_handles[texture.source().index()],
+            sampler: convert_sampler(&texture.sampler()),
+         }))
+         .collect()
+   };
+   let (materials, default_material) = {
+      let mut assets = assets_mut::<Material>(world);
+      let materials: Vec<Handle<Material>> = document
+         .materials()
+         .map(|material| assets.add(convert_material(&material, &texture_handles)))
+         .collect();
+      (materials, assets.add(Material::default()))
+   };
+   let meshes: Vec<Vec<MeshInstance>> = {
+      let mut assets = assets_mut::<MeshData>(world);
+      document
+         .meshes()
+         .map(|mesh| {
+            mesh.primitives()
+               .filter_map(|primitive| {
+                  let data = read_primitive(&primitive, buffers)?;
+                  let material = primitive.material().index().map_or(default_material, |index| materials[index]);
+                  Some(MeshInstance { mesh: assets.add(data), material })
+               })
+               .collect()
+         })
+         .collect()
+   };
+
+   let root = world.spawn((Transform::IDENTITY,));
+   if let Some(name) = scene.name().map(str::to_owned).or(file_name) {
+      world.insert_one(root, Name(name));
+   }
+   let mut cameras = Vec::new();
+   let mut stack: Vec<(gltf::Node, Entity)> = scene.nodes().map(|node| (node, root)).collect();
+   stack.reverse();
+   while let Some((node, parent)) = stack.pop() {
+      let entity = spawn_node(world, &node, &meshes);
+      set_parent(world, entity, parent);
+      if world.has::<Camera>(entity) {
+         cameras.push(entity);
+      }
+      let first_child = stack.len();
+      stack.extend(node.children().map(|child| (child, entity)));
+      // Children are visited in the order of the file
+      stack[first_child..].reverse();
+   }
+
+   Ok(GltfScene { root, meshes, materials, cameras })
+}
+
+fn spawn_node(world: &mut World, node: &gltf::Node, meshes: &[Vec<MeshInstance>]) -> Entity {
+   let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+   let transform = Transform {
+      translation: translation.into(),
+      rotation: Quaternion::new(w, x, y, z),
+      scale: scale.into(),
+   };
+   let entity = world.spawn((transform,));
+   if let Some(name) = node.name() {
+      world.insert_one(entity, Name(name.to_owned()));
+   }
+   if let Some(camera) = node.camera() {
+      world.insert_one(entity, convert_camera(&camera));
+   }
+   if let Some(light) = node.light() {
+      let color = light.color().into();
+      let intensity = light.intensity();
+      let range = light.range();
+      match light.kind() {
+         Kind::Directional => {
+            world.insert_one(entity, DirectionalLight { color, intensity });
+         }
+         Kind::Point => {
+            world.insert_one(entity, PointLight { color, intensity, range });
+         }
+         Kind::Spot { inner_cone_angle, outer_cone_angle } => {
+            world.insert_one(entity, SpotLight {
+               color,
+               intensity,
+               range,
+               inner_cone_angle: Rad(inner_cone_angle),
+               outer_cone_angle: Rad(outer_cone_angle),
+            });
+         }
+      }
+   }
+   if let Some(mesh) = node.mesh() {
+      match &meshes[mesh.index()][..] {
+         [] => {}
+         [instance] => {
+            world.insert_one(entity, *instance);
+         }
+         instances => {
+            for &instance in instances {
+               let primitive = world.spawn((Transform::IDENTITY, instance));
+               set_parent(world, primitive, entity);
+            }
+         }
+      }
+   }
+   entity
+}
+
+fn read_primitive(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Option<MeshData> {
+   let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+   let mut vertices: Vec<Vertex> = reader
+      .read_positions()?
+      .map(|position| Vertex { position: position.into(), ..Default::default() })
+      .collect();
+   if let Some(normals) = reader.read_normals() {
+      vertices.iter_mut().zip(normals).for_each(|(vertex, normal)| vertex.normal = normal.into());
+   }
+   if let Some(tangents) = reader.read_tangents() {
+      vertices.iter_mut().zip(tangents).for_each(|(vertex, tangent)| vertex.tangent = tangent.into());
+   }
+   if let Some(uvs) = reader.read_tex_coords(0) {
+      vertices.iter_mut().zip(uvs.into_f32()).for_each(|(vertex, uv)| vertex.uv = uv.into());
+   }
+   if let Some(uvs) = reader.read_tex_coords(1) {
+      vertices.iter_mut().zip(uvs.into_f32()).for_each(|(vertex, uv)| vertex.uv1 = uv.into());
+   }
+   if let Some(colors) = reader.read_colors(0) {
+      vertices.iter_mut().zip(colors.into_rgba_f32()).for_each(|(vertex, color)| vertex.color = color.into());
+   }
+   if let Some(joints) = reader.read_joints(0) {
+      vertices.iter_mut().zip(joints.into_u16()).for_each(|(vertex, joints)| vertex.joints = joints);
+   }
+   if let Some(weights) = reader.read_weights(0) {
+      vertices.iter_mut().zip(weights.into_f32()).for_each(|(vertex, weights)| vertex.weights = weights.into());
+   }
+
+   let indices: Vec<u32> = match reader.read_indices() {
+      Some(indices) => indices.into_u32().collect(),
+      None => (0..vertices.len() as u32).collect(),
+   };
+   let indices = match primitive.mode() {
+      Mode::Triangles => indices,
+      Mode::TriangleStrip => (0..indices.len().saturating_sub(2))
+         .flat_map(|i| {
+            // Every second triangle of a strip is wound the other way
+            if i % 2 == 0 {
+               [indices[i], indices[i + 1], indices[i + 2]]
+            } else {
+               [indices[i + 1], indices[i], indices[i + 2]]
+            }
+         })
+         .collect(),
+      Mode::TriangleFan => (1..indices.len().saturating_sub(1))
+         .flat_map(|i| [indices[0], indices[i], indices[i + 1]])
+         .collect(),
+      Mode::Points | Mode::Lines | Mode::LineLoop | Mode::LineStrip => return None,
+   };
+   if vertices.is_empty() || indices.is_empty() {
+      return None;
+   }
+
+   let mut mesh = MeshData::new(vertices, Indices::compact(indices));
+   // Missing normals must be flat by the spec
+   if reader.read_normals().is_none() {
+      mesh.compute_flat_normals();
+   }
+   // Tangents depend on the final normals, and are only meaningful with UVs to follow
+   if reader.read_tangents().is_none() && reader.read_tex_coords(0).is_some() {
+      mesh.compute_tangents();
+   }
+   Some(mesh)
+}
+
+fn convert_material(material: &gltf::Material, textures: &[Handle<Texture>]) -> Material {
+   let slot = |info: Option<gltf::texture::Info>| {
+      info.map(|info| TextureSlot {
+         texture: textures[info.texture().index()],
+         uv_set: info.tex_coord(),
+      })
+   };
+   let pbr = material.pbr_metallic_roughness();
+   let normal = material.normal_texture();
+   let occlusion = material.occlusion_texture();
+   Material {
+      base_color: pbr.base_color_factor().into(),
+      base_color_texture: slot(pbr.base_color_texture()),
+      metallic: pbr.metallic_factor(),
+      roughness: pbr.roughness_factor(),
+      metallic_roughness_texture: slot(pbr.metallic_roughness_texture()),
+      normal_texture: normal.as_ref().map(|normal| TextureSlot {
+         texture: textures[normal.texture().index()],
+         uv_set: normal.tex_coord(),
+      }),
+      normal_scale: normal.as_ref().map_or(1.0, |normal| normal.scale()),
+      occlusion_texture: occlusion.as_ref().map(|occlusion| TextureSlot {
+         texture: textures[occlusion.texture().index()],
+         uv_set: occlusion.tex_coord(),
+      }),
+      occlusion_strength: occlusion.as_ref().map_or(1.0, |occlusion| occlusion.strength()),
+      emissive: material.emissive_factor().into(),
+      emissive_texture: slot(material.emissive_texture()),
+      alpha_mode: match material.alpha_mode() {
+         gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+         gltf::material::AlphaMode::Mask => AlphaMode::Mask {
+            cutoff: material.alpha_cutoff().unwrap_or(0.5),
+         },
+         gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+      },
+      double_sided: material.double_sided(),
+   }
+}
+
+fn convert_sampler(sampler: &gltf::texture::Sampler) -> SamplerDesc {
+   let mag_filter = match sampler.mag_filter() {
+      Some(MagFilter::Nearest) => vk::Filter::NEAREST,
+      Some(MagFilter::Linear) | None => vk::Filter::LINEAR,
+   };
+   let (min_filter, mipmap_mode) = match sampler.min_filter() {
+      Some(MinFilter::Nearest) | Some(MinFilter::NearestMipmapNearest) => {
+         (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST)
+      }
+      Some(MinFilter::Linear) | Some(MinFilter::LinearMipmapNearest) => {
+         (vk::Filter::LINEAR, vk::SamplerMipmapMode::NEAREST)
+      }
+      Some(MinFilter::NearestMipmapLinear) => (vk::Filter::NEAREST, vk::SamplerMipmapMode::LINEAR),
+      Some(MinFilter::LinearMipmapLinear) | None => (vk::Filter::LINEAR, vk::SamplerMipmapMode::LINEAR),
+   };
+   let address_mode = |mode| match mode {
+      WrappingMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+      WrappingMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+      WrappingMode::Repeat => vk::SamplerAddressMode::REPEAT,
+   };
+   SamplerDesc {
+      mag_filter,
+      min_filter,
+      mipmap_mode,
+      address_mode_u: address_mode(sampler.wrap_s()),
+      address_mode_v: address_mode(sampler.wrap_t()),
+   }
+}
+
+fn convert_camera(camera: &gltf::Camera) -> Camera {
+   match camera.projection() {
+      GltfProjection::Perspective(perspective) => Camera {
+         aspect_ratio: perspective.aspect_ratio().unwrap_or(1.0),
+         ..Camera::perspective(Perspective {
+            fov_y: Rad(perspective.yfov()),
+            near: perspective.znear(),
+            far: perspective.zfar(),
+            ..Default::default()
+         })
+      },
+      GltfProjection::Orthographic(orthographic) => Camera {
+         aspect_ratio: orthographic.xmag() / orthographic.ymag(),
+         ..Camera::orthographic(Orthographic {
+            height: 2.0 * orthographic.ymag(),
+            near: orthographic.znear(),
+            far: orthographic.zfar(),
+         })
+      },
+   }
+}
+
+/// Expands any glTF image format to 8 bit RGBA. Gray images are replicated to RGB
+fn convert_image(image: &gltf::image::Data) -> Image {
+   let unorm16 = |bytes: &[u8]| (u16::from_ne_bytes([bytes[0], bytes[1]]) >> 8) as u8;
+   let float32 = |bytes: &[u8]| {
+      let value = f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+      (value.clamp(0.0, 1.0) * 255.0).round() as u8
+   };
+   let pixels = &image.pixels;
+   let rgba: Vec<u8> = match image.format {
+      Format::R8 => pixels.iter().flat_map(|&l| [l, l, l, 255]).collect(),
+      Format::R8G8 => pixels.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+      Format::R8G8B8 => pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+      Format::R8G8B8A8 => pixels.clone(),
+      Format::R16 => pixels.chunks_exact(2).flat_map(|p| {
+         let l = unorm16(p);
+         [l, l, l, 255]
+      }).collect(),
+      Format::R16G16 => pixels.chunks_exact(4).flat_map(|p| {
+         let l = unorm16(&p[0..2]);
+         [l, l, l, unorm16(&p[2..4])]
+      }).collect(),
+      Format::R16G16B16 => pixels.chunks_exact(6)
+         .flat_map(|p| [unorm16(&p[0..2]), unorm16(&p[2..4]), unorm16(&p[4..6]), 255])
+         .collect(),
+      Format::R16G16B16A16 => pixels.chunks_exact(2).map(unorm16).collect(),
+      Format::R32G32B32FLOAT => pixels.chunks_exact(12)
+         .flat_map(|p| [float32(&p[0..4]), float32(&p[4..8]), float32(&p[8..12]), 255])
+         .collect(),
+      Format::R32G32B32A32FLOAT => pixels.chunks_exact(4).map(float32).collect(),
+   };
+   Image::new(image.width, image.height, rgba)
+}
+
+#[cfg(test)]
+mod tests {
+   use std::io::Cursor;
+
+   use cgmath::{Vector3, Vector4};
+   use image::{DynamicImage, ImageOutputFormat, RgbaImage};
+
+   use super::*;
+   use crate::asset::Assets;
+   use crate::scene::{propagate_transforms, Children, GlobalTransform, Parent, Projection};
+
+   /// A scene root "Parent" node with a mesh "Child" and a spot light "Spot", and a camera next to it.
+   /// "Pair" has a mesh of two primitives, the second one without a material. The triangle has UVs, but
+   /// neither normals nor tangents. `BIN_LENGTH` and `PNG_LENGTH` are replaced by the actual lengths
+   const SCENE: &str = r#"{
+      "asset": { "version": "2.0" },
+      "extensionsUsed": ["KHR_lights_punctual"],
+      "extensions": { "KHR_lights_punctual": { "lights": [{
+         "type": "spot", "color": [1.0, 1.0, 0.5], "intensity": 3.0, "range": 10.0,
+         "spot": { "innerConeAngle": 0.2, "outerConeAngle": 0.4 }
+      }] } },
+      "scene": 0,
+      "scenes": [{ "name": "Fixture", "nodes": [0, 3, 4] }],
+      "nodes": [
+         { "name": "Parent", "translation": [1.0, 2.0, 3.0], "children": [1, 2] },
+         { "name": "Child", "mesh": 0, "scale": [2.0, 2.0, 2.0] },
+         {
+            "name": "Spot", "rotation": [-0.5, 0.0, 0.0, 0.8660254],
+            "extensions": { "KHR_lights_punctual": { "light": 0 } }
+         },
+         { "name": "Camera", "camera": 0 },
+         { "name": "Pair", "mesh": 1 }
+      ],
+      "cameras": [{
+         "type": "perspective",
+         "perspective": { "yfov": 0.8, "znear": 0.05, "zfar": 50.0, "aspectRatio": 1.5 }
+      }],
+      "meshes": [
+         { "primitives": [{ "attributes": { "POSITION": 0, "TEXCOORD_0": 1 }, "indices": 2, "material": 0 }] },
+         { "primitives": [
+            { "attributes": { "POSITION": 0 }, "indices": 2, "material": 0 },
+            { "attributes": { "POSITION": 0 } }
+         ] }
+      ],
+      "materials": [{
+         "pbrMetallicRoughness": {
+            "baseColorFactor": [0.5, 0.25, 1.0, 1.0],
+            "baseColorTexture": { "index": 0 },
+            "metallicFactor": 0.1,
+            "roughnessFactor": 0.7
+         },
+         "normalTexture": { "index": 0, "texCoord": 1, "scale": 0.5 },
+         "emissiveFactor": [1.0, 0.0, 0.0],
+         "alphaMode": "MASK",
+         "alphaCutoff": 0.3,
+         "doubleSided": true
+      }],
+      "textures": [{ "source": 0, "sampler": 0 }],
+      "samplers": [{ "magFilter": 9728, "minFilter": 9987, "wrapS": 33071, "wrapT": 33648 }],
+      "images": [{ "bufferView": 3, "mimeType": "image/png" }],
+      "accessors": [
+         { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+         { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" },
+         { "bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }
+      ],
+      "bufferViews": [
+         { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+         { "buffer": 0, "byteOffset": 36, "byteLength": 24 },
+         { "buffer": 0, "byteOffset": 60, "byteLength": 6 },
+         { "buffer": 0, "byteOffset": 68, "byteLength": PNG_LENGTH }
+      ],
+      "buffers": [{ "byteLength": BIN_LENGTH }]
+   }"#;
+
+   /// Red and blue pixels
+   fn png() -> Vec<u8> {
+      let image = RgbaImage::from_raw(2, 1, vec![255, 0, 0, 255, 0, 0, 255, 255]).unwrap();
+      let mut png = Cursor::new(Vec::new());
+      DynamicImage::ImageRgba8(image).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+      png.into_inner()
+   }
+
+   /// Packs the JSON and the binary buffer into a `.glb`
+   fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+      let padded = |bytes: &[u8], fill: u8| {
+         let mut bytes = bytes.to_vec();
+         bytes.resize(bytes.len().next_multiple_of(4), fill);
+         bytes
+      };
+      let chunks = [(padded(json.as_bytes(), b' '), b"JSON"), (padded(bin, 0), b"BIN\0")];
+      let length = 12 + chunks.iter().map(|(chunk, _)| 8 + chunk.len()).sum::<usize>();
+      let mut glb = b"glTF".to_vec();
+      glb.extend(2_u32.to_le_bytes());
+      glb.extend((length as u32).to_le_bytes());
+      for (chunk, kind) in chunks {
+         glb.extend((chunk.len() as u32).to_le_bytes());
+         glb.extend(kind);
+         glb.extend(chunk);
+      }
+      glb
+   }
+
+   fn load_fixture(world: &mut World) -> GltfScene {
+      let floats = |values: &[f32]| values.iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<u8>>();
+      let png = png();
+      let mut bin = floats(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+      bin.extend(floats(&[0.0, 1.0, 1.0, 1.0, 0.0, 0.0]));
+      bin.extend([0_u16, 1, 2, 0].iter().flat_map(|index| index.to_le_bytes()));
+      bin.extend(&png);
+      let json = SCENE
+         .replace("PNG_LENGTH", &png.len().to_string())
+         .replace("BIN_LENGTH", &bin.len().to_string());
+      load_gltf_from_slice(world, &glb(&json, &bin)).unwrap()
+   }
+
+   fn find(world: &World, name: &str) -> Entity {
+      let mut query = world.query::<&Name>();
+      let found = query.iter().find(|(_, other)| other.0 == name).map(|(entity, _)| entity);
+      found.unwrap_or_else(|| panic!("No entity named {}", name))
+   }
+
+   #[test]
+   fn nodes_become_a_hierarchy_under_the_root() {
+      let mut world = World::new();
+      let scene = load_fixture(&mut world);
+      assert_eq!(world.get::<Name>(scene.root).unwrap().0, "Fixture");
+      let (parent, child) = (find(&world, "Parent"), find(&world, "Child"));
+      assert_eq!(*world.get::<Parent>(parent).unwrap(), Parent(scene.root));
+      assert_eq!(*world.get::<Parent>(child).unwrap(), Parent(parent));
+      assert_eq!(*world.get::<Parent>(find(&world, "Spot")).unwrap(), Parent(parent));
+      assert_eq!(world.get::<Transform>(parent).unwrap().translation, Vector3::new(1.0, 2.0, 3.0));
+      assert_eq!(world.get::<Transform>(child).unwrap().scale, Vector3::new(2.0, 2.0, 2.0));
+      // glTF stores quaternions as XYZW
+      let rotation = world.get::<Transform>(find(&world, "Spot")).unwrap().rotation;
+      assert_eq!(rotation, Quaternion::new(0.8660254, -0.5, 0.0, 0.0));
+
+      propagate_transforms(&mut world);
+      assert_eq!(world.get::<GlobalTransform>(child).unwrap().translation(), Vector3::new(1.0, 2.0, 3.0));
+   }
+
+   #[test]
+   fn primitives_become_mesh_instances() {
+      let mut world = World::new();
+      let scene = load_fixture(&mut world);
+      let child = find(&world, "Child");
+      let instance = *world.get::<MeshInstance>(child).unwrap();
+      assert_eq!(scene.meshes[0], [instance]);
+      assert_eq!(instance.material, scene.materials[0]);
+
+      // Several primitives get an entity each, a missing material is the default one
+      let pair = find(&world, "Pair");
+      assert!(!world.has::<MeshInstance>(pair));
+      let children: Vec<Entity> = world.get::<Children>(pair).unwrap().iter().collect();
+      assert_eq!(children.len(), 2);
+      let materials = world.resource::<Assets<Material>>();
+      let second = *world.get::<MeshInstance>(children[1]).unwrap();
+      assert_eq!(materials[second.material], Material::default());
+   }
+
+   #[test]
+   fn generates_flat_normals_and_tangents_from_uvs() {
+      let mut world = World::new();
+      let scene = load_fixture(&mut world);
+      let meshes = world.resource::<Assets<MeshData>>();
+      let mesh = &meshes[scene.meshes[0][0].mesh];
+      assert_eq!(mesh.vertices.len(), 3);
+      for vertex in &mesh.vertices {
+         assert_eq!(vertex.normal, Vector3::unit_z());
+         // U grows along +X, V along -Y
+         assert_eq!(vertex.tangent, Vector4::new(1.0, 0.0, 0.0, 1.0));
+      }
+      // Without UVs there is nothing for the tangents to follow
+      let without_uvs = &meshes[scene.meshes[1][0].mesh];
+      assert!(without_uvs.vertices.iter().all(|vertex| vertex.tangent == Vector4::new(0.0, 0.0, 0.0, 0.0)));
+   }
+
+   #[test]
+   fn converts_material_factors_and_textures() {
+      let mut world = World::new();
+      let scene = load_fixture(&mut world);
+      let material = world.resource::<Assets<Material>>()[scene.materials[0]].clone();
+      assert_eq!(material.base_color, Vector4::new(0.5, 0.25, 1.0, 1.0));
+      assert_eq!((material.metallic, material.roughness), (0.1, 0.7));
+      assert_eq!(material.emissive, Vector3::new(1.0, 0.0, 0.0));
+      assert_eq!(material.alpha_mode, AlphaMode::Mask { cutoff: 0.3 });
+      assert!(material.double_sided);
+      assert_eq!(material.normal_scale, 0.5);
+      assert!(material.metallic_roughness_texture.is_none() && material.occlusion_texture.is_none());
+
+      let base_color = material.base_color_texture.unwrap();
+      let normal = material.normal_texture.unwrap();
+      assert_eq!((base_color.uv_set, normal.uv_set), (0, 1));
+      assert_eq!(base_color.texture, normal.texture);
+      let texture = world.resource::<Assets<Texture>>()[base_color.texture];
+      assert_eq!(texture.sampler, SamplerDesc {
+         mag_filter: vk::Filter::NEAREST,
+         min_filter: vk::Filter::LINEAR,
+         mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+         address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+         address_mode_v: vk::SamplerAddressMode::MIRRORED_REPEAT,
+      });
+      let images = world.resource::<Assets<Image>>();
+      assert_eq!(images[texture.image], Image::new(2, 1, vec![255, 0, 0, 255, 0, 0, 255, 255]));
+   }
+
+   #[test]
+   fn converts_lights_and_cameras() {
+      let mut world = World::new();
+      let scene = load_fixture(&mut world);
+      let spot = *world.get::<SpotLight>(find(&world, "Spot")).unwrap();
+      assert_eq!(spot, SpotLight {
+         color: Vector3::new(1.0, 1.0, 0.5),
+         intensity: 3.0,
+         range: Some(10.0),
+         inner_cone_angle: Rad(0.2),
+         outer_cone_angle: Rad(0.4),
+      });
+
+      let camera_entity = find(&world, "Camera");
+      assert_eq!(scene.cameras, [camera_entity]);
+      let camera = *world.get::<Camera>(camera_entity).unwrap();
+      assert_eq!(camera.aspect_ratio, 1.5);
+      let Projection::Perspective(perspective) = camera.projection else { panic!("Not a perspective camera") };
+      assert_eq!(perspective.fov_y, Rad(0.8));
+      assert_eq!((perspective.near, perspective.far), (0.05, Some(50.0)));
+   }
+
+   #[test]
+   fn rejects_files_without_scenes_and_invalid_files() {
+      let mut world = World::new();
+      let empty = glb(r#"{ "asset": { "version": "2.0" } }"#, &[]);
+      assert!(matches!(load_gltf_from_slice(&mut world, &empty), Err(GltfError::NoScene)));
+      assert!(matches!(load_gltf_from_slice(&mut world, b"glTF"), Err(GltfError::Import(_))));
+   }
+}
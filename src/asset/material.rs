@@ -0,0 +1,108 @@
+use cgmath::{Vector3, Vector4, Zero};
+
+use super::{Handle, Texture};
+
+/// How the alpha of the base color is interpreted
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum AlphaMode {
+   /// Alpha is ignored
+   #[default]
+   Opaque,
+   /// Fragments with alpha below the cutoff are discarded, the rest are opaque
+   Mask { cutoff: f32 },
+   /// Blended over what's behind
+   Blend,
+}
+
+/// Texture bound to a material slot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureSlot {
+   pub texture: Handle<Texture>,
+   /// Index of the UV set of the vertex, 0 for `Vertex::uv` and 1 for `Vertex::uv1`
+   pub uv_set: u32,
+}
+
+impl TextureSlot {
+   pub fn new(texture: Handle<Texture>) -> Self {
+      TextureSlot { texture, uv_set: 0 }
+   }
+}
+
+/// Metallic-roughness PBR material, as in glTF. Texture values multiply the factors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+   /// Linear RGBA
+   pub base_color: Vector4<f32>,
+   /// sRGB encoded RGBA
+   pub base_color_texture: Option<TextureSlot>,
+   pub metallic: f32,
+   pub roughness: f32,
+   /// Roughness in the G channel, metalness in the B channel
+   pub metallic_roughness_texture: Option<TextureSlot>,
+   /// Tangent space normal map
+   pub normal_texture: Option<TextureSlot>,
+   pub normal_scale: f32,
+   /// Ambient occlusion in the R channel
+   pub occlusion_texture: Option<TextureSlot>,
+   pub occlusion_strength: f32,
+   /// Linear RGB
+   pub emissive: Vector3<f32>,
+   /// sRGB encoded RGB
+   pub emissive_texture: Option<TextureSlot>,
+   pub alpha_mode: AlphaMode,
+   /// Disables back face culling and flips normals of back faces
+   pub double_sided: bool,
+}
+
+impl Default for Material {
+   fn default() -> Self {
+      Material {
+         base_color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+         base_color_texture: None,
+         metallic: 1.0,
+         roughness: 1.0,
+         metallic_roughness_texture: None,
+         normal_texture: None,
+         normal_scale: 1.0,
+         occlusion_texture: None,
+         occlusion_strength: 1.0,
+         emissive: Vector3::zero(),
+         emissive_texture: None,
+         alpha_mode: AlphaMode::Opaque,
+         double_sided: false,
+      }
+   }
+}
+
+impl Material {
+   pub fn with_base_color(mut self, base_color: Vector4<f32>) -> Self {
+      self.base_color = base_color;
+      self
+   }
+
+   pub fn with_base_color_texture(mut self, texture: Handle<Texture>) -> Self {
+      self.base_color_texture = Some(TextureSlot::new(texture));
+      self
+   }
+
+   pub fn with_metallic_roughness(mut self, metallic: f32, roughness: f32) -> Self {
+      self.metallic = metallic;
+      self.roughness = roughness;
+      self
+   }
+
+   pub fn with_emissive(mut self, emissive: Vector3<f32>) -> Self {
+      self.emissive = emissive;
+      self
+   }
+
+   pub fn with_alpha_mode(mut self, alpha_mode: AlphaMode) -> Self {
+      self.alpha_mode = alpha_mode;
+      self
+   }
+
+   pub fn with_double_sided(mut self, double_sided: bool) -> Self {
+      self.double_sided = double_sided;
+      self
+   }
+}
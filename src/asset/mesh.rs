@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector2, Vector3, Vector4, Zero};
+
+use crate::platform::gpu::vulkan_context::VulkanContext;
+use crate::platform::gpu::vulkan_mesh::{Indices, Mesh};
+use crate::vertex_layout;
+
+vertex_layout! {
+   /// Vertex with all standard glTF attributes. Attributes missing in the source are zero,
+   /// except the color, which is white.
+   #[derive(Clone, Copy, Debug, PartialEq)]
+   pub struct Vertex {
+      pub position: Vector3<f32>,
+      pub normal: Vector3<f32>,
+      /// XYZ is the tangent, W is the handedness of the bitangent, +1 or -1
+      pub tangent: Vector4<f32>,
+      pub uv: Vector2<f32>,
+      pub uv1: Vector2<f32>,
+      pub color: Vector4<f32>,
+      pub joints: [u16; 4],
+      pub weights: Vector4<f32>,
+   }
+}
+
+impl Default for Vertex {
+   fn default() -> Self {
+      Vertex {
+         position: Vector3::zero(),
+         normal: Vector3::zero(),
+         tangent: Vector4::zero(),
+         uv: Vector2::zero(),
+         uv1: Vector2::zero(),
+         color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+         joints: [0; 4],
+         weights: Vector4::zero(),
+      }
+   }
+}
+
+/// Indexed triangle list on the CPU, upload it with `upload` to draw it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshData {
+   pub vertices: Vec<Vertex>,
+   pub indices: Indices,
+}
+
+impl MeshData {
+   pub fn new(vertices: Vec<Vertex>, indices: Indices) -> Self {
+      MeshData { vertices, indices }
+   }
+
+   pub fn triangles(&self) -> impl Iterator<Item = [u32; 3]> + '_ {
+      let mut indices = self.indices.iter();
+      std::iter::from_fn(move || Some([indices.next()?, indices.next()?, indices.next()?]))
+   }
+
+   /// Gives every triangle its own vertices, so attributes aren't shared between faces
+   pub fn unweld(&mut self) {
+      let vertices: Vec<Vertex> = self.indices.iter().map(|index| self.vertices[index as usize]).collect();
+      self.indices = Indices::compact((0..vertices.len() as u32).collect());
+      self.vertices = vertices;
+   }
+
+   /// Merges vertices with identical attributes
+   pub fn weld(&mut self) {
+      let mut unique: HashMap<[u32; 24], u32> = HashMap::new();
+      let mut vertices = Vec::new();
+      let indices = self
+         .indices
+         .iter()
+         .map(|index| {
+            let vertex = self.vertices[index as usize];
+            *unique.entry(vertex_key(&vertex)).or_insert_with(|| {
+               vertices.push(vertex);
+               vertices.len() as u32 - 1
+            })
+         })
+         .collect();
+      self.indices = Indices::compact(indices);
+      self.vertices = vertices;
+   }
+
+   /// Unwelds the mesh and sets normals to the ones of the faces, for a faceted look
+   pub fn compute_flat_normals(&mut self) {
+      self.unweld();
+      for triangle in self.vertices.chunks_exact_mut(3) {
+         let normal = face_normal(triangle, [0, 1, 2]);
+         // Degenerate triangles keep a zero normal
+         let normal = if normal.is_zero() { normal } else { normal.normalize() };
+         triangle.iter_mut().for_each(|vertex| vertex.normal = normal);
+      }
+   }
+
+   /// Sets normals to the area weighted average of the faces around each position. Vertices at
+   /// the same position share the normal even if they differ in other attributes
+   pub fn compute_smooth_normals(&mut self) {
+      let position_key = |vertex: &Vertex| [vertex.position.x.to_bits(), vertex.position.y.to_bits(), vertex.position.z.to_bits()];
+      let mut normals: HashMap<[u32; 3], Vector3<f32>> = HashMap::new();
+      for triangle in self.triangles() {
+         let normal = face_normal(&self.vertices, triangle);
+         for index in triangle {
+            *normals.entry(position_key(&self.vertices[index as usize])).or_insert_with(Vector3::zero) += normal;
+         }
+      }
+      for vertex in self.vertices.iter_mut() {
+         let normal = normals.get(&position_key(vertex)).copied().unwrap_or_else(Vector3::zero);
+         vertex.normal = if normal.is_zero() { normal } else { normal.normalize() };
+      }
+   }
+
+   /// Computes tangents from the UVs, for normal mapping. Tangents point along +U, and the
+   /// bitangent `cross(normal, tangent.xyz) * tangent.w` points along -V, to the top of the
+   /// texture, as glTF normal maps expect
+   pub fn compute_tangents(&mut self) {
+      let mut tangents = vec![Vector3::zero(); self.vertices.len()];
+      let mut bitangents = vec![Vector3::zero(); self.vertices.len()];
+      for triangle in self.triangles() {
+         let [a, b, c] = triangle.map(|index| &self.vertices[index as usize]);
+         let (edge1, edge2) = (b.position - a.position, c.position - a.position);
+         let (delta_uv1, delta_uv2) = (b.uv - a.uv, c.uv - a.uv);
+         let determinant = delta_uv1.x * delta_uv2.y - delta_uv2.x * delta_uv1.y;
+         if determinant.abs() < f32::EPSILON {
+            continue;
+         }
+         // Weighted by the area, like the normals
+         let area = face_normal(&self.vertices, triangle).magnitude();
+         let tangent = ((edge1 * delta_uv2.y - edge2 * delta_uv1.y) / determinant).normalize() * area;
+         let bitangent = ((edge1 * delta_uv2.x - edge2 * delta_uv1.x) / determinant).normalize() * area;
+         for index in triangle {
+            tangents[index as usize] += tangent;
+            bitangents[index as usize] += bitangent;
+         }
+      }
+      for ((vertex, tangent), bitangent) in self.vertices.iter_mut().zip(tangents).zip(bitangents) {
+         let normal = vertex.normal;
+         // Gram-Schmidt, so the tangent is perpendicular to the normal
+         let mut tangent = tangent - normal * normal.dot(tangent);
+         if tangent.magnitude2() < 1e-12 {
+            // Any perpendicular works where UVs don't vary
+            let axis = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+            tangent = axis - normal * normal.dot(axis);
+         }
+         let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 { -1.0 } else { 1.0 };
+         vertex.tangent = tangent.normalize().extend(handedness);
+      }
+   }
+
+   pub fn upload(&self, context: &VulkanContext) -> Mesh {
+      Mesh::new(context, &self.vertices, &self.indices)
+   }
+}
+
+fn vertex_key(vertex: &Vertex) -> [u32; 24] {
+   let Vertex { position: p, normal: n, tangent: t, uv, uv1, color: c, joints: j, weights: w } = *vertex;
+   [
+      p.x, p.y, p.z, n.x, n.y, n.z, t.x, t.y, t.z, t.w, uv.x, uv.y, uv1.x, uv1.y, c.x, c.y, c.z, c.w,
+      w.x, w.y, w.z, w.w,
+   ]
+   .map(f32::to_bits)
+   .into_iter()
+   .chain([u32::from(j[0]) | u32::from(j[1]) << 16, u32::from(j[2]) | u32::from(j[3]) << 16])
+   .collect::<Vec<_>>()
+   .try_into()
+   .unwrap()
+}
+
+/// Normal of a counter-clockwise triangle, its length is twice the area
+pub(super) fn face_normal(vertices: &[Vertex], [a, b, c]: [u32; 3]) -> Vector3<f32> {
+   let a = vertices[a as usize].position;
+   let b = vertices[b as usize].position;
+   let c = vertices[c as usize].position;
+   (b - a).cross(c - a)
+}
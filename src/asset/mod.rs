@@ -0,0 +1,139 @@
+//! CPU side assets shared between entities, and importers filling them from files.
+//!
+//! Assets of each type live in an `Assets<T>` world resource, components refer to them by `Handle<T>`:
+//!
+//! ```ignore
+//! let model = load_gltf(&mut world, "assets/helmet.glb")?;
+//! for (_, instance) in world.query::<&MeshInstance>().iter() {
+//!     let mesh = &world.resource::<Assets<MeshData>>()[instance.mesh];
+//! }
+//! ```
+
+mod gltf_import;
+mod material;
+mod mesh;
+mod obj_import;
+mod shape;
+mod texture;
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
+
+use crate::ecs::{ResMut, World};
+
+pub use gltf_import::{load_gltf, load_gltf_from_slice, GltfError, GltfScene};
+pub use material::{AlphaMode, Material, TextureSlot};
+pub use mesh::{MeshData, Vertex};
+pub use obj_import::{load_obj, NormalGeneration, ObjError, ObjScene};
+pub use texture::{HdrImage, Image, SamplerDesc, Texture};
+
+/// Reference to an asset in `Assets<T>`.
+pub struct Handle<T> {
+   index: u32,
+   marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+   pub fn index(&self) -> u32 {
+      self.index
+   }
+}
+
+impl<T> Clone for Handle<T> {
+   fn clone(&self) -> Self {
+      *self
+   }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+   fn eq(&self, other: &Self) -> bool {
+      self.index == other.index
+   }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+   fn hash<H: Hasher>(&self, state: &mut H) {
+      self.index.hash(state);
+   }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      write!(f, "Handle<{}>({})", std::any::type_name::<T>(), self.index)
+   }
+}
+
+/// Storage of assets of one type. Assets are never removed, so handles stay valid.
+pub struct Assets<T> {
+   items: Vec<T>,
+}
+
+impl<T> Default for Assets<T> {
+   fn default() -> Self {
+      Assets { items: Vec::new() }
+   }
+}
+
+impl<T> Assets<T> {
+   pub fn new() -> Self {
+      Default::default()
+   }
+
+   pub fn add(&mut self, asset: T) -> Handle<T> {
+      self.items.push(asset);
+      Handle {
+         index: self.items.len() as u32 - 1,
+         marker: PhantomData,
+      }
+   }
+
+   pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+      self.items.get(handle.index as usize)
+   }
+
+   pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+      self.items.get_mut(handle.index as usize)
+   }
+
+   pub fn len(&self) -> usize {
+      self.items.len()
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.items.is_empty()
+   }
+
+   pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+      self.items.iter().enumerate().map(|(index, asset)| {
+         (Handle { index: index as u32, marker: PhantomData }, asset)
+      })
+   }
+}
+
+impl<T> Index<Handle<T>> for Assets<T> {
+   type Output = T;
+
+   fn index(&self, handle: Handle<T>) -> &T {
+      &self.items[handle.index as usize]
+   }
+}
+
+impl<T> IndexMut<Handle<T>> for Assets<T> {
+   fn index_mut(&mut self, handle: Handle<T>) -> &mut T {
+      &mut self.items[handle.index as usize]
+   }
+}
+
+/// `Assets<T>` resource of the world, inserted if missing
+pub(crate) fn assets_mut<T: Send + Sync + 'static>(world: &mut World) -> ResMut<'_, Assets<T>> {
+   if !world.has_resource::<Assets<T>>() {
+      world.insert_resource(Assets::<T>::new());
+   }
+   world.resource_mut::<Assets<T>>()
+}
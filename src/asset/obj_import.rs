@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use cgmath::{Vector2, Vector3, Vector4};
+
+use crate::ecs::{Entity, World};
+use crate::platform::gpu::vulkan_mesh::Indices;
+use crate::scene::{set_parent, MeshInstance, Name, Transform};
+
+use super::{assets_mut, AlphaMode, Handle, Image, Material, MeshData, SamplerDesc, Texture, TextureSlot, Vertex};
+
+#[derive(Debug)]
+pub enum ObjError {
+   Io(PathBuf, std::io::Error),
+   Parse { path: PathBuf, line: usize, message: String },
+   Image(PathBuf, image::ImageError),
+}
+
+impl fmt::Display for ObjError {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         ObjError::Io(path, err) => write!(f, "failed to read {}: {}", path.display(), err),
+         ObjError::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+         ObjError::Image(path, err) => write!(f, "failed to load texture {}: {}", path.display(), err),
+      }
+   }
+}
+
+impl std::error::Error for ObjError {}
+
+/// Normals generated for groups where some faces have none
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalGeneration {
+   /// Normal of the face, for a faceted look
+   Flat,
+   /// Average of the faces around a position
+   Smooth,
+}
+
+/// Entities and assets created by `load_obj`
+#[derive(Clone, Debug)]
+pub struct ObjScene {
+   /// Entity the meshes are attached to, move it to place the whole model
+   pub root: Entity,
+   /// One per object, group and material combination, in the order of the file
+   pub meshes: Vec<MeshInstance>,
+   /// Materials of the MTL libraries by name
+   pub materials: HashMap<String, Handle<Material>>,
+}
+
+/// Loads a Wavefront `.obj` file with the `.mtl` libraries and textures it references, and spawns
+/// a child entity of `ObjScene::root` for each object, group and material combination.
+///
+/// Polygons are triangulated as fans, so they must be convex. Vertices are deduplicated within a
+/// mesh. Faces without texture coordinates get zero UVs.
+pub fn load_obj(world: &mut World, path: impl AsRef<Path>, normals: NormalGeneration) -> Result<ObjScene, ObjError> {
+   let path = path.as_ref();
+   let source = read_file(path)?;
+   let obj = parse_obj(path, &source)?;
+
+   let mut materials = HashMap::new();
+   let mut textures: HashMap<PathBuf, Handle<Texture>> = HashMap::new();
+   for library in obj.material_libraries.iter() {
+      let library_path = path.with_file_name(library);
+      let source = read_file(&library_path)?;
+      for (name, mtl) in parse_mtl(&library_path, &source)? {
+         let material = load_material(world, &library_path, mtl, &mut textures)?;
+         materials.insert(name, assets_mut::<Material>(world).add(material));
+      }
+   }
+   let mut default_material = None;
+
+   let root = world.spawn((Transform::IDENTITY,));
+   if let Some(stem) = path.file_stem() {
+      world.insert_one(root, Name(stem.to_string_lossy().into_owned()));
+   }
+   let mut meshes = Vec::new();
+   for group in obj.groups.iter().filter(|group| !group.faces.is_empty()) {
+      let material = match group.material.as_ref().and_then(|name| materials.get(name)) {
+         Some(&material) => material,
+         None => *default_material.get_or_insert_with(|| {
+            assets_mut::<Material>(world).add(Material::default().with_metallic_roughness(0.0, 1.0))
+         }),
+      };
+      let instance = MeshInstance {
+         mesh: assets_mut::<MeshData>(world).add(build_mesh(&obj, group, normals)),
+         material,
+      };
+      let entity = world.spawn((Transform::IDENTITY, instance));
+      if let Some(name) = &group.name {
+         world.insert_one(entity, Name(name.clone()));
+      }
+      set_parent(world, entity, root);
+      meshes.push(instance);
+   }
+   Ok(ObjScene { root, meshes, materials })
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+   std::fs::read_to_string(path).map_err(|err| ObjError::Io(path.to_owned(), err))
+}
+
+/// Vertex of a face: indices of position, texture coordinate and normal
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+#[derive(Default)]
+struct Group {
+   name: Option<String>,
+   material: Option<String>,
+   /// Triangles
+   faces: Vec<[FaceVertex; 3]>,
+}
+
+#[derive(Default)]
+struct Obj {
+   positions: Vec<Vector3<f32>>,
+   colors: Vec<Option<Vector3<f32>>>,
+   uvs: Vec<Vector2<f32>>,
+   normals: Vec<Vector3<f32>>,
+   groups: Vec<Group>,
+   material_libraries: Vec<String>,
+}
+
+fn parse_floats<const N: usize>(args: &[&str], path: &Path, line: usize) -> Result<[f32; N], ObjError> {
+   let error = |message: String| ObjError::Parse { path: path.to_owned(), line, message };
+   if args.len() < N {
+      return Err(error(format!("expected {} numbers, got {}", N, args.len())));
+   }
+   let mut values = [0.0; N];
+   for (value, arg) in values.iter_mut().zip(args) {
+      *value = arg.parse().map_err(|_| error(format!("invalid number '{}'", arg)))?;
+   }
+   Ok(values)
+}
+
+fn parse_obj(path: &Path, source: &str) -> Result<Obj, ObjError> {
+   let mut obj = Obj { groups: vec![Group::default()], ..Default::default() };
+   let mut object_name: Option<String> = None;
+   for (line_index, line) in source.lines().enumerate() {
+      let line_number = line_index + 1;
+      let error = |message: String| ObjError::Parse { path: path.to_owned(), line: line_number, message };
+      let line = line.split('#').next().unwrap();
+      let mut tokens = line.split_whitespace();
+      let Some(keyword) = tokens.next() else { continue };
+      let args: Vec<&str> = tokens.collect();
+      match keyword {
+         "v" => {
+            let [x, y, z] = parse_floats(&args, path, line_number)?;
+            obj.positions.push(Vector3::new(x, y, z));
+            // A common extension puts vertex colors after the position
+            let color = match args.len() {
+               6 | 7 => Some(Vector3::from(parse_floats::<3>(&args[3..], path, line_number)?)),
+               _ => None,
+            };
+            obj.colors.push(color);
+         }
+         "vt" => {
+            let [u] = parse_floats(&args, path, line_number)?;
+            let v = if args.len() > 1 { parse_floats::<1>(&args[1..], path, line_number)?[0] } else { 0.0 };
+            // OBJ puts the origin at the bottom left, Vulkan at the top left
+            obj.uvs.push(Vector2::new(u, 1.0 - v));
+         }
+         "vn" => obj.normals.push(parse_floats::<3>(&args, path, line_number)?.into()),
+         "f" => {
+            if args.len() < 3 {
+               return Err(error(format!("face needs at least 3 vertices, got {}", args.len())));
+            }
+            let vertices = args
+               .iter()
+               .map(|arg| parse_face_vertex(arg, &obj).ok_or_else(|| error(format!("invalid face vertex '{}'", arg))))
+               .collect::<Result<Vec<_>, _>>()?;
+            let current = obj.groups.last_mut().unwrap();
+            for i in 1..vertices.len() - 1 {
+               current.faces.push([vertices[0], vertices[i], vertices[i + 1]]);
+            }
+         }
+         "o" | "g" | "usemtl" => {
+            let current = obj.groups.last_mut().unwrap();
+            let name = (!args.is_empty()).then(|| args.join(" "));
+            let mut group = Group {
+               name: current.name.clone(),
+               material: current.material.clone(),
+               faces: Vec::new(),
+            };
+            match keyword {
+               "o" => {
+                  object_name = name;
+                  group.name = object_name.clone();
+               }
+               "g" => {
+                  group.name = match (&object_name, name) {
+                     (Some(object), Some(name)) => Some(format!("{}/{}", object, name)),
+                     (object, name) => name.or_else(|| object.clone()),
+                  };
+               }
+               _ => group.material = name,
+            }
+            if current.faces.is_empty() {
+               *current = group;
+            } else {
+               obj.groups.push(group);
+            }
+         }
+         "mtllib" => obj.material_libraries.extend(args.iter().map(|&library| library.to_owned())),
+         // Smoothing groups, lines, points and free-form geometry aren't supported
+         _ => {}
+      }
+   }
+   Ok(obj)
+}
+
+/// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn`, indices are 1-based or negative from the end
+fn parse_face_vertex(arg: &str, obj: &Obj) -> Option<FaceVertex> {
+   let resolve = |index: &str, count: usize| -> Option<usize> {
+      let index: i64 = index.parse().ok()?;
+      let index = if index < 0 { count as i64 + index } else { index - 1 };
+      (0..count as i64).contains(&index).then_some(index as usize)
+   };
+   let mut parts = arg.split('/');
+   let position = resolve(parts.next()?, obj.positions.len())?;
+   let uv = match parts.next() {
+      None | Some("") => None,
+      Some(uv) => Some(resolve(uv, obj.uvs.len())?),
+   };
+   let normal = match parts.next() {
+      None | Some("") => None,
+      Some(normal) => Some(resolve(normal, obj.normals.len())?),
+   };
+   Some((position, uv, normal))
+}
+
+fn build_mesh(obj: &Obj, group: &Group, normals: NormalGeneration) -> MeshData {
+   let mut vertices = Vec::new();
+   let mut indices = Vec::with_capacity(group.faces.len() * 3);
+   let mut unique: HashMap<FaceVertex, u32> = HashMap::new();
+   for &face_vertex in group.faces.iter().flatten() {
+      let index = *unique.entry(face_vertex).or_insert_with(|| {
+         let (position, uv, normal) = face_vertex;
+         vertices.push(Vertex {
+            position: obj.positions[position],
+            normal: normal.map_or(Vector3::new(0.0, 0.0, 0.0), |normal| obj.normals[normal]),
+            uv: uv.map_or(Vector2::new(0.0, 0.0), |uv| obj.uvs[uv]),
+            color: obj.colors[position].map_or(Vector4::new(1.0, 1.0, 1.0, 1.0), |color| color.extend(1.0)),
+            ..Default::default()
+         });
+         vertices.len() as u32 - 1
+      });
+      indices.push(index);
+   }
+   let mut mesh = MeshData::new(vertices, Indices::compact(indices));
+   if group.faces.iter().flatten().any(|&(_, _, normal)| normal.is_none()) {
+      match normals {
+         NormalGeneration::Flat => mesh.compute_flat_normals(),
+         NormalGeneration::Smooth => mesh.compute_smooth_normals(),
+      }
+   }
+   // Normal maps need tangents, which follow the final normals
+   if group.faces.iter().flatten().any(|&(_, uv, _)| uv.is_some()) {
+      mesh.compute_tangents();
+   }
+   mesh
+}
+
+/// Material statements of a MTL library
+#[derive(Default)]
+struct Mtl {
+   diffuse: Option<[f32; 3]>,
+   diffuse_texture: Option<String>,
+   emissive: Option<[f32; 3]>,
+   emissive_texture: Option<String>,
+   normal_texture: Option<String>,
+   opacity: Option<f32>,
+   shininess: Option<f32>,
+   roughness: Option<f32>,
+   metallic: Option<f32>,
+}
+
+fn parse_mtl(path: &Path, source: &str) -> Result<Vec<(String, Mtl)>, ObjError> {
+   let mut materials: Vec<(String, Mtl)> = Vec::new();
+   for (line_index, line) in source.lines().enumerate() {
+      let line_number = line_index + 1;
+      let line = line.split('#').next().unwrap();
+      let mut tokens = line.split_whitespace();
+      let Some(keyword) = tokens.next() else { continue };
+      let args: Vec<&str> = tokens.collect();
+      if keyword == "newmtl" {
+         materials.push((args.join(" "), Mtl::default()));
+         continue;
+      }
+      let Some((_, mtl)) = materials.last_mut() else {
+         return Err(ObjError::Parse {
+            path: path.to_owned(),
+            line: line_number,
+            message: format!("'{}' before any 'newmtl'", keyword),
+         });
+      };
+      // Texture statements may have options before the file name, which is the last argument
+      let texture = || args.last().map(|&file| file.to_owned());
+      match keyword {
+         "Kd" => mtl.diffuse = Some(parse_floats(&args, path, line_number)?),
+         "Ke" => mtl.emissive = Some(parse_floats(&args, path, line_number)?),
+         "d" => mtl.opacity = Some(parse_floats::<1>(&args, path, line_number)?[0]),
+         "Tr" => mtl.opacity = Some(1.0 - parse_floats::<1>(&args, path, line_number)?[0]),
+         "Ns" => mtl.shininess = Some(parse_floats::<1>(&args, path, line_number)?[0]),
+         "Pr" => mtl.roughness = Some(parse_floats::<1>(&args, path, line_number)?[0]),
+         "Pm" => mtl.metallic = Some(parse_floats::<1>(&args, path, line_number)?[0]),
+         "map_Kd" => mtl.diffuse_texture = texture(),
+         "map_Ke" => mtl.emissive_texture = texture(),
+         "norm" | "map_Bump" | "map_bump" | "bump" => mtl.normal_texture = texture(),
+         _ => {}
+      }
+   }
+   Ok(materials)
+}
+
+fn load_material(
+   world: &mut World,
+   library_path: &Path,
+   mtl: Mtl,
+   textures: &mut HashMap<PathBuf, Handle<Texture>>,
+) -> Result<Material, ObjError> {
+   let mut load_texture = |file: Option<String>| -> Result<Option<TextureSlot>, ObjError> {
+      let Some(file) = file else { return Ok(None) };
+      let path = library_path.with_file_name(file.replace('\\', "/"));
+      if let Some(&texture) = textures.get(&path) {
+         return Ok(Some(TextureSlot::new(texture)));
+      }
+      let image = image::open(&path).map_err(|err| ObjError::Image(path.clone(), err))?.to_rgba8();
+      let image = Image::new(image.width(), image.height(), image.into_raw());
+      let image = assets_mut::<Image>(world).add(image);
+      let texture = assets_mut::<Texture>(world).add(Texture { image, sampler: SamplerDesc::default() });
+      textures.insert(path, texture);
+      Ok(Some(TextureSlot::new(texture)))
+   };
+
+   let opacity = mtl.opacity.unwrap_or(1.0);
+   let [r, g, b] = mtl.diffuse.unwrap_or([1.0, 1.0, 1.0]);
+   // Blinn-Phong exponent to the roughness with a similar highlight
+   let roughness = mtl.roughness
+      .or_else(|| mtl.shininess.map(|shininess| (2.0 / (shininess.max(0.0) + 2.0)).sqrt()))
+      .unwrap_or(1.0);
+   Ok(Material {
+      base_color: Vector4::new(r, g, b, opacity),
+      base_color_texture: load_texture(mtl.diffuse_texture)?,
+      metallic: mtl.metallic.unwrap_or(0.0),
+      roughness,
+      normal_texture: load_texture(mtl.normal_texture)?,
+      emissive: mtl.emissive.unwrap_or([0.0; 3]).into(),
+      emissive_texture: load_texture(mtl.emissive_texture)?,
+      alpha_mode: if opacity < 1.0 { AlphaMode::Blend } else { AlphaMode::Opaque },
+      ..Default::default()
+   })
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   use cgmath::{InnerSpace, Vector4};
+
+   fn parse(source: &str) -> Obj {
+      parse_obj(Path::new("test.obj"), source).unwrap()
+   }
+
+   fn build(source: &str, normals: NormalGeneration) -> MeshData {
+      let obj = parse(source);
+      build_mesh(&obj, &obj.groups[0], normals)
+   }
+
+   /// Two triangles folded along the shared edge from the origin to +Y, at a right angle
+   const FOLD: &str = "
+      v 0 0 0
+      v 0 1 0
+      v 1 0 0
+      v 0 0 1
+      f 1 3 2
+      f 1 2 4
+   ";
+
+   #[test]
+   fn texture_coordinates_are_flipped_vertically() {
+      let obj = parse("vt 0.25 0.75\nvt 0.5\n");
+      assert_eq!(obj.uvs, [Vector2::new(0.25, 0.25), Vector2::new(0.5, 1.0)]);
+   }
+
+   #[test]
+   fn negative_indices_count_from_the_end() {
+      let obj = parse("
+         v 0 0 0
+         v 1 0 0
+         v 0 1 0
+         vt 0 0
+         vn 0 0 1
+         f -3/-1/-1 -2/1/1 -1//-1
+         v 1 1 0
+         f -4 -2 -1
+      ");
+      assert_eq!(obj.groups[0].faces, [
+         [(0, Some(0), Some(0)), (1, Some(0), Some(0)), (2, None, Some(0))],
+         [(0, None, None), (2, None, None), (3, None, None)],
+      ]);
+   }
+
+   #[test]
+   fn out_of_range_index_is_an_error() {
+      let result = parse_obj(Path::new("test.obj"), "v 0 0 0\nv 1 0 0\nf 1 2 -3\n");
+      assert!(matches!(result, Err(ObjError::Parse { line: 3, .. })));
+   }
+
+   #[test]
+   fn polygons_are_triangulated_as_fans() {
+      let obj = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n");
+      let positions = |face: &[FaceVertex; 3]| face.map(|(position, _, _)| position);
+      assert_eq!(obj.groups[0].faces.iter().map(positions).collect::<Vec<_>>(), [[0, 1, 2], [0, 2, 3]]);
+   }
+
+   #[test]
+   fn missing_normals_are_flat() {
+      let mesh = build(FOLD, NormalGeneration::Flat);
+      assert_eq!(mesh.vertices.len(), 6);
+      let normals: Vec<_> = mesh.vertices.iter().map(|vertex| vertex.normal).collect();
+      assert_eq!(normals[..3], [Vector3::new(0.0, 0.0, 1.0); 3]);
+      assert_eq!(normals[3..], [Vector3::new(1.0, 0.0, 0.0); 3]);
+   }
+
+   #[test]
+   fn missing_normals_are_smooth() {
+      let mesh = build(FOLD, NormalGeneration::Smooth);
+      assert_eq!(mesh.vertices.len(), 4);
+      let shared = Vector3::new(1.0, 0.0, 1.0).normalize();
+      for vertex in mesh.vertices.iter() {
+         let expected = match vertex.position {
+            Vector3 { x, .. } if x > 0.0 => Vector3::new(0.0, 0.0, 1.0),
+            Vector3 { z, .. } if z > 0.0 => Vector3::new(1.0, 0.0, 0.0),
+            _ => shared,
+         };
+         assert!((vertex.normal - expected).magnitude() < 1e-6, "{:?}", vertex);
+      }
+   }
+
+   #[test]
+   fn given_normals_are_kept() {
+      let mesh = build("v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 -1\nf 1//1 2//1 3//1\n", NormalGeneration::Flat);
+      assert!(mesh.vertices.iter().all(|vertex| vertex.normal == Vector3::new(0.0, 0.0, -1.0)));
+      assert!(mesh.vertices.iter().all(|vertex| vertex.tangent == Vector4::new(0.0, 0.0, 0.0, 0.0)));
+   }
+
+   #[test]
+   fn tangents_follow_texture_coordinates() {
+      let mesh = build("
+         v 0 0 0
+         v 1 0 0
+         v 0 1 0
+         vt 0 0
+         vt 1 0
+         vt 0 1
+         f 1/1 2/2 3/3
+      ", NormalGeneration::Smooth);
+      for vertex in mesh.vertices.iter() {
+         assert_eq!(vertex.normal, Vector3::new(0.0, 0.0, 1.0));
+         assert_eq!(vertex.tangent, Vector4::new(1.0, 0.0, 0.0, 1.0));
+      }
+   }
+}
@@ -0,0 +1,405 @@
+//! Procedural meshes. All of them are centered at the origin with +Y up, have counter-clockwise
+//! front faces, and UVs with the origin at the top left of the texture.
+
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+
+use cgmath::{vec2, vec3, InnerSpace, Vector2, Vector3};
+
+use crate::platform::gpu::vulkan_mesh::Indices;
+
+use super::{MeshData, Vertex};
+
+/// Collects vertices and triangles of a shape, then computes tangents
+#[derive(Default)]
+struct ShapeBuilder {
+   vertices: Vec<Vertex>,
+   indices: Vec<u32>,
+}
+
+impl ShapeBuilder {
+   fn vertex(&mut self, position: Vector3<f32>, normal: Vector3<f32>, uv: Vector2<f32>) -> u32 {
+      self.vertices.push(Vertex { position, normal, uv, ..Default::default() });
+      self.vertices.len() as u32 - 1
+   }
+
+   fn triangle(&mut self, a: u32, b: u32, c: u32) {
+      self.indices.extend([a, b, c]);
+   }
+
+   /// Rectangle spanning `center ± right ± up`, facing `right × up`, with UV (0, 0) at `center - right + up`
+   fn face(&mut self, center: Vector3<f32>, right: Vector3<f32>, up: Vector3<f32>, columns: u32, rows: u32) {
+      let normal = right.cross(up).normalize();
+      let first = self.vertices.len() as u32;
+      for row in 0..=rows {
+         for column in 0..=columns {
+            let uv = vec2(column as f32 / columns as f32, row as f32 / rows as f32);
+            let position = center + right * (uv.x * 2.0 - 1.0) + up * (1.0 - uv.y * 2.0);
+            self.vertex(position, normal, uv);
+         }
+      }
+      let index = |column: u32, row: u32| first + row * (columns + 1) + column;
+      for row in 0..rows {
+         for column in 0..columns {
+            let (top_left, bottom_left) = (index(column, row), index(column, row + 1));
+            let (top_right, bottom_right) = (index(column + 1, row), index(column + 1, row + 1));
+            self.triangle(top_left, bottom_left, bottom_right);
+            self.triangle(top_left, bottom_right, top_right);
+         }
+      }
+   }
+
+   /// Disk in the XZ plane at the given height, facing +Y or -Y
+   fn cap(&mut self, radius: f32, y: f32, segments: u32, facing_up: bool) {
+      let normal = vec3(0.0, if facing_up { 1.0 } else { -1.0 }, 0.0);
+      // Seen from the front, the texture isn't mirrored
+      let v_sign = if facing_up { 1.0 } else { -1.0 };
+      let center = self.vertex(vec3(0.0, y, 0.0), normal, vec2(0.5, 0.5));
+      let first = self.vertices.len() as u32;
+      for segment in 0..=segments {
+         let (sin, cos) = (TAU * segment as f32 / segments as f32).sin_cos();
+         self.vertex(vec3(radius * sin, y, radius * cos), normal, vec2(0.5 + sin * 0.5, 0.5 + cos * 0.5 * v_sign));
+      }
+      for segment in first..first + segments {
+         if facing_up {
+            self.triangle(center, segment, segment + 1);
+         } else {
+            self.triangle(center, segment + 1, segment);
+         }
+      }
+   }
+
+   fn build(self) -> MeshData {
+      let mut mesh = MeshData::new(self.vertices, Indices::compact(self.indices));
+      mesh.weld();
+      mesh.compute_tangents();
+      mesh
+   }
+}
+
+impl MeshData {
+   /// Square in the XZ plane facing +Y
+   pub fn plane(size: f32) -> Self {
+      Self::grid(size, size, 1, 1)
+   }
+
+   /// Rectangle in the XZ plane facing +Y, split into `columns` along X and `rows` along Z
+   pub fn grid(width: f32, depth: f32, columns: u32, rows: u32) -> Self {
+      assert!(columns > 0 && rows > 0, "Grid must have at least one cell");
+      let mut shape = ShapeBuilder::default();
+      shape.face(vec3(0.0, 0.0, 0.0), vec3(width * 0.5, 0.0, 0.0), vec3(0.0, 0.0, -depth * 0.5), columns, rows);
+      shape.build()
+   }
+
+   /// Rectangle in the XY plane facing +Z, e.g. for sprites
+   pub fn quad(width: f32, height: f32) -> Self {
+      let mut shape = ShapeBuilder::default();
+      shape.face(vec3(0.0, 0.0, 0.0), vec3(width * 0.5, 0.0, 0.0), vec3(0.0, height * 0.5, 0.0), 1, 1);
+      shape.build()
+   }
+
+   /// Isosceles triangle in the XY plane facing +Z, with the apex at the top
+   pub fn triangle(width: f32, height: f32) -> Self {
+      let (half_width, half_height) = (width * 0.5, height * 0.5);
+      let normal = Vector3::unit_z();
+      let mut shape = ShapeBuilder::default();
+      let apex = shape.vertex(vec3(0.0, half_height, 0.0), normal, vec2(0.5, 0.0));
+      let left = shape.vertex(vec3(-half_width, -half_height, 0.0), normal, vec2(0.0, 1.0));
+      let right = shape.vertex(vec3(half_width, -half_height, 0.0), normal, vec2(1.0, 1.0));
+      shape.triangle(apex, left, right);
+      shape.build()
+   }
+
+   /// Axis aligned cube, each face has the whole texture
+   pub fn cube(size: f32) -> Self {
+      let half = size * 0.5;
+      let (x, y, z) = (Vector3::unit_x() * half, Vector3::unit_y() * half, Vector3::unit_z() * half);
+      let mut shape = ShapeBuilder::default();
+      // Center, right and up of each face, right × up points outwards
+      for (center, right, up) in [(x, -z, y), (-x, z, y), (y, x, -z), (-y, x, z), (z, x, y), (-z, -x, y)] {
+         shape.face(center, right, up, 1, 1);
+      }
+      shape.build()
+   }
+
+   /// Sphere of `sectors` meridians and `stacks` parallels. U goes around the Y axis starting at
+   /// +Z, V goes from the north pole to the south pole
+   pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> Self {
+      assert!(sectors >= 3 && stacks >= 2, "Sphere needs at least 3 sectors and 2 stacks");
+      let mut shape = ShapeBuilder::default();
+      for stack in 0..=stacks {
+         let (sin_phi, cos_phi) = (PI * stack as f32 / stacks as f32).sin_cos();
+         for sector in 0..=sectors {
+            let (sin_theta, cos_theta) = (TAU * sector as f32 / sectors as f32).sin_cos();
+            let normal = vec3(sin_phi * sin_theta, cos_phi, sin_phi * cos_theta);
+            let uv = vec2(sector as f32 / sectors as f32, stack as f32 / stacks as f32);
+            shape.vertex(normal * radius, normal, uv);
+         }
+      }
+      let index = |sector: u32, stack: u32| stack * (sectors + 1) + sector;
+      for stack in 0..stacks {
+         for sector in 0..sectors {
+            let (top, bottom) = (index(sector, stack), index(sector, stack + 1));
+            let (top_next, bottom_next) = (index(sector + 1, stack), index(sector + 1, stack + 1));
+            // Triangles touching the poles would be degenerate
+            if stack != stacks - 1 {
+               shape.triangle(top, bottom, bottom_next);
+            }
+            if stack != 0 {
+               shape.triangle(top, bottom_next, top_next);
+            }
+         }
+      }
+      shape.build()
+   }
+
+   /// Sphere made by subdividing an icosahedron, with evenly sized triangles. `subdivisions` of 0
+   /// is the icosahedron itself, each one more quadruples the triangle count. UVs are mapped like
+   /// `uv_sphere`'s
+   pub fn icosphere(radius: f32, subdivisions: u32) -> Self {
+      let t = (1.0 + 5.0_f32.sqrt()) * 0.5;
+      let mut positions: Vec<Vector3<f32>> = [
+         (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+         (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+         (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+      ]
+      .iter()
+      .map(|&(x, y, z)| vec3(x, y, z).normalize())
+      .collect();
+      let mut triangles: Vec<[u32; 3]> = vec![
+         [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+         [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+         [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+         [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+      ];
+      for _ in 0..subdivisions {
+         let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+         let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+               positions.push((positions[a as usize] + positions[b as usize]).normalize());
+               positions.len() as u32 - 1
+            })
+         };
+         triangles = triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+               let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+               [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+      }
+
+      let spherical_uv = |normal: Vector3<f32>| {
+         vec2(0.5 + normal.x.atan2(normal.z) / TAU, normal.y.clamp(-1.0, 1.0).acos() / PI)
+      };
+      let mut shape = ShapeBuilder::default();
+      for triangle in triangles {
+         let normals = triangle.map(|index| positions[index as usize]);
+         let mut uvs = normals.map(spherical_uv);
+         // Triangles crossing the seam at -Z would wrap around the whole texture
+         let (min_u, max_u) = uvs.iter().fold((1.0_f32, 0.0_f32), |(min, max), uv| (min.min(uv.x), max.max(uv.x)));
+         if max_u - min_u > 0.5 {
+            uvs.iter_mut().filter(|uv| uv.x < 0.5).for_each(|uv| uv.x += 1.0);
+         }
+         // U is undefined at the poles, the middle of the other two corners looks best
+         for corner in 0..3 {
+            if normals[corner].x.abs() < 1e-6 && normals[corner].z.abs() < 1e-6 {
+               uvs[corner].x = (uvs[(corner + 1) % 3].x + uvs[(corner + 2) % 3].x) * 0.5;
+            }
+         }
+         let [a, b, c] = [0, 1, 2].map(|corner| shape.vertex(normals[corner] * radius, normals[corner], uvs[corner]));
+         shape.triangle(a, b, c);
+      }
+      shape.build()
+   }
+
+   /// Cylinder along the Y axis with caps
+   pub fn cylinder(radius: f32, height: f32, segments: u32) -> Self {
+      assert!(segments >= 3, "Cylinder needs at least 3 segments");
+      let half = height * 0.5;
+      let mut shape = ShapeBuilder::default();
+      let first = shape.vertices.len() as u32;
+      for segment in 0..=segments {
+         let u = segment as f32 / segments as f32;
+         let (sin, cos) = (TAU * u).sin_cos();
+         let normal = vec3(sin, 0.0, cos);
+         shape.vertex(normal * radius + vec3(0.0, half, 0.0), normal, vec2(u, 0.0));
+         shape.vertex(normal * radius - vec3(0.0, half, 0.0), normal, vec2(u, 1.0));
+      }
+      for segment in 0..segments {
+         let (top, bottom) = (first + segment * 2, first + segment * 2 + 1);
+         shape.triangle(top, bottom, bottom + 2);
+         shape.triangle(top, bottom + 2, top + 2);
+      }
+      shape.cap(radius, half, segments, true);
+      shape.cap(radius, -half, segments, false);
+      shape.build()
+   }
+
+   /// Cone along the Y axis with the apex at the top and a capped base
+   pub fn cone(radius: f32, height: f32, segments: u32) -> Self {
+      assert!(segments >= 3, "Cone needs at least 3 segments");
+      let half = height * 0.5;
+      // Normal of the side tilts up by the angle of the slope
+      let slope = vec2(height, radius).normalize();
+      let side_normal = |angle: f32| {
+         let (sin, cos) = angle.sin_cos();
+         vec3(sin * slope.x, slope.y, cos * slope.x)
+      };
+      let mut shape = ShapeBuilder::default();
+      for segment in 0..segments {
+         let (u, next_u) = (segment as f32 / segments as f32, (segment + 1) as f32 / segments as f32);
+         // Each side triangle has its own apex, with the normal halfway between the base corners
+         let apex_normal = side_normal(TAU * (u + next_u) * 0.5);
+         let apex = shape.vertex(vec3(0.0, half, 0.0), apex_normal, vec2((u + next_u) * 0.5, 0.0));
+         let base = |u: f32| {
+            let (sin, cos) = (TAU * u).sin_cos();
+            vec3(radius * sin, -half, radius * cos)
+         };
+         let a = shape.vertex(base(u), side_normal(TAU * u), vec2(u, 1.0));
+         let b = shape.vertex(base(next_u), side_normal(TAU * next_u), vec2(next_u, 1.0));
+         shape.triangle(apex, a, b);
+      }
+      shape.cap(radius, -half, segments, false);
+      shape.build()
+   }
+
+   /// Torus around the Y axis. `major_radius` is the distance from the center to the middle of the
+   /// tube, `minor_radius` is the radius of the tube
+   pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> Self {
+      assert!(major_segments >= 3 && minor_segments >= 3, "Torus needs at least 3 segments each way");
+      let mut shape = ShapeBuilder::default();
+      for major in 0..=major_segments {
+         let u = major as f32 / major_segments as f32;
+         let (sin_theta, cos_theta) = (TAU * u).sin_cos();
+         let outward = vec3(sin_theta, 0.0, cos_theta);
+         for minor in 0..=minor_segments {
+            let v = minor as f32 / minor_segments as f32;
+            let (sin_phi, cos_phi) = (TAU * v).sin_cos();
+            let normal = outward * cos_phi + Vector3::unit_y() * sin_phi;
+            shape.vertex(outward * major_radius + normal * minor_radius, normal, vec2(u, v));
+         }
+      }
+      let index = |major: u32, minor: u32| major * (minor_segments + 1) + minor;
+      for major in 0..major_segments {
+         for minor in 0..minor_segments {
+            let (a, b) = (index(major, minor), index(major + 1, minor));
+            let (c, d) = (index(major, minor + 1), index(major + 1, minor + 1));
+            shape.triangle(a, b, c);
+            shape.triangle(b, d, c);
+         }
+      }
+      shape.build()
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use std::collections::HashSet;
+
+   use super::*;
+
+   const EPSILON: f32 = 1e-4;
+
+   fn face_normal(mesh: &MeshData, triangle: [u32; 3]) -> Vector3<f32> {
+      let [a, b, c] = triangle.map(|index| mesh.vertices[index as usize].position);
+      (b - a).cross(c - a)
+   }
+
+   /// Checks the invariants every generator guarantees
+   fn check(name: &str, mesh: &MeshData) {
+      assert!(!mesh.vertices.is_empty() && mesh.indices.len().is_multiple_of(3), "{}: not a triangle list", name);
+      assert!(
+         mesh.indices.iter().all(|index| (index as usize) < mesh.vertices.len()),
+         "{}: index out of bounds",
+         name
+      );
+      for triangle in mesh.triangles() {
+         let face = face_normal(mesh, triangle);
+         assert!(face.magnitude() > 1e-6, "{}: degenerate triangle {:?}", name, triangle);
+         // Counter-clockwise front faces point the same way as the vertex normals
+         let normals: Vector3<f32> = triangle.iter().map(|&index| mesh.vertices[index as usize].normal).sum();
+         assert!(face.dot(normals) > 0.0, "{}: triangle {:?} is wound against its normals", name, triangle);
+      }
+      for vertex in &mesh.vertices {
+         let tangent = vertex.tangent.truncate();
+         assert!((vertex.normal.magnitude() - 1.0).abs() < EPSILON, "{}: normal {:?}", name, vertex.normal);
+         assert!((tangent.magnitude() - 1.0).abs() < EPSILON, "{}: tangent {:?}", name, vertex.tangent);
+         assert!(tangent.dot(vertex.normal).abs() < EPSILON, "{}: tangent not orthogonal to {:?}", name, vertex);
+         assert!(vertex.tangent.w.abs() == 1.0, "{}: handedness {}", name, vertex.tangent.w);
+         assert!((0.0..=1.0).contains(&vertex.uv.y), "{}: uv {:?}", name, vertex.uv);
+      }
+   }
+
+   /// Closed shapes around the origin have faces pointing away from it
+   fn check_outward(name: &str, mesh: &MeshData) {
+      for triangle in mesh.triangles() {
+         let centroid: Vector3<f32> = triangle.iter().map(|&index| mesh.vertices[index as usize].position).sum();
+         assert!(face_normal(mesh, triangle).dot(centroid) > 0.0, "{}: triangle {:?} faces inwards", name, triangle);
+      }
+   }
+
+   #[test]
+   fn flat_shapes() {
+      for (name, mesh) in [
+         ("plane", MeshData::plane(2.0)),
+         ("grid", MeshData::grid(4.0, 2.0, 4, 3)),
+         ("quad", MeshData::quad(1.0, 2.0)),
+         ("triangle", MeshData::triangle(2.0, 2.0)),
+      ] {
+         check(name, &mesh);
+      }
+      let grid = MeshData::grid(4.0, 2.0, 4, 3);
+      assert_eq!(grid.vertices.len(), 5 * 4);
+      assert_eq!(grid.indices.len(), 4 * 3 * 6);
+      assert!(grid.vertices.iter().all(|vertex| vertex.normal == Vector3::unit_y()));
+      // Tangents point along +U
+      assert!(grid.vertices.iter().all(|vertex| (vertex.tangent.truncate() - Vector3::unit_x()).magnitude() < EPSILON));
+   }
+
+   #[test]
+   fn closed_shapes() {
+      for (name, mesh) in [
+         ("cube", MeshData::cube(1.0)),
+         ("uv_sphere", MeshData::uv_sphere(1.0, 16, 8)),
+         ("icosphere", MeshData::icosphere(1.0, 2)),
+         ("cylinder", MeshData::cylinder(0.5, 2.0, 12)),
+         ("cone", MeshData::cone(0.5, 1.0, 12)),
+      ] {
+         check(name, &mesh);
+         check_outward(name, &mesh);
+      }
+      // The torus has a hole in the middle, so its faces point away from the tube instead
+      check("torus", &MeshData::torus(1.0, 0.25, 16, 8));
+   }
+
+   #[test]
+   fn spheres_have_their_radius() {
+      for mesh in [MeshData::uv_sphere(2.0, 12, 6), MeshData::icosphere(2.0, 1)] {
+         assert!(mesh.vertices.iter().all(|vertex| (vertex.position.magnitude() - 2.0).abs() < EPSILON));
+      }
+   }
+
+   #[test]
+   fn icosphere_counts() {
+      for subdivisions in 0..4 {
+         let mesh = MeshData::icosphere(1.0, subdivisions);
+         let faces = 20 * 4_usize.pow(subdivisions);
+         assert_eq!(mesh.indices.len(), faces * 3);
+         // Vertices are split along the UV seam, but the positions are shared: V - E + F = 2
+         let positions: HashSet<[u32; 3]> = mesh
+            .vertices
+            .iter()
+            .map(|vertex| [vertex.position.x, vertex.position.y, vertex.position.z].map(f32::to_bits))
+            .collect();
+         assert_eq!(positions.len(), faces / 2 + 2, "subdivisions: {}", subdivisions);
+      }
+   }
+
+   #[test]
+   fn cube_counts() {
+      let cube = MeshData::cube(1.0);
+      assert_eq!(cube.vertices.len(), 24);
+      assert_eq!(cube.indices.len(), 36);
+   }
+}
@@ -0,0 +1,91 @@
+use std::path::Path;
+
+use ash::vk;
+
+use super::Handle;
+
+/// 8 bit RGBA pixels, rows top to bottom. Whether they are sRGB encoded depends on the material
+/// slot the image is used in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+   pub width: u32,
+   pub height: u32,
+   pub pixels: Vec<u8>,
+}
+
+impl Image {
+   pub fn new(width: u32, height: u32, pixels: Vec<u8>) -> Self {
+      assert_eq!(pixels.len(), (width * height * 4) as usize, "Image must have 4 bytes per pixel");
+      Image { width, height, pixels }
+   }
+
+   /// 1x1 image of the given color
+   pub fn solid([r, g, b, a]: [u8; 4]) -> Self {
+      Self::new(1, 1, vec![r, g, b, a])
+   }
+
+   pub fn extent(&self) -> vk::Extent2D {
+      vk::Extent2D {
+         width: self.width,
+         height: self.height,
+      }
+   }
+}
+
+/// Linear 32 bit float RGBA pixels, rows top to bottom, like an equirectangular environment map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HdrImage {
+   pub width: u32,
+   pub height: u32,
+   pub pixels: Vec<f32>,
+}
+
+impl HdrImage {
+   pub fn new(width: u32, height: u32, pixels: Vec<f32>) -> Self {
+      assert_eq!(pixels.len(), (width * height * 4) as usize, "HDR image must have 4 floats per pixel");
+      HdrImage { width, height, pixels }
+   }
+
+   /// Reads a Radiance `.hdr` or OpenEXR `.exr` file, or any other format the `image` crate
+   /// knows, converted to linear float RGBA
+   pub fn load(path: impl AsRef<Path>) -> Result<Self, image::ImageError> {
+      let image = image::open(path)?.to_rgba32f();
+      Ok(Self::new(image.width(), image.height(), image.into_raw()))
+   }
+
+   pub fn extent(&self) -> vk::Extent2D {
+      vk::Extent2D {
+         width: self.width,
+         height: self.height,
+      }
+   }
+}
+
+/// Sampling parameters, in terms of `vk::SamplerCreateInfo`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerDesc {
+   pub mag_filter: vk::Filter,
+   pub min_filter: vk::Filter,
+   pub mipmap_mode: vk::SamplerMipmapMode,
+   pub address_mode_u: vk::SamplerAddressMode,
+   pub address_mode_v: vk::SamplerAddressMode,
+}
+
+impl Default for SamplerDesc {
+   fn default() -> Self {
+      SamplerDesc {
+         mag_filter: vk::Filter::LINEAR,
+         min_filter: vk::Filter::LINEAR,
+         mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+         address_mode_u: vk::SamplerAddressMode::REPEAT,
+         address_mode_v: vk::SamplerAddressMode::REPEAT,
+      }
+   }
+}
+
+/// Image together with the way to sample it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Texture {
+   pub image: Handle<Image>,
+   pub sampler: SamplerDesc,
+}
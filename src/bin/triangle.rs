@@ -1,28 +1,59 @@
 extern crate lazy_static;
 
-use ash::util::*;
 use ash::vk;
-use cgmath::{Vector4,vec4};
 use cupio::*;
 use std::default::Default;
 use std::io::Cursor;
-use std::mem;
-use std::mem::align_of;
 
-use crate::offset_of;
-use platform::gpu::vulkan_context::{VulkanContext, find_memorytype_index, record_submit_commandbuffer};
+use asset::{MeshData, Vertex};
+use platform::input::InputSession;
+use ecs::{Schedule, Stage, System, World};
+use platform::gpu::vulkan_framebuffer::SwapchainFramebuffers;
+use platform::gpu::vulkan_context::{FrameTarget, VulkanContext};
+use platform::gpu::vulkan_mesh::{Mesh, VertexLayout};
+use platform::gpu::vulkan_pipeline::Multisampling;
 use platform::gpu::vulkan_shader::VulkanShader;
 use platform::gpu::VulkanDrop;
 
-#[derive(Clone, Debug, Copy)]
-struct Vertex {
-    pos: Vector4<f32>,
-    color: Vector4<f32>,
-}
-
 fn main() {
     unsafe {
-        let base = VulkanContext::new(1920, 1080);
+        let mut base = VulkanContext::new(1920, 1080);
+        *base.input_session.borrow_mut() = InputSession::from_args().unwrap();
+        // `--samples 4` multisamples the triangle, `--sample-shading` shades each sample
+        let samples = std::env::args()
+            .skip_while(|arg| arg != "--samples")
+            .nth(1)
+            .map_or(1, |samples| samples.parse().expect("--samples takes a number"));
+        let sample_shading = std::env::args().any(|arg| arg == "--sample-shading");
+        let multisampling = Multisampling::new(&base, samples, sample_shading);
+        let depth_format = base.depth_format;
+        // Multisampled color and depth images, the color resolved into the present image
+        let multisampled_attachments = [
+            vk::AttachmentDescription {
+                format: base.surface_format.format,
+                samples: multisampling.samples,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            },
+            vk::AttachmentDescription {
+                format: depth_format,
+                samples: multisampling.samples,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            },
+            vk::AttachmentDescription {
+                format: base.surface_format.format,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::DONT_CARE,
+                store_op: vk::AttachmentStoreOp::STORE,
+                final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                ..Default::default()
+            },
+        ];
         let renderpass_attachments = [
             vk::AttachmentDescription {
                 format: base.surface_format.format,
@@ -33,7 +64,7 @@ fn main() {
                 ..Default::default()
             },
             vk::AttachmentDescription {
-                format: vk::Format::D16_UNORM,
+                format: depth_format,
                 samples: vk::SampleCountFlags::TYPE_1,
                 load_op: vk::AttachmentLoadOp::CLEAR,
                 initial_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
@@ -49,6 +80,10 @@ fn main() {
             attachment: 1,
             layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
         };
+        let resolve_attachment_refs = [vk::AttachmentReference {
+            attachment: 2,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
         let dependencies = [vk::SubpassDependency {
             src_subpass: vk::SUBPASS_EXTERNAL,
             src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
@@ -58,13 +93,20 @@ fn main() {
             ..Default::default()
         }];
 
-        let subpass = vk::SubpassDescription::builder()
+        let mut subpass = vk::SubpassDescription::builder()
             .color_attachments(&color_attachment_refs)
             .depth_stencil_attachment(&depth_attachment_ref)
             .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+        if multisampling.is_multisampled() {
+            subpass = subpass.resolve_attachments(&resolve_attachment_refs);
+        }
 
         let renderpass_create_info = vk::RenderPassCreateInfo::builder()
-            .attachments(&renderpass_attachments)
+            .attachments(if multisampling.is_multisampled() {
+                &multisampled_attachments[..]
+            } else {
+                &renderpass_attachments[..]
+            })
             .subpasses(std::slice::from_ref(&subpass))
             .dependencies(&dependencies);
 
@@ -73,137 +115,7 @@ fn main() {
             .create_render_pass(&renderpass_create_info, None)
             .unwrap();
 
-        let framebuffers: Vec<vk::Framebuffer> = base
-            .present_image_views
-            .iter()
-            .map(|&present_image_view| {
-                let framebuffer_attachments = [present_image_view, base.depth_image_view];
-                let frame_buffer_create_info = vk::FramebufferCreateInfo::builder()
-                    .render_pass(renderpass)
-                    .attachments(&framebuffer_attachments)
-                    .width(base.surface_resolution.width)
-                    .height(base.surface_resolution.height)
-                    .layers(1);
-
-                base.device
-                    .create_framebuffer(&frame_buffer_create_info, None)
-                    .unwrap()
-            })
-            .collect();
-
-        let index_buffer_data = [0u32, 1, 2];
-        let index_buffer_info = vk::BufferCreateInfo::builder()
-            .size(std::mem::size_of_val(&index_buffer_data) as u64)
-            .usage(vk::BufferUsageFlags::INDEX_BUFFER)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
-
-        let index_buffer = base.device.create_buffer(&index_buffer_info, None).unwrap();
-        let index_buffer_memory_req = base.device.get_buffer_memory_requirements(index_buffer);
-        let index_buffer_memory_index = find_memorytype_index(
-            &index_buffer_memory_req,
-            &base.device_memory_properties,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        )
-        .expect("Unable to find suitable memorytype for the index buffer.");
-
-        let index_allocate_info = vk::MemoryAllocateInfo {
-            allocation_size: index_buffer_memory_req.size,
-            memory_type_index: index_buffer_memory_index,
-            ..Default::default()
-        };
-        let index_buffer_memory = base
-            .device
-            .allocate_memory(&index_allocate_info, None)
-            .unwrap();
-        let index_ptr = base
-            .device
-            .map_memory(
-                index_buffer_memory,
-                0,
-                index_buffer_memory_req.size,
-                vk::MemoryMapFlags::empty(),
-            )
-            .unwrap();
-        let mut index_slice = Align::new(
-            index_ptr,
-            align_of::<u32>() as u64,
-            index_buffer_memory_req.size,
-        );
-        index_slice.copy_from_slice(&index_buffer_data);
-        base.device.unmap_memory(index_buffer_memory);
-        base.device
-            .bind_buffer_memory(index_buffer, index_buffer_memory, 0)
-            .unwrap();
-
-        let vertex_input_buffer_info = vk::BufferCreateInfo {
-            size: 3 * std::mem::size_of::<Vertex>() as u64,
-            usage: vk::BufferUsageFlags::VERTEX_BUFFER,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
-        };
-
-        let vertex_input_buffer = base
-            .device
-            .create_buffer(&vertex_input_buffer_info, None)
-            .unwrap();
-
-        let vertex_input_buffer_memory_req = base
-            .device
-            .get_buffer_memory_requirements(vertex_input_buffer);
-
-        let vertex_input_buffer_memory_index = find_memorytype_index(
-            &vertex_input_buffer_memory_req,
-            &base.device_memory_properties,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        )
-        .expect("Unable to find suitable memorytype for the vertex buffer.");
-
-        let vertex_buffer_allocate_info = vk::MemoryAllocateInfo {
-            allocation_size: vertex_input_buffer_memory_req.size,
-            memory_type_index: vertex_input_buffer_memory_index,
-            ..Default::default()
-        };
-
-        let vertex_input_buffer_memory = base
-            .device
-            .allocate_memory(&vertex_buffer_allocate_info, None)
-            .unwrap();
-
-        let vertices = [
-            Vertex {
-                pos: vec4(-1.0, 1.0, 0.0, 1.0),
-                color: vec4(0.0, 1.0, 0.0, 1.0),
-            },
-            Vertex {
-                pos: vec4(1.0, 1.0, 0.0, 1.0),
-                color: vec4(0.0, 0.0, 1.0, 1.0),
-            },
-            Vertex {
-                pos: vec4(0.0, -1.0, 0.0, 1.0),
-                color: vec4(1.0, 0.0, 0.0, 1.0),
-            },
-        ];
-
-        let vert_ptr = base
-            .device
-            .map_memory(
-                vertex_input_buffer_memory,
-                0,
-                vertex_input_buffer_memory_req.size,
-                vk::MemoryMapFlags::empty(),
-            )
-            .unwrap();
-
-        let mut vert_align = Align::new(
-            vert_ptr,
-            align_of::<Vertex>() as u64,
-            vertex_input_buffer_memory_req.size,
-        );
-        vert_align.copy_from_slice(&vertices);
-        base.device.unmap_memory(vertex_input_buffer_memory);
-        base.device
-            .bind_buffer_memory(vertex_input_buffer, vertex_input_buffer_memory, 0)
-            .unwrap();
+        let mesh = MeshData::triangle(2.0, 2.0).upload(&base);
 
         let shader = VulkanShader::builder(&base.device)
             .with_vertex_shader(0, &mut Cursor::new(
@@ -218,25 +130,8 @@ fn main() {
             .create_pipeline_layout(&layout_create_info, None)
             .unwrap();
 
-        let vertex_input_binding_descriptions = [vk::VertexInputBindingDescription {
-            binding: 0,
-            stride: mem::size_of::<Vertex>() as u32,
-            input_rate: vk::VertexInputRate::VERTEX,
-        }];
-        let vertex_input_attribute_descriptions = [
-            vk::VertexInputAttributeDescription {
-                location: 0,
-                binding: 0,
-                format: vk::Format::R32G32B32A32_SFLOAT,
-                offset: offset_of!(Vertex, pos) as u32,
-            },
-            vk::VertexInputAttributeDescription {
-                location: 1,
-                binding: 0,
-                format: vk::Format::R32G32B32A32_SFLOAT,
-                offset: offset_of!(Vertex, color) as u32,
-            },
-        ];
+        let vertex_input_binding_descriptions = [Vertex::binding_description(0)];
+        let vertex_input_attribute_descriptions = Vertex::attribute_descriptions(0);
 
         let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::builder()
             .vertex_attribute_descriptions(&vertex_input_attribute_descriptions)
@@ -264,10 +159,7 @@ fn main() {
             polygon_mode: vk::PolygonMode::FILL,
             ..Default::default()
         };
-        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
-            rasterization_samples: vk::SampleCountFlags::TYPE_1,
-            ..Default::default()
-        };
+        let multisample_state_info = multisampling.state_info();
         let noop_stencil_state = vk::StencilOpState {
             fail_op: vk::StencilOp::KEEP,
             pass_op: vk::StencilOp::KEEP,
@@ -323,16 +215,21 @@ fn main() {
 
         let graphic_pipeline = graphics_pipelines[0];
 
-        base.render_loop(|| {
-            let (present_index, _) = base
-                .swapchain_loader
-                .acquire_next_image(
-                    base.swapchain,
-                    std::u64::MAX,
-                    base.present_complete_semaphore,
-                    vk::Fence::null(),
-                )
-                .unwrap();
+        let device = base.device.clone();
+        let mut schedule = Schedule::new();
+        schedule.add_system(Stage::Render, System::parallel("draw_triangle", move |world| {
+            let frame = world.resource::<FrameTarget>();
+            let framebuffer = world.resource_mut::<SwapchainFramebuffers>().get(&device, &frame);
+            let render_area: vk::Rect2D = frame.extent.into();
+            let viewports = [vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: frame.extent.width as f32,
+                height: frame.extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }];
+            let scissors = [render_area];
             let clear_values = [
                 vk::ClearValue {
                     color: vk::ClearColorValue {
@@ -349,69 +246,42 @@ fn main() {
 
             let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
                 .render_pass(renderpass)
-                .framebuffer(framebuffers[present_index as usize])
-                .render_area(base.surface_resolution.into())
+                .framebuffer(framebuffer)
+                .render_area(render_area)
                 .clear_values(&clear_values);
 
-            record_submit_commandbuffer(
-                &base.device,
-                base.draw_command_buffer,
-                base.draw_commands_reuse_fence,
-                base.present_queue,
-                &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT],
-                &[base.present_complete_semaphore],
-                &[base.rendering_complete_semaphore],
-                |device, draw_command_buffer| {
-                    device.cmd_begin_render_pass(
-                        draw_command_buffer,
-                        &render_pass_begin_info,
-                        vk::SubpassContents::INLINE,
-                    );
-                    device.cmd_bind_pipeline(
-                        draw_command_buffer,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        graphic_pipeline,
-                    );
-                    device.cmd_set_viewport(draw_command_buffer, 0, &viewports);
-                    device.cmd_set_scissor(draw_command_buffer, 0, &scissors);
-                    device.cmd_bind_vertex_buffers(
-                        draw_command_buffer,
-                        0,
-                        &[vertex_input_buffer],
-                        &[0],
-                    );
-                    device.cmd_bind_index_buffer(
-                        draw_command_buffer,
-                        index_buffer,
-                        0,
-                        vk::IndexType::UINT32,
-                    );
-                    device.cmd_draw_indexed(
-                        draw_command_buffer,
-                        index_buffer_data.len() as u32,
-                        1,
-                        0,
-                        0,
-                        1,
-                    );
-                    // Or draw without the index buffer
-                    // device.cmd_draw(draw_command_buffer, 3, 1, 0, 0);
-                    device.cmd_end_render_pass(draw_command_buffer);
-                },
+            let draw_command_buffer = frame.command_buffer;
+            device.cmd_begin_render_pass(
+                draw_command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
             );
-            //let mut present_info_err = mem::zeroed();
-            let wait_semaphors = [base.rendering_complete_semaphore];
-            let swapchains = [base.swapchain];
-            let image_indices = [present_index];
-            let present_info = vk::PresentInfoKHR::builder()
-                .wait_semaphores(&wait_semaphors) // &base.rendering_complete_semaphore)
-                .swapchains(&swapchains)
-                .image_indices(&image_indices);
-
-            base.swapchain_loader
-                .queue_present(base.present_queue, &present_info)
-                .unwrap();
-        });
+            device.cmd_bind_pipeline(
+                draw_command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                graphic_pipeline,
+            );
+            device.cmd_set_viewport(draw_command_buffer, 0, &viewports);
+            device.cmd_set_scissor(draw_command_buffer, 0, &scissors);
+            let mesh = world.resource::<Mesh>();
+            mesh.bind(&device, draw_command_buffer);
+            mesh.draw(&device, draw_command_buffer, 0);
+            device.cmd_end_render_pass(draw_command_buffer);
+        }).writes_resource::<FrameTarget>().writes_resource::<SwapchainFramebuffers>().reads_resource::<Mesh>());
+
+        let mut world = World::new();
+        if multisampling.is_multisampled() {
+            world.insert_resource(SwapchainFramebuffers::multisampled(
+                &base,
+                renderpass,
+                depth_format,
+                multisampling.samples,
+            ));
+        } else {
+            world.insert_resource(SwapchainFramebuffers::new(renderpass));
+        }
+        world.insert_resource(mesh);
+        base.render_loop_with_schedule(&mut world, &mut schedule, None, |_, _| {});
 
         base.device.device_wait_idle().unwrap();
         for pipeline in graphics_pipelines {
@@ -419,13 +289,11 @@ fn main() {
         }
         base.device.destroy_pipeline_layout(pipeline_layout, None);
         shader.drop(&base.device);
-        base.device.free_memory(index_buffer_memory, None);
-        base.device.destroy_buffer(index_buffer, None);
-        base.device.free_memory(vertex_input_buffer_memory, None);
-        base.device.destroy_buffer(vertex_input_buffer, None);
-        for framebuffer in framebuffers {
-            base.device.destroy_framebuffer(framebuffer, None);
-        }
+        world.remove_resource::<Mesh>().unwrap().drop(&base.device);
+        world
+            .remove_resource::<SwapchainFramebuffers>()
+            .unwrap()
+            .drop(&base.device);
         base.device.destroy_render_pass(renderpass, None);
     }
 }
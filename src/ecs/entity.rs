@@ -0,0 +1,136 @@
+use std::fmt;
+
+/// Handle of an entity. The generation makes handles of despawned entities invalid,
+/// even after their index is reused by a newly spawned entity.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Entity {
+   index: u32,
+   generation: u32,
+}
+
+impl Entity {
+   pub fn index(self) -> u32 {
+      self.index
+   }
+
+   pub fn generation(self) -> u32 {
+      self.generation
+   }
+
+   /// Packs the handle into a single number, e.g. for storing it on the GPU or in a file
+   pub fn to_bits(self) -> u64 {
+      u64::from(self.generation) << 32 | u64::from(self.index)
+   }
+
+   pub fn from_bits(bits: u64) -> Self {
+      Entity {
+         index: bits as u32,
+         generation: (bits >> 32) as u32,
+      }
+   }
+}
+
+impl fmt::Debug for Entity {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      write!(f, "Entity({}v{})", self.index, self.generation)
+   }
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+   generation: u32,
+   alive: bool,
+}
+
+/// Allocator of entity handles, reuses indices of despawned entities.
+#[derive(Default)]
+pub struct Entities {
+   slots: Vec<Slot>,
+   free_indices: Vec<u32>,
+}
+
+impl Entities {
+   pub fn alloc(&mut self) -> Entity {
+      match self.free_indices.pop() {
+         Some(index) => {
+            let slot = &mut self.slots[index as usize];
+            slot.alive = true;
+            Entity { index, generation: slot.generation }
+         }
+         None => {
+            let index = u32::try_from(self.slots.len()).expect("Too many entities");
+            self.slots.push(Slot { generation: 0, alive: true });
+            Entity { index, generation: 0 }
+         }
+      }
+   }
+
+   /// Returns false if the entity was already freed
+   pub fn free(&mut self, entity: Entity) -> bool {
+      if !self.contains(entity) {
+         return false;
+      }
+      let slot = &mut self.slots[entity.index as usize];
+      slot.alive = false;
+      slot.generation = slot.generation.wrapping_add(1);
+      self.free_indices.push(entity.index);
+      true
+   }
+
+   pub fn contains(&self, entity: Entity) -> bool {
+      self.slots
+         .get(entity.index as usize)
+         .is_some_and(|slot| slot.alive && slot.generation == entity.generation)
+   }
+
+   pub fn len(&self) -> usize {
+      self.slots.len() - self.free_indices.len()
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.len() == 0
+   }
+
+   pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+      self.slots
+         .iter()
+         .enumerate()
+         .filter(|(_, slot)| slot.alive)
+         .map(|(index, slot)| Entity { index: index as u32, generation: slot.generation })
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn freed_index_is_reused_with_next_generation() {
+      let mut entities = Entities::default();
+      let first = entities.alloc();
+      let second = entities.alloc();
+      assert!(entities.free(first));
+      let reused = entities.alloc();
+      assert_eq!(reused.index(), first.index());
+      assert_eq!(reused.generation(), first.generation() + 1);
+      assert_eq!(entities.iter().collect::<Vec<_>>(), vec![reused, second]);
+   }
+
+   #[test]
+   fn stale_handle_is_rejected() {
+      let mut entities = Entities::default();
+      let stale = entities.alloc();
+      entities.free(stale);
+      let reused = entities.alloc();
+      assert!(!entities.contains(stale));
+      assert!(!entities.free(stale));
+      assert!(entities.contains(reused));
+      assert_eq!(entities.len(), 1);
+   }
+
+   #[test]
+   fn bits_round_trip() {
+      let entity = Entity { index: 7, generation: 3 };
+      assert_eq!(Entity::from_bits(entity.to_bits()), entity);
+   }
+}
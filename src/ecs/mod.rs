@@ -0,0 +1,34 @@
+//! Entity Component System. Entities are plain generational handles, components of each type
+//! live in their own sparse set, and global state lives in resources.
+//!
+//! ```ignore
+//! let mut world = World::new();
+//! let camera = world.spawn((Transform::default(), Camera::default()));
+//! for (entity, (transform, velocity)) in world.query::<(&mut Transform, &Velocity)>().iter() {
+//!     transform.translation += velocity.0;
+//! }
+//! ```
+//!
+//! Systems are grouped into a `Schedule` by stages, systems of a stage that don't conflict
+//! in their declared access run in parallel:
+//!
+//! ```ignore
+//! let mut schedule = Schedule::new();
+//! schedule.add_system(Stage::Update, System::parallel("move", |world| {
+//!     for (_, (transform, velocity)) in world.query::<(&mut Transform, &Velocity)>().iter() {
+//!         transform.translation += velocity.0;
+//!     }
+//! }).with_query::<(&mut Transform, &Velocity)>());
+//! ```
+
+mod entity;
+mod query;
+mod schedule;
+mod storage;
+mod world;
+
+pub use entity::{Entities, Entity};
+pub use query::{Query, QueryFilter, QueryParam, ReadStorage, With, Without, WriteStorage};
+pub use schedule::{Access, Schedule, Stage, System};
+pub use storage::{AnyStorage, Component, SparseSet};
+pub use world::{Bundle, ComponentRef, Res, ResMut, World};
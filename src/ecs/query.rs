@@ -0,0 +1,361 @@
+use std::marker::PhantomData;
+use std::sync::RwLockReadGuard;
+
+use std::sync::RwLockWriteGuard;
+
+use super::schedule::Access;
+use super::storage::{AnyStorage, Component, SparseSet};
+use super::world::{downcast_storage, downcast_storage_mut, read_lock, write_lock};
+use super::{Entities, Entity, World};
+
+/// Locked storage of a component type that a query reads, `None` if no entity ever had the component.
+pub type ReadStorage<'w> = Option<RwLockReadGuard<'w, Box<dyn AnyStorage>>>;
+
+/// Locked storage of a component type that a query writes.
+pub struct WriteStorage<'w, T> {
+   _guard: RwLockWriteGuard<'w, Box<dyn AnyStorage>>,
+   storage: *mut SparseSet<T>,
+}
+
+/// What a query fetches per entity: `&T`, `&mut T`, `Option<&T>`, `Entity`, or a tuple of those.
+pub trait QueryParam {
+   type Lock<'w>;
+   type Item<'a>;
+
+   fn lock(world: &World) -> Self::Lock<'_>;
+
+   /// Declares component types the parameter reads and writes, for scheduling systems
+   fn access(access: &mut Access);
+
+   /// Entities that can possibly match, `None` if the parameter doesn't restrict them
+   fn candidates<'a>(lock: &'a Self::Lock<'_>) -> Option<&'a [Entity]>;
+
+   /// # Safety
+   /// Items with mutable references must not be fetched twice for the same entity while the
+   /// first item is alive
+   unsafe fn fetch<'a>(lock: &'a Self::Lock<'_>, entity: Entity) -> Option<Self::Item<'a>>;
+}
+
+impl<T: Component> QueryParam for &T {
+   type Lock<'w> = ReadStorage<'w>;
+   type Item<'a> = &'a T;
+
+   fn lock(world: &World) -> Self::Lock<'_> {
+      world.storage_lock::<T>().map(read_lock::<T, _>)
+   }
+
+   fn access(access: &mut Access) {
+      access.read::<T>();
+   }
+
+   fn candidates<'a>(lock: &'a Self::Lock<'_>) -> Option<&'a [Entity]> {
+      Some(lock.as_ref().map_or(&[], |guard| downcast_storage::<T>(&***guard).entities()))
+   }
+
+   unsafe fn fetch<'a>(lock: &'a Self::Lock<'_>, entity: Entity) -> Option<&'a T> {
+      downcast_storage::<T>(&***lock.as_ref()?).get(entity)
+   }
+}
+
+impl<T: Component> QueryParam for &mut T {
+   type Lock<'w> = Option<WriteStorage<'w, T>>;
+   type Item<'a> = &'a mut T;
+
+   fn lock(world: &World) -> Self::Lock<'_> {
+      world.storage_lock::<T>().map(|lock| {
+         let mut guard = write_lock::<T, _>(lock);
+         // The storage lives on the heap, moving the guard doesn't move it
+         let storage = downcast_storage_mut::<T>(&mut **guard) as *mut SparseSet<T>;
+         WriteStorage { _guard: guard, storage }
+      })
+   }
+
+   fn access(access: &mut Access) {
+      access.write::<T>();
+   }
+
+   fn candidates<'a>(lock: &'a Self::Lock<'_>) -> Option<&'a [Entity]> {
+      Some(lock.as_ref().map_or(&[], |lock| unsafe { (*lock.storage).entities() }))
+   }
+
+   unsafe fn fetch<'a>(lock: &'a Self::Lock<'_>, entity: Entity) -> Option<&'a mut T> {
+      (*lock.as_ref()?.storage).value_ptr(entity).map(|value| &mut *value)
+   }
+}
+
+impl<Q: QueryParam> QueryParam for Option<Q> {
+   type Lock<'w> = Q::Lock<'w>;
+   type Item<'a> = Option<Q::Item<'a>>;
+
+   fn lock(world: &World) -> Self::Lock<'_> {
+      Q::lock(world)
+   }
+
+   fn access(access: &mut Access) {
+      Q::access(access);
+   }
+
+   fn candidates<'a>(_: &'a Self::Lock<'_>) -> Option<&'a [Entity]> {
+      None
+   }
+
+   unsafe fn fetch<'a>(lock: &'a Self::Lock<'_>, entity: Entity) -> Option<Self::Item<'a>> {
+      Some(Q::fetch(lock, entity))
+   }
+}
+
+impl QueryParam for Entity {
+   type Lock<'w> = ();
+   type Item<'a> = Entity;
+
+   fn lock(_: &World) -> Self::Lock<'_> {}
+
+   fn access(_: &mut Access) {}
+
+   fn candidates<'a>(_: &'a Self::Lock<'_>) -> Option<&'a [Entity]> {
+      None
+   }
+
+   unsafe fn fetch(_: &Self::Lock<'_>, entity: Entity) -> Option<Entity> {
+      Some(entity)
+   }
+}
+
+macro_rules! impl_query_param {
+   ($($name:ident),*) => {
+      impl<$($name: QueryParam),*> QueryParam for ($($name,)*) {
+         type Lock<'w> = ($($name::Lock<'w>,)*);
+         type Item<'a> = ($($name::Item<'a>,)*);
+
+         fn lock(world: &World) -> Self::Lock<'_> {
+            ($($name::lock(world),)*)
+         }
+
+         fn access(access: &mut Access) {
+            $($name::access(access);)*
+         }
+
+         #[allow(non_snake_case)]
+         fn candidates<'a>(lock: &'a Self::Lock<'_>) -> Option<&'a [Entity]> {
+            let ($($name,)*) = lock;
+            [$($name::candidates($name)),*]
+               .into_iter()
+               .flatten()
+               .min_by_key(|candidates| candidates.len())
+         }
+
+         #[allow(non_snake_case)]
+         unsafe fn fetch<'a>(lock: &'a Self::Lock<'_>, entity: Entity) -> Option<Self::Item<'a>> {
+            let ($($name,)*) = lock;
+            Some(($($name::fetch($name, entity)?,)*))
+         }
+      }
+   };
+}
+
+impl_query_param!(A);
+impl_query_param!(A, B);
+impl_query_param!(A, B, C);
+impl_query_param!(A, B, C, D);
+impl_query_param!(A, B, C, D, E);
+impl_query_param!(A, B, C, D, E, F);
+impl_query_param!(A, B, C, D, E, F, G);
+impl_query_param!(A, B, C, D, E, F, G, H);
+
+/// Additional condition on matched entities that doesn't fetch anything.
+pub trait QueryFilter {
+   type Lock<'w>;
+
+   fn lock(world: &World) -> Self::Lock<'_>;
+
+   fn access(access: &mut Access);
+
+   fn matches(lock: &Self::Lock<'_>, entity: Entity) -> bool;
+}
+
+/// Matches entities that have the `T` component.
+pub struct With<T>(PhantomData<T>);
+
+/// Matches entities that don't have the `T` component.
+pub struct Without<T>(PhantomData<T>);
+
+impl<T: Component> QueryFilter for With<T> {
+   type Lock<'w> = ReadStorage<'w>;
+
+   fn lock(world: &World) -> Self::Lock<'_> {
+      world.storage_lock::<T>().map(read_lock::<T, _>)
+   }
+
+   fn access(access: &mut Access) {
+      access.read::<T>();
+   }
+
+   fn matches(lock: &Self::Lock<'_>, entity: Entity) -> bool {
+      lock.as_ref().is_some_and(|guard| guard.contains(entity))
+   }
+}
+
+impl<T: Component> QueryFilter for Without<T> {
+   type Lock<'w> = ReadStorage<'w>;
+
+   fn lock(world: &World) -> Self::Lock<'_> {
+      world.storage_lock::<T>().map(read_lock::<T, _>)
+   }
+
+   fn access(access: &mut Access) {
+      access.read::<T>();
+   }
+
+   fn matches(lock: &Self::Lock<'_>, entity: Entity) -> bool {
+      !lock.as_ref().is_some_and(|guard| guard.contains(entity))
+   }
+}
+
+macro_rules! impl_query_filter {
+   ($($name:ident),*) => {
+      impl<$($name: QueryFilter),*> QueryFilter for ($($name,)*) {
+         type Lock<'w> = ($($name::Lock<'w>,)*);
+
+         #[allow(clippy::unused_unit)]
+         fn lock(_world: &World) -> Self::Lock<'_> {
+            ($($name::lock(_world),)*)
+         }
+
+         fn access(_access: &mut Access) {
+            $($name::access(_access);)*
+         }
+
+         #[allow(non_snake_case)]
+         fn matches(lock: &Self::Lock<'_>, _entity: Entity) -> bool {
+            let ($($name,)*) = lock;
+            true $(&& $name::matches($name, _entity))*
+         }
+      }
+   };
+}
+
+impl_query_filter!();
+impl_query_filter!(A);
+impl_query_filter!(A, B);
+impl_query_filter!(A, B, C);
+impl_query_filter!(A, B, C, D);
+
+/// Borrowed component storages of a query. Storages stay locked until the query is dropped.
+pub struct Query<'w, Q: QueryParam, F: QueryFilter> {
+   entities: &'w Entities,
+   lock: Q::Lock<'w>,
+   filter: F::Lock<'w>,
+}
+
+impl<'w, Q: QueryParam, F: QueryFilter> Query<'w, Q, F> {
+   pub(super) fn new(world: &'w World) -> Self {
+      Query {
+         entities: world.entities(),
+         lock: Q::lock(world),
+         filter: F::lock(world),
+      }
+   }
+
+   pub fn iter(&mut self) -> QueryIter<'_, 'w, Q, F> {
+      let candidates = match Q::candidates(&self.lock) {
+         Some(candidates) => Candidates::Slice(candidates.iter()),
+         None => Candidates::All(Box::new(self.entities.iter())),
+      };
+      QueryIter {
+         candidates,
+         lock: &self.lock,
+         filter: &self.filter,
+      }
+   }
+
+   pub fn get(&mut self, entity: Entity) -> Option<Q::Item<'_>> {
+      if !self.entities.contains(entity) || !F::matches(&self.filter, entity) {
+         return None;
+      }
+      // The returned item borrows the query mutably, so it can't alias with another one
+      unsafe { Q::fetch(&self.lock, entity) }
+   }
+
+   /// The only matching entity, `None` if there are none or several
+   pub fn single(&mut self) -> Option<(Entity, Q::Item<'_>)> {
+      let mut iter = self.iter();
+      let single = iter.next()?;
+      iter.next().is_none().then_some(single)
+   }
+
+   pub fn count(&mut self) -> usize {
+      self.iter().count()
+   }
+}
+
+enum Candidates<'a> {
+   Slice(std::slice::Iter<'a, Entity>),
+   All(Box<dyn Iterator<Item = Entity> + 'a>),
+}
+
+pub struct QueryIter<'a, 'w, Q: QueryParam, F: QueryFilter> {
+   candidates: Candidates<'a>,
+   lock: &'a Q::Lock<'w>,
+   filter: &'a F::Lock<'w>,
+}
+
+impl<'a, Q: QueryParam, F: QueryFilter> Iterator for QueryIter<'a, '_, Q, F> {
+   type Item = (Entity, Q::Item<'a>);
+
+   fn next(&mut self) -> Option<Self::Item> {
+      loop {
+         let entity = match &mut self.candidates {
+            Candidates::Slice(iter) => *iter.next()?,
+            Candidates::All(iter) => iter.next()?,
+         };
+         if !F::matches(self.filter, entity) {
+            continue;
+         }
+         // Every entity is visited once, so mutable items never alias
+         if let Some(item) = unsafe { Q::fetch(self.lock, entity) } {
+            return Some((entity, item));
+         }
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   struct Position(f32);
+   struct Velocity;
+   struct Frozen;
+
+   #[test]
+   fn filters_select_by_presence() {
+      let mut world = World::new();
+      let moving = world.spawn((Position(0.0), Velocity));
+      let frozen = world.spawn((Position(1.0), Velocity, Frozen));
+      let still = world.spawn((Position(2.0),));
+
+      let mut with = world.query_filtered::<Entity, With<Velocity>>();
+      assert_eq!(with.iter().map(|(entity, _)| entity).collect::<Vec<_>>(), vec![moving, frozen]);
+      drop(with);
+
+      let mut without = world.query_filtered::<&Position, Without<Velocity>>();
+      assert_eq!(without.single().map(|(entity, position)| (entity, position.0)), Some((still, 2.0)));
+      drop(without);
+
+      let mut both = world.query_filtered::<&mut Position, (With<Velocity>, Without<Frozen>)>();
+      assert_eq!(both.count(), 1);
+      assert!(both.get(frozen).is_none());
+      both.get(moving).unwrap().0 = 5.0;
+   }
+
+   #[test]
+   fn despawned_entities_dont_match() {
+      let mut world = World::new();
+      let first = world.spawn((Position(0.0),));
+      world.despawn(first);
+      let second = world.spawn((Position(1.0),));
+      let mut query = world.query::<(Entity, Option<&Velocity>)>();
+      assert!(query.get(first).is_none());
+      assert_eq!(query.iter().map(|(entity, _)| entity).collect::<Vec<_>>(), vec![second]);
+   }
+}
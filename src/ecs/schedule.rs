@@ -0,0 +1,533 @@
+use std::any::{type_name, TypeId};
+use std::collections::HashMap;
+
+use super::query::{QueryFilter, QueryParam};
+use super::storage::Component;
+use super::World;
+
+/// Stages run in the order of declaration. Ordering of systems within a stage is given
+/// by `System::before`/`System::after` and by the order of addition of conflicting systems.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Stage {
+   Input,
+   /// Runs once per elapsed fixed step, see `Schedule::run_fixed_update`
+   FixedUpdate,
+   Update,
+   TransformPropagation,
+   Culling,
+   RenderExtraction,
+   Render,
+}
+
+impl Stage {
+   /// Stages executed by `Schedule::run`, `FixedUpdate` is executed separately
+   pub const FRAME_STAGES: [Stage; 6] = [
+      Stage::Input,
+      Stage::Update,
+      Stage::TransformPropagation,
+      Stage::Culling,
+      Stage::RenderExtraction,
+      Stage::Render,
+   ];
+}
+
+/// Component and resource types a system reads and writes.
+#[derive(Clone, Debug, Default)]
+pub struct Access {
+   reads: HashMap<TypeId, &'static str>,
+   writes: HashMap<TypeId, &'static str>,
+   resource_reads: HashMap<TypeId, &'static str>,
+   resource_writes: HashMap<TypeId, &'static str>,
+   exclusive: bool,
+}
+
+impl Access {
+   pub fn read<T: Component>(&mut self) {
+      self.reads.insert(TypeId::of::<T>(), type_name::<T>());
+   }
+
+   pub fn write<T: Component>(&mut self) {
+      self.writes.insert(TypeId::of::<T>(), type_name::<T>());
+   }
+
+   pub fn read_resource<R: Component>(&mut self) {
+      self.resource_reads.insert(TypeId::of::<R>(), type_name::<R>());
+   }
+
+   pub fn write_resource<R: Component>(&mut self) {
+      self.resource_writes.insert(TypeId::of::<R>(), type_name::<R>());
+   }
+
+   /// Access to the whole world, conflicts with everything
+   pub fn exclusive() -> Self {
+      Access {
+         exclusive: true,
+         ..Default::default()
+      }
+   }
+
+   pub fn is_exclusive(&self) -> bool {
+      self.exclusive
+   }
+
+   /// Name of a type one of the accesses writes and the other reads or writes, if any
+   pub fn conflict(&self, other: &Access) -> Option<&'static str> {
+      if self.exclusive || other.exclusive {
+         return Some("World");
+      }
+      fn first_shared(
+         writes: &HashMap<TypeId, &'static str>,
+         reads: &HashMap<TypeId, &'static str>,
+         other_writes: &HashMap<TypeId, &'static str>,
+      ) -> Option<&'static str> {
+         writes
+            .iter()
+            .find(|(id, _)| reads.contains_key(id) || other_writes.contains_key(id))
+            .map(|(_, &name)| name)
+      }
+      first_shared(&self.writes, &other.reads, &other.writes)
+         .or_else(|| first_shared(&other.writes, &self.reads, &self.writes))
+         .or_else(|| first_shared(&self.resource_writes, &other.resource_reads, &other.resource_writes))
+         .or_else(|| first_shared(&other.resource_writes, &self.resource_reads, &self.resource_writes))
+   }
+}
+
+enum SystemFn {
+   Parallel(Box<dyn FnMut(&World) + Send>),
+   Exclusive(Box<dyn FnMut(&mut World) + Send>),
+}
+
+/// A function run on the world each frame, along with its declared access and ordering.
+///
+/// Systems of a stage that don't conflict run in parallel, so the declared access must cover
+/// everything the system borrows from the world, otherwise it may panic on a conflicting borrow.
+/// Render systems recording commands should write the `FrameTarget` resource, that serializes them.
+pub struct System {
+   name: String,
+   access: Access,
+   before: Vec<String>,
+   after: Vec<String>,
+   run: SystemFn,
+}
+
+impl System {
+   pub fn parallel(name: &str, f: impl FnMut(&World) + Send + 'static) -> Self {
+      System {
+         name: name.to_owned(),
+         access: Access::default(),
+         before: Vec::new(),
+         after: Vec::new(),
+         run: SystemFn::Parallel(Box::new(f)),
+      }
+   }
+
+   /// System with mutable access to the world, e.g. for spawning entities. Runs alone
+   pub fn exclusive(name: &str, f: impl FnMut(&mut World) + Send + 'static) -> Self {
+      System {
+         name: name.to_owned(),
+         access: Access::exclusive(),
+         before: Vec::new(),
+         after: Vec::new(),
+         run: SystemFn::Exclusive(Box::new(f)),
+      }
+   }
+
+   pub fn name(&self) -> &str {
+      &self.name
+   }
+
+   pub fn access(&self) -> &Access {
+      &self.access
+   }
+
+   /// Declares access of `world.query::<Q>()`
+   pub fn with_query<Q: QueryParam>(mut self) -> Self {
+      Q::access(&mut self.access);
+      self
+   }
+
+   /// Declares access of the filter part of `world.query_filtered::<_, F>()`
+   pub fn with_filter<F: QueryFilter>(mut self) -> Self {
+      F::access(&mut self.access);
+      self
+   }
+
+   pub fn reads<T: Component>(mut self) -> Self {
+      self.access.read::<T>();
+      self
+   }
+
+   pub fn writes<T: Component>(mut self) -> Self {
+      self.access.write::<T>();
+      self
+   }
+
+   pub fn reads_resource<R: Component>(mut self) -> Self {
+      self.access.read_resource::<R>();
+      self
+   }
+
+   pub fn writes_resource<R: Component>(mut self) -> Self {
+      self.access.write_resource::<R>();
+      self
+   }
+
+   /// Runs this system before the named one. If they are in different stages, the stage order must agree
+   pub fn before(mut self, system: &str) -> Self {
+      self.before.push(system.to_owned());
+      self
+   }
+
+   /// Runs this system after the named one. If they are in different stages, the stage order must agree
+   pub fn after(mut self, system: &str) -> Self {
+      self.after.push(system.to_owned());
+      self
+   }
+}
+
+/// Systems of one stage, grouped into waves. Systems of a wave don't conflict and run in parallel,
+/// waves run one after another.
+#[derive(Default)]
+struct StagePlan {
+   systems: Vec<System>,
+   waves: Vec<Vec<usize>>,
+}
+
+impl StagePlan {
+   fn run(&mut self, world: &mut World) {
+      for wave in self.waves.iter() {
+         if let [index] = wave[..] {
+            run_system(&mut self.systems[index], world);
+            continue;
+         }
+         let world: &World = world;
+         let mut wave_systems: Vec<&mut System> = self.systems
+            .iter_mut()
+            .enumerate()
+            .filter(|(index, _)| wave.contains(index))
+            .map(|(_, system)| system)
+            .collect();
+         let (first, rest) = wave_systems.split_first_mut().unwrap();
+         // Rayon's threads persist between waves and frames
+         rayon::scope(|scope| {
+            for system in rest.iter_mut() {
+               scope.spawn(move |_| run_parallel_system(system, world));
+            }
+            // The calling thread takes a share of the work instead of idling
+            run_parallel_system(first, world);
+         });
+      }
+   }
+}
+
+fn run_system(system: &mut System, world: &mut World) {
+   match &mut system.run {
+      SystemFn::Parallel(f) => f(world),
+      SystemFn::Exclusive(f) => f(world),
+   }
+}
+
+fn run_parallel_system(system: &mut System, world: &World) {
+   match &mut system.run {
+      SystemFn::Parallel(f) => f(world),
+      SystemFn::Exclusive(_) => unreachable!("Exclusive systems always run alone"),
+   }
+}
+
+/// Systems grouped by stages.
+#[derive(Default)]
+pub struct Schedule {
+   stages: HashMap<Stage, StagePlan>,
+   needs_rebuild: bool,
+}
+
+impl Schedule {
+   pub fn new() -> Self {
+      Default::default()
+   }
+
+   pub fn add_system(&mut self, stage: Stage, system: System) -> &mut Self {
+      let duplicate = self.stages.values()
+         .flat_map(|plan| plan.systems.iter())
+         .any(|existing| existing.name == system.name);
+      assert!(!duplicate, "System '{}' is already in the schedule", system.name);
+      self.stages.entry(stage).or_default().systems.push(system);
+      self.needs_rebuild = true;
+      self
+   }
+
+   /// Runs all stages except `FixedUpdate`, in order
+   pub fn run(&mut self, world: &mut World) {
+      for stage in Stage::FRAME_STAGES {
+         self.run_stage(stage, world);
+      }
+   }
+
+   /// Runs `FixedUpdate` stage, call it once per fixed step
+   pub fn run_fixed_update(&mut self, world: &mut World) {
+      self.run_stage(Stage::FixedUpdate, world);
+   }
+
+   pub fn run_stage(&mut self, stage: Stage, world: &mut World) {
+      if self.needs_rebuild {
+         self.rebuild();
+      }
+      if let Some(plan) = self.stages.get_mut(&stage) {
+         plan.run(world);
+      }
+   }
+
+   /// Orders systems and splits stages into waves. Happens automatically on the next run after
+   /// systems are added, call it explicitly to validate the schedule early.
+   /// Panics on unknown system names and cyclic ordering
+   pub fn rebuild(&mut self) {
+      let stage_of: HashMap<String, Stage> = self.stages.iter()
+         .flat_map(|(&stage, plan)| plan.systems.iter().map(move |system| (system.name.clone(), stage)))
+         .collect();
+      for (&stage, plan) in self.stages.iter_mut() {
+         plan.waves = plan_waves(stage, &plan.systems, &stage_of);
+      }
+      self.needs_rebuild = false;
+   }
+}
+
+fn plan_waves(stage: Stage, systems: &[System], stage_of: &HashMap<String, Stage>) -> Vec<Vec<usize>> {
+   let n = systems.len();
+   let index_of: HashMap<&str, usize> = systems.iter()
+      .enumerate()
+      .map(|(index, system)| (system.name.as_str(), index))
+      .collect();
+
+   // reachable[a][b] means `a` runs before `b`
+   let mut reachable = vec![vec![false; n]; n];
+   for system in systems.iter() {
+      let orders = system.before.iter()
+         .map(|other| (other, true))
+         .chain(system.after.iter().map(|other| (other, false)));
+      for (other, other_is_later) in orders {
+         let other_stage = *stage_of.get(other)
+            .unwrap_or_else(|| panic!("System '{}' is ordered relative to unknown system '{}'", system.name, other));
+         if other_stage != stage {
+            assert!(
+               (other_stage > stage) == other_is_later,
+               "System '{}' in {:?} can't be ordered relative to '{}' in {:?}",
+               system.name, stage, other, other_stage,
+            );
+            continue;
+         }
+         let (this, other) = (index_of[system.name.as_str()], index_of[other.as_str()]);
+         if other_is_later {
+            reachable[this][other] = true;
+         } else {
+            reachable[other][this] = true;
+         }
+      }
+   }
+   transitive_closure(&mut reachable);
+   if let Some(index) = (0..n).find(|&index| reachable[index][index]) {
+      panic!("System '{}' is in an ordering cycle", systems[index].name);
+   }
+
+   // Conflicting systems without explicit order run in the order they were added
+   for second in 0..n {
+      for first in 0..second {
+         let ordered = reachable[first][second] || reachable[second][first];
+         if !ordered && systems[first].access.conflict(&systems[second].access).is_some() {
+            add_order(&mut reachable, first, second);
+         }
+      }
+   }
+
+   // Wave of a system is the length of the longest chain of systems that must run before it
+   let mut wave_of = vec![0usize; n];
+   let mut order: Vec<usize> = (0..n).collect();
+   order.sort_by_key(|&index| (0..n).filter(|&other| reachable[other][index]).count());
+   for &index in order.iter() {
+      wave_of[index] = (0..n)
+         .filter(|&other| reachable[other][index])
+         .map(|other| wave_of[other] + 1)
+         .max()
+         .unwrap_or(0);
+   }
+   let n_waves = wave_of.iter().max().map_or(0, |&max| max + 1);
+   let mut waves = vec![Vec::new(); n_waves];
+   for (index, &wave) in wave_of.iter().enumerate() {
+      waves[wave].push(index);
+   }
+   waves
+}
+
+/// Orders `first` before `second` in an already transitively closed `reachable`, keeping it closed
+fn add_order(reachable: &mut [Vec<bool>], first: usize, second: usize) {
+   let n = reachable.len();
+   let earlier: Vec<usize> = (0..n).filter(|&index| index == first || reachable[index][first]).collect();
+   let later: Vec<usize> = (0..n).filter(|&index| index == second || reachable[second][index]).collect();
+   for &before in earlier.iter() {
+      for &after in later.iter() {
+         reachable[before][after] = true;
+      }
+   }
+}
+
+fn transitive_closure(reachable: &mut [Vec<bool>]) {
+   for via in 0..reachable.len() {
+      let via_row = reachable[via].clone();
+      for row in reachable.iter_mut().filter(|row| row[via]) {
+         row.iter_mut()
+            .zip(via_row.iter())
+            .for_each(|(reaches, &via_reaches)| *reaches |= via_reaches);
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   struct Position;
+   struct Velocity;
+   #[derive(Default)]
+   struct Log(Vec<&'static str>);
+
+   fn logging(name: &'static str) -> System {
+      System::parallel(name, move |world| world.resource_mut::<Log>().0.push(name)).writes_resource::<Log>()
+   }
+
+   fn noop(name: &str) -> System {
+      System::parallel(name, |_| {})
+   }
+
+   fn wave_names(schedule: &mut Schedule, stage: Stage) -> Vec<Vec<&str>> {
+      schedule.rebuild();
+      let plan = &schedule.stages[&stage];
+      plan.waves
+         .iter()
+         .map(|wave| wave.iter().map(|&index| plan.systems[index].name()).collect())
+         .collect()
+   }
+
+   #[test]
+   fn stages_run_in_declaration_order() {
+      let mut world = World::new();
+      world.insert_resource(Log::default());
+      let mut schedule = Schedule::new();
+      schedule
+         .add_system(Stage::Render, logging("render"))
+         .add_system(Stage::FixedUpdate, logging("fixed"))
+         .add_system(Stage::Update, logging("update"))
+         .add_system(Stage::Input, logging("input"));
+      schedule.run(&mut world);
+      assert_eq!(world.resource::<Log>().0, ["input", "update", "render"]);
+
+      schedule.run_fixed_update(&mut world);
+      assert_eq!(world.resource::<Log>().0, ["input", "update", "render", "fixed"]);
+   }
+
+   #[test]
+   fn before_and_after_split_waves() {
+      let mut schedule = Schedule::new();
+      schedule
+         .add_system(Stage::Update, noop("last").after("middle"))
+         .add_system(Stage::Update, noop("middle"))
+         .add_system(Stage::Update, noop("first").before("middle"))
+         .add_system(Stage::Update, noop("free"));
+      assert_eq!(wave_names(&mut schedule, Stage::Update), [vec!["first", "free"], vec!["middle"], vec!["last"]]);
+   }
+
+   #[test]
+   fn ordering_across_stages_follows_stage_order() {
+      let mut schedule = Schedule::new();
+      schedule
+         .add_system(Stage::Update, noop("update").before("render"))
+         .add_system(Stage::Render, noop("render").after("update"));
+      schedule.rebuild();
+   }
+
+   #[test]
+   #[should_panic(expected = "can't be ordered relative to")]
+   fn ordering_against_stage_order_panics() {
+      let mut schedule = Schedule::new();
+      schedule
+         .add_system(Stage::Update, noop("update").after("render"))
+         .add_system(Stage::Render, noop("render"));
+      schedule.rebuild();
+   }
+
+   #[test]
+   #[should_panic(expected = "is in an ordering cycle")]
+   fn ordering_cycle_panics() {
+      let mut schedule = Schedule::new();
+      schedule
+         .add_system(Stage::Update, noop("a").before("b"))
+         .add_system(Stage::Update, noop("b").before("c"))
+         .add_system(Stage::Update, noop("c").before("a"));
+      schedule.rebuild();
+   }
+
+   #[test]
+   #[should_panic(expected = "unknown system 'missing'")]
+   fn ordering_relative_to_unknown_system_panics() {
+      let mut schedule = Schedule::new();
+      schedule.add_system(Stage::Update, noop("a").after("missing"));
+      schedule.rebuild();
+   }
+
+   #[test]
+   fn conflicting_systems_land_in_separate_waves() {
+      let mut schedule = Schedule::new();
+      schedule
+         .add_system(Stage::Update, noop("integrate").with_query::<(&mut Position, &Velocity)>())
+         .add_system(Stage::Update, noop("read_velocity").reads::<Velocity>())
+         .add_system(Stage::Update, noop("read_position").reads::<Position>())
+         .add_system(Stage::Update, noop("write_velocity").writes::<Velocity>())
+         .add_system(Stage::Update, System::exclusive("spawn", |_| {}));
+      assert_eq!(
+         wave_names(&mut schedule, Stage::Update),
+         [vec!["integrate", "read_velocity"], vec!["read_position", "write_velocity"], vec!["spawn"]],
+      );
+   }
+
+   #[test]
+   fn conflicting_systems_run_in_order_of_addition() {
+      let mut world = World::new();
+      world.insert_resource(Log::default());
+      let mut schedule = Schedule::new();
+      for name in ["a", "b", "c"] {
+         schedule.add_system(Stage::Update, logging(name));
+      }
+      schedule.run(&mut world);
+      assert_eq!(world.resource::<Log>().0, ["a", "b", "c"]);
+   }
+
+   #[test]
+   fn conflict_order_follows_explicit_order_through_other_systems() {
+      // "c" runs before "b" through "a", so ordering the conflicting "b" and "c" by addition would be a cycle
+      let mut schedule = Schedule::new();
+      schedule
+         .add_system(Stage::Update, noop("a").writes::<Position>())
+         .add_system(Stage::Update, noop("b").writes::<Position>().writes::<Velocity>())
+         .add_system(Stage::Update, noop("c").writes::<Velocity>().before("a"));
+      assert_eq!(wave_names(&mut schedule, Stage::Update), [vec!["c"], vec!["a"], vec!["b"]]);
+   }
+
+   #[test]
+   fn parallel_systems_all_run_every_time() {
+      use std::sync::atomic::{AtomicUsize, Ordering};
+      use std::sync::Arc;
+
+      let runs = Arc::new(AtomicUsize::new(0));
+      let mut schedule = Schedule::new();
+      for index in 0..8 {
+         let runs = runs.clone();
+         schedule.add_system(Stage::Update, System::parallel(&format!("system_{}", index), move |_| {
+            runs.fetch_add(1, Ordering::Relaxed);
+         }));
+      }
+      assert_eq!(wave_names(&mut schedule, Stage::Update).len(), 1);
+      let mut world = World::new();
+      for _ in 0..10 {
+         schedule.run(&mut world);
+      }
+      assert_eq!(runs.load(Ordering::Relaxed), 80);
+   }
+}
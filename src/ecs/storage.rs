@@ -0,0 +1,176 @@
+use std::any::Any;
+
+use super::Entity;
+
+const EMPTY: u32 = u32::MAX;
+
+/// Anything that is `Send + Sync + 'static` can be a component.
+pub trait Component: Send + Sync + 'static {}
+
+impl<T: Send + Sync + 'static> Component for T {}
+
+/// Components of one type. Values are packed densely for fast iteration, while the sparse array
+/// indexed by entity index gives constant time lookup, insertion and removal.
+pub struct SparseSet<T> {
+   sparse: Vec<u32>,
+   entities: Vec<Entity>,
+   values: Vec<T>,
+}
+
+impl<T> SparseSet<T> {
+   pub fn new() -> Self {
+      SparseSet {
+         sparse: Vec::new(),
+         entities: Vec::new(),
+         values: Vec::new(),
+      }
+   }
+
+   fn dense_index(&self, entity: Entity) -> Option<usize> {
+      let dense_index = *self.sparse.get(entity.index() as usize)?;
+      if dense_index == EMPTY || self.entities[dense_index as usize] != entity {
+         return None;
+      }
+      Some(dense_index as usize)
+   }
+
+   pub fn contains(&self, entity: Entity) -> bool {
+      self.dense_index(entity).is_some()
+   }
+
+   pub fn get(&self, entity: Entity) -> Option<&T> {
+      self.dense_index(entity).map(|index| &self.values[index])
+   }
+
+   pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+      self.dense_index(entity).map(move |index| &mut self.values[index])
+   }
+
+   /// Pointer to the value of the entity. Unlike `get_mut`, doesn't borrow the other values,
+   /// so references previously made from such pointers stay valid
+   pub(super) fn value_ptr(&mut self, entity: Entity) -> Option<*mut T> {
+      let index = self.dense_index(entity)?;
+      Some(unsafe { self.values.as_mut_ptr().add(index) })
+   }
+
+   /// Returns the previous value if the entity already had one
+   pub fn insert(&mut self, entity: Entity, value: T) -> Option<T> {
+      if let Some(index) = self.dense_index(entity) {
+         return Some(std::mem::replace(&mut self.values[index], value));
+      }
+      let sparse_index = entity.index() as usize;
+      if sparse_index >= self.sparse.len() {
+         self.sparse.resize(sparse_index + 1, EMPTY);
+      }
+      self.sparse[sparse_index] = self.entities.len() as u32;
+      self.entities.push(entity);
+      self.values.push(value);
+      None
+   }
+
+   pub fn remove(&mut self, entity: Entity) -> Option<T> {
+      let index = self.dense_index(entity)?;
+      self.sparse[entity.index() as usize] = EMPTY;
+      self.entities.swap_remove(index);
+      if let Some(&moved) = self.entities.get(index) {
+         self.sparse[moved.index() as usize] = index as u32;
+      }
+      Some(self.values.swap_remove(index))
+   }
+
+   pub fn len(&self) -> usize {
+      self.values.len()
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.values.is_empty()
+   }
+
+   pub fn entities(&self) -> &[Entity] {
+      &self.entities
+   }
+
+   pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+      self.entities.iter().copied().zip(self.values.iter())
+   }
+
+   pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+      self.entities.iter().copied().zip(self.values.iter_mut())
+   }
+}
+
+impl<T> Default for SparseSet<T> {
+   fn default() -> Self {
+      Self::new()
+   }
+}
+
+/// Type-erased `SparseSet`, lets the world despawn entities without knowing their component types.
+pub trait AnyStorage: Send + Sync {
+   fn remove_entity(&mut self, entity: Entity);
+   fn contains(&self, entity: Entity) -> bool;
+   fn as_any(&self) -> &dyn Any;
+   fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Component> AnyStorage for SparseSet<T> {
+   fn remove_entity(&mut self, entity: Entity) {
+      self.remove(entity);
+   }
+
+   fn contains(&self, entity: Entity) -> bool {
+      SparseSet::contains(self, entity)
+   }
+
+   fn as_any(&self) -> &dyn Any {
+      self
+   }
+
+   fn as_any_mut(&mut self) -> &mut dyn Any {
+      self
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn entity(index: u32) -> Entity {
+      Entity::from_bits(u64::from(index))
+   }
+
+   #[test]
+   fn insert_replaces_previous_value() {
+      let mut set = SparseSet::new();
+      assert_eq!(set.insert(entity(3), "a"), None);
+      assert_eq!(set.insert(entity(3), "b"), Some("a"));
+      assert_eq!(set.get(entity(3)), Some(&"b"));
+      assert_eq!(set.len(), 1);
+   }
+
+   #[test]
+   fn remove_moves_last_value_into_the_hole() {
+      let mut set = SparseSet::new();
+      for index in 0..3 {
+         set.insert(entity(index), index * 10);
+      }
+      assert_eq!(set.remove(entity(0)), Some(0));
+      assert_eq!(set.entities(), &[entity(2), entity(1)]);
+      assert_eq!(set.get(entity(2)), Some(&20));
+      assert_eq!(set.get(entity(1)), Some(&10));
+      assert_eq!(set.remove(entity(0)), None);
+
+      assert_eq!(set.remove(entity(1)), Some(10));
+      assert_eq!(set.iter().collect::<Vec<_>>(), vec![(entity(2), &20)]);
+   }
+
+   #[test]
+   fn other_generation_doesnt_match() {
+      let mut set = SparseSet::new();
+      set.insert(entity(1), ());
+      let other = Entity::from_bits(1 << 32 | 1);
+      assert!(!set.contains(other));
+      assert_eq!(set.remove(other), None);
+      assert!(set.contains(entity(1)));
+   }
+}
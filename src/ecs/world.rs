@@ -0,0 +1,325 @@
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+use super::query::{Query, QueryFilter, QueryParam};
+use super::storage::{AnyStorage, Component, SparseSet};
+use super::{Entities, Entity};
+
+pub(super) type StorageLock = RwLock<Box<dyn AnyStorage>>;
+type ResourceLock = RwLock<Box<dyn Any + Send + Sync>>;
+
+/// Entities, their components and global resources.
+///
+/// Component storages and resources are individually locked, so a query can read some types and
+/// write others at the same time, and systems with disjoint access can run in parallel.
+/// Conflicting borrows panic instead of blocking, much like `RefCell`.
+#[derive(Default)]
+pub struct World {
+   entities: Entities,
+   storages: HashMap<TypeId, StorageLock>,
+   resources: HashMap<TypeId, ResourceLock>,
+}
+
+impl World {
+   pub fn new() -> Self {
+      Default::default()
+   }
+
+   /// Creates an entity with the given components, e.g. `world.spawn((transform, mesh))`
+   pub fn spawn(&mut self, bundle: impl Bundle) -> Entity {
+      let entity = self.entities.alloc();
+      bundle.insert_into(self, entity);
+      entity
+   }
+
+   /// Removes the entity with all its components. Returns false if it was already despawned
+   pub fn despawn(&mut self, entity: Entity) -> bool {
+      if !self.entities.free(entity) {
+         return false;
+      }
+      for storage in self.storages.values_mut() {
+         storage.get_mut().unwrap_or_else(|err| err.into_inner()).remove_entity(entity);
+      }
+      true
+   }
+
+   pub fn contains(&self, entity: Entity) -> bool {
+      self.entities.contains(entity)
+   }
+
+   pub fn entities(&self) -> &Entities {
+      &self.entities
+   }
+
+   /// Adds components to the entity, replacing the ones of the same type
+   pub fn insert(&mut self, entity: Entity, bundle: impl Bundle) {
+      assert!(self.contains(entity), "Inserting components into despawned {:?}", entity);
+      bundle.insert_into(self, entity);
+   }
+
+   /// Adds a component to the entity, returns the replaced one if any
+   pub fn insert_one<T: Component>(&mut self, entity: Entity, component: T) -> Option<T> {
+      assert!(self.contains(entity), "Inserting components into despawned {:?}", entity);
+      self.storage_mut::<T>().insert(entity, component)
+   }
+
+   pub fn remove<T: Component>(&mut self, entity: Entity) -> Option<T> {
+      let storage = self.storages.get_mut(&TypeId::of::<T>())?;
+      downcast_storage_mut::<T>(&mut **storage.get_mut().unwrap_or_else(|err| err.into_inner()))
+         .remove(entity)
+   }
+
+   pub fn has<T: Component>(&self, entity: Entity) -> bool {
+      self.storages
+         .get(&TypeId::of::<T>())
+         .is_some_and(|storage| read_lock::<T, _>(storage).contains(entity))
+   }
+
+   pub fn get<T: Component>(&self, entity: Entity) -> Option<ComponentRef<'_, T>> {
+      let guard = read_lock::<T, _>(self.storages.get(&TypeId::of::<T>())?);
+      downcast_storage::<T>(&**guard).contains(entity).then(|| ComponentRef {
+         guard,
+         entity,
+         marker: std::marker::PhantomData,
+      })
+   }
+
+   /// Exclusive access doesn't need locking, so the reference is plain
+   pub fn get_mut<T: Component>(&mut self, entity: Entity) -> Option<&mut T> {
+      let storage = self.storages.get_mut(&TypeId::of::<T>())?;
+      downcast_storage_mut::<T>(&mut **storage.get_mut().unwrap_or_else(|err| err.into_inner()))
+         .get_mut(entity)
+   }
+
+   /// Iterates entities matching the query, e.g. `world.query::<(&Transform, &mut Velocity)>()`
+   pub fn query<Q: QueryParam>(&self) -> Query<'_, Q, ()> {
+      Query::new(self)
+   }
+
+   /// Same as `query`, but also requires the filter to match,
+   /// e.g. `world.query_filtered::<&Transform, (With<Camera>, Without<Light>)>()`
+   pub fn query_filtered<Q: QueryParam, F: QueryFilter>(&self) -> Query<'_, Q, F> {
+      Query::new(self)
+   }
+
+   pub(super) fn storage_lock<T: Component>(&self) -> Option<&StorageLock> {
+      self.storages.get(&TypeId::of::<T>())
+   }
+
+   /// Storage of `T`, created if it doesn't exist yet
+   pub fn storage_mut<T: Component>(&mut self) -> &mut SparseSet<T> {
+      let storage = self.storages
+         .entry(TypeId::of::<T>())
+         .or_insert_with(|| RwLock::new(Box::new(SparseSet::<T>::new())));
+      downcast_storage_mut::<T>(&mut **storage.get_mut().unwrap_or_else(|err| err.into_inner()))
+   }
+
+   /// Adds a resource, a global singleton not attached to any entity. Returns the replaced one if any
+   pub fn insert_resource<R: Component>(&mut self, resource: R) -> Option<R> {
+      self.resources
+         .insert(TypeId::of::<R>(), RwLock::new(Box::new(resource)))
+         .map(|previous| {
+            *previous
+               .into_inner()
+               .unwrap_or_else(|err| err.into_inner())
+               .downcast::<R>()
+               .unwrap()
+         })
+   }
+
+   pub fn remove_resource<R: Component>(&mut self) -> Option<R> {
+      self.resources.remove(&TypeId::of::<R>()).map(|resource| {
+         *resource
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner())
+            .downcast::<R>()
+            .unwrap()
+      })
+   }
+
+   pub fn has_resource<R: Component>(&self) -> bool {
+      self.resources.contains_key(&TypeId::of::<R>())
+   }
+
+   /// Panics if the resource doesn't exist, use `get_resource` to check
+   pub fn resource<R: Component>(&self) -> Res<'_, R> {
+      self.get_resource::<R>()
+         .unwrap_or_else(|| panic!("Resource {} doesn't exist", type_name::<R>()))
+   }
+
+   /// Panics if the resource doesn't exist, use `get_resource_mut` to check
+   pub fn resource_mut<R: Component>(&self) -> ResMut<'_, R> {
+      self.get_resource_mut::<R>()
+         .unwrap_or_else(|| panic!("Resource {} doesn't exist", type_name::<R>()))
+   }
+
+   pub fn get_resource<R: Component>(&self) -> Option<Res<'_, R>> {
+      let lock = self.resources.get(&TypeId::of::<R>())?;
+      Some(Res {
+         guard: read_lock::<R, _>(lock),
+         marker: std::marker::PhantomData,
+      })
+   }
+
+   pub fn get_resource_mut<R: Component>(&self) -> Option<ResMut<'_, R>> {
+      let lock = self.resources.get(&TypeId::of::<R>())?;
+      Some(ResMut {
+         guard: write_lock::<R, _>(lock),
+         marker: std::marker::PhantomData,
+      })
+   }
+}
+
+/// Set of components inserted together, implemented for components and their tuples.
+pub trait Bundle {
+   fn insert_into(self, world: &mut World, entity: Entity);
+}
+
+macro_rules! impl_bundle {
+   ($($name:ident),*) => {
+      impl<$($name: Component),*> Bundle for ($($name,)*) {
+         #[allow(non_snake_case, unused_variables)]
+         fn insert_into(self, world: &mut World, entity: Entity) {
+            let ($($name,)*) = self;
+            $(world.storage_mut::<$name>().insert(entity, $name);)*
+         }
+      }
+   };
+}
+
+impl_bundle!();
+impl_bundle!(A);
+impl_bundle!(A, B);
+impl_bundle!(A, B, C);
+impl_bundle!(A, B, C, D);
+impl_bundle!(A, B, C, D, E);
+impl_bundle!(A, B, C, D, E, F);
+impl_bundle!(A, B, C, D, E, F, G);
+impl_bundle!(A, B, C, D, E, F, G, H);
+
+/// Shared borrow of a single component.
+pub struct ComponentRef<'w, T> {
+   guard: RwLockReadGuard<'w, Box<dyn AnyStorage>>,
+   entity: Entity,
+   marker: std::marker::PhantomData<T>,
+}
+
+impl<T: Component> Deref for ComponentRef<'_, T> {
+   type Target = T;
+
+   fn deref(&self) -> &T {
+      downcast_storage::<T>(&**self.guard).get(self.entity).unwrap()
+   }
+}
+
+/// Shared borrow of a resource.
+pub struct Res<'w, R> {
+   guard: RwLockReadGuard<'w, Box<dyn Any + Send + Sync>>,
+   marker: std::marker::PhantomData<R>,
+}
+
+impl<R: Component> Deref for Res<'_, R> {
+   type Target = R;
+
+   fn deref(&self) -> &R {
+      self.guard.downcast_ref::<R>().unwrap()
+   }
+}
+
+/// Exclusive borrow of a resource.
+pub struct ResMut<'w, R> {
+   guard: RwLockWriteGuard<'w, Box<dyn Any + Send + Sync>>,
+   marker: std::marker::PhantomData<R>,
+}
+
+impl<R: Component> Deref for ResMut<'_, R> {
+   type Target = R;
+
+   fn deref(&self) -> &R {
+      self.guard.downcast_ref::<R>().unwrap()
+   }
+}
+
+impl<R: Component> DerefMut for ResMut<'_, R> {
+   fn deref_mut(&mut self) -> &mut R {
+      self.guard.downcast_mut::<R>().unwrap()
+   }
+}
+
+pub(super) fn downcast_storage<T: Component>(storage: &dyn AnyStorage) -> &SparseSet<T> {
+   storage.as_any().downcast_ref::<SparseSet<T>>().unwrap()
+}
+
+pub(super) fn downcast_storage_mut<T: Component>(storage: &mut dyn AnyStorage) -> &mut SparseSet<T> {
+   storage.as_any_mut().downcast_mut::<SparseSet<T>>().unwrap()
+}
+
+/// Locks for reading, `T` only names the borrowed type in the panic message
+pub(super) fn read_lock<T, U: ?Sized>(lock: &RwLock<Box<U>>) -> RwLockReadGuard<'_, Box<U>> {
+   match lock.try_read() {
+      Ok(guard) => guard,
+      Err(TryLockError::Poisoned(err)) => err.into_inner(),
+      Err(TryLockError::WouldBlock) =>
+         panic!("{} is already borrowed mutably", type_name::<T>()),
+   }
+}
+
+/// Locks for writing, `T` only names the borrowed type in the panic message
+pub(super) fn write_lock<T, U: ?Sized>(lock: &RwLock<Box<U>>) -> RwLockWriteGuard<'_, Box<U>> {
+   match lock.try_write() {
+      Ok(guard) => guard,
+      Err(TryLockError::Poisoned(err)) => err.into_inner(),
+      Err(TryLockError::WouldBlock) =>
+         panic!("{} is already borrowed", type_name::<T>()),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   struct Health(u32);
+
+   #[test]
+   #[should_panic(expected = "is already borrowed mutably")]
+   fn read_while_written_panics() {
+      let mut world = World::new();
+      world.spawn((Health(1),));
+      let _write = world.query::<&mut Health>();
+      let _read = world.query::<&Health>();
+   }
+
+   #[test]
+   #[should_panic(expected = "is already borrowed")]
+   fn write_while_read_panics() {
+      let mut world = World::new();
+      world.spawn((Health(1),));
+      let _read = world.query::<&Health>();
+      let _write = world.query::<(Entity, &mut Health)>();
+   }
+
+   #[test]
+   fn shared_reads_dont_conflict() {
+      let mut world = World::new();
+      let entity = world.spawn((Health(1),));
+      let mut first = world.query::<&Health>();
+      let second = world.get::<Health>(entity).unwrap();
+      assert_eq!(first.get(entity).unwrap().0, second.0);
+   }
+
+   #[test]
+   fn stale_handle_doesnt_see_new_components() {
+      let mut world = World::new();
+      let stale = world.spawn((Health(1),));
+      assert!(world.despawn(stale));
+      let reused = world.spawn((Health(2),));
+      assert_eq!(reused.index(), stale.index());
+      assert!(!world.contains(stale));
+      assert!(!world.despawn(stale));
+      assert!(world.get::<Health>(stale).is_none());
+      assert!(world.remove::<Health>(stale).is_none());
+      assert_eq!(world.get::<Health>(reused).unwrap().0, 2);
+   }
+}
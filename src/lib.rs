@@ -1 +1,5 @@
-pub mod platform;
\ No newline at end of file
+pub mod asset;
+pub mod ecs;
+pub mod platform;
+pub mod render;
+pub mod scene;
@@ -1,7 +1,13 @@
 pub mod abstraction;
+pub mod vulkan_buffer;
 pub mod vulkan_context; // TODO: make private
+pub mod vulkan_descriptor;
+pub mod vulkan_framebuffer;
+pub mod vulkan_mesh;
+pub mod vulkan_pipeline;
 pub mod vulkan_shader;
+pub mod vulkan_texture;
 
 pub trait VulkanDrop {
    fn drop(self, device: &ash::Device);
-}
\ No newline at end of file
+}
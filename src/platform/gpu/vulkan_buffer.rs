@@ -0,0 +1,152 @@
+use std::mem::align_of;
+
+use ash::util::Align;
+use ash::vk;
+
+use super::vulkan_context::{find_memorytype_index, record_submit_commandbuffer, VulkanContext};
+use super::VulkanDrop;
+
+/// Buffer with its own memory allocation.
+pub struct VulkanBuffer {
+   buffer: vk::Buffer,
+   memory: vk::DeviceMemory,
+   size: vk::DeviceSize,
+}
+
+impl VulkanBuffer {
+   /// Buffer in host visible and coherent memory, written directly by `write`
+   pub fn new_host_visible(
+      device: &ash::Device,
+      memory_properties: &vk::PhysicalDeviceMemoryProperties,
+      size: vk::DeviceSize,
+      usage: vk::BufferUsageFlags,
+   ) -> Self {
+      Self::new(
+         device,
+         memory_properties,
+         size,
+         usage,
+         vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+      )
+   }
+
+   /// Buffer in device local memory filled with the data through a staging buffer. Waits until
+   /// the copy finishes
+   pub fn new_device_local<T: Copy>(context: &VulkanContext, usage: vk::BufferUsageFlags, data: &[T]) -> Self {
+      let size = std::mem::size_of_val(data) as vk::DeviceSize;
+      let staging = Self::new_host_visible(
+         &context.device,
+         &context.device_memory_properties,
+         size,
+         vk::BufferUsageFlags::TRANSFER_SRC,
+      );
+      staging.write(&context.device, data);
+      let buffer = Self::new(
+         &context.device,
+         &context.device_memory_properties,
+         size,
+         usage | vk::BufferUsageFlags::TRANSFER_DST,
+         vk::MemoryPropertyFlags::DEVICE_LOCAL,
+      );
+      record_submit_commandbuffer(
+         &context.device,
+         context.setup_command_buffer,
+         context.setup_commands_reuse_fence,
+         context.present_queue,
+         &[],
+         &[],
+         &[],
+         |device, command_buffer| unsafe {
+            let region = vk::BufferCopy { src_offset: 0, dst_offset: 0, size };
+            device.cmd_copy_buffer(command_buffer, staging.buffer, buffer.buffer, &[region]);
+         },
+      );
+      unsafe {
+         context
+            .device
+            .wait_for_fences(&[context.setup_commands_reuse_fence], true, u64::MAX)
+            .expect("Wait for fence failed.");
+      }
+      staging.drop(&context.device);
+      buffer
+   }
+
+   pub fn new(
+      device: &ash::Device,
+      memory_properties: &vk::PhysicalDeviceMemoryProperties,
+      size: vk::DeviceSize,
+      usage: vk::BufferUsageFlags,
+      memory_flags: vk::MemoryPropertyFlags,
+   ) -> Self {
+      unsafe {
+         let buffer_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+         let buffer = device.create_buffer(&buffer_info, None).unwrap();
+         let memory_req = device.get_buffer_memory_requirements(buffer);
+         let memory_index = find_memorytype_index(&memory_req, memory_properties, memory_flags)
+            .expect("Unable to find suitable memorytype for the buffer.");
+         let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(memory_req.size)
+            .memory_type_index(memory_index);
+         let memory = device.allocate_memory(&allocate_info, None).unwrap();
+         device.bind_buffer_memory(buffer, memory, 0).unwrap();
+         VulkanBuffer { buffer, memory, size }
+      }
+   }
+
+   pub fn buffer(&self) -> vk::Buffer {
+      self.buffer
+   }
+
+   pub fn size(&self) -> vk::DeviceSize {
+      self.size
+   }
+
+   pub fn descriptor_info(&self) -> vk::DescriptorBufferInfo {
+      vk::DescriptorBufferInfo {
+         buffer: self.buffer,
+         offset: 0,
+         range: self.size,
+      }
+   }
+
+   /// Copies the data to the start of a host visible buffer. The GPU must not be using the buffer
+   pub fn write<T: Copy>(&self, device: &ash::Device, data: &[T]) {
+      self.write_at(device, 0, data);
+   }
+
+   /// Copies the data to a host visible buffer at the byte offset. The GPU must not be using
+   /// that part of the buffer
+   pub fn write_at<T: Copy>(&self, device: &ash::Device, offset: vk::DeviceSize, data: &[T]) {
+      let data_size = std::mem::size_of_val(data) as vk::DeviceSize;
+      assert!(
+         offset + data_size <= self.size,
+         "Writing {} bytes at {} into a buffer of {} bytes",
+         data_size,
+         offset,
+         self.size
+      );
+      if data_size == 0 {
+         return;
+      }
+      unsafe {
+         let ptr = device
+            .map_memory(self.memory, offset, data_size, vk::MemoryMapFlags::empty())
+            .unwrap();
+         let mut slice = Align::new(ptr, align_of::<T>() as vk::DeviceSize, data_size);
+         slice.copy_from_slice(data);
+         device.unmap_memory(self.memory);
+      }
+   }
+}
+
+impl VulkanDrop for VulkanBuffer {
+   fn drop(self, device: &ash::Device) {
+      unsafe {
+         device.destroy_buffer(self.buffer, None);
+         device.free_memory(self.memory, None);
+      }
+   }
+}
@@ -6,6 +6,7 @@ use ash::extensions::{
     khr::{Surface, Swapchain},
 };
 
+use ash::vk::ExtSwapchainColorspaceFn;
 use ash::{vk, Entry};
 pub use ash::{Device, Instance};
 use std::borrow::Cow;
@@ -15,6 +16,14 @@ use std::ffi::CStr;
 use std::ops::Drop;
 use std::os::raw::c_char;
 
+use cgmath::Vector2;
+
+use crate::ecs::{Schedule, World};
+use crate::platform::input::{Input, InputEvent, InputReplay, InputSession};
+use crate::platform::time::{FixedStep, FixedTimestep, FrameTimer, Time};
+
+use super::vulkan_texture::find_depth_format;
+
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 use ash::vk::{
     KhrGetPhysicalDeviceProperties2Fn, KhrPortabilityEnumerationFn, KhrPortabilitySubsetFn,
@@ -43,7 +52,7 @@ pub fn record_submit_commandbuffer<F: FnOnce(&Device, vk::CommandBuffer)>(
 ) {
     unsafe {
         device
-            .wait_for_fences(&[command_buffer_reuse_fence], true, std::u64::MAX)
+            .wait_for_fences(&[command_buffer_reuse_fence], true, u64::MAX)
             .expect("Wait for fence failed.");
 
         device
@@ -89,7 +98,7 @@ unsafe extern "system" fn vulkan_debug_callback(
     _user_data: *mut std::os::raw::c_void,
 ) -> vk::Bool32 {
     let callback_data = *p_callback_data;
-    let message_id_number: i32 = callback_data.message_id_number as i32;
+    let message_id_number: i32 = callback_data.message_id_number;
 
     let message_id_name = if callback_data.p_message_id_name.is_null() {
         Cow::from("")
@@ -130,6 +139,78 @@ pub fn find_memorytype_index(
         .map(|(index, _memory_type)| index as _)
 }
 
+/// Color space of the swapchain images, requested with `VulkanContext::with_display_output`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DisplayOutput {
+   /// sRGB, values in [0, 1]
+   #[default]
+   Sdr,
+   /// 10 bit Rec. 2020 primaries encoded with the ST 2084 perceptual quantizer, in absolute nits
+   Hdr10,
+   /// 16 bit float linear sRGB, 1 is 80 nits, brighter and wider gamut colors go beyond [0, 1]
+   ScRgb,
+}
+
+/// Surface format of the requested output if the surface offers one, else `DisplayOutput::Sdr`
+fn choose_surface_format(
+   formats: &[vk::SurfaceFormatKHR],
+   output: DisplayOutput,
+) -> (vk::SurfaceFormatKHR, DisplayOutput) {
+   let (color_space, hdr_formats) = match output {
+      DisplayOutput::Sdr => (vk::ColorSpaceKHR::SRGB_NONLINEAR, &[][..]),
+      DisplayOutput::Hdr10 => (
+         vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+         &[vk::Format::A2B10G10R10_UNORM_PACK32, vk::Format::A2R10G10B10_UNORM_PACK32][..],
+      ),
+      DisplayOutput::ScRgb => (vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT, &[vk::Format::R16G16B16A16_SFLOAT][..]),
+   };
+   let hdr = hdr_formats.iter().find_map(|&format| {
+      formats
+         .iter()
+         .copied()
+         .find(|surface_format| surface_format.format == format && surface_format.color_space == color_space)
+   });
+   match hdr {
+      Some(surface_format) => (surface_format, output),
+      None => (choose_sdr_surface_format(formats), DisplayOutput::Sdr),
+   }
+}
+
+/// 8 bit sRGB swapchain format, encoded by the hardware on write, else an 8 bit UNORM one,
+/// else the first reported. See `is_srgb_format` for whether shaders must encode themselves
+fn choose_sdr_surface_format(formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
+   let preferred = [
+      vk::Format::B8G8R8A8_SRGB,
+      vk::Format::R8G8B8A8_SRGB,
+      vk::Format::B8G8R8A8_UNORM,
+      vk::Format::R8G8B8A8_UNORM,
+   ];
+   // A single undefined entry leaves the choice to the application
+   if let [vk::SurfaceFormatKHR { format: vk::Format::UNDEFINED, color_space }] = formats {
+      return vk::SurfaceFormatKHR { format: preferred[0], color_space: *color_space };
+   }
+   preferred
+      .iter()
+      .find_map(|&format| {
+         formats.iter().copied().find(|surface_format| {
+            surface_format.format == format && surface_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+         })
+      })
+      .unwrap_or(formats[0])
+}
+
+/// Whether writes to the format are sRGB encoded by the hardware
+pub fn is_srgb_format(format: vk::Format) -> bool {
+   matches!(
+      format,
+      vk::Format::B8G8R8A8_SRGB
+         | vk::Format::R8G8B8A8_SRGB
+         | vk::Format::A8B8G8R8_SRGB_PACK32
+         | vk::Format::R8G8B8_SRGB
+         | vk::Format::B8G8R8_SRGB
+   )
+}
+
 pub struct VulkanContext {
    pub entry: Entry,
    pub instance: Instance,
@@ -138,19 +219,37 @@ pub struct VulkanContext {
    pub swapchain_loader: Swapchain,
    pub debug_utils_loader: DebugUtils,
    pub window: winit::window::Window,
-   pub event_loop: RefCell<EventLoop<()>>,
+   /// Taken out while a render loop runs
+   event_loop: Option<EventLoop<()>>,
+   /// Input state fed by `render_loop`. Configure it before the loop starts; during the loop
+   /// it's borrowed and accessible only through `FrameContext::input`
+   pub input: RefCell<Input>,
+   /// Live, recording or replaying input for `render_loop`, live by default
+   pub input_session: RefCell<InputSession>,
    pub debug_call_back: vk::DebugUtilsMessengerEXT,
 
    pub pdevice: vk::PhysicalDevice,
    pub device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+   pub device_limits: vk::PhysicalDeviceLimits,
+   /// Whether cube array images are enabled, for point light shadows
+   pub image_cube_array: bool,
+   /// Whether shading once per sample is enabled, for `Multisampling::min_sample_shading`
+   pub sample_rate_shading: bool,
    pub queue_family_index: u32,
    pub present_queue: vk::Queue,
 
    pub surface: vk::SurfaceKHR,
+   /// Preferably 8 bit sRGB, see `is_srgb_format`, or in the color space of `display_output`
    pub surface_format: vk::SurfaceFormatKHR,
+   /// Output requested of `with_display_output`, or `DisplayOutput::Sdr` if the surface
+   /// doesn't support it
+   pub display_output: DisplayOutput,
+   /// Current size of the swapchain images, changes when the window is resized
    pub surface_resolution: vk::Extent2D,
 
    pub swapchain: vk::SwapchainKHR,
+   /// Incremented on every swapchain recreation, see `FrameContext::swapchain_generation`
+   pub swapchain_generation: u64,
    pub present_images: Vec<vk::Image>,
    pub present_image_views: Vec<vk::ImageView>,
 
@@ -158,6 +257,8 @@ pub struct VulkanContext {
    pub draw_command_buffer: vk::CommandBuffer,
    pub setup_command_buffer: vk::CommandBuffer,
 
+   /// Of the depth image, the most precise one supported, for reverse-Z
+   pub depth_format: vk::Format,
    pub depth_image: vk::Image,
    pub depth_image_view: vk::ImageView,
    pub depth_image_memory: vk::DeviceMemory,
@@ -169,11 +270,251 @@ pub struct VulkanContext {
    pub setup_commands_reuse_fence: vk::Fence,
 }
 
+/// Starts a frame: applies the window events that arrived since the previous one to the input, or the events
+/// of the next recorded frame when replaying, advances the timer and runs the fixed updates. `None` once the
+/// replay is over
+fn start_frame<U: FnMut(FixedStep, &Input)>(
+   session: &mut InputSession,
+   input: &mut Input,
+   events: &mut Vec<InputEvent>,
+   timer: &mut FrameTimer,
+   fixed_timestep: Option<&mut FixedTimestep>,
+   update: &mut U,
+) -> Option<Time> {
+   match session {
+       InputSession::Live => {
+           events.drain(..).for_each(|event| input.apply(event));
+           timer.tick();
+       }
+       InputSession::Record(recorder) => {
+           for event in events.drain(..) {
+               recorder.push_event(event);
+               input.apply(event);
+           }
+           timer.tick();
+           recorder
+               .end_frame(timer.delta_time())
+               .expect("Failed to write input recording");
+       }
+       InputSession::Replay(replay) => {
+           events.clear();
+           timer.advance(replay.apply_next_frame(input)?);
+       }
+   }
+   let fixed_alpha = match fixed_timestep {
+       Some(fixed_timestep) => {
+           fixed_timestep.run(timer.delta_time(), |step| update(step, input));
+           fixed_timestep.alpha()
+       }
+       None => 0.0,
+   };
+   Some(Time {
+       frame_index: timer.frame_index(),
+       delta_time: timer.delta_time().as_secs_f32(),
+       total_time: timer.total_time().as_secs_f32(),
+       fixed_alpha,
+   })
+}
+
+/// Outcome of `VulkanContext::draw_frame`
+enum FrameStatus {
+   Presented,
+   /// Presented, but the swapchain has to be recreated for the next frame
+   Outdated,
+   /// The swapchain was out of date before an image was acquired, nothing was drawn
+   NotAcquired,
+}
+
+/// Per-frame state handed to the `render_loop` closure. The swapchain image `present_index` is
+/// already acquired and `command_buffer` is in the recording state. Once the closure returns,
+/// the command buffer is submitted and the image is presented.
+pub struct FrameContext<'a> {
+   pub frame_index: u64,
+   /// Seconds elapsed since the previous frame
+   pub delta_time: f32,
+   /// Seconds elapsed since the loop has started
+   pub total_time: f32,
+   /// Fraction of a fixed step elapsed after the latest fixed update, in [0, 1).
+   /// Always 0 if the loop runs without a fixed update
+   pub fixed_alpha: f32,
+   pub present_index: u32,
+   pub present_image: vk::Image,
+   pub present_image_view: vk::ImageView,
+   pub depth_image_view: vk::ImageView,
+   pub extent: vk::Extent2D,
+   /// Changes when the swapchain is recreated, e.g. after a resize. Framebuffers and other
+   /// resources made from swapchain images must be recreated then
+   pub swapchain_generation: u64,
+   pub command_buffer: vk::CommandBuffer,
+   pub input: &'a Input,
+   /// The previous frame has finished on the GPU, so resources it used can be changed
+   pub context: &'a VulkanContext,
+}
+
+/// Swapchain image and command buffer of the current frame, a world resource in
+/// `render_loop_with_schedule`. Systems recording commands should declare writing it,
+/// so they never run in parallel and the order of their commands is defined.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameTarget {
+   pub present_index: u32,
+   pub present_image: vk::Image,
+   pub present_image_view: vk::ImageView,
+   pub depth_image_view: vk::ImageView,
+   pub extent: vk::Extent2D,
+   pub swapchain_generation: u64,
+   pub command_buffer: vk::CommandBuffer,
+}
+
+impl FrameTarget {
+   /// Whether the swapchain was recreated since `generation`, which is updated to the frame's. Resources made
+   /// for the old swapchain can be destroyed right away then: systems record the frame after the previous one
+   /// has finished on the GPU, so nothing uses them anymore
+   pub fn swapchain_changed(&self, generation: &mut u64) -> bool {
+      let changed = *generation != self.swapchain_generation;
+      *generation = self.swapchain_generation;
+      changed
+   }
+
+   /// Target of `replay_headless`, of the extent but without images and a command buffer
+   pub fn headless(extent: vk::Extent2D) -> Self {
+      FrameTarget {
+         present_index: 0,
+         present_image: vk::Image::null(),
+         present_image_view: vk::ImageView::null(),
+         depth_image_view: vk::ImageView::null(),
+         extent,
+         swapchain_generation: 0,
+         command_buffer: vk::CommandBuffer::null(),
+      }
+   }
+}
+
+/// Replays a recording without a window or a GPU, e.g. in regression tests of what input drives. Runs the
+/// schedule like `render_loop_with_schedule` does, for each recorded frame until the recording ends, with the
+/// recorded input and delta times. `input` is the initial state, with the action map.
+///
+/// The `FrameTarget` is `FrameTarget::headless` with the recorded extent, so the schedule must not have systems
+/// recording commands. Its generation changes with the extent, like the swapchain's
+pub fn replay_headless(
+   replay: InputReplay,
+   mut input: Input,
+   world: &mut World,
+   schedule: &mut Schedule,
+   mut fixed_timestep: Option<FixedTimestep>,
+) {
+   let mut session = InputSession::Replay(replay);
+   let mut timer = FrameTimer::new();
+   let mut target = FrameTarget::headless(vk::Extent2D::default());
+   loop {
+       let mut update = |step, input: &Input| {
+           world.insert_resource(input.clone());
+           world.insert_resource(step);
+           schedule.run_fixed_update(world);
+       };
+       let fixed_timestep = fixed_timestep.as_mut();
+       let Some(time) = start_frame(&mut session, &mut input, &mut Vec::new(), &mut timer, fixed_timestep, &mut update)
+       else {
+           break;
+       };
+       let size = input.surface_size();
+       let extent = vk::Extent2D { width: size.x, height: size.y };
+       if extent != target.extent {
+           target.extent = extent;
+           target.swapchain_generation += 1;
+       }
+       world.insert_resource(time);
+       world.insert_resource(input.clone());
+       world.insert_resource(target);
+       schedule.run(world);
+       input.end_frame();
+   }
+}
+
 impl VulkanContext {
-   pub fn render_loop<F: Fn()>(&self, f: F) {
-       self.event_loop
-           .borrow_mut()
-           .run_return(|event, _, control_flow| {
+   pub fn render_loop<F: FnMut(&mut FrameContext)>(&mut self, f: F) {
+       self.run_loop(None, |_, _| {}, |_, _| {}, f);
+   }
+
+   /// Same as `render_loop`, but before each frame also calls `update` as many times
+   /// as whole fixed steps have elapsed. Input pressed/released transitions are visible to
+   /// all updates of the frame
+   pub fn render_loop_with_fixed_update<U, F>(&mut self, fixed_timestep: FixedTimestep, update: U, f: F)
+   where
+       U: FnMut(FixedStep, &Input),
+       F: FnMut(&mut FrameContext),
+   {
+       self.run_loop(Some(fixed_timestep), update, |_, _| {}, f);
+   }
+
+   /// Same as `render_loop`, but each frame runs the schedule on the world. Before the stages run,
+   /// the world gets `Time`, `Input` and `FrameTarget` resources of the frame. With a fixed timestep,
+   /// `FixedUpdate` stage also runs once per elapsed step, with the `FixedStep` resource of the step.
+   ///
+   /// `prepare` runs each frame before the swapchain image is acquired and the stages run, after the
+   /// previous frame has finished on the GPU, e.g. to upload assets added since then. The world has
+   /// the frame's `Time` already
+   pub fn render_loop_with_schedule<P: FnMut(&VulkanContext, &mut World)>(
+       &mut self,
+       world: &mut World,
+       schedule: &mut Schedule,
+       fixed_timestep: Option<FixedTimestep>,
+       mut prepare: P,
+   ) {
+       // Both closures of the loop need the world, but they never run at the same time
+       let state = RefCell::new((world, schedule));
+       self.run_loop(
+           fixed_timestep,
+           |step, input| {
+               let (world, schedule) = &mut *state.borrow_mut();
+               world.insert_resource(input.clone());
+               world.insert_resource(step);
+               schedule.run_fixed_update(world);
+           },
+           |context, time| {
+               let (world, _) = &mut *state.borrow_mut();
+               world.insert_resource(*time);
+               prepare(context, world);
+           },
+           |frame| {
+               let (world, schedule) = &mut *state.borrow_mut();
+               world.insert_resource(frame.input.clone());
+               world.insert_resource(FrameTarget {
+                   present_index: frame.present_index,
+                   present_image: frame.present_image,
+                   present_image_view: frame.present_image_view,
+                   depth_image_view: frame.depth_image_view,
+                   extent: frame.extent,
+                   swapchain_generation: frame.swapchain_generation,
+                   command_buffer: frame.command_buffer,
+               });
+               schedule.run(world);
+           },
+       );
+   }
+
+   fn run_loop<U, P, F>(&mut self, mut fixed_timestep: Option<FixedTimestep>, mut update: U, mut prepare: P, mut f: F)
+   where
+       U: FnMut(FixedStep, &Input),
+       P: FnMut(&VulkanContext, &Time),
+       F: FnMut(&mut FrameContext),
+   {
+       let mut timer = FrameTimer::new();
+       let mut event_loop = self.event_loop.take().expect("Render loop is already running");
+       let mut swapchain_outdated = false;
+       let mut exit = false;
+       // Window events since the previous frame started, and the latest extent they have told about
+       let mut events = Vec::new();
+       let mut extent = None;
+       // Frame started but not drawn because the swapchain image couldn't be acquired. It's drawn again with the
+       // same time, so its updates don't run twice
+       let mut pending_frame = None;
+       loop {
+           if swapchain_outdated {
+               swapchain_outdated = !self.recreate_swapchain();
+           }
+           // Events are pumped once per frame, so the context isn't borrowed by the event loop
+           // between frames and can recreate the swapchain
+           event_loop.run_return(|event, _, control_flow| {
                *control_flow = ControlFlow::Poll;
                match event {
                    Event::WindowEvent {
@@ -189,14 +530,366 @@ impl VulkanContext {
                                ..
                            },
                        ..
-                   } => *control_flow = ControlFlow::Exit,
-                   Event::MainEventsCleared => f(),
+                   } => {
+                       exit = true;
+                       *control_flow = ControlFlow::Exit;
+                   }
+                   Event::WindowEvent { event: WindowEvent::Resized(_), .. } => {
+                       swapchain_outdated = true;
+                   }
+                   Event::WindowEvent { event, .. } => events.extend(InputEvent::from_window_event(&event)),
+                   Event::DeviceEvent { event, .. } => events.extend(InputEvent::from_device_event(&event)),
+                   Event::MainEventsCleared => *control_flow = ControlFlow::Exit,
                    _ => (),
                }
            });
+           if exit {
+               break;
+           }
+           // Frames that can't be drawn don't start at all, their events go to the next one. So the recording
+           // and the replay have the same frames, and the updates run for the frames drawn only
+           if swapchain_outdated {
+               continue;
+           }
+
+           let mut input = self.input.borrow_mut();
+           let time = match pending_frame.take() {
+               Some(time) => time,
+               None => {
+                   if extent != Some(self.surface_resolution) {
+                       extent = Some(self.surface_resolution);
+                       let size = Vector2::new(self.surface_resolution.width, self.surface_resolution.height);
+                       events.insert(0, InputEvent::Resized(size));
+                   }
+                   let mut session = self.input_session.borrow_mut();
+                   let fixed_timestep = fixed_timestep.as_mut();
+                   match start_frame(&mut session, &mut input, &mut events, &mut timer, fixed_timestep, &mut update) {
+                       Some(time) => time,
+                       None => break,
+                   }
+               }
+           };
+           match self.draw_frame(&time, &input, &mut prepare, &mut f) {
+               FrameStatus::Presented => input.end_frame(),
+               FrameStatus::Outdated => {
+                   input.end_frame();
+                   swapchain_outdated = true;
+               }
+               FrameStatus::NotAcquired => {
+                   pending_frame = Some(time);
+                   swapchain_outdated = true;
+               }
+           }
+       }
+       self.event_loop = Some(event_loop);
+       if let InputSession::Record(recorder) = std::mem::take(&mut *self.input_session.borrow_mut()) {
+           recorder.finish().expect("Failed to write input recording");
+       }
+   }
+
+   fn draw_frame<P: FnMut(&VulkanContext, &Time), F: FnMut(&mut FrameContext)>(
+       &self,
+       time: &Time,
+       input: &Input,
+       prepare: &mut P,
+       f: &mut F,
+   ) -> FrameStatus {
+       unsafe {
+           // Waits for the previous frame here rather than when recording, so `prepare` can change
+           // its resources without holding a swapchain image
+           self.device
+               .wait_for_fences(&[self.draw_commands_reuse_fence], true, u64::MAX)
+               .expect("Wait for fence failed.");
+           prepare(self, time);
+           let present_index = match self.swapchain_loader.acquire_next_image(
+               self.swapchain,
+               u64::MAX,
+               self.present_complete_semaphore,
+               vk::Fence::null(),
+           ) {
+               Ok((present_index, _)) => present_index,
+               Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return FrameStatus::NotAcquired,
+               Err(err) => panic!("Failed to acquire swapchain image: {}", err),
+           };
+           let mut frame = FrameContext {
+               frame_index: time.frame_index,
+               delta_time: time.delta_time,
+               total_time: time.total_time,
+               fixed_alpha: time.fixed_alpha,
+               present_index,
+               present_image: self.present_images[present_index as usize],
+               present_image_view: self.present_image_views[present_index as usize],
+               depth_image_view: self.depth_image_view,
+               extent: self.surface_resolution,
+               swapchain_generation: self.swapchain_generation,
+               command_buffer: self.draw_command_buffer,
+               input,
+               context: self,
+           };
+           record_submit_commandbuffer(
+               &self.device,
+               self.draw_command_buffer,
+               self.draw_commands_reuse_fence,
+               self.present_queue,
+               &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT],
+               &[self.present_complete_semaphore],
+               &[self.rendering_complete_semaphore],
+               |_, _| f(&mut frame),
+           );
+           let wait_semaphores = [self.rendering_complete_semaphore];
+           let swapchains = [self.swapchain];
+           let image_indices = [present_index];
+           let present_info = vk::PresentInfoKHR::builder()
+               .wait_semaphores(&wait_semaphores)
+               .swapchains(&swapchains)
+               .image_indices(&image_indices);
+           match self.swapchain_loader.queue_present(self.present_queue, &present_info) {
+               Ok(false) => FrameStatus::Presented,
+               Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => FrameStatus::Outdated,
+               Err(err) => panic!("Failed to present swapchain image: {}", err),
+           }
+       }
+   }
+
+   /// Recreates the swapchain with the current window size. Returns false if the window is
+   /// minimized, then there's nothing to draw to
+   pub fn recreate_swapchain(&mut self) -> bool {
+       let window_size = self.window.inner_size();
+       if window_size.width == 0 || window_size.height == 0 {
+           return false;
+       }
+       unsafe {
+           self.device.device_wait_idle().unwrap();
+           let old_swapchain = self.swapchain;
+           self.destroy_swapchain_views();
+           self.create_swapchain(old_swapchain);
+           self.swapchain_loader.destroy_swapchain(old_swapchain, None);
+       }
+       self.swapchain_generation += 1;
+       true
+   }
+
+   /// Creates the swapchain, its image views and the depth image of the matching size
+   unsafe fn create_swapchain(&mut self, old_swapchain: vk::SwapchainKHR) {
+       let surface_capabilities = self
+           .surface_loader
+           .get_physical_device_surface_capabilities(self.pdevice, self.surface)
+           .unwrap();
+       let mut desired_image_count = surface_capabilities.min_image_count + 1;
+       if surface_capabilities.max_image_count > 0
+           && desired_image_count > surface_capabilities.max_image_count
+       {
+           desired_image_count = surface_capabilities.max_image_count;
+       }
+       let surface_resolution = match surface_capabilities.current_extent.width {
+           u32::MAX => {
+               let window_size = self.window.inner_size();
+               vk::Extent2D {
+                   width: window_size.width.clamp(
+                       surface_capabilities.min_image_extent.width,
+                       surface_capabilities.max_image_extent.width,
+                   ),
+                   height: window_size.height.clamp(
+                       surface_capabilities.min_image_extent.height,
+                       surface_capabilities.max_image_extent.height,
+                   ),
+               }
+           }
+           _ => surface_capabilities.current_extent,
+       };
+       let pre_transform = if surface_capabilities
+           .supported_transforms
+           .contains(vk::SurfaceTransformFlagsKHR::IDENTITY)
+       {
+           vk::SurfaceTransformFlagsKHR::IDENTITY
+       } else {
+           surface_capabilities.current_transform
+       };
+       let present_modes = self
+           .surface_loader
+           .get_physical_device_surface_present_modes(self.pdevice, self.surface)
+           .unwrap();
+       let present_mode = present_modes
+           .iter()
+           .cloned()
+           .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
+           .unwrap_or(vk::PresentModeKHR::FIFO);
+
+       let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
+           .surface(self.surface)
+           .min_image_count(desired_image_count)
+           .image_color_space(self.surface_format.color_space)
+           .image_format(self.surface_format.format)
+           .image_extent(surface_resolution)
+           .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+           .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+           .pre_transform(pre_transform)
+           .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+           .present_mode(present_mode)
+           .clipped(true)
+           .image_array_layers(1)
+           .old_swapchain(old_swapchain);
+
+       let swapchain = self
+           .swapchain_loader
+           .create_swapchain(&swapchain_create_info, None)
+           .unwrap();
+
+       let present_images = self.swapchain_loader.get_swapchain_images(swapchain).unwrap();
+       let present_image_views: Vec<vk::ImageView> = present_images
+           .iter()
+           .map(|&image| {
+               let create_view_info = vk::ImageViewCreateInfo::builder()
+                   .view_type(vk::ImageViewType::TYPE_2D)
+                   .format(self.surface_format.format)
+                   .components(vk::ComponentMapping {
+                       r: vk::ComponentSwizzle::R,
+                       g: vk::ComponentSwizzle::G,
+                       b: vk::ComponentSwizzle::B,
+                       a: vk::ComponentSwizzle::A,
+                   })
+                   .subresource_range(vk::ImageSubresourceRange {
+                       aspect_mask: vk::ImageAspectFlags::COLOR,
+                       base_mip_level: 0,
+                       level_count: 1,
+                       base_array_layer: 0,
+                       layer_count: 1,
+                   })
+                   .image(image);
+               self.device.create_image_view(&create_view_info, None).unwrap()
+           })
+           .collect();
+       let depth_image_create_info = vk::ImageCreateInfo::builder()
+           .image_type(vk::ImageType::TYPE_2D)
+           .format(self.depth_format)
+           .extent(surface_resolution.into())
+           .mip_levels(1)
+           .array_layers(1)
+           .samples(vk::SampleCountFlags::TYPE_1)
+           .tiling(vk::ImageTiling::OPTIMAL)
+           .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+           .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+       let depth_image = self.device.create_image(&depth_image_create_info, None).unwrap();
+       let depth_image_memory_req = self.device.get_image_memory_requirements(depth_image);
+       let depth_image_memory_index = find_memorytype_index(
+           &depth_image_memory_req,
+           &self.device_memory_properties,
+           vk::MemoryPropertyFlags::DEVICE_LOCAL,
+       )
+       .expect("Unable to find suitable memory index for depth image.");
+
+       let depth_image_allocate_info = vk::MemoryAllocateInfo::builder()
+           .allocation_size(depth_image_memory_req.size)
+           .memory_type_index(depth_image_memory_index);
+
+       let depth_image_memory = self
+           .device
+           .allocate_memory(&depth_image_allocate_info, None)
+           .unwrap();
+
+       self.device
+           .bind_image_memory(depth_image, depth_image_memory, 0)
+           .expect("Unable to bind depth image memory");
+
+       record_submit_commandbuffer(
+           &self.device,
+           self.setup_command_buffer,
+           self.setup_commands_reuse_fence,
+           self.present_queue,
+           &[],
+           &[],
+           &[],
+           |device, setup_command_buffer| {
+               let layout_transition_barriers = vk::ImageMemoryBarrier::builder()
+                   .image(depth_image)
+                   .dst_access_mask(
+                       vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                           | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                   )
+                   .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                   .old_layout(vk::ImageLayout::UNDEFINED)
+                   .subresource_range(
+                       vk::ImageSubresourceRange::builder()
+                           .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                           .layer_count(1)
+                           .level_count(1)
+                           .build(),
+                   )
+                   .build();
+
+               device.cmd_pipeline_barrier(
+                   setup_command_buffer,
+                   vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                   vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                   vk::DependencyFlags::empty(),
+                   &[],
+                   &[],
+                   &[layout_transition_barriers],
+               );
+           },
+       );
+
+       let depth_image_view_info = vk::ImageViewCreateInfo::builder()
+           .subresource_range(
+               vk::ImageSubresourceRange::builder()
+                   .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                   .level_count(1)
+                   .layer_count(1)
+                   .build(),
+           )
+           .image(depth_image)
+           .format(depth_image_create_info.format)
+           .view_type(vk::ImageViewType::TYPE_2D);
+
+       let depth_image_view = self
+           .device
+           .create_image_view(&depth_image_view_info, None)
+           .unwrap();
+
+       self.surface_resolution = surface_resolution;
+       self.swapchain = swapchain;
+       self.present_images = present_images;
+       self.present_image_views = present_image_views;
+       self.depth_image = depth_image;
+       self.depth_image_view = depth_image_view;
+       self.depth_image_memory = depth_image_memory;
+   }
+
+   /// Destroys everything made by `create_swapchain` except the swapchain itself,
+   /// which may be still needed to create the new one
+   unsafe fn destroy_swapchain_views(&mut self) {
+       // The setup commands transitioning the depth image may be still running
+       self.device
+           .wait_for_fences(&[self.setup_commands_reuse_fence], true, u64::MAX)
+           .unwrap();
+       self.device.free_memory(self.depth_image_memory, None);
+       self.device.destroy_image_view(self.depth_image_view, None);
+       self.device.destroy_image(self.depth_image, None);
+       for &image_view in self.present_image_views.iter() {
+           self.device.destroy_image_view(image_view, None);
+       }
+   }
+
+   /// Highest sample count up to `requested` that both color and depth attachments support,
+   /// `TYPE_1` for none
+   pub fn sample_count(&self, requested: u32) -> vk::SampleCountFlags {
+       let supported = self.device_limits.framebuffer_color_sample_counts
+           & self.device_limits.framebuffer_depth_sample_counts;
+       (0..=6)
+           .rev()
+           .map(|bit| vk::SampleCountFlags::from_raw(1 << bit))
+           .find(|&count| count.as_raw() <= requested && supported.contains(count))
+           .unwrap_or(vk::SampleCountFlags::TYPE_1)
    }
 
    pub fn new(window_width: u32, window_height: u32) -> Self {
+       Self::with_display_output(window_width, window_height, DisplayOutput::Sdr)
+   }
+
+   /// Creates the context with an HDR swapchain if the surface supports the output, see
+   /// `display_output` for the one selected
+   pub fn with_display_output(window_width: u32, window_height: u32, display_output: DisplayOutput) -> Self {
        unsafe {
            let event_loop = EventLoop::new();
            let window = WindowBuilder::new()
@@ -208,11 +901,9 @@ impl VulkanContext {
                .build(&event_loop)
                .unwrap();
            let entry = Entry::linked();
-           let app_name = CStr::from_bytes_with_nul_unchecked(b"VulkanTriangle\0");
+           let app_name = c"VulkanTriangle";
 
-           let layer_names = [CStr::from_bytes_with_nul_unchecked(
-               b"VK_LAYER_KHRONOS_validation\0",
-           )];
+           let layer_names = [c"VK_LAYER_KHRONOS_validation"];
            let layers_names_raw: Vec<*const c_char> = layer_names
                .iter()
                .map(|raw_name| raw_name.as_ptr())
@@ -222,6 +913,15 @@ impl VulkanContext {
                .unwrap()
                .to_vec();
            extension_names.push(DebugUtils::name().as_ptr());
+           // HDR color spaces of the surface are only reported with it
+           let colorspace_supported = entry
+               .enumerate_instance_extension_properties(None)
+               .unwrap_or_default()
+               .iter()
+               .any(|extension| CStr::from_ptr(extension.extension_name.as_ptr()) == ExtSwapchainColorspaceFn::name());
+           if display_output != DisplayOutput::Sdr && colorspace_supported {
+               extension_names.push(ExtSwapchainColorspaceFn::name().as_ptr());
+           }
 
            #[cfg(any(target_os = "macos", target_os = "ios"))]
            {
@@ -230,12 +930,19 @@ impl VulkanContext {
                extension_names.push(KhrGetPhysicalDeviceProperties2Fn::name().as_ptr());
            }
 
+           // 1.2 where available
+           let api_version = entry
+               .try_enumerate_instance_version()
+               .ok()
+               .flatten()
+               .unwrap_or(vk::API_VERSION_1_0)
+               .min(vk::API_VERSION_1_2);
            let appinfo = vk::ApplicationInfo::builder()
                .application_name(app_name)
                .application_version(0)
                .engine_name(app_name)
                .engine_version(0)
-               .api_version(vk::make_api_version(0, 1, 0, 0));
+               .api_version(api_version);
 
            let create_flags = if cfg!(any(target_os = "macos", target_os = "ios")) {
                vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
@@ -306,8 +1013,13 @@ impl VulkanContext {
                #[cfg(any(target_os = "macos", target_os = "ios"))]
                KhrPortabilitySubsetFn::name().as_ptr(),
            ];
+           let supported_features = instance.get_physical_device_features(pdevice);
+           let image_cube_array = supported_features.image_cube_array == vk::TRUE;
+           let sample_rate_shading = supported_features.sample_rate_shading == vk::TRUE;
            let features = vk::PhysicalDeviceFeatures {
                shader_clip_distance: 1,
+               image_cube_array: image_cube_array as vk::Bool32,
+               sample_rate_shading: sample_rate_shading as vk::Bool32,
                ..Default::default()
            };
            let priorities = [1.0];
@@ -325,64 +1037,17 @@ impl VulkanContext {
                .create_device(pdevice, &device_create_info, None)
                .unwrap();
 
-           let present_queue = device.get_device_queue(queue_family_index as u32, 0);
+           let present_queue = device.get_device_queue(queue_family_index, 0);
 
-           let surface_format = surface_loader
-               .get_physical_device_surface_formats(pdevice, surface)
-               .unwrap()[0];
+           let (surface_format, display_output) = choose_surface_format(
+               &surface_loader
+                   .get_physical_device_surface_formats(pdevice, surface)
+                   .unwrap(),
+               display_output,
+           );
 
-           let surface_capabilities = surface_loader
-               .get_physical_device_surface_capabilities(pdevice, surface)
-               .unwrap();
-           let mut desired_image_count = surface_capabilities.min_image_count + 1;
-           if surface_capabilities.max_image_count > 0
-               && desired_image_count > surface_capabilities.max_image_count
-           {
-               desired_image_count = surface_capabilities.max_image_count;
-           }
-           let surface_resolution = match surface_capabilities.current_extent.width {
-               std::u32::MAX => vk::Extent2D {
-                   width: window_width,
-                   height: window_height,
-               },
-               _ => surface_capabilities.current_extent,
-           };
-           let pre_transform = if surface_capabilities
-               .supported_transforms
-               .contains(vk::SurfaceTransformFlagsKHR::IDENTITY)
-           {
-               vk::SurfaceTransformFlagsKHR::IDENTITY
-           } else {
-               surface_capabilities.current_transform
-           };
-           let present_modes = surface_loader
-               .get_physical_device_surface_present_modes(pdevice, surface)
-               .unwrap();
-           let present_mode = present_modes
-               .iter()
-               .cloned()
-               .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
-               .unwrap_or(vk::PresentModeKHR::FIFO);
            let swapchain_loader = Swapchain::new(&instance, &device);
 
-           let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
-               .surface(surface)
-               .min_image_count(desired_image_count)
-               .image_color_space(surface_format.color_space)
-               .image_format(surface_format.format)
-               .image_extent(surface_resolution)
-               .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-               .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-               .pre_transform(pre_transform)
-               .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-               .present_mode(present_mode)
-               .clipped(true)
-               .image_array_layers(1);
-
-           let swapchain = swapchain_loader
-               .create_swapchain(&swapchain_create_info, None)
-               .unwrap();
-
            let pool_create_info = vk::CommandPoolCreateInfo::builder()
                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
                .queue_family_index(queue_family_index);
@@ -400,62 +1065,8 @@ impl VulkanContext {
            let setup_command_buffer = command_buffers[0];
            let draw_command_buffer = command_buffers[1];
 
-           let present_images = swapchain_loader.get_swapchain_images(swapchain).unwrap();
-           let present_image_views: Vec<vk::ImageView> = present_images
-               .iter()
-               .map(|&image| {
-                   let create_view_info = vk::ImageViewCreateInfo::builder()
-                       .view_type(vk::ImageViewType::TYPE_2D)
-                       .format(surface_format.format)
-                       .components(vk::ComponentMapping {
-                           r: vk::ComponentSwizzle::R,
-                           g: vk::ComponentSwizzle::G,
-                           b: vk::ComponentSwizzle::B,
-                           a: vk::ComponentSwizzle::A,
-                       })
-                       .subresource_range(vk::ImageSubresourceRange {
-                           aspect_mask: vk::ImageAspectFlags::COLOR,
-                           base_mip_level: 0,
-                           level_count: 1,
-                           base_array_layer: 0,
-                           layer_count: 1,
-                       })
-                       .image(image);
-                   device.create_image_view(&create_view_info, None).unwrap()
-               })
-               .collect();
            let device_memory_properties = instance.get_physical_device_memory_properties(pdevice);
-           let depth_image_create_info = vk::ImageCreateInfo::builder()
-               .image_type(vk::ImageType::TYPE_2D)
-               .format(vk::Format::D16_UNORM)
-               .extent(surface_resolution.into())
-               .mip_levels(1)
-               .array_layers(1)
-               .samples(vk::SampleCountFlags::TYPE_1)
-               .tiling(vk::ImageTiling::OPTIMAL)
-               .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
-               .sharing_mode(vk::SharingMode::EXCLUSIVE);
-
-           let depth_image = device.create_image(&depth_image_create_info, None).unwrap();
-           let depth_image_memory_req = device.get_image_memory_requirements(depth_image);
-           let depth_image_memory_index = find_memorytype_index(
-               &depth_image_memory_req,
-               &device_memory_properties,
-               vk::MemoryPropertyFlags::DEVICE_LOCAL,
-           )
-           .expect("Unable to find suitable memory index for depth image.");
-
-           let depth_image_allocate_info = vk::MemoryAllocateInfo::builder()
-               .allocation_size(depth_image_memory_req.size)
-               .memory_type_index(depth_image_memory_index);
-
-           let depth_image_memory = device
-               .allocate_memory(&depth_image_allocate_info, None)
-               .unwrap();
-
-           device
-               .bind_image_memory(depth_image, depth_image_memory, 0)
-               .expect("Unable to bind depth image memory");
+           let device_limits = instance.get_physical_device_properties(pdevice).limits;
 
            let fence_create_info =
                vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
@@ -467,60 +1078,6 @@ impl VulkanContext {
                .create_fence(&fence_create_info, None)
                .expect("Create fence failed.");
 
-           record_submit_commandbuffer(
-               &device,
-               setup_command_buffer,
-               setup_commands_reuse_fence,
-               present_queue,
-               &[],
-               &[],
-               &[],
-               |device, setup_command_buffer| {
-                   let layout_transition_barriers = vk::ImageMemoryBarrier::builder()
-                       .image(depth_image)
-                       .dst_access_mask(
-                           vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
-                               | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
-                       )
-                       .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                       .old_layout(vk::ImageLayout::UNDEFINED)
-                       .subresource_range(
-                           vk::ImageSubresourceRange::builder()
-                               .aspect_mask(vk::ImageAspectFlags::DEPTH)
-                               .layer_count(1)
-                               .level_count(1)
-                               .build(),
-                       )
-                       .build();
-
-                   device.cmd_pipeline_barrier(
-                       setup_command_buffer,
-                       vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                       vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
-                       vk::DependencyFlags::empty(),
-                       &[],
-                       &[],
-                       &[layout_transition_barriers],
-                   );
-               },
-           );
-
-           let depth_image_view_info = vk::ImageViewCreateInfo::builder()
-               .subresource_range(
-                   vk::ImageSubresourceRange::builder()
-                       .aspect_mask(vk::ImageAspectFlags::DEPTH)
-                       .level_count(1)
-                       .layer_count(1)
-                       .build(),
-               )
-               .image(depth_image)
-               .format(depth_image_create_info.format)
-               .view_type(vk::ImageViewType::TYPE_2D);
-
-           let depth_image_view = device
-               .create_image_view(&depth_image_view_info, None)
-               .unwrap();
-
            let semaphore_create_info = vk::SemaphoreCreateInfo::default();
 
            let present_complete_semaphore = device
@@ -530,28 +1087,36 @@ impl VulkanContext {
                .create_semaphore(&semaphore_create_info, None)
                .unwrap();
 
-           VulkanContext {
-               event_loop: RefCell::new(event_loop),
+           let mut context = VulkanContext {
+               event_loop: Some(event_loop),
+               input: RefCell::new(Input::new()),
+               input_session: RefCell::new(InputSession::Live),
                entry,
                instance,
                device,
                queue_family_index,
                pdevice,
                device_memory_properties,
+               device_limits,
+               image_cube_array,
+               sample_rate_shading,
                window,
                surface_loader,
                surface_format,
+               display_output,
                present_queue,
-               surface_resolution,
+               surface_resolution: vk::Extent2D::default(),
                swapchain_loader,
-               swapchain,
-               present_images,
-               present_image_views,
+               swapchain: vk::SwapchainKHR::null(),
+               swapchain_generation: 0,
+               present_images: Vec::new(),
+               present_image_views: Vec::new(),
                pool,
                draw_command_buffer,
                setup_command_buffer,
-               depth_image,
-               depth_image_view,
+               depth_format: vk::Format::UNDEFINED,
+               depth_image: vk::Image::null(),
+               depth_image_view: vk::ImageView::null(),
                present_complete_semaphore,
                rendering_complete_semaphore,
                draw_commands_reuse_fence,
@@ -559,8 +1124,16 @@ impl VulkanContext {
                surface,
                debug_call_back,
                debug_utils_loader,
-               depth_image_memory,
-           }
+               depth_image_memory: vk::DeviceMemory::null(),
+           };
+           context.depth_format = find_depth_format(
+               &context,
+               &[vk::Format::D32_SFLOAT, vk::Format::D16_UNORM],
+               vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+           )
+           .expect("No depth format for the depth image");
+           context.create_swapchain(vk::SwapchainKHR::null());
+           context
        }
    }
 }
@@ -577,12 +1150,7 @@ impl Drop for VulkanContext {
                .destroy_fence(self.draw_commands_reuse_fence, None);
            self.device
                .destroy_fence(self.setup_commands_reuse_fence, None);
-           self.device.free_memory(self.depth_image_memory, None);
-           self.device.destroy_image_view(self.depth_image_view, None);
-           self.device.destroy_image(self.depth_image, None);
-           for &image_view in self.present_image_views.iter() {
-               self.device.destroy_image_view(image_view, None);
-           }
+           self.destroy_swapchain_views();
            self.device.destroy_command_pool(self.pool, None);
            self.swapchain_loader
                .destroy_swapchain(self.swapchain, None);
@@ -593,4 +1161,116 @@ impl Drop for VulkanContext {
            self.instance.destroy_instance(None);
        }
    }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+   use std::time::Duration;
+
+   use cgmath::{vec2, InnerSpace, Rad, Vector3};
+   use winit::event::MouseButton;
+
+   use super::*;
+   use crate::ecs::{Entity, Stage, System};
+   use crate::platform::input::{ActionMap, InputRecorder};
+   use crate::scene::{
+      camera_aspect_system, fly_controller_system, transform_propagation_system, Camera, FlyController,
+      GlobalTransform, Transform,
+   };
+
+   const FRAME: Duration = Duration::from_millis(16);
+
+   /// Whether `move_forward` was held in each fixed step
+   struct ForwardSteps(Vec<bool>);
+
+   /// Holds W for 11 frames at 800x600, then looks around in a window resized to 400x600
+   fn recording() -> Vec<u8> {
+      let mut recorder = InputRecorder::new(Vec::new()).unwrap();
+      recorder.push_event(InputEvent::Resized(vec2(800, 600)));
+      recorder.push_event(InputEvent::Key(VirtualKeyCode::W, ElementState::Pressed));
+      for _ in 0..10 {
+         recorder.end_frame(FRAME).unwrap();
+      }
+      recorder.push_event(InputEvent::Resized(vec2(400, 600)));
+      recorder.push_event(InputEvent::MouseButton(MouseButton::Right, ElementState::Pressed));
+      recorder.push_event(InputEvent::MouseMotion(vec2(100.0, 0.0)));
+      recorder.end_frame(FRAME).unwrap();
+      recorder.push_event(InputEvent::Key(VirtualKeyCode::W, ElementState::Released));
+      recorder.push_event(InputEvent::MouseButton(MouseButton::Right, ElementState::Released));
+      recorder.end_frame(FRAME).unwrap();
+      recorder.finish().unwrap()
+   }
+
+   /// Replays the recording into a world with a fly camera, returns the world and the camera
+   fn replay(recording: &[u8]) -> (World, Entity) {
+      let action_map = ActionMap::from_toml_str(
+         r#"
+         [actions]
+         move_forward = "W"
+         look = "Mouse:Right"
+         "#,
+      )
+      .unwrap();
+      let mut world = World::new();
+      world.insert_resource(ForwardSteps(Vec::new()));
+      let camera = world.spawn((Transform::IDENTITY, FlyController::default(), Camera::default()));
+      let mut schedule = Schedule::new();
+      schedule
+         .add_system(Stage::Input, camera_aspect_system())
+         .add_system(
+            Stage::FixedUpdate,
+            System::parallel("record_forward_steps", |world| {
+               let held = world.resource::<Input>().action_held("move_forward");
+               world.resource_mut::<ForwardSteps>().0.push(held);
+            })
+            .reads_resource::<Input>()
+            .writes_resource::<ForwardSteps>(),
+         )
+         .add_system(Stage::Update, fly_controller_system())
+         .add_system(Stage::TransformPropagation, transform_propagation_system());
+      let replay = InputReplay::read(recording).unwrap();
+      let input = Input::new().with_action_map(action_map);
+      let fixed_timestep = FixedTimestep::new(Duration::from_millis(10));
+      replay_headless(replay, input, &mut world, &mut schedule, Some(fixed_timestep));
+      (world, camera)
+   }
+
+   #[test]
+   fn headless_replay_reproduces_the_session() {
+      let recording = recording();
+      let (world, camera) = replay(&recording);
+
+      let time = world.resource::<Time>();
+      assert_eq!(time.frame_index, 11);
+      assert_eq!(time.total_time, (FRAME * 12).as_secs_f32());
+      // 192 ms make 19 steps of 10 ms, W is released in the frame after the 17th
+      let mut expected_steps = vec![true; 17];
+      expected_steps.extend([false; 2]);
+      assert_eq!(world.resource::<ForwardSteps>().0, expected_steps);
+
+      let target = world.resource::<FrameTarget>();
+      assert_eq!(target.extent, vk::Extent2D { width: 400, height: 600 });
+      assert_eq!(target.swapchain_generation, 2);
+      assert_eq!(world.get::<Camera>(camera).unwrap().aspect_ratio, 400.0 / 600.0);
+
+      // 10 frames forward, then one more after turning 0.3 radians to the right
+      let controller = world.get::<FlyController>(camera).unwrap();
+      assert_eq!(controller.yaw, Rad(-100.0 * controller.sensitivity));
+      let step = controller.speed * FRAME.as_secs_f32();
+      let yaw = controller.yaw.0;
+      let expected = Vector3::new(0.0, 0.0, -10.0 * step) + Vector3::new(-yaw.sin(), 0.0, -yaw.cos()) * step;
+      let translation = world.get::<Transform>(camera).unwrap().translation;
+      assert!((translation - expected).magnitude() < 1e-5, "{:?} != {:?}", translation, expected);
+   }
+
+   #[test]
+   fn replays_are_identical() {
+      let recording = recording();
+      let (first, first_camera) = replay(&recording);
+      let (second, second_camera) = replay(&recording);
+      assert_eq!(*first.get::<Transform>(first_camera).unwrap(), *second.get::<Transform>(second_camera).unwrap());
+      assert_eq!(
+         first.get::<GlobalTransform>(first_camera).unwrap().matrix(),
+         second.get::<GlobalTransform>(second_camera).unwrap().matrix()
+      );
+   }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use ash::vk;
+
+use super::VulkanDrop;
+
+/// Allocates descriptor sets of its layouts, creating pools as they fill up. Each pool serves
+/// one layout and is sized for `sets_per_pool` of its sets.
+///
+/// Keep long lived sets and sets rewritten every frame in separate allocators, `reset` frees
+/// all sets at once.
+pub struct DescriptorAllocator {
+   sets_per_pool: u32,
+   layouts: HashMap<vk::DescriptorSetLayout, LayoutPools>,
+}
+
+struct LayoutPools {
+   /// Descriptors of `sets_per_pool` sets
+   pool_sizes: Vec<vk::DescriptorPoolSize>,
+   pools: Vec<vk::DescriptorPool>,
+   /// Pool sets are allocated from, the ones before it are full
+   current: usize,
+}
+
+impl DescriptorAllocator {
+   pub fn new(sets_per_pool: u32) -> Self {
+      assert!(sets_per_pool > 0, "Pools must hold at least one set");
+      DescriptorAllocator { sets_per_pool, layouts: HashMap::new() }
+   }
+
+   /// Creates a layout to allocate sets of, owned by the allocator
+   pub fn create_layout(
+      &mut self,
+      device: &ash::Device,
+      bindings: &[vk::DescriptorSetLayoutBinding],
+   ) -> vk::DescriptorSetLayout {
+      let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+      let layout = unsafe { device.create_descriptor_set_layout(&layout_info, None).unwrap() };
+      let mut pool_sizes: Vec<vk::DescriptorPoolSize> = Vec::new();
+      for binding in bindings {
+         let count = binding.descriptor_count * self.sets_per_pool;
+         match pool_sizes.iter_mut().find(|size| size.ty == binding.descriptor_type) {
+            Some(size) => size.descriptor_count += count,
+            None => pool_sizes.push(vk::DescriptorPoolSize { ty: binding.descriptor_type, descriptor_count: count }),
+         }
+      }
+      self.layouts.insert(layout, LayoutPools { pool_sizes, pools: Vec::new(), current: 0 });
+      layout
+   }
+
+   /// Allocates a set from the current pool of the layout, moving on to a new pool when it's full
+   pub fn allocate(&mut self, device: &ash::Device, layout: vk::DescriptorSetLayout) -> vk::DescriptorSet {
+      let sets_per_pool = self.sets_per_pool;
+      let pools = self
+         .layouts
+         .get_mut(&layout)
+         .expect("Descriptor set layout wasn't created by this allocator");
+      loop {
+         let new_pool = pools.current == pools.pools.len();
+         if new_pool {
+            let pool_info = vk::DescriptorPoolCreateInfo::builder()
+               .pool_sizes(&pools.pool_sizes)
+               .max_sets(sets_per_pool);
+            pools.pools.push(unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() });
+         }
+         let set_layouts = [layout];
+         let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pools.pools[pools.current])
+            .set_layouts(&set_layouts);
+         match unsafe { device.allocate_descriptor_sets(&allocate_info) } {
+            Ok(sets) => return sets[0],
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) if !new_pool => {
+               pools.current += 1;
+            }
+            Err(err) => panic!("Descriptor set allocation failed: {}", err),
+         }
+      }
+   }
+
+   /// Frees all sets, keeping the pools for the next allocations. The GPU must not be using the
+   /// sets anymore
+   pub fn reset(&mut self, device: &ash::Device) {
+      for pools in self.layouts.values_mut() {
+         for &pool in &pools.pools {
+            unsafe {
+               device
+                  .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())
+                  .unwrap()
+            };
+         }
+         pools.current = 0;
+      }
+   }
+}
+
+impl VulkanDrop for DescriptorAllocator {
+   fn drop(self, device: &ash::Device) {
+      for (layout, pools) in self.layouts {
+         unsafe {
+            for pool in pools.pools {
+               device.destroy_descriptor_pool(pool, None);
+            }
+            device.destroy_descriptor_set_layout(layout, None);
+         }
+      }
+   }
+}
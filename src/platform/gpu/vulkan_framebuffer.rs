@@ -0,0 +1,139 @@
+use ash::vk;
+
+use super::vulkan_context::{FrameTarget, VulkanContext};
+use super::vulkan_texture::VulkanTexture;
+use super::VulkanDrop;
+
+/// Framebuffers of a render pass for each swapchain image, with the frame's present image and
+/// depth image as attachments, the present image alone, or multisampled color and depth images
+/// resolved into the present image. They are made on first use and remade after the swapchain
+/// is recreated.
+pub struct SwapchainFramebuffers {
+   render_pass: vk::RenderPass,
+   /// Whether the depth image is attached after the present image
+   depth: bool,
+   multisampled: Option<MultisampledTargets>,
+   framebuffers: Vec<vk::Framebuffer>,
+   swapchain_generation: u64,
+}
+
+/// Color and depth images shared by the framebuffers of all swapchain images
+struct MultisampledTargets {
+   memory_properties: vk::PhysicalDeviceMemoryProperties,
+   color_format: vk::Format,
+   depth_format: vk::Format,
+   samples: vk::SampleCountFlags,
+   /// Color then depth, of the swapchain generation
+   images: Vec<VulkanTexture>,
+}
+
+impl MultisampledTargets {
+   fn views(&mut self, device: &ash::Device, extent: vk::Extent2D) -> [vk::ImageView; 2] {
+      if self.images.is_empty() {
+         let targets = [
+            (self.color_format, vk::ImageAspectFlags::COLOR),
+            (self.depth_format, vk::ImageAspectFlags::DEPTH),
+         ];
+         self.images = targets
+            .iter()
+            .map(|&(format, aspect_mask)| {
+               VulkanTexture::new_multisampled_target(
+                  device,
+                  &self.memory_properties,
+                  extent,
+                  format,
+                  self.samples,
+                  aspect_mask,
+               )
+            })
+            .collect();
+      }
+      [self.images[0].image_view(), self.images[1].image_view()]
+   }
+
+   fn destroy_images(&mut self, device: &ash::Device) {
+      for image in self.images.drain(..) {
+         image.drop(device);
+      }
+   }
+}
+
+impl SwapchainFramebuffers {
+   pub fn new(render_pass: vk::RenderPass) -> Self {
+      SwapchainFramebuffers {
+         render_pass,
+         depth: true,
+         multisampled: None,
+         framebuffers: Vec::new(),
+         swapchain_generation: 0,
+      }
+   }
+
+   /// Framebuffers attaching only the present image, for passes drawing over the whole screen
+   pub fn color_only(render_pass: vk::RenderPass) -> Self {
+      SwapchainFramebuffers { depth: false, ..Self::new(render_pass) }
+   }
+
+   /// Framebuffers attaching a color image of the swapchain format and a depth image of
+   /// `depth_format`, both of `samples`, then the present image they resolve into
+   pub fn multisampled(
+      context: &VulkanContext,
+      render_pass: vk::RenderPass,
+      depth_format: vk::Format,
+      samples: vk::SampleCountFlags,
+   ) -> Self {
+      let multisampled = MultisampledTargets {
+         memory_properties: context.device_memory_properties,
+         color_format: context.surface_format.format,
+         depth_format,
+         samples,
+         images: Vec::new(),
+      };
+      SwapchainFramebuffers { multisampled: Some(multisampled), ..Self::new(render_pass) }
+   }
+
+   pub fn get(&mut self, device: &ash::Device, frame: &FrameTarget) -> vk::Framebuffer {
+      if frame.swapchain_changed(&mut self.swapchain_generation) {
+         self.destroy_framebuffers(device);
+      }
+      let index = frame.present_index as usize;
+      if index >= self.framebuffers.len() {
+         self.framebuffers.resize(index + 1, vk::Framebuffer::null());
+      }
+      if self.framebuffers[index] == vk::Framebuffer::null() {
+         let attachments = match &mut self.multisampled {
+            Some(multisampled) => {
+               let [color_view, depth_view] = multisampled.views(device, frame.extent);
+               vec![color_view, depth_view, frame.present_image_view]
+            }
+            None if self.depth => vec![frame.present_image_view, frame.depth_image_view],
+            None => vec![frame.present_image_view],
+         };
+         let create_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(self.render_pass)
+            .attachments(&attachments)
+            .width(frame.extent.width)
+            .height(frame.extent.height)
+            .layers(1);
+         self.framebuffers[index] = unsafe { device.create_framebuffer(&create_info, None).unwrap() };
+      }
+      self.framebuffers[index]
+   }
+
+   fn destroy_framebuffers(&mut self, device: &ash::Device) {
+      for framebuffer in self.framebuffers.drain(..) {
+         if framebuffer != vk::Framebuffer::null() {
+            unsafe { device.destroy_framebuffer(framebuffer, None) };
+         }
+      }
+      if let Some(multisampled) = &mut self.multisampled {
+         multisampled.destroy_images(device);
+      }
+   }
+}
+
+impl VulkanDrop for SwapchainFramebuffers {
+   fn drop(mut self, device: &ash::Device) {
+      self.destroy_framebuffers(device);
+   }
+}
@@ -0,0 +1,309 @@
+use ash::vk;
+use cgmath::{Point2, Point3, Vector2, Vector3, Vector4};
+
+use super::vulkan_buffer::VulkanBuffer;
+use super::vulkan_context::VulkanContext;
+use super::vulkan_pipeline::VulkanPipelineLayout;
+use super::VulkanDrop;
+
+/// Attribute of a vertex type with binding 0, see `VertexLayout::attribute_descriptions`
+pub type VertexAttribute = vk::VertexInputAttributeDescription;
+
+/// Type of a vertex attribute, maps it to the `vk::Format` of the attribute.
+pub trait VertexFormat {
+   const FORMAT: vk::Format;
+}
+
+macro_rules! impl_vertex_format {
+   ($($ty:ty => $format:ident),* $(,)?) => {
+      $(impl VertexFormat for $ty {
+         const FORMAT: vk::Format = vk::Format::$format;
+      })*
+   };
+}
+
+impl_vertex_format!(
+   f32 => R32_SFLOAT,
+   [f32; 2] => R32G32_SFLOAT,
+   [f32; 3] => R32G32B32_SFLOAT,
+   [f32; 4] => R32G32B32A32_SFLOAT,
+   Vector2<f32> => R32G32_SFLOAT,
+   Vector3<f32> => R32G32B32_SFLOAT,
+   Vector4<f32> => R32G32B32A32_SFLOAT,
+   Point2<f32> => R32G32_SFLOAT,
+   Point3<f32> => R32G32B32_SFLOAT,
+   u32 => R32_UINT,
+   [u32; 4] => R32G32B32A32_UINT,
+   i32 => R32_SINT,
+   [u16; 4] => R16G16B16A16_UINT,
+   // Colors, read as floats in [0, 1]
+   [u8; 4] => R8G8B8A8_UNORM,
+);
+
+/// Vertex type that describes its own attributes for the vertex input state of a pipeline.
+/// Implement it with `vertex_layout!`, attribute locations follow the order of the fields.
+pub trait VertexLayout: Copy + 'static {
+   /// Attributes of binding 0
+   const ATTRIBUTES: &'static [VertexAttribute];
+
+   fn binding_description(binding: u32) -> vk::VertexInputBindingDescription {
+      vk::VertexInputBindingDescription {
+         binding,
+         stride: std::mem::size_of::<Self>() as u32,
+         input_rate: vk::VertexInputRate::VERTEX,
+      }
+   }
+
+   fn attribute_descriptions(binding: u32) -> Vec<vk::VertexInputAttributeDescription> {
+      Self::ATTRIBUTES
+         .iter()
+         .map(|&attribute| vk::VertexInputAttributeDescription { binding, ..attribute })
+         .collect()
+   }
+}
+
+#[doc(hidden)]
+pub const fn vertex_attributes<const N: usize>(
+   formats: [vk::Format; N],
+   offsets: [usize; N],
+) -> [VertexAttribute; N] {
+   let mut attributes = [VertexAttribute { location: 0, binding: 0, format: vk::Format::UNDEFINED, offset: 0 }; N];
+   let mut index = 0;
+   while index < N {
+      attributes[index] = VertexAttribute {
+         location: index as u32,
+         binding: 0,
+         format: formats[index],
+         offset: offsets[index] as u32,
+      };
+      index += 1;
+   }
+   attributes
+}
+
+/// Declares a `#[repr(C)]` vertex struct and implements `VertexLayout` for it. Field types must
+/// implement `VertexFormat`.
+///
+/// ```ignore
+/// vertex_layout! {
+///     #[derive(Clone, Copy, Debug)]
+///     struct Vertex {
+///         pos: Vector4<f32>,
+///         uv: Vector2<f32>,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! vertex_layout {
+   (
+      $(#[$meta:meta])*
+      $vis:vis struct $name:ident {
+         $($(#[$field_meta:meta])* $field_vis:vis $field:ident : $ty:ty),+ $(,)?
+      }
+   ) => {
+      $(#[$meta])*
+      #[repr(C)]
+      $vis struct $name {
+         $($(#[$field_meta])* $field_vis $field: $ty),+
+      }
+
+      impl $crate::platform::gpu::vulkan_mesh::VertexLayout for $name {
+         const ATTRIBUTES: &'static [$crate::platform::gpu::vulkan_mesh::VertexAttribute] =
+            &$crate::platform::gpu::vulkan_mesh::vertex_attributes(
+               [$(<$ty as $crate::platform::gpu::vulkan_mesh::VertexFormat>::FORMAT),+],
+               [$(::core::mem::offset_of!($name, $field)),+],
+            );
+      }
+   };
+}
+
+/// Index data of a mesh. 16 bit indices take half the memory, but address only 65536 vertices.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Indices {
+   U16(Vec<u16>),
+   U32(Vec<u32>),
+}
+
+impl Indices {
+   /// Uses 16 bit indices if all of them fit
+   pub fn compact(indices: Vec<u32>) -> Self {
+      if indices.iter().all(|&index| index <= u32::from(u16::MAX)) {
+         Indices::U16(indices.into_iter().map(|index| index as u16).collect())
+      } else {
+         Indices::U32(indices)
+      }
+   }
+
+   pub fn len(&self) -> usize {
+      match self {
+         Indices::U16(indices) => indices.len(),
+         Indices::U32(indices) => indices.len(),
+      }
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.len() == 0
+   }
+
+   pub fn index_type(&self) -> vk::IndexType {
+      match self {
+         Indices::U16(_) => vk::IndexType::UINT16,
+         Indices::U32(_) => vk::IndexType::UINT32,
+      }
+   }
+
+   pub fn iter(&self) -> Box<dyn Iterator<Item = u32> + '_> {
+      match self {
+         Indices::U16(indices) => Box::new(indices.iter().map(|&index| u32::from(index))),
+         Indices::U32(indices) => Box::new(indices.iter().copied()),
+      }
+   }
+}
+
+impl From<Vec<u16>> for Indices {
+   fn from(indices: Vec<u16>) -> Self {
+      Indices::U16(indices)
+   }
+}
+
+impl From<Vec<u32>> for Indices {
+   fn from(indices: Vec<u32>) -> Self {
+      Indices::U32(indices)
+   }
+}
+
+/// Indexed triangle list in device local vertex and index buffers.
+pub struct Mesh {
+   vertex_buffer: VulkanBuffer,
+   index_buffer: VulkanBuffer,
+   index_type: vk::IndexType,
+   index_count: u32,
+   vertex_count: u32,
+}
+
+impl Mesh {
+   /// Uploads the vertices and indices, waiting until the upload finishes
+   pub fn new<V: VertexLayout>(context: &VulkanContext, vertices: &[V], indices: &Indices) -> Self {
+      assert!(!vertices.is_empty() && !indices.is_empty(), "Mesh must have vertices and indices");
+      let vertex_buffer = VulkanBuffer::new_device_local(context, vk::BufferUsageFlags::VERTEX_BUFFER, vertices);
+      let index_buffer = match indices {
+         Indices::U16(indices) => VulkanBuffer::new_device_local(context, vk::BufferUsageFlags::INDEX_BUFFER, indices),
+         Indices::U32(indices) => VulkanBuffer::new_device_local(context, vk::BufferUsageFlags::INDEX_BUFFER, indices),
+      };
+      Mesh {
+         vertex_buffer,
+         index_buffer,
+         index_type: indices.index_type(),
+         index_count: indices.len() as u32,
+         vertex_count: vertices.len() as u32,
+      }
+   }
+
+   pub fn index_type(&self) -> vk::IndexType {
+      self.index_type
+   }
+
+   pub fn index_count(&self) -> u32 {
+      self.index_count
+   }
+
+   pub fn vertex_count(&self) -> u32 {
+      self.vertex_count
+   }
+
+   /// Binds the vertex buffer to binding 0 and the index buffer
+   pub fn bind(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+      unsafe {
+         device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer.buffer()], &[0]);
+         device.cmd_bind_index_buffer(command_buffer, self.index_buffer.buffer(), 0, self.index_type);
+      }
+   }
+
+   /// Draws one instance of the bound mesh. `first_instance` is visible to shaders as `gl_InstanceIndex`
+   pub fn draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, first_instance: u32) {
+      unsafe {
+         device.cmd_draw_indexed(command_buffer, self.index_count, 1, 0, 0, first_instance);
+      }
+   }
+
+   /// Pushes the per object constants, like a model matrix, and draws one instance of the bound mesh
+   pub fn draw_object<T: Copy + 'static>(
+      &self,
+      device: &ash::Device,
+      command_buffer: vk::CommandBuffer,
+      layout: &VulkanPipelineLayout,
+      constants: &T,
+   ) {
+      layout.cmd_push(device, command_buffer, constants);
+      self.draw(device, command_buffer, 0);
+   }
+}
+
+impl VulkanDrop for Mesh {
+   fn drop(self, device: &ash::Device) {
+      self.vertex_buffer.drop(device);
+      self.index_buffer.drop(device);
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use std::mem::{align_of, offset_of, size_of};
+
+   use super::*;
+
+   /// Forces padding before and after it in the vertex below
+   #[derive(Clone, Copy, Debug)]
+   #[repr(C, align(16))]
+   struct Aligned([f32; 4]);
+
+   impl VertexFormat for Aligned {
+      const FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+   }
+
+   vertex_layout! {
+      #[derive(Clone, Copy, Debug)]
+      struct PaddedVertex {
+         position: Vector3<f32>,
+         normal: Aligned,
+         color: [u8; 4],
+      }
+   }
+
+   #[test]
+   fn attributes_match_the_struct_layout() {
+      assert_eq!(offset_of!(PaddedVertex, normal), 16, "padding after position");
+      assert_eq!(size_of::<PaddedVertex>(), 48, "padding after color");
+      let expected = [
+         (vk::Format::R32G32B32_SFLOAT, offset_of!(PaddedVertex, position)),
+         (vk::Format::R32G32B32A32_SFLOAT, offset_of!(PaddedVertex, normal)),
+         (vk::Format::R8G8B8A8_UNORM, offset_of!(PaddedVertex, color)),
+      ];
+      assert_eq!(PaddedVertex::ATTRIBUTES.len(), expected.len());
+      for (location, (attribute, (format, offset))) in PaddedVertex::ATTRIBUTES.iter().zip(expected).enumerate() {
+         assert_eq!(attribute.location, location as u32);
+         assert_eq!(attribute.binding, 0);
+         assert_eq!(attribute.format, format);
+         assert_eq!(attribute.offset, offset as u32);
+      }
+   }
+
+   #[test]
+   fn descriptions_use_the_requested_binding_and_stride() {
+      let binding = PaddedVertex::binding_description(2);
+      assert_eq!(binding.binding, 2);
+      assert_eq!(binding.stride as usize, size_of::<PaddedVertex>());
+      assert_eq!(binding.stride as usize % align_of::<PaddedVertex>(), 0);
+      assert_eq!(binding.input_rate, vk::VertexInputRate::VERTEX);
+      let attributes = PaddedVertex::attribute_descriptions(2);
+      assert!(attributes.iter().all(|attribute| attribute.binding == 2));
+      assert_eq!(attributes[1].offset, 16);
+   }
+
+   #[test]
+   fn compact_indices_pick_the_smallest_type() {
+      assert_eq!(Indices::compact(vec![0, 65535]), Indices::U16(vec![0, 65535]));
+      assert_eq!(Indices::compact(vec![0, 65536]), Indices::U32(vec![0, 65536]));
+      assert_eq!(Indices::compact(vec![3, 1]).iter().collect::<Vec<_>>(), [3, 1]);
+   }
+}
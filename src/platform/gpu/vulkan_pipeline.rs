@@ -0,0 +1,146 @@
+use std::any::{type_name, TypeId};
+use std::mem;
+
+use ash::vk;
+
+use super::vulkan_context::VulkanContext;
+use super::VulkanDrop;
+
+/// Pipeline layout that knows the types of its push constant ranges, so pushes can't go to the
+/// wrong offset or stages.
+pub struct VulkanPipelineLayout {
+   layout: vk::PipelineLayout,
+   push_constants: Vec<(TypeId, vk::PushConstantRange)>,
+}
+
+impl VulkanPipelineLayout {
+   pub fn builder(context: &VulkanContext) -> VulkanPipelineLayoutBuilder {
+      VulkanPipelineLayoutBuilder {
+         set_layouts: Vec::new(),
+         push_constants: Vec::new(),
+         max_push_constants_size: context.device_limits.max_push_constants_size,
+      }
+   }
+
+   pub fn layout(&self) -> vk::PipelineLayout {
+      self.layout
+   }
+
+   /// Range of the push constants of type `T`, if the layout has one
+   pub fn push_constant_range<T: 'static>(&self) -> Option<vk::PushConstantRange> {
+      self.push_constants
+         .iter()
+         .find(|(type_id, _)| *type_id == TypeId::of::<T>())
+         .map(|&(_, range)| range)
+   }
+
+   /// Records a push of the value to its range
+   pub fn cmd_push<T: Copy + 'static>(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, value: &T) {
+      let range = self
+         .push_constant_range::<T>()
+         .unwrap_or_else(|| panic!("Pipeline layout has no push constants of type {}", type_name::<T>()));
+      unsafe {
+         let bytes = std::slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>());
+         device.cmd_push_constants(command_buffer, self.layout, range.stage_flags, range.offset, bytes);
+      }
+   }
+}
+
+impl VulkanDrop for VulkanPipelineLayout {
+   fn drop(self, device: &ash::Device) {
+      unsafe {
+         device.destroy_pipeline_layout(self.layout, None);
+      }
+   }
+}
+
+pub struct VulkanPipelineLayoutBuilder {
+   set_layouts: Vec<vk::DescriptorSetLayout>,
+   push_constants: Vec<(TypeId, vk::PushConstantRange)>,
+   max_push_constants_size: u32,
+}
+
+impl VulkanPipelineLayoutBuilder {
+   /// Adds the layout of the next set, starting from set 0
+   pub fn with_set_layout(mut self, set_layout: vk::DescriptorSetLayout) -> Self {
+      self.set_layouts.push(set_layout);
+      self
+   }
+
+   /// Adds a push constant range holding a `T` after the previous ranges. `T` must be `#[repr(C)]`
+   /// and match the layout of the `push_constant` block of the shaders
+   pub fn with_push_constants<T: Copy + 'static>(mut self, stage_flags: vk::ShaderStageFlags) -> Self {
+      assert!(
+         self.push_constant_range::<T>().is_none(),
+         "Push constants of type {} are already in the layout",
+         type_name::<T>()
+      );
+      let size = mem::size_of::<T>() as u32;
+      assert!(size.is_multiple_of(4), "Size of push constants {} isn't a multiple of 4", type_name::<T>());
+      let offset = self
+         .push_constants
+         .last()
+         .map_or(0, |(_, range)| range.offset + range.size);
+      assert!(
+         offset + size <= self.max_push_constants_size,
+         "Push constants end at {} bytes, the device supports {}",
+         offset + size,
+         self.max_push_constants_size
+      );
+      self.push_constants.push((TypeId::of::<T>(), vk::PushConstantRange { stage_flags, offset, size }));
+      self
+   }
+
+   fn push_constant_range<T: 'static>(&self) -> Option<vk::PushConstantRange> {
+      self.push_constants
+         .iter()
+         .find(|(type_id, _)| *type_id == TypeId::of::<T>())
+         .map(|&(_, range)| range)
+   }
+
+   pub fn build(self, device: &ash::Device) -> VulkanPipelineLayout {
+      let ranges: Vec<vk::PushConstantRange> = self.push_constants.iter().map(|&(_, range)| range).collect();
+      let layout_info = vk::PipelineLayoutCreateInfo::builder()
+         .set_layouts(&self.set_layouts)
+         .push_constant_ranges(&ranges);
+      let layout = unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() };
+      VulkanPipelineLayout { layout, push_constants: self.push_constants }
+   }
+}
+
+/// Multisample state of pipelines drawing into attachments of `samples`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Multisampling {
+   pub samples: vk::SampleCountFlags,
+   /// Fraction of the samples shaded separately, `None` shading once per pixel. Needs
+   /// `VulkanContext::sample_rate_shading`
+   pub min_sample_shading: Option<f32>,
+}
+
+impl Default for Multisampling {
+   fn default() -> Self {
+      Multisampling { samples: vk::SampleCountFlags::TYPE_1, min_sample_shading: None }
+   }
+}
+
+impl Multisampling {
+   /// The samples the context supports of those requested, shading each of them if asked and
+   /// supported
+   pub fn new(context: &VulkanContext, samples: u32, sample_shading: bool) -> Self {
+      let samples = context.sample_count(samples);
+      let sample_shading = sample_shading && context.sample_rate_shading && samples != vk::SampleCountFlags::TYPE_1;
+      Multisampling { samples, min_sample_shading: sample_shading.then_some(1.0) }
+   }
+
+   pub fn is_multisampled(&self) -> bool {
+      self.samples != vk::SampleCountFlags::TYPE_1
+   }
+
+   pub fn state_info(&self) -> vk::PipelineMultisampleStateCreateInfo {
+      vk::PipelineMultisampleStateCreateInfo::builder()
+         .rasterization_samples(self.samples)
+         .sample_shading_enable(self.min_sample_shading.is_some())
+         .min_sample_shading(self.min_sample_shading.unwrap_or(0.0))
+         .build()
+   }
+}
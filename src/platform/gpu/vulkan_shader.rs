@@ -9,9 +9,7 @@ const MAX_SHADER_STAGES: usize = 4;
 use lazy_static::lazy_static;
 
 lazy_static! {
-   static ref SHADER_ENTRY_FUNCTION_NAME: &'static CStr = unsafe {
-      CStr::from_bytes_with_nul_unchecked(b"main\0")
-   };
+   static ref SHADER_ENTRY_FUNCTION_NAME: &'static CStr = c"main";
 }
 pub struct VulkanShader {
    shader_stage_create_infos: [vk::PipelineShaderStageCreateInfo; MAX_SHADER_STAGES],
@@ -19,7 +17,7 @@ pub struct VulkanShader {
 }
 
 impl VulkanShader {
-   pub fn builder(device: &ash::Device) -> VulkanShaderBuilder {
+   pub fn builder(device: &ash::Device) -> VulkanShaderBuilder<'_> {
       VulkanShaderBuilder {
          shader_stage_create_infos: Default::default(),
          n_stages: 0,
@@ -90,6 +88,18 @@ impl<'a> VulkanShaderBuilder<'a>{
       self
    }
 
+   pub fn with_compute_shader(mut self, stage_idx: usize, shader_spv_file: &mut Cursor<impl AsRef<[u8]>>) -> Self {
+      Self::check_stage_idx(stage_idx);
+      let shader_module = self.make_shader_module(shader_spv_file);
+      self.add_shader_stage_create_info(stage_idx, vk::PipelineShaderStageCreateInfo {
+         module: shader_module,
+         p_name: SHADER_ENTRY_FUNCTION_NAME.as_ptr(),
+         stage: vk::ShaderStageFlags::COMPUTE,
+         ..Default::default()
+      });
+      self
+   }
+
    fn make_shader_module(&self, shader_spv_file: &mut Cursor<impl AsRef<[u8]>>) -> ShaderModule {
       let code = read_spv(shader_spv_file)
          .expect("Failed to read fragment shader spv file");
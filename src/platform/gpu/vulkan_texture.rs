@@ -0,0 +1,768 @@
+use std::mem;
+use std::ops::Range;
+
+use ash::vk;
+
+use super::vulkan_buffer::VulkanBuffer;
+use super::vulkan_context::{find_memorytype_index, record_submit_commandbuffer, VulkanContext};
+use super::VulkanDrop;
+
+/// Sampled image in device local memory: 2D color with a full mip chain, 2D color or depth
+/// rendered to, multisampled attachments, a cube array of depth, 2D and cube storage images
+/// written by compute shaders, or a 3D lookup table.
+pub struct VulkanTexture {
+   image: vk::Image,
+   memory: vk::DeviceMemory,
+   view: vk::ImageView,
+   extent: vk::Extent2D,
+   format: vk::Format,
+   mip_levels: u32,
+   layers: u32,
+   aspect_mask: vk::ImageAspectFlags,
+   /// Layout the texture is sampled in
+   layout: vk::ImageLayout,
+}
+
+impl VulkanTexture {
+   /// Uploads 8 bit RGBA pixels, rows top to bottom, and generates mips by blitting. Formats
+   /// that can't be blitted with linear filtering get a single level. Waits until the upload
+   /// finishes
+   pub fn new_rgba8(context: &VulkanContext, extent: vk::Extent2D, format: vk::Format, pixels: &[u8]) -> Self {
+      assert_eq!(
+         pixels.len(),
+         (extent.width * extent.height * 4) as usize,
+         "Texture must have 4 bytes per pixel"
+      );
+      Self::new_uploaded(context, extent, format, pixels)
+   }
+
+   /// Uploads 32 bit float RGBA pixels, rows top to bottom, as `R32G32B32A32_SFLOAT`. Such images
+   /// may not support linear filtering, read them with `texelFetch`. Waits until the upload
+   /// finishes
+   pub fn new_rgba32f(context: &VulkanContext, extent: vk::Extent2D, pixels: &[f32]) -> Self {
+      assert_eq!(
+         pixels.len(),
+         (extent.width * extent.height * 4) as usize,
+         "Texture must have 4 floats per pixel"
+      );
+      Self::new_uploaded(context, extent, vk::Format::R32G32B32A32_SFLOAT, pixels)
+   }
+
+   fn new_uploaded<T: Copy>(context: &VulkanContext, extent: vk::Extent2D, format: vk::Format, pixels: &[T]) -> Self {
+      let mip_levels = if supports_linear_blit(context, format) {
+         32 - extent.width.max(extent.height).leading_zeros()
+      } else {
+         1
+      };
+      let staging = VulkanBuffer::new_host_visible(
+         &context.device,
+         &context.device_memory_properties,
+         mem::size_of_val(pixels) as vk::DeviceSize,
+         vk::BufferUsageFlags::TRANSFER_SRC,
+      );
+      staging.write(&context.device, pixels);
+
+      let usage = vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+      let image_info = image_info(extent, format, mip_levels, usage);
+      let texture =
+         unsafe { Self::new_image(context, &image_info, vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR) };
+      let image = texture.image;
+      record_submit_commandbuffer(
+         &context.device,
+         context.setup_command_buffer,
+         context.setup_commands_reuse_fence,
+         context.present_queue,
+         &[],
+         &[],
+         &[],
+         |device, command_buffer| unsafe {
+            transition(
+               device,
+               command_buffer,
+               image,
+               0..mip_levels,
+               (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+               (vk::AccessFlags::empty(), vk::AccessFlags::TRANSFER_WRITE),
+               (vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::TRANSFER),
+            );
+            let region = vk::BufferImageCopy::builder()
+               .image_subresource(subresource_layers(0))
+               .image_extent(extent.into())
+               .build();
+            device.cmd_copy_buffer_to_image(
+               command_buffer,
+               staging.buffer(),
+               image,
+               vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+               &[region],
+            );
+            // Each level is blitted from the previous one, which then is done
+            for level in 1..mip_levels {
+               transition(
+                  device,
+                  command_buffer,
+                  image,
+                  level - 1..level,
+                  (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+                  (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::TRANSFER_READ),
+                  (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::TRANSFER),
+               );
+               let blit = vk::ImageBlit {
+                  src_subresource: subresource_layers(level - 1),
+                  src_offsets: [vk::Offset3D::default(), mip_offset(extent, level - 1)],
+                  dst_subresource: subresource_layers(level),
+                  dst_offsets: [vk::Offset3D::default(), mip_offset(extent, level)],
+               };
+               device.cmd_blit_image(
+                  command_buffer,
+                  image,
+                  vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                  image,
+                  vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                  &[blit],
+                  vk::Filter::LINEAR,
+               );
+               transition(
+                  device,
+                  command_buffer,
+                  image,
+                  level - 1..level,
+                  (vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                  (vk::AccessFlags::TRANSFER_READ, vk::AccessFlags::SHADER_READ),
+                  (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::FRAGMENT_SHADER),
+               );
+            }
+            transition(
+               device,
+               command_buffer,
+               image,
+               mip_levels - 1..mip_levels,
+               (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+               (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ),
+               (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::FRAGMENT_SHADER),
+            );
+         },
+      );
+      unsafe {
+         context
+            .device
+            .wait_for_fences(&[context.setup_commands_reuse_fence], true, u64::MAX)
+            .expect("Wait for fence failed.");
+      }
+      staging.drop(&context.device);
+      texture
+   }
+
+   /// Depth image rendered to and then sampled, like a shadow map. Its contents are undefined
+   /// until a render pass writes them, see `find_depth_format` for the format
+   pub fn new_depth(context: &VulkanContext, extent: vk::Extent2D, format: vk::Format) -> Self {
+      let usage = vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED;
+      let image_info = image_info(extent, format, 1, usage);
+      unsafe { Self::new_image(context, &image_info, vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::DEPTH) }
+   }
+
+   /// Color image rendered to and then sampled, like an HDR scene target. Its contents are
+   /// undefined until a render pass writes them, ending in the shader read layout. Takes no
+   /// context so renderers can remake it when the swapchain is resized
+   pub fn new_color_target(
+      device: &ash::Device,
+      memory_properties: &vk::PhysicalDeviceMemoryProperties,
+      extent: vk::Extent2D,
+      format: vk::Format,
+   ) -> Self {
+      let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED;
+      let image_info = image_info(extent, format, 1, usage);
+      let aspect_mask = vk::ImageAspectFlags::COLOR;
+      unsafe { Self::allocate_image(device, memory_properties, &image_info, vk::ImageViewType::TYPE_2D, aspect_mask) }
+   }
+
+   /// Multisampled color or depth attachment, transient as it's resolved or discarded at the end
+   /// of the render pass and never sampled. Takes no context so renderers can remake it when
+   /// the swapchain is resized
+   pub fn new_multisampled_target(
+      device: &ash::Device,
+      memory_properties: &vk::PhysicalDeviceMemoryProperties,
+      extent: vk::Extent2D,
+      format: vk::Format,
+      samples: vk::SampleCountFlags,
+      aspect_mask: vk::ImageAspectFlags,
+   ) -> Self {
+      let attachment_usage = if aspect_mask.contains(vk::ImageAspectFlags::DEPTH) {
+         vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+      } else {
+         vk::ImageUsageFlags::COLOR_ATTACHMENT
+      };
+      let usage = attachment_usage | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT;
+      let image_info = vk::ImageCreateInfo { samples, ..image_info(extent, format, 1, usage) };
+      unsafe { Self::allocate_image(device, memory_properties, &image_info, vk::ImageViewType::TYPE_2D, aspect_mask) }
+   }
+
+   /// Cube array of depth images, `cubes` times six layers ordered +X, -X, +Y, -Y, +Z, -Z, like
+   /// point light shadow maps. Every layer starts cleared to the far depth of 1 in the shader
+   /// read layout, so cubes not rendered yet can be sampled. Waits until the clear finishes
+   pub fn new_depth_cube_array(context: &VulkanContext, size: u32, format: vk::Format, cubes: u32) -> Self {
+      let extent = vk::Extent2D { width: size, height: size };
+      let usage = vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+         | vk::ImageUsageFlags::SAMPLED
+         | vk::ImageUsageFlags::TRANSFER_DST;
+      let image_info = vk::ImageCreateInfo {
+         flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
+         array_layers: cubes * 6,
+         ..image_info(extent, format, 1, usage)
+      };
+      let texture =
+         unsafe { Self::new_image(context, &image_info, vk::ImageViewType::CUBE_ARRAY, vk::ImageAspectFlags::DEPTH) };
+      let range = texture.subresource_range();
+      record_submit_commandbuffer(
+         &context.device,
+         context.setup_command_buffer,
+         context.setup_commands_reuse_fence,
+         context.present_queue,
+         &[],
+         &[],
+         &[],
+         |device, command_buffer| unsafe {
+            barrier(
+               device,
+               command_buffer,
+               texture.image,
+               range,
+               (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+               (vk::AccessFlags::empty(), vk::AccessFlags::TRANSFER_WRITE),
+               (vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::TRANSFER),
+            );
+            let clear = vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 };
+            device.cmd_clear_depth_stencil_image(
+               command_buffer,
+               texture.image,
+               vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+               &clear,
+               &[range],
+            );
+            barrier(
+               device,
+               command_buffer,
+               texture.image,
+               range,
+               (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+               (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ),
+               (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::FRAGMENT_SHADER),
+            );
+         },
+      );
+      unsafe {
+         context
+            .device
+            .wait_for_fences(&[context.setup_commands_reuse_fence], true, u64::MAX)
+            .expect("Wait for fence failed.");
+      }
+      texture
+   }
+
+   /// Cube written by compute shaders and then sampled, with `mip_levels` levels of six layers.
+   /// Stays in the `GENERAL` layout, starting cleared to transparent black. Waits until the
+   /// clear finishes
+   pub fn new_storage_cube(context: &VulkanContext, size: u32, format: vk::Format, mip_levels: u32) -> Self {
+      let extent = vk::Extent2D { width: size, height: size };
+      let image_info = vk::ImageCreateInfo {
+         flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
+         array_layers: 6,
+         ..image_info(extent, format, mip_levels, storage_usage())
+      };
+      let texture =
+         unsafe { Self::new_image(context, &image_info, vk::ImageViewType::CUBE, vk::ImageAspectFlags::COLOR) };
+      texture.clear_to_general(context)
+   }
+
+   /// 2D image written by compute shaders and then sampled. Stays in the `GENERAL` layout,
+   /// starting cleared to transparent black. Waits until the clear finishes
+   pub fn new_storage(context: &VulkanContext, extent: vk::Extent2D, format: vk::Format) -> Self {
+      let image_info = image_info(extent, format, 1, storage_usage());
+      let texture =
+         unsafe { Self::new_image(context, &image_info, vk::ImageViewType::TYPE_2D, vk::ImageAspectFlags::COLOR) };
+      texture.clear_to_general(context)
+   }
+
+   /// 3D image of `size` texels along each axis, like a color grading lookup table. Texels are
+   /// packed `A2B10G10R10_UNORM_PACK32`, ordered by red, then green, then blue. Waits until the
+   /// upload finishes
+   pub fn new_lut(context: &VulkanContext, size: u32, texels: &[u32]) -> Self {
+      assert_eq!(texels.len(), (size * size * size) as usize, "Lookup table must have size cubed texels");
+      let staging = VulkanBuffer::new_host_visible(
+         &context.device,
+         &context.device_memory_properties,
+         mem::size_of_val(texels) as vk::DeviceSize,
+         vk::BufferUsageFlags::TRANSFER_SRC,
+      );
+      staging.write(&context.device, texels);
+
+      let extent = vk::Extent2D { width: size, height: size };
+      let usage = vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+      let image_info = vk::ImageCreateInfo {
+         image_type: vk::ImageType::TYPE_3D,
+         extent: vk::Extent3D { width: size, height: size, depth: size },
+         ..image_info(extent, vk::Format::A2B10G10R10_UNORM_PACK32, 1, usage)
+      };
+      let texture =
+         unsafe { Self::new_image(context, &image_info, vk::ImageViewType::TYPE_3D, vk::ImageAspectFlags::COLOR) };
+      let image = texture.image;
+      record_submit_commandbuffer(
+         &context.device,
+         context.setup_command_buffer,
+         context.setup_commands_reuse_fence,
+         context.present_queue,
+         &[],
+         &[],
+         &[],
+         |device, command_buffer| unsafe {
+            transition(
+               device,
+               command_buffer,
+               image,
+               0..1,
+               (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+               (vk::AccessFlags::empty(), vk::AccessFlags::TRANSFER_WRITE),
+               (vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::TRANSFER),
+            );
+            let region = vk::BufferImageCopy::builder()
+               .image_subresource(subresource_layers(0))
+               .image_extent(image_info.extent)
+               .build();
+            device.cmd_copy_buffer_to_image(
+               command_buffer,
+               staging.buffer(),
+               image,
+               vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+               &[region],
+            );
+            transition(
+               device,
+               command_buffer,
+               image,
+               0..1,
+               (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+               (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ),
+               (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::FRAGMENT_SHADER),
+            );
+         },
+      );
+      unsafe {
+         context
+            .device
+            .wait_for_fences(&[context.setup_commands_reuse_fence], true, u64::MAX)
+            .expect("Wait for fence failed.");
+      }
+      staging.drop(&context.device);
+      texture
+   }
+
+   /// 2D image with `mip_levels` levels written by compute shaders every frame, like the
+   /// intermediate images of post-processing. Used in the `GENERAL` layout after
+   /// `cmd_discard_to_general`. Takes no context so renderers can remake it when the swapchain is
+   /// resized
+   pub fn new_storage_target(
+      device: &ash::Device,
+      memory_properties: &vk::PhysicalDeviceMemoryProperties,
+      extent: vk::Extent2D,
+      format: vk::Format,
+      mip_levels: u32,
+   ) -> Self {
+      let usage = vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED;
+      let image_info = image_info(extent, format, mip_levels, usage);
+      let aspect_mask = vk::ImageAspectFlags::COLOR;
+      let mut texture = unsafe {
+         Self::allocate_image(device, memory_properties, &image_info, vk::ImageViewType::TYPE_2D, aspect_mask)
+      };
+      texture.layout = vk::ImageLayout::GENERAL;
+      texture
+   }
+
+   /// Records the transition of a storage target to the `GENERAL` layout, discarding its
+   /// contents, after the shader reads of the previous frame
+   pub fn cmd_discard_to_general(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+      unsafe {
+         barrier(
+            device,
+            command_buffer,
+            self.image,
+            self.subresource_range(),
+            (vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL),
+            (vk::AccessFlags::empty(), vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE),
+            (
+               vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+               vk::PipelineStageFlags::COMPUTE_SHADER,
+            ),
+         );
+      }
+   }
+
+   fn clear_to_general(mut self, context: &VulkanContext) -> Self {
+      let range = self.subresource_range();
+      record_submit_commandbuffer(
+         &context.device,
+         context.setup_command_buffer,
+         context.setup_commands_reuse_fence,
+         context.present_queue,
+         &[],
+         &[],
+         &[],
+         |device, command_buffer| unsafe {
+            barrier(
+               device,
+               command_buffer,
+               self.image,
+               range,
+               (vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL),
+               (vk::AccessFlags::empty(), vk::AccessFlags::TRANSFER_WRITE),
+               (vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::TRANSFER),
+            );
+            let clear = vk::ClearColorValue { float32: [0.0; 4] };
+            device.cmd_clear_color_image(command_buffer, self.image, vk::ImageLayout::GENERAL, &clear, &[range]);
+            barrier(
+               device,
+               command_buffer,
+               self.image,
+               range,
+               (vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL),
+               (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE),
+               (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::ALL_COMMANDS),
+            );
+         },
+      );
+      unsafe {
+         context
+            .device
+            .wait_for_fences(&[context.setup_commands_reuse_fence], true, u64::MAX)
+            .expect("Wait for fence failed.");
+      }
+      self.layout = vk::ImageLayout::GENERAL;
+      self
+   }
+
+   unsafe fn new_image(
+      context: &VulkanContext,
+      image_info: &vk::ImageCreateInfo,
+      view_type: vk::ImageViewType,
+      aspect_mask: vk::ImageAspectFlags,
+   ) -> Self {
+      Self::allocate_image(&context.device, &context.device_memory_properties, image_info, view_type, aspect_mask)
+   }
+
+   unsafe fn allocate_image(
+      device: &ash::Device,
+      memory_properties: &vk::PhysicalDeviceMemoryProperties,
+      image_info: &vk::ImageCreateInfo,
+      view_type: vk::ImageViewType,
+      aspect_mask: vk::ImageAspectFlags,
+   ) -> Self {
+      let image = device.create_image(image_info, None).unwrap();
+      let memory_req = device.get_image_memory_requirements(image);
+      let memory_index = find_memorytype_index(&memory_req, memory_properties, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+      .expect("Unable to find suitable memorytype for the texture.");
+      let allocate_info = vk::MemoryAllocateInfo::builder()
+         .allocation_size(memory_req.size)
+         .memory_type_index(memory_index);
+      let memory = device.allocate_memory(&allocate_info, None).unwrap();
+      device.bind_image_memory(image, memory, 0).unwrap();
+
+      let mut texture = VulkanTexture {
+         image,
+         memory,
+         view: vk::ImageView::null(),
+         extent: vk::Extent2D { width: image_info.extent.width, height: image_info.extent.height },
+         format: image_info.format,
+         mip_levels: image_info.mip_levels,
+         layers: image_info.array_layers,
+         aspect_mask,
+         layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+      };
+      let view_info = vk::ImageViewCreateInfo::builder()
+         .image(image)
+         .view_type(view_type)
+         .format(texture.format)
+         .subresource_range(texture.subresource_range());
+      texture.view = device.create_image_view(&view_info, None).unwrap();
+      texture
+   }
+
+   /// 2D view of a single layer, e.g. to render into one face of a cube. Destroyed by the caller
+   pub fn layer_view(&self, device: &ash::Device, layer: u32) -> vk::ImageView {
+      self.subresource_view(device, vk::ImageViewType::TYPE_2D, 0..self.mip_levels, layer..layer + 1)
+   }
+
+   /// View of some levels and layers, e.g. to write one level of a cube as a 2D array from a
+   /// compute shader. Destroyed by the caller
+   pub fn subresource_view(
+      &self,
+      device: &ash::Device,
+      view_type: vk::ImageViewType,
+      levels: Range<u32>,
+      layers: Range<u32>,
+   ) -> vk::ImageView {
+      assert!(
+         levels.end <= self.mip_levels && layers.end <= self.layers,
+         "Texture has {} levels of {} layers, no levels {:?} of layers {:?}",
+         self.mip_levels,
+         self.layers,
+         levels,
+         layers
+      );
+      let view_info = vk::ImageViewCreateInfo::builder()
+         .image(self.image)
+         .view_type(view_type)
+         .format(self.format)
+         .subresource_range(vk::ImageSubresourceRange {
+            base_mip_level: levels.start,
+            level_count: levels.len() as u32,
+            base_array_layer: layers.start,
+            layer_count: layers.len() as u32,
+            ..self.subresource_range()
+         });
+      unsafe { device.create_image_view(&view_info, None).unwrap() }
+   }
+
+   /// Records blits filling each level from the previous one, for storage textures staying in
+   /// the `GENERAL` layout. Waits for earlier compute writes, and makes the levels visible to
+   /// compute and fragment shaders
+   pub fn cmd_generate_mips(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+      assert_eq!(self.layout, vk::ImageLayout::GENERAL, "Mips are generated for storage textures");
+      let general = (vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+      let shader_stages = vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER;
+      let level_range = |level: u32| vk::ImageSubresourceRange {
+         base_mip_level: level,
+         level_count: 1,
+         ..self.subresource_range()
+      };
+      let level_layers = |level: u32| vk::ImageSubresourceLayers {
+         aspect_mask: self.aspect_mask,
+         mip_level: level,
+         base_array_layer: 0,
+         layer_count: self.layers,
+      };
+      unsafe {
+         barrier(
+            device,
+            command_buffer,
+            self.image,
+            level_range(0),
+            general,
+            (vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::TRANSFER_READ),
+            (vk::PipelineStageFlags::COMPUTE_SHADER, vk::PipelineStageFlags::TRANSFER),
+         );
+         for level in 1..self.mip_levels {
+            let blit = vk::ImageBlit {
+               src_subresource: level_layers(level - 1),
+               src_offsets: [vk::Offset3D::default(), mip_offset(self.extent, level - 1)],
+               dst_subresource: level_layers(level),
+               dst_offsets: [vk::Offset3D::default(), mip_offset(self.extent, level)],
+            };
+            device.cmd_blit_image(
+               command_buffer,
+               self.image,
+               vk::ImageLayout::GENERAL,
+               self.image,
+               vk::ImageLayout::GENERAL,
+               &[blit],
+               vk::Filter::LINEAR,
+            );
+            barrier(
+               device,
+               command_buffer,
+               self.image,
+               level_range(level),
+               general,
+               (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::TRANSFER_READ),
+               (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::TRANSFER),
+            );
+         }
+         barrier(
+            device,
+            command_buffer,
+            self.image,
+            self.subresource_range(),
+            general,
+            (vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ),
+            (vk::PipelineStageFlags::TRANSFER, shader_stages),
+         );
+      }
+   }
+
+   fn subresource_range(&self) -> vk::ImageSubresourceRange {
+      vk::ImageSubresourceRange {
+         aspect_mask: self.aspect_mask,
+         base_mip_level: 0,
+         level_count: self.mip_levels,
+         base_array_layer: 0,
+         layer_count: self.layers,
+      }
+   }
+
+   pub fn image_view(&self) -> vk::ImageView {
+      self.view
+   }
+
+   pub fn extent(&self) -> vk::Extent2D {
+      self.extent
+   }
+
+   pub fn format(&self) -> vk::Format {
+      self.format
+   }
+
+   pub fn mip_levels(&self) -> u32 {
+      self.mip_levels
+   }
+
+   pub fn layers(&self) -> u32 {
+      self.layers
+   }
+
+   /// Info for a `SAMPLED_IMAGE` descriptor, the sampler is bound separately
+   pub fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+      vk::DescriptorImageInfo {
+         sampler: vk::Sampler::null(),
+         image_view: self.view,
+         image_layout: self.layout,
+      }
+   }
+
+   /// Info for a `STORAGE_IMAGE` or `SAMPLED_IMAGE` descriptor of a view of the storage texture
+   pub fn storage_descriptor_info(&self, image_view: vk::ImageView) -> vk::DescriptorImageInfo {
+      assert_eq!(self.layout, vk::ImageLayout::GENERAL, "Only storage textures can be written by shaders");
+      vk::DescriptorImageInfo {
+         sampler: vk::Sampler::null(),
+         image_view,
+         image_layout: vk::ImageLayout::GENERAL,
+      }
+   }
+}
+
+impl VulkanDrop for VulkanTexture {
+   fn drop(self, device: &ash::Device) {
+      unsafe {
+         device.destroy_image_view(self.view, None);
+         device.destroy_image(self.image, None);
+         device.free_memory(self.memory, None);
+      }
+   }
+}
+
+fn storage_usage() -> vk::ImageUsageFlags {
+   vk::ImageUsageFlags::STORAGE
+      | vk::ImageUsageFlags::SAMPLED
+      | vk::ImageUsageFlags::TRANSFER_SRC
+      | vk::ImageUsageFlags::TRANSFER_DST
+}
+
+fn image_info(extent: vk::Extent2D, format: vk::Format, mip_levels: u32, usage: vk::ImageUsageFlags) -> vk::ImageCreateInfo {
+   vk::ImageCreateInfo::builder()
+      .image_type(vk::ImageType::TYPE_2D)
+      .format(format)
+      .extent(extent.into())
+      .mip_levels(mip_levels)
+      .array_layers(1)
+      .samples(vk::SampleCountFlags::TYPE_1)
+      .tiling(vk::ImageTiling::OPTIMAL)
+      .usage(usage)
+      .sharing_mode(vk::SharingMode::EXCLUSIVE)
+      .build()
+}
+
+/// First of the depth formats whose optimal tiling supports the features
+pub fn find_depth_format(
+   context: &VulkanContext,
+   candidates: &[vk::Format],
+   features: vk::FormatFeatureFlags,
+) -> Option<vk::Format> {
+   candidates.iter().copied().find(|&format| {
+      let properties = unsafe {
+         context
+            .instance
+            .get_physical_device_format_properties(context.pdevice, format)
+      };
+      properties.optimal_tiling_features.contains(features)
+   })
+}
+
+fn supports_linear_blit(context: &VulkanContext, format: vk::Format) -> bool {
+   let properties = unsafe {
+      context
+         .instance
+         .get_physical_device_format_properties(context.pdevice, format)
+   };
+   properties.optimal_tiling_features.contains(
+      vk::FormatFeatureFlags::BLIT_SRC
+         | vk::FormatFeatureFlags::BLIT_DST
+         | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+   )
+}
+
+fn subresource_range(levels: Range<u32>) -> vk::ImageSubresourceRange {
+   vk::ImageSubresourceRange {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      base_mip_level: levels.start,
+      level_count: levels.end - levels.start,
+      base_array_layer: 0,
+      layer_count: 1,
+   }
+}
+
+fn subresource_layers(level: u32) -> vk::ImageSubresourceLayers {
+   vk::ImageSubresourceLayers {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      mip_level: level,
+      base_array_layer: 0,
+      layer_count: 1,
+   }
+}
+
+/// Far corner of a mip level
+fn mip_offset(extent: vk::Extent2D, level: u32) -> vk::Offset3D {
+   vk::Offset3D {
+      x: (extent.width >> level).max(1) as i32,
+      y: (extent.height >> level).max(1) as i32,
+      z: 1,
+   }
+}
+
+unsafe fn transition(
+   device: &ash::Device,
+   command_buffer: vk::CommandBuffer,
+   image: vk::Image,
+   levels: std::ops::Range<u32>,
+   layouts: (vk::ImageLayout, vk::ImageLayout),
+   access_masks: (vk::AccessFlags, vk::AccessFlags),
+   stages: (vk::PipelineStageFlags, vk::PipelineStageFlags),
+) {
+   barrier(device, command_buffer, image, subresource_range(levels), layouts, access_masks, stages);
+}
+
+unsafe fn barrier(
+   device: &ash::Device,
+   command_buffer: vk::CommandBuffer,
+   image: vk::Image,
+   range: vk::ImageSubresourceRange,
+   (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+   (src_access_mask, dst_access_mask): (vk::AccessFlags, vk::AccessFlags),
+   (src_stage, dst_stage): (vk::PipelineStageFlags, vk::PipelineStageFlags),
+) {
+   let barrier = vk::ImageMemoryBarrier::builder()
+      .old_layout(old_layout)
+      .new_layout(new_layout)
+      .src_access_mask(src_access_mask)
+      .dst_access_mask(dst_access_mask)
+      .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+      .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+      .image(image)
+      .subresource_range(range)
+      .build();
+   device.cmd_pipeline_barrier(
+      command_buffer,
+      src_stage,
+      dst_stage,
+      vk::DependencyFlags::empty(),
+      &[],
+      &[],
+      &[barrier],
+   );
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::de::value::{Error as ValueError, StrDeserializer};
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+use winit::event::{MouseButton, VirtualKeyCode};
+
+/// Physical input that can trigger a named action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+   Key(VirtualKeyCode),
+   Mouse(MouseButton),
+}
+
+impl Binding {
+   /// Parses a binding as written in the config file: a winit key name (`W`, `LShift`, `Space`)
+   /// or a mouse button prefixed with `Mouse:` (`Mouse:Left`, `Mouse:Right`, `Mouse:Middle`, `Mouse:4`).
+   pub fn parse(name: &str) -> Result<Self, ActionMapError> {
+      let name = name.trim();
+      if let Some(button) = name.strip_prefix("Mouse:") {
+         let button = match button {
+            "Left" => MouseButton::Left,
+            "Right" => MouseButton::Right,
+            "Middle" => MouseButton::Middle,
+            other => MouseButton::Other(
+               other
+                  .parse()
+                  .map_err(|_| ActionMapError::UnknownBinding(name.to_owned()))?,
+            ),
+         };
+         return Ok(Binding::Mouse(button));
+      }
+      let deserializer: StrDeserializer<ValueError> = name.into_deserializer();
+      VirtualKeyCode::deserialize(deserializer)
+         .map(Binding::Key)
+         .map_err(|_| ActionMapError::UnknownBinding(name.to_owned()))
+   }
+}
+
+#[derive(Debug)]
+pub enum ActionMapError {
+   Io(std::io::Error),
+   Parse(String),
+   UnknownBinding(String),
+}
+
+impl fmt::Display for ActionMapError {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         ActionMapError::Io(err) => write!(f, "failed to read action map: {}", err),
+         ActionMapError::Parse(err) => write!(f, "failed to parse action map: {}", err),
+         ActionMapError::UnknownBinding(name) => write!(f, "unknown input binding '{}'", name),
+      }
+   }
+}
+
+impl std::error::Error for ActionMapError {}
+
+/// Maps action names like `move_forward` to one or several bindings, so the app logic
+/// doesn't depend on concrete keys.
+///
+/// The config file is TOML with a single `[actions]` table:
+/// ```toml
+/// [actions]
+/// move_forward = ["W", "Up"]
+/// look = ["Mouse:Right"]
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ActionMap {
+   bindings: HashMap<String, Vec<Binding>>,
+}
+
+impl ActionMap {
+   pub fn new() -> Self {
+      Default::default()
+   }
+
+   pub fn load(path: impl AsRef<Path>) -> Result<Self, ActionMapError> {
+      let text = std::fs::read_to_string(path).map_err(ActionMapError::Io)?;
+      Self::from_toml_str(&text)
+   }
+
+   pub fn from_toml_str(text: &str) -> Result<Self, ActionMapError> {
+      let config: toml::Value =
+         toml::from_str(text).map_err(|err| ActionMapError::Parse(err.to_string()))?;
+      let actions = match config.get("actions") {
+         Some(toml::Value::Table(actions)) => actions,
+         Some(_) => return Err(ActionMapError::Parse("'actions' must be a table".to_owned())),
+         None => return Ok(Self::new()),
+      };
+      let mut map = Self::new();
+      for (action, bindings) in actions {
+         let bindings = match bindings {
+            toml::Value::String(binding) => vec![binding.as_str()],
+            toml::Value::Array(bindings) => bindings
+               .iter()
+               .map(|binding| binding.as_str().ok_or_else(|| {
+                  ActionMapError::Parse(format!("bindings of '{}' must be strings", action))
+               }))
+               .collect::<Result<_, _>>()?,
+            _ => return Err(ActionMapError::Parse(
+               format!("'{}' must be a binding or an array of bindings", action))),
+         };
+         for binding in bindings {
+            map.bind(action, Binding::parse(binding)?);
+         }
+      }
+      Ok(map)
+   }
+
+   pub fn bind(&mut self, action: &str, binding: Binding) -> &mut Self {
+      let bindings = self.bindings.entry(action.to_owned()).or_default();
+      if !bindings.contains(&binding) {
+         bindings.push(binding);
+      }
+      self
+   }
+
+   pub fn unbind_all(&mut self, action: &str) {
+      self.bindings.remove(action);
+   }
+
+   /// Bindings of the action, empty if the action is unknown
+   pub fn bindings(&self, action: &str) -> &[Binding] {
+      self.bindings.get(action).map_or(&[], Vec::as_slice)
+   }
+
+   pub fn actions(&self) -> impl Iterator<Item = &str> {
+      self.bindings.keys().map(String::as_str)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn parses_keys_and_mouse_buttons() {
+      assert_eq!(Binding::parse("W").unwrap(), Binding::Key(VirtualKeyCode::W));
+      assert_eq!(Binding::parse(" LShift ").unwrap(), Binding::Key(VirtualKeyCode::LShift));
+      assert_eq!(Binding::parse("Mouse:Left").unwrap(), Binding::Mouse(MouseButton::Left));
+      assert_eq!(Binding::parse("Mouse:Middle").unwrap(), Binding::Mouse(MouseButton::Middle));
+      assert_eq!(Binding::parse("Mouse:4").unwrap(), Binding::Mouse(MouseButton::Other(4)));
+   }
+
+   #[test]
+   fn rejects_unknown_bindings() {
+      for name in ["NoSuchKey", "w", "Mouse:", "Mouse:Sideways", "Mouse:-1", ""] {
+         assert!(
+            matches!(Binding::parse(name), Err(ActionMapError::UnknownBinding(_))),
+            "'{}' parsed",
+            name
+         );
+      }
+   }
+
+   #[test]
+   fn loads_single_and_multiple_bindings() {
+      let map = ActionMap::from_toml_str(
+         r#"
+         [actions]
+         move_forward = ["W", "Up", "W"]
+         look = "Mouse:Right"
+         "#,
+      )
+      .unwrap();
+      assert_eq!(
+         map.bindings("move_forward"),
+         [Binding::Key(VirtualKeyCode::W), Binding::Key(VirtualKeyCode::Up)]
+      );
+      assert_eq!(map.bindings("look"), [Binding::Mouse(MouseButton::Right)]);
+      assert!(map.bindings("jump").is_empty());
+   }
+
+   #[test]
+   fn missing_actions_table_is_empty() {
+      let map = ActionMap::from_toml_str("title = 'demo'").unwrap();
+      assert_eq!(map.actions().count(), 0);
+   }
+
+   #[test]
+   fn reports_toml_errors() {
+      let parse_error = |text: &str| matches!(ActionMap::from_toml_str(text), Err(ActionMapError::Parse(_)));
+      assert!(parse_error("[actions"));
+      assert!(parse_error("actions = 1"));
+      assert!(parse_error("[actions]\njump = 1"));
+      assert!(parse_error("[actions]\njump = ['Space', 2]"));
+      assert!(matches!(
+         ActionMap::from_toml_str("[actions]\njump = ['Spacebar']"),
+         Err(ActionMapError::UnknownBinding(name)) if name == "Spacebar"
+      ));
+   }
+}
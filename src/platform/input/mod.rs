@@ -0,0 +1,370 @@
+mod action;
+mod record;
+
+pub use action::{ActionMap, ActionMapError, Binding};
+pub use record::{InputEvent, InputRecorder, InputReplay, InputSession, RecordedFrame, RecordingError};
+
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use cgmath::{Vector2, Zero};
+use winit::event::{DeviceEvent, ElementState, MouseButton, VirtualKeyCode, WindowEvent};
+
+/// Scroll distance of one wheel "line", in pixels. Used to bring line and pixel deltas to one unit.
+const PIXELS_PER_SCROLL_LINE: f32 = 20.0;
+
+/// Held/pressed/released state of buttons of a single device.
+#[derive(Clone, Debug)]
+struct ButtonState<T> {
+   held: HashSet<T>,
+   pressed: HashSet<T>,
+   released: HashSet<T>,
+}
+
+impl<T: Copy + Eq + Hash> ButtonState<T> {
+   fn new() -> Self {
+      ButtonState {
+         held: HashSet::new(),
+         pressed: HashSet::new(),
+         released: HashSet::new(),
+      }
+   }
+
+   fn set(&mut self, button: T, state: ElementState) {
+      match state {
+         // Key repeats arrive as presses of an already held key, they are not new presses
+         ElementState::Pressed => if self.held.insert(button) {
+            self.pressed.insert(button);
+         },
+         ElementState::Released => if self.held.remove(&button) {
+            self.released.insert(button);
+         },
+      }
+   }
+
+   fn release_all(&mut self) {
+      self.released.extend(self.held.drain());
+   }
+
+   fn end_frame(&mut self) {
+      self.pressed.clear();
+      self.released.clear();
+   }
+}
+
+/// Snapshot of keyboard, mouse and window focus state, updated from winit events.
+/// "Pressed" and "released" queries refer to transitions that happened since the previous frame,
+/// "held" queries refer to the current state.
+#[derive(Clone, Debug)]
+pub struct Input {
+   keys: ButtonState<VirtualKeyCode>,
+   mouse_buttons: ButtonState<MouseButton>,
+   cursor_position: Option<Vector2<f32>>,
+   cursor_delta: Vector2<f32>,
+   mouse_motion: Vector2<f32>,
+   scroll_delta: Vector2<f32>,
+   focused: bool,
+   focus_changed: bool,
+   surface_size: Vector2<u32>,
+   action_map: ActionMap,
+}
+
+impl Input {
+   pub fn new() -> Self {
+      Input {
+         keys: ButtonState::new(),
+         mouse_buttons: ButtonState::new(),
+         cursor_position: None,
+         cursor_delta: Vector2::zero(),
+         mouse_motion: Vector2::zero(),
+         scroll_delta: Vector2::zero(),
+         focused: true,
+         focus_changed: false,
+         surface_size: Vector2::zero(),
+         action_map: ActionMap::new(),
+      }
+   }
+
+   pub fn with_action_map(mut self, action_map: ActionMap) -> Self {
+      self.action_map = action_map;
+      self
+   }
+
+   pub fn set_action_map(&mut self, action_map: ActionMap) {
+      self.action_map = action_map;
+   }
+
+   pub fn action_map(&self) -> &ActionMap {
+      &self.action_map
+   }
+
+   pub fn handle_window_event(&mut self, event: &WindowEvent) {
+      if let Some(event) = InputEvent::from_window_event(event) {
+         self.apply(event);
+      }
+   }
+
+   pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+      if let Some(event) = InputEvent::from_device_event(event) {
+         self.apply(event);
+      }
+   }
+
+   pub fn apply(&mut self, event: InputEvent) {
+      match event {
+         InputEvent::Key(key, state) => self.keys.set(key, state),
+         InputEvent::MouseButton(button, state) => self.mouse_buttons.set(button, state),
+         InputEvent::CursorMoved(position) => {
+            if let Some(previous) = self.cursor_position {
+               self.cursor_delta += position - previous;
+            }
+            self.cursor_position = Some(position);
+         }
+         InputEvent::CursorLeft => self.cursor_position = None,
+         InputEvent::Scroll(delta) => self.scroll_delta += delta,
+         InputEvent::Focused(focused) => {
+            // Release events of keys held while switching windows never arrive
+            if !focused {
+               self.keys.release_all();
+               self.mouse_buttons.release_all();
+            }
+            self.focus_changed |= self.focused != focused;
+            self.focused = focused;
+         }
+         InputEvent::MouseMotion(delta) => if self.focused {
+            self.mouse_motion += delta;
+         },
+         InputEvent::Resized(size) => self.surface_size = size,
+      }
+   }
+
+   /// Clears per-frame transitions and deltas, must be called after the frame has consumed the input.
+   pub fn end_frame(&mut self) {
+      self.keys.end_frame();
+      self.mouse_buttons.end_frame();
+      self.cursor_delta = Vector2::zero();
+      self.mouse_motion = Vector2::zero();
+      self.scroll_delta = Vector2::zero();
+      self.focus_changed = false;
+   }
+
+   pub fn key_held(&self, key: VirtualKeyCode) -> bool {
+      self.keys.held.contains(&key)
+   }
+
+   pub fn key_pressed(&self, key: VirtualKeyCode) -> bool {
+      self.keys.pressed.contains(&key)
+   }
+
+   pub fn key_released(&self, key: VirtualKeyCode) -> bool {
+      self.keys.released.contains(&key)
+   }
+
+   pub fn mouse_held(&self, button: MouseButton) -> bool {
+      self.mouse_buttons.held.contains(&button)
+   }
+
+   pub fn mouse_pressed(&self, button: MouseButton) -> bool {
+      self.mouse_buttons.pressed.contains(&button)
+   }
+
+   pub fn mouse_released(&self, button: MouseButton) -> bool {
+      self.mouse_buttons.released.contains(&button)
+   }
+
+   /// Cursor position in physical pixels relative to the top-left corner of the window,
+   /// `None` if the cursor is outside of the window
+   pub fn cursor_position(&self) -> Option<Vector2<f32>> {
+      self.cursor_position
+   }
+
+   /// Cursor movement within the window since the previous frame, in physical pixels
+   pub fn cursor_delta(&self) -> Vector2<f32> {
+      self.cursor_delta
+   }
+
+   /// Raw mouse movement since the previous frame, not limited by the window borders and
+   /// not affected by pointer acceleration. Prefer it for camera look controls
+   pub fn mouse_motion(&self) -> Vector2<f32> {
+      self.mouse_motion
+   }
+
+   /// Scroll since the previous frame in pixels, positive `y` is scrolling up
+   pub fn scroll_delta(&self) -> Vector2<f32> {
+      self.scroll_delta
+   }
+
+   pub fn focused(&self) -> bool {
+      self.focused
+   }
+
+   pub fn focus_changed(&self) -> bool {
+      self.focus_changed
+   }
+
+   /// Extent of the surface rendered to from the latest `InputEvent::Resized`, zero before the first frame.
+   /// When replaying, the recorded one
+   pub fn surface_size(&self) -> Vector2<u32> {
+      self.surface_size
+   }
+
+   pub fn binding_held(&self, binding: Binding) -> bool {
+      match binding {
+         Binding::Key(key) => self.key_held(key),
+         Binding::Mouse(button) => self.mouse_held(button),
+      }
+   }
+
+   pub fn binding_pressed(&self, binding: Binding) -> bool {
+      match binding {
+         Binding::Key(key) => self.key_pressed(key),
+         Binding::Mouse(button) => self.mouse_pressed(button),
+      }
+   }
+
+   pub fn binding_released(&self, binding: Binding) -> bool {
+      match binding {
+         Binding::Key(key) => self.key_released(key),
+         Binding::Mouse(button) => self.mouse_released(button),
+      }
+   }
+
+   /// True if any binding of the action is held
+   pub fn action_held(&self, action: &str) -> bool {
+      self.action_map.bindings(action).iter().any(|&binding| self.binding_held(binding))
+   }
+
+   /// True if a binding of the action was pressed this frame, while no other binding was already held
+   pub fn action_pressed(&self, action: &str) -> bool {
+      let bindings = self.action_map.bindings(action);
+      bindings.iter().any(|&binding| self.binding_pressed(binding))
+         && bindings.iter().all(|&binding| self.binding_pressed(binding) || !self.binding_held(binding))
+   }
+
+   /// True if the last held binding of the action was released this frame
+   pub fn action_released(&self, action: &str) -> bool {
+      let bindings = self.action_map.bindings(action);
+      bindings.iter().any(|&binding| self.binding_released(binding))
+         && !bindings.iter().any(|&binding| self.binding_held(binding))
+   }
+
+   /// -1, 0 or 1 depending on which of two opposite actions is held, e.g. for movement axes
+   pub fn action_axis(&self, negative: &str, positive: &str) -> f32 {
+      let to_f32 = |held: bool| if held { 1.0 } else { 0.0 };
+      to_f32(self.action_held(positive)) - to_f32(self.action_held(negative))
+   }
+}
+
+impl Default for Input {
+   fn default() -> Self {
+      Self::new()
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn key(key: VirtualKeyCode, state: ElementState) -> InputEvent {
+      InputEvent::Key(key, state)
+   }
+
+   #[test]
+   fn key_goes_pressed_held_released() {
+      let mut input = Input::new();
+      input.apply(key(VirtualKeyCode::W, ElementState::Pressed));
+      assert!(input.key_pressed(VirtualKeyCode::W) && input.key_held(VirtualKeyCode::W));
+
+      input.end_frame();
+      // A key repeat is not a new press
+      input.apply(key(VirtualKeyCode::W, ElementState::Pressed));
+      assert!(!input.key_pressed(VirtualKeyCode::W) && input.key_held(VirtualKeyCode::W));
+
+      input.end_frame();
+      input.apply(key(VirtualKeyCode::W, ElementState::Released));
+      assert!(input.key_released(VirtualKeyCode::W) && !input.key_held(VirtualKeyCode::W));
+
+      input.end_frame();
+      assert!(!input.key_released(VirtualKeyCode::W));
+   }
+
+   #[test]
+   fn press_and_release_within_a_frame_are_both_seen() {
+      let mut input = Input::new();
+      input.apply(InputEvent::MouseButton(MouseButton::Left, ElementState::Pressed));
+      input.apply(InputEvent::MouseButton(MouseButton::Left, ElementState::Released));
+      assert!(input.mouse_pressed(MouseButton::Left));
+      assert!(input.mouse_released(MouseButton::Left));
+      assert!(!input.mouse_held(MouseButton::Left));
+   }
+
+   #[test]
+   fn losing_focus_releases_held_buttons() {
+      let mut input = Input::new();
+      input.apply(key(VirtualKeyCode::A, ElementState::Pressed));
+      input.apply(InputEvent::MouseButton(MouseButton::Right, ElementState::Pressed));
+      input.end_frame();
+      input.apply(InputEvent::Focused(false));
+      assert!(input.focus_changed());
+      assert!(input.key_released(VirtualKeyCode::A) && !input.key_held(VirtualKeyCode::A));
+      assert!(input.mouse_released(MouseButton::Right));
+   }
+
+   #[test]
+   fn deltas_accumulate_and_reset_at_end_of_frame() {
+      let mut input = Input::new();
+      // The first position has nothing to be relative to
+      input.apply(InputEvent::CursorMoved(Vector2::new(10.0, 10.0)));
+      input.apply(InputEvent::CursorMoved(Vector2::new(13.0, 8.0)));
+      input.apply(InputEvent::CursorMoved(Vector2::new(15.0, 9.0)));
+      input.apply(InputEvent::Scroll(Vector2::new(0.0, 20.0)));
+      input.apply(InputEvent::Scroll(Vector2::new(5.0, -5.0)));
+      input.apply(InputEvent::MouseMotion(Vector2::new(1.0, 2.0)));
+      assert_eq!(input.cursor_delta(), Vector2::new(5.0, -1.0));
+      assert_eq!(input.scroll_delta(), Vector2::new(5.0, 15.0));
+      assert_eq!(input.mouse_motion(), Vector2::new(1.0, 2.0));
+
+      input.end_frame();
+      assert_eq!(input.cursor_delta(), Vector2::zero());
+      assert_eq!(input.scroll_delta(), Vector2::zero());
+      assert_eq!(input.mouse_motion(), Vector2::zero());
+      assert_eq!(input.cursor_position(), Some(Vector2::new(15.0, 9.0)));
+
+      input.apply(InputEvent::CursorLeft);
+      input.apply(InputEvent::CursorMoved(Vector2::new(0.0, 0.0)));
+      assert_eq!(input.cursor_delta(), Vector2::zero());
+   }
+
+   #[test]
+   fn mouse_motion_is_ignored_without_focus() {
+      let mut input = Input::new();
+      input.apply(InputEvent::Focused(false));
+      input.apply(InputEvent::MouseMotion(Vector2::new(1.0, 2.0)));
+      assert_eq!(input.mouse_motion(), Vector2::zero());
+   }
+
+   #[test]
+   fn actions_follow_any_of_their_bindings() {
+      let mut map = ActionMap::new();
+      map.bind("forward", Binding::Key(VirtualKeyCode::W)).bind("forward", Binding::Key(VirtualKeyCode::Up));
+      map.bind("back", Binding::Key(VirtualKeyCode::S));
+      let mut input = Input::new().with_action_map(map);
+
+      input.apply(key(VirtualKeyCode::W, ElementState::Pressed));
+      assert!(input.action_pressed("forward"));
+      assert_eq!(input.action_axis("back", "forward"), 1.0);
+      input.end_frame();
+
+      // The action is already active, a second binding doesn't press it again
+      input.apply(key(VirtualKeyCode::Up, ElementState::Pressed));
+      assert!(!input.action_pressed("forward"));
+      input.apply(key(VirtualKeyCode::W, ElementState::Released));
+      assert!(!input.action_released("forward"));
+      input.end_frame();
+
+      input.apply(key(VirtualKeyCode::Up, ElementState::Released));
+      assert!(input.action_released("forward"));
+      assert_eq!(input.action_axis("back", "forward"), 0.0);
+      assert!(!input.action_held("unknown"));
+   }
+}
@@ -0,0 +1,518 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use cgmath::{vec2, Vector2};
+use serde::de::value::{Error as ValueError, U32Deserializer};
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+use winit::event::{
+   DeviceEvent, ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
+   WindowEvent,
+};
+
+use super::{Input, PIXELS_PER_SCROLL_LINE};
+
+const MAGIC: &[u8; 8] = b"CUPIOREC";
+const VERSION: u32 = 2;
+
+/// Window-independent input event, the unit of recording and replay.
+/// Everything that `Input` reacts to is expressed by one of these, along with the size of the surface
+/// rendered to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+   Key(VirtualKeyCode, ElementState),
+   MouseButton(MouseButton, ElementState),
+   CursorMoved(Vector2<f32>),
+   CursorLeft,
+   Scroll(Vector2<f32>),
+   Focused(bool),
+   MouseMotion(Vector2<f32>),
+   /// Extent of the surface in pixels. The render loops send it on the first frame and after the
+   /// swapchain is recreated with another extent
+   Resized(Vector2<u32>),
+}
+
+impl InputEvent {
+   pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+      match *event {
+         WindowEvent::KeyboardInput {
+            input: KeyboardInput {
+               state,
+               virtual_keycode: Some(key),
+               ..
+            },
+            ..
+         } => Some(InputEvent::Key(key, state)),
+         WindowEvent::MouseInput { state, button, .. } => Some(InputEvent::MouseButton(button, state)),
+         WindowEvent::CursorMoved { position, .. } =>
+            Some(InputEvent::CursorMoved(vec2(position.x as f32, position.y as f32))),
+         WindowEvent::CursorLeft { .. } => Some(InputEvent::CursorLeft),
+         WindowEvent::MouseWheel { delta, .. } => Some(InputEvent::Scroll(match delta {
+            MouseScrollDelta::LineDelta(x, y) => vec2(x, y) * PIXELS_PER_SCROLL_LINE,
+            MouseScrollDelta::PixelDelta(position) => vec2(position.x as f32, position.y as f32),
+         })),
+         WindowEvent::Focused(focused) => Some(InputEvent::Focused(focused)),
+         _ => None,
+      }
+   }
+
+   pub fn from_device_event(event: &DeviceEvent) -> Option<Self> {
+      match *event {
+         DeviceEvent::MouseMotion { delta: (x, y) } =>
+            Some(InputEvent::MouseMotion(vec2(x as f32, y as f32))),
+         _ => None,
+      }
+   }
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+   Io(io::Error),
+   BadHeader,
+   UnsupportedVersion(u32),
+   Corrupted(&'static str),
+   BadArguments(String),
+}
+
+impl fmt::Display for RecordingError {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         RecordingError::Io(err) => write!(f, "input recording io error: {}", err),
+         RecordingError::BadHeader => write!(f, "not an input recording file"),
+         RecordingError::UnsupportedVersion(version) =>
+            write!(f, "unsupported input recording version {}, expected {}", version, VERSION),
+         RecordingError::Corrupted(reason) => write!(f, "corrupted input recording: {}", reason),
+         RecordingError::BadArguments(reason) => write!(f, "{}", reason),
+      }
+   }
+}
+
+impl std::error::Error for RecordingError {}
+
+impl From<io::Error> for RecordingError {
+   fn from(err: io::Error) -> Self {
+      RecordingError::Io(err)
+   }
+}
+
+/// Input events that arrived before a frame, along with the frame delta time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedFrame {
+   pub delta_time: Duration,
+   pub events: Vec<InputEvent>,
+}
+
+/// Streams frames to a file, or any other writer. Layout: magic, version, then per frame a varint
+/// delta time in nanoseconds, a varint event count and the events, each as a tag byte and a payload.
+/// Floats are stored bit-exact, so the replay reproduces `Input` state precisely.
+pub struct InputRecorder<W: Write = BufWriter<File>> {
+   writer: W,
+   pending_events: Vec<InputEvent>,
+   n_frames: u64,
+}
+
+impl InputRecorder {
+   pub fn create(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+      Self::new(BufWriter::new(File::create(path)?))
+   }
+}
+
+impl<W: Write> InputRecorder<W> {
+   pub fn new(mut writer: W) -> Result<Self, RecordingError> {
+      writer.write_all(MAGIC)?;
+      writer.write_all(&VERSION.to_le_bytes())?;
+      Ok(InputRecorder {
+         writer,
+         pending_events: Vec::new(),
+         n_frames: 0,
+      })
+   }
+
+   pub fn push_event(&mut self, event: InputEvent) {
+      self.pending_events.push(event);
+   }
+
+   /// Writes the frame consisting of the events pushed since the previous frame
+   pub fn end_frame(&mut self, delta_time: Duration) -> Result<(), RecordingError> {
+      write_varint(&mut self.writer, delta_time.as_nanos() as u64)?;
+      write_varint(&mut self.writer, self.pending_events.len() as u64)?;
+      for event in self.pending_events.drain(..) {
+         write_event(&mut self.writer, event)?;
+      }
+      self.n_frames += 1;
+      Ok(())
+   }
+
+   pub fn n_frames(&self) -> u64 {
+      self.n_frames
+   }
+
+   /// Flushes and returns the writer
+   pub fn finish(mut self) -> Result<W, RecordingError> {
+      self.writer.flush()?;
+      Ok(self.writer)
+   }
+}
+
+/// Frames of a recording, loaded fully into memory.
+pub struct InputReplay {
+   frames: Vec<RecordedFrame>,
+   next_frame: usize,
+}
+
+impl InputReplay {
+   pub fn open(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+      Self::read(BufReader::new(File::open(path)?))
+   }
+
+   pub fn read(mut reader: impl Read) -> Result<Self, RecordingError> {
+      let mut magic = [0u8; 8];
+      reader.read_exact(&mut magic).map_err(|_| RecordingError::BadHeader)?;
+      if &magic != MAGIC {
+         return Err(RecordingError::BadHeader);
+      }
+      let version = u32::from_le_bytes(read_array(&mut reader)?);
+      if version != VERSION {
+         return Err(RecordingError::UnsupportedVersion(version));
+      }
+      let mut frames = Vec::new();
+      while let Some(delta_nanos) = read_varint_or_eof(&mut reader)? {
+         let n_events = read_varint(&mut reader)?;
+         let events = (0..n_events)
+            .map(|_| read_event(&mut reader))
+            .collect::<Result<_, _>>()?;
+         frames.push(RecordedFrame {
+            delta_time: Duration::from_nanos(delta_nanos),
+            events,
+         });
+      }
+      Ok(InputReplay { frames, next_frame: 0 })
+   }
+
+   pub fn next_frame(&mut self) -> Option<&RecordedFrame> {
+      let frame = self.frames.get(self.next_frame)?;
+      self.next_frame += 1;
+      Some(frame)
+   }
+
+   /// Applies events of the next frame to the input, returns the frame delta time.
+   /// `None` after the last frame
+   pub fn apply_next_frame(&mut self, input: &mut Input) -> Option<Duration> {
+      let frame = self.next_frame()?;
+      frame.events.iter().for_each(|&event| input.apply(event));
+      Some(frame.delta_time)
+   }
+
+   pub fn frames(&self) -> &[RecordedFrame] {
+      &self.frames
+   }
+
+   pub fn rewind(&mut self) {
+      self.next_frame = 0;
+   }
+}
+
+/// Where `render_loop` takes input from.
+#[derive(Default)]
+pub enum InputSession {
+   /// Window events, as usual
+   #[default]
+   Live,
+   /// Window events, additionally written to a recording
+   Record(InputRecorder),
+   /// Recorded events and frame delta times, window input is ignored. The loop exits after the last frame
+   Replay(InputReplay),
+}
+
+impl InputSession {
+   /// Picks the session from `--record <file>` or `--replay <file>` command line arguments,
+   /// `Live` if there are none
+   pub fn from_args() -> Result<Self, RecordingError> {
+      let mut args = std::env::args().skip(1);
+      while let Some(arg) = args.next() {
+         let mut path = || args.next()
+            .ok_or_else(|| RecordingError::BadArguments(format!("{} expects a file path", arg)));
+         match arg.as_str() {
+            "--record" => return Ok(InputSession::Record(InputRecorder::create(path()?)?)),
+            "--replay" => return Ok(InputSession::Replay(InputReplay::open(path()?)?)),
+            _ => (),
+         }
+      }
+      Ok(InputSession::Live)
+   }
+}
+
+fn write_event(writer: &mut impl Write, event: InputEvent) -> io::Result<()> {
+   let pressed = |state| state == ElementState::Pressed;
+   match event {
+      InputEvent::Key(key, state) => {
+         writer.write_all(&[if pressed(state) { 0 } else { 1 }])?;
+         write_varint(writer, key as u32 as u64)
+      }
+      InputEvent::MouseButton(button, state) => {
+         writer.write_all(&[if pressed(state) { 2 } else { 3 }])?;
+         write_varint(writer, match button {
+            MouseButton::Left => 0,
+            MouseButton::Right => 1,
+            MouseButton::Middle => 2,
+            MouseButton::Other(index) => 3 + u64::from(index),
+         })
+      }
+      InputEvent::CursorMoved(position) => {
+         writer.write_all(&[4])?;
+         write_vec2(writer, position)
+      }
+      InputEvent::CursorLeft => writer.write_all(&[5]),
+      InputEvent::Scroll(delta) => {
+         writer.write_all(&[6])?;
+         write_vec2(writer, delta)
+      }
+      InputEvent::Focused(focused) => writer.write_all(&[if focused { 7 } else { 8 }]),
+      InputEvent::MouseMotion(delta) => {
+         writer.write_all(&[9])?;
+         write_vec2(writer, delta)
+      }
+      InputEvent::Resized(size) => {
+         writer.write_all(&[10])?;
+         write_varint(writer, u64::from(size.x))?;
+         write_varint(writer, u64::from(size.y))
+      }
+   }
+}
+
+fn read_event(reader: &mut impl Read) -> Result<InputEvent, RecordingError> {
+   let [tag] = read_array(reader)?;
+   let state = |tag| if tag % 2 == 0 { ElementState::Pressed } else { ElementState::Released };
+   Ok(match tag {
+      0 | 1 => {
+         let index = u32::try_from(read_varint(reader)?)
+            .map_err(|_| RecordingError::Corrupted("key code out of range"))?;
+         let deserializer: U32Deserializer<ValueError> = index.into_deserializer();
+         let key = VirtualKeyCode::deserialize(deserializer)
+            .map_err(|_| RecordingError::Corrupted("unknown key code"))?;
+         InputEvent::Key(key, state(tag))
+      }
+      2 | 3 => {
+         let button = match read_varint(reader)? {
+            0 => MouseButton::Left,
+            1 => MouseButton::Right,
+            2 => MouseButton::Middle,
+            index => MouseButton::Other(u16::try_from(index - 3)
+               .map_err(|_| RecordingError::Corrupted("mouse button out of range"))?),
+         };
+         InputEvent::MouseButton(button, state(tag))
+      }
+      4 => InputEvent::CursorMoved(read_vec2(reader)?),
+      5 => InputEvent::CursorLeft,
+      6 => InputEvent::Scroll(read_vec2(reader)?),
+      7 => InputEvent::Focused(true),
+      8 => InputEvent::Focused(false),
+      9 => InputEvent::MouseMotion(read_vec2(reader)?),
+      10 => {
+         let mut dimension = || u32::try_from(read_varint(reader)?)
+            .map_err(|_| RecordingError::Corrupted("size out of range"));
+         InputEvent::Resized(vec2(dimension()?, dimension()?))
+      }
+      _ => return Err(RecordingError::Corrupted("unknown event tag")),
+   })
+}
+
+fn write_vec2(writer: &mut impl Write, value: Vector2<f32>) -> io::Result<()> {
+   writer.write_all(&value.x.to_bits().to_le_bytes())?;
+   writer.write_all(&value.y.to_bits().to_le_bytes())
+}
+
+fn read_vec2(reader: &mut impl Read) -> Result<Vector2<f32>, RecordingError> {
+   let x = f32::from_bits(u32::from_le_bytes(read_array(reader)?));
+   let y = f32::from_bits(u32::from_le_bytes(read_array(reader)?));
+   Ok(vec2(x, y))
+}
+
+fn write_varint(writer: &mut impl Write, mut value: u64) -> io::Result<()> {
+   loop {
+      let byte = (value & 0x7f) as u8;
+      value >>= 7;
+      if value == 0 {
+         return writer.write_all(&[byte]);
+      }
+      writer.write_all(&[byte | 0x80])?;
+   }
+}
+
+fn read_varint(reader: &mut impl Read) -> Result<u64, RecordingError> {
+   read_varint_or_eof(reader)?.ok_or(RecordingError::Corrupted("unexpected end of file"))
+}
+
+/// Reads a varint, `None` if the reader is exhausted before the first byte
+fn read_varint_or_eof(reader: &mut impl Read) -> Result<Option<u64>, RecordingError> {
+   let mut value = 0u64;
+   for shift in (0..64).step_by(7) {
+      let mut byte = [0u8];
+      if reader.read(&mut byte)? == 0 {
+         return if shift == 0 {
+            Ok(None)
+         } else {
+            Err(RecordingError::Corrupted("unexpected end of file"))
+         };
+      }
+      value |= u64::from(byte[0] & 0x7f) << shift;
+      if byte[0] & 0x80 == 0 {
+         return Ok(Some(value));
+      }
+   }
+   Err(RecordingError::Corrupted("varint is too long"))
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N], RecordingError> {
+   let mut bytes = [0u8; N];
+   reader.read_exact(&mut bytes)
+      .map_err(|_| RecordingError::Corrupted("unexpected end of file"))?;
+   Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const HEADER_LEN: usize = MAGIC.len() + 4;
+
+   fn frames() -> Vec<RecordedFrame> {
+      vec![
+         RecordedFrame {
+            delta_time: Duration::from_millis(16),
+            events: vec![
+               InputEvent::Resized(vec2(1280, 720)),
+               InputEvent::Focused(true),
+               InputEvent::Key(VirtualKeyCode::W, ElementState::Pressed),
+               InputEvent::MouseButton(MouseButton::Other(300), ElementState::Released),
+               InputEvent::CursorMoved(vec2(12.5, -0.25)),
+            ],
+         },
+         RecordedFrame {
+            delta_time: Duration::from_nanos(1),
+            events: Vec::new(),
+         },
+         RecordedFrame {
+            delta_time: Duration::from_secs(3),
+            events: vec![
+               InputEvent::Key(VirtualKeyCode::W, ElementState::Released),
+               InputEvent::MouseButton(MouseButton::Left, ElementState::Pressed),
+               InputEvent::CursorLeft,
+               InputEvent::Scroll(vec2(0.0, f32::MIN_POSITIVE)),
+               InputEvent::MouseMotion(vec2(-3.0, 1e9)),
+               InputEvent::Resized(vec2(1, u32::MAX)),
+               InputEvent::Focused(false),
+            ],
+         },
+      ]
+   }
+
+   fn record(frames: &[RecordedFrame]) -> Vec<u8> {
+      let mut recorder = InputRecorder::new(Vec::new()).unwrap();
+      for frame in frames {
+         frame.events.iter().for_each(|&event| recorder.push_event(event));
+         recorder.end_frame(frame.delta_time).unwrap();
+      }
+      assert_eq!(recorder.n_frames(), frames.len() as u64);
+      recorder.finish().unwrap()
+   }
+
+   #[test]
+   fn write_read_round_trip() {
+      let bytes = record(&frames());
+      assert_eq!(&bytes[..MAGIC.len()], MAGIC);
+      let mut replay = InputReplay::read(&bytes[..]).unwrap();
+      assert_eq!(replay.frames(), &frames()[..]);
+
+      assert_eq!(replay.next_frame(), Some(&frames()[0]));
+      replay.rewind();
+      assert_eq!(replay.next_frame(), Some(&frames()[0]));
+   }
+
+   #[test]
+   fn empty_recording_has_no_frames() {
+      let bytes = record(&[]);
+      assert_eq!(bytes.len(), HEADER_LEN);
+      assert!(InputReplay::read(&bytes[..]).unwrap().frames().is_empty());
+   }
+
+   #[test]
+   fn replay_drives_input_without_window() {
+      let mut replay = InputReplay::read(&record(&frames())[..]).unwrap();
+      let mut input = Input::new();
+
+      assert_eq!(replay.apply_next_frame(&mut input), Some(Duration::from_millis(16)));
+      assert!(input.key_pressed(VirtualKeyCode::W));
+      assert_eq!(input.cursor_position(), Some(vec2(12.5, -0.25)));
+      assert_eq!(input.surface_size(), vec2(1280, 720));
+      input.end_frame();
+
+      assert_eq!(replay.apply_next_frame(&mut input), Some(Duration::from_nanos(1)));
+      assert!(input.key_held(VirtualKeyCode::W) && !input.key_pressed(VirtualKeyCode::W));
+      input.end_frame();
+
+      assert_eq!(replay.apply_next_frame(&mut input), Some(Duration::from_secs(3)));
+      assert!(input.key_released(VirtualKeyCode::W));
+      assert_eq!(input.cursor_position(), None);
+      assert_eq!(input.surface_size(), vec2(1, u32::MAX));
+      assert!(!input.focused());
+      assert_eq!(replay.apply_next_frame(&mut input), None);
+   }
+
+   #[test]
+   fn truncated_recording_is_an_error() {
+      let bytes = record(&frames());
+      assert!(matches!(InputReplay::read(&bytes[..0]), Err(RecordingError::BadHeader)));
+      assert!(matches!(InputReplay::read(&bytes[..MAGIC.len() - 1]), Err(RecordingError::BadHeader)));
+      assert!(matches!(InputReplay::read(&bytes[..HEADER_LEN - 1]), Err(RecordingError::Corrupted(_))));
+      assert!(matches!(InputReplay::read(&bytes[..bytes.len() - 1]), Err(RecordingError::Corrupted(_))));
+      // Cutting at a frame boundary yields a shorter recording, anywhere else is corrupted
+      for len in HEADER_LEN..bytes.len() {
+         match InputReplay::read(&bytes[..len]) {
+            Ok(replay) => assert!(replay.frames().len() < frames().len()),
+            Err(err) => assert!(matches!(err, RecordingError::Corrupted(_)), "{}", err),
+         }
+      }
+   }
+
+   #[test]
+   fn bad_magic_is_an_error() {
+      let mut bytes = record(&frames());
+      bytes[0] = b'X';
+      assert!(matches!(InputReplay::read(&bytes[..]), Err(RecordingError::BadHeader)));
+   }
+
+   #[test]
+   fn unsupported_version_is_an_error() {
+      let mut bytes = record(&frames());
+      bytes[MAGIC.len()..HEADER_LEN].copy_from_slice(&(VERSION + 1).to_le_bytes());
+      assert!(matches!(
+         InputReplay::read(&bytes[..]),
+         Err(RecordingError::UnsupportedVersion(version)) if version == VERSION + 1
+      ));
+   }
+
+   #[test]
+   fn corrupted_events_are_errors() {
+      let header = record(&[]);
+      let frame = |payload: &[u8]| {
+         let mut bytes = header.clone();
+         bytes.extend_from_slice(&[0, 1]);
+         bytes.extend_from_slice(payload);
+         InputReplay::read(&bytes[..])
+      };
+      assert!(matches!(frame(&[11]), Err(RecordingError::Corrupted("unknown event tag"))));
+      let too_wide = [10, 0xff, 0xff, 0xff, 0xff, 0x7f, 1];
+      assert!(matches!(frame(&too_wide), Err(RecordingError::Corrupted("size out of range"))));
+      assert!(matches!(frame(&[0, 0xff, 0x7f]), Err(RecordingError::Corrupted("unknown key code"))));
+      assert!(matches!(frame(&[2, 0xff, 0xff, 0x7f]), Err(RecordingError::Corrupted("mouse button out of range"))));
+      assert!(matches!(frame(&[4, 0, 0]), Err(RecordingError::Corrupted("unexpected end of file"))));
+
+      let mut bytes = header.clone();
+      bytes.extend_from_slice(&[0xff; 11]);
+      assert!(matches!(InputReplay::read(&bytes[..]), Err(RecordingError::Corrupted("varint is too long"))));
+   }
+}
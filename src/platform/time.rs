@@ -0,0 +1,211 @@
+use std::time::{Duration, Instant};
+
+/// Upper bound for a single frame delta. Protects simulation from huge steps after
+/// a breakpoint, a window drag or a long shader compilation hitch.
+const MAX_FRAME_DELTA: Duration = Duration::from_millis(250);
+
+/// Measures wall-clock time between consecutive frames.
+pub struct FrameTimer {
+   last_frame: Instant,
+   n_frames: u64,
+   delta_time: Duration,
+   total_time: Duration,
+}
+
+impl FrameTimer {
+   pub fn new() -> Self {
+      FrameTimer {
+         last_frame: Instant::now(),
+         n_frames: 0,
+         delta_time: Duration::ZERO,
+         total_time: Duration::ZERO,
+      }
+   }
+
+   /// Advances the timer, must be called exactly once per frame.
+   pub fn tick(&mut self) {
+      let now = Instant::now();
+      self.advance(now.duration_since(self.last_frame));
+      self.last_frame = now;
+   }
+
+   /// Advances the timer by a known delta instead of measuring it.
+   pub fn advance(&mut self, delta_time: Duration) {
+      self.n_frames += 1;
+      self.delta_time = delta_time.min(MAX_FRAME_DELTA);
+      self.total_time += self.delta_time;
+   }
+
+   /// Index of the current frame, starting from 0 after the first `tick`.
+   pub fn frame_index(&self) -> u64 {
+      self.n_frames.saturating_sub(1)
+   }
+
+   pub fn delta_time(&self) -> Duration {
+      self.delta_time
+   }
+
+   pub fn total_time(&self) -> Duration {
+      self.total_time
+   }
+}
+
+impl Default for FrameTimer {
+   fn default() -> Self {
+      Self::new()
+   }
+}
+
+/// Timing of the current frame, a world resource in `render_loop_with_schedule`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Time {
+   pub frame_index: u64,
+   /// Seconds elapsed since the previous frame
+   pub delta_time: f32,
+   /// Seconds elapsed since the loop has started
+   pub total_time: f32,
+   /// Fraction of a fixed step elapsed after the latest fixed update, in [0, 1)
+   pub fixed_alpha: f32,
+}
+
+/// One invocation of the fixed-timestep update.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedStep {
+   pub step_index: u64,
+   pub delta_time: f32,
+}
+
+/// Accumulator for running simulation at a constant rate, decoupled from the frame rate.
+/// The leftover accumulated time is exposed as `alpha` to interpolate between the two
+/// latest simulated states when rendering.
+pub struct FixedTimestep {
+   step: Duration,
+   max_steps_per_frame: u32,
+   accumulator: Duration,
+   step_index: u64,
+}
+
+impl FixedTimestep {
+   pub fn new(step: Duration) -> Self {
+      assert!(step > Duration::ZERO, "Fixed timestep must be positive");
+      FixedTimestep {
+         step,
+         max_steps_per_frame: 8,
+         accumulator: Duration::ZERO,
+         step_index: 0,
+      }
+   }
+
+   pub fn from_hz(hz: u32) -> Self {
+      Self::new(Duration::from_secs_f64(1.0 / f64::from(hz)))
+   }
+
+   /// Caps the number of updates per frame, so a slow update can't make every next frame slower.
+   /// Time that didn't fit is dropped.
+   pub fn with_max_steps_per_frame(mut self, max_steps_per_frame: u32) -> Self {
+      assert!(max_steps_per_frame > 0, "Must allow atleast 1 step per frame");
+      self.max_steps_per_frame = max_steps_per_frame;
+      self
+   }
+
+   pub fn step(&self) -> Duration {
+      self.step
+   }
+
+   /// Accumulates the frame delta and calls `update` for each whole step that fits into it.
+   pub fn run<U: FnMut(FixedStep)>(&mut self, frame_delta: Duration, mut update: U) {
+      self.accumulator += frame_delta;
+      let mut n_steps = 0;
+      while self.accumulator >= self.step {
+         if n_steps == self.max_steps_per_frame {
+            self.accumulator = Duration::ZERO;
+            break;
+         }
+         update(FixedStep {
+            step_index: self.step_index,
+            delta_time: self.step.as_secs_f32(),
+         });
+         self.accumulator -= self.step;
+         self.step_index += 1;
+         n_steps += 1;
+      }
+   }
+
+   /// Fraction of the next step that has already elapsed, in [0, 1).
+   pub fn alpha(&self) -> f32 {
+      (self.accumulator.as_secs_f64() / self.step.as_secs_f64()) as f32
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn ms(millis: u64) -> Duration {
+      Duration::from_millis(millis)
+   }
+
+   fn run(timestep: &mut FixedTimestep, frame_delta: Duration) -> Vec<u64> {
+      let mut steps = Vec::new();
+      timestep.run(frame_delta, |step| steps.push(step.step_index));
+      steps
+   }
+
+   #[test]
+   fn frame_timer_clamps_long_frames() {
+      let mut timer = FrameTimer::new();
+      assert_eq!(timer.frame_index(), 0);
+      timer.advance(ms(16));
+      assert_eq!(timer.frame_index(), 0);
+      timer.advance(Duration::from_secs(5));
+      assert_eq!(timer.frame_index(), 1);
+      assert_eq!(timer.delta_time(), MAX_FRAME_DELTA);
+      assert_eq!(timer.total_time(), ms(16) + MAX_FRAME_DELTA);
+   }
+
+   #[test]
+   fn accumulates_partial_steps_across_frames() {
+      let mut timestep = FixedTimestep::new(ms(10));
+      assert!(run(&mut timestep, ms(4)).is_empty());
+      assert!(run(&mut timestep, ms(4)).is_empty());
+      assert_eq!(run(&mut timestep, ms(4)), [0]);
+      assert_eq!(run(&mut timestep, ms(25)), [1, 2]);
+      assert!(run(&mut timestep, ms(2)).is_empty());
+      assert_eq!(run(&mut timestep, ms(1)), [3]);
+   }
+
+   #[test]
+   fn passes_the_step_as_delta_time() {
+      let mut timestep = FixedTimestep::from_hz(50);
+      let mut deltas = Vec::new();
+      timestep.run(ms(40), |step| deltas.push(step.delta_time));
+      assert_eq!(deltas, [0.02, 0.02]);
+   }
+
+   #[test]
+   fn drops_time_beyond_max_steps() {
+      let mut timestep = FixedTimestep::new(ms(10)).with_max_steps_per_frame(3);
+      assert_eq!(run(&mut timestep, ms(105)), [0, 1, 2]);
+      assert_eq!(timestep.alpha(), 0.0);
+      // Step indices continue, the dropped time doesn't carry over
+      assert_eq!(run(&mut timestep, ms(10)), [3]);
+   }
+
+   #[test]
+   fn alpha_is_the_elapsed_fraction_of_the_next_step() {
+      let mut timestep = FixedTimestep::new(ms(10));
+      assert_eq!(timestep.alpha(), 0.0);
+      run(&mut timestep, ms(4));
+      assert!((timestep.alpha() - 0.4).abs() < 1e-6);
+      run(&mut timestep, ms(13));
+      assert!((timestep.alpha() - 0.7).abs() < 1e-6);
+      run(&mut timestep, ms(3));
+      assert_eq!(timestep.alpha(), 0.0);
+   }
+
+   #[test]
+   #[should_panic(expected = "atleast 1 step")]
+   fn zero_max_steps_panics() {
+      FixedTimestep::new(ms(10)).with_max_steps_per_frame(0);
+   }
+}
@@ -0,0 +1,448 @@
+use ash::vk;
+
+use crate::ecs::{System, World};
+use crate::platform::gpu::vulkan_context::{FrameTarget, VulkanContext};
+use crate::platform::gpu::vulkan_descriptor::DescriptorAllocator;
+use crate::platform::gpu::vulkan_pipeline::VulkanPipelineLayout;
+use crate::platform::gpu::vulkan_texture::VulkanTexture;
+use crate::platform::gpu::VulkanDrop;
+
+use super::post::{cmd_begin_fullscreen, create_compute_pipeline, create_fullscreen_pipeline};
+use super::SceneColor;
+
+/// Invocations along X and Y of the downsampling and upsampling shaders
+const GROUP_SIZE: u32 = 8;
+
+/// Settings of `Bloom`, changeable between frames through `Bloom::settings_mut` except for
+/// `max_levels`, applied when the swapchain is recreated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomSettings {
+   /// Weight of the bloom in the mix with the scene color, 0 skips the pass
+   pub intensity: f32,
+   /// Radius of the tent filter upsampling each level, in texels of the smaller level
+   pub radius: f32,
+   /// Most levels of the chain, halving in size from half the scene color's. More levels spread
+   /// the glow wider
+   pub max_levels: u32,
+}
+
+impl Default for BloomSettings {
+   fn default() -> Self {
+      BloomSettings { intensity: 0.04, radius: 1.0, max_levels: 6 }
+   }
+}
+
+impl BloomSettings {
+   pub fn with_intensity(mut self, intensity: f32) -> Self {
+      self.intensity = intensity;
+      self
+   }
+
+   pub fn with_radius(mut self, radius: f32) -> Self {
+      self.radius = radius;
+      self
+   }
+
+   pub fn with_max_levels(mut self, max_levels: u32) -> Self {
+      self.max_levels = max_levels;
+      self
+   }
+}
+
+/// Push constants of the bloom shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct BloomConstants {
+   radius: f32,
+   /// Whether the downsample averages its taps weighted by inverse luminance, for the first level
+   karis_average: u32,
+   /// Scale of the bloom mixed into the scene color
+   scale: f32,
+}
+
+/// Levels of the bloom chain sized for the scene color, with a view of each
+struct BloomChain {
+   texture: VulkanTexture,
+   level_views: Vec<vk::ImageView>,
+}
+
+impl BloomChain {
+   fn new(
+      device: &ash::Device,
+      memory_properties: &vk::PhysicalDeviceMemoryProperties,
+      scene_extent: vk::Extent2D,
+      max_levels: u32,
+   ) -> Self {
+      let extent = vk::Extent2D {
+         width: (scene_extent.width / 2).max(1),
+         height: (scene_extent.height / 2).max(1),
+      };
+      let levels = max_levels.clamp(1, u32::BITS - extent.width.min(extent.height).leading_zeros());
+      let texture = VulkanTexture::new_storage_target(device, memory_properties, extent, SceneColor::FORMAT, levels);
+      let level_views = (0..levels)
+         .map(|level| texture.subresource_view(device, vk::ImageViewType::TYPE_2D, level..level + 1, 0..1))
+         .collect();
+      BloomChain { texture, level_views }
+   }
+
+   fn level_extent(&self, level: u32) -> vk::Extent2D {
+      let extent = self.texture.extent();
+      vk::Extent2D {
+         width: (extent.width >> level).max(1),
+         height: (extent.height >> level).max(1),
+      }
+   }
+
+   fn level_info(&self, level: u32) -> vk::DescriptorImageInfo {
+      self.texture.storage_descriptor_info(self.level_views[level as usize])
+   }
+}
+
+impl VulkanDrop for BloomChain {
+   fn drop(self, device: &ash::Device) {
+      for view in self.level_views {
+         unsafe { device.destroy_image_view(view, None) };
+      }
+      self.texture.drop(device);
+   }
+}
+
+/// Threshold free bloom of the HDR `SceneColor`, after "Next Generation Post Processing in Call
+/// of Duty". Compute shaders downsample the scene color along a chain of halving levels with a
+/// 13 tap filter, then upsample back up with a tent filter, adding each level to the next
+/// larger one. The top level is mixed into the scene color by `intensity`, so bright areas
+/// glow without a cutoff.
+///
+/// A post-processing node: it rewrites the scene color in place, so nodes compose in the order
+/// their systems are added, before `tonemap_system`. A world resource recorded by
+/// `bloom_system`.
+pub struct Bloom {
+   settings: BloomSettings,
+   memory_properties: vk::PhysicalDeviceMemoryProperties,
+   /// Mixes the chain into the scene color
+   render_pass: vk::RenderPass,
+   /// Of the `SceneColor` of the swapchain generation
+   framebuffer: vk::Framebuffer,
+   swapchain_generation: u64,
+   chain: Option<BloomChain>,
+   sampler: vk::Sampler,
+   /// Sets are allocated again each frame, after the previous one finished
+   descriptors: DescriptorAllocator,
+   set_layout: vk::DescriptorSetLayout,
+   layout: VulkanPipelineLayout,
+   downsample: vk::Pipeline,
+   upsample: vk::Pipeline,
+   composite: vk::Pipeline,
+}
+
+impl Bloom {
+   pub fn new(context: &VulkanContext, settings: BloomSettings) -> Self {
+      let device = &context.device;
+      let render_pass = create_render_pass(device);
+      let sampler_info = vk::SamplerCreateInfo::builder()
+         .mag_filter(vk::Filter::LINEAR)
+         .min_filter(vk::Filter::LINEAR)
+         .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+         .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+         .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+         .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+      let sampler = unsafe { device.create_sampler(&sampler_info, None).unwrap() };
+
+      // Down and up the chain, and the mix, each read a level in a set of their own
+      let mut descriptors = DescriptorAllocator::new(2 * settings.max_levels.max(1));
+      let stage_flags = vk::ShaderStageFlags::COMPUTE | vk::ShaderStageFlags::FRAGMENT;
+      let set_layout = descriptors.create_layout(device, &[
+         vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 1,
+            descriptor_type: vk::DescriptorType::SAMPLER,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 2,
+            descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            ..Default::default()
+         },
+      ]);
+      let layout = VulkanPipelineLayout::builder(context)
+         .with_set_layout(set_layout)
+         .with_push_constants::<BloomConstants>(stage_flags)
+         .build(device);
+      // Scene color times one minus the intensity, plus the bloom times the intensity
+      let mix = vk::PipelineColorBlendAttachmentState {
+         blend_enable: vk::TRUE,
+         src_color_blend_factor: vk::BlendFactor::CONSTANT_ALPHA,
+         dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_CONSTANT_ALPHA,
+         color_blend_op: vk::BlendOp::ADD,
+         src_alpha_blend_factor: vk::BlendFactor::ZERO,
+         dst_alpha_blend_factor: vk::BlendFactor::ONE,
+         alpha_blend_op: vk::BlendOp::ADD,
+         color_write_mask: vk::ColorComponentFlags::RGBA,
+      };
+      Bloom {
+         settings,
+         memory_properties: context.device_memory_properties,
+         render_pass,
+         framebuffer: vk::Framebuffer::null(),
+         swapchain_generation: 0,
+         chain: None,
+         sampler,
+         descriptors,
+         set_layout,
+         downsample: create_compute_pipeline(device, &layout, include_bytes!("../../shader/post/bloom_downsample.spv")),
+         upsample: create_compute_pipeline(device, &layout, include_bytes!("../../shader/post/bloom_upsample.spv")),
+         composite: create_fullscreen_pipeline(
+            device,
+            render_pass,
+            &layout,
+            include_bytes!("../../shader/post/bloom.spv"),
+            Some(mix),
+         ),
+         layout,
+      }
+   }
+
+   pub fn settings(&self) -> &BloomSettings {
+      &self.settings
+   }
+
+   pub fn settings_mut(&mut self) -> &mut BloomSettings {
+      &mut self.settings
+   }
+
+   /// Remakes the chain and framebuffer after the swapchain is recreated, with the scene color
+   /// resized too
+   fn prepare(&mut self, device: &ash::Device, frame: &FrameTarget, scene_color: &VulkanTexture) {
+      if frame.swapchain_changed(&mut self.swapchain_generation) {
+         unsafe { device.destroy_framebuffer(self.framebuffer, None) };
+         self.framebuffer = vk::Framebuffer::null();
+         if let Some(chain) = self.chain.take() {
+            chain.drop(device);
+         }
+      }
+      if self.chain.is_none() {
+         let chain = BloomChain::new(device, &self.memory_properties, scene_color.extent(), self.settings.max_levels);
+         self.chain = Some(chain);
+      }
+      if self.framebuffer == vk::Framebuffer::null() {
+         let attachments = [scene_color.image_view()];
+         let extent = scene_color.extent();
+         let create_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(self.render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+         self.framebuffer = unsafe { device.create_framebuffer(&create_info, None).unwrap() };
+      }
+   }
+
+   /// Allocates a set reading the source and writing the destination, and writes it
+   fn allocate_set(
+      &mut self,
+      device: &ash::Device,
+      source: vk::DescriptorImageInfo,
+      destination: vk::DescriptorImageInfo,
+   ) -> vk::DescriptorSet {
+      let set = self.descriptors.allocate(device, self.set_layout);
+      let source_info = [source];
+      let sampler_info = [vk::DescriptorImageInfo { sampler: self.sampler, ..Default::default() }];
+      let destination_info = [destination];
+      let writes = [
+         vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&source_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(1)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(&sampler_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(2)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .image_info(&destination_info)
+            .build(),
+      ];
+      unsafe { device.update_descriptor_sets(&writes, &[]) };
+      set
+   }
+
+   /// Records the chain and its mix into the scene color. Does nothing before the scene is first
+   /// rendered, or without intensity
+   pub fn render(&mut self, device: &ash::Device, world: &World) {
+      let scene_color = world.resource::<SceneColor>();
+      let Some(scene_color) = scene_color.texture() else { return };
+      if self.settings.intensity <= 0.0 {
+         return;
+      }
+      let frame = world.resource::<FrameTarget>();
+      let command_buffer = frame.command_buffer;
+      self.prepare(device, &frame, scene_color);
+      self.descriptors.reset(device);
+      let chain = self.chain.take().expect("Bloom chain is made by prepare");
+      let levels = chain.level_views.len() as u32;
+      let layout = self.layout.layout();
+      let radius = self.settings.radius;
+
+      chain.texture.cmd_discard_to_general(device, command_buffer);
+      for level in 0..levels {
+         let source = if level == 0 { scene_color.descriptor_info() } else { chain.level_info(level - 1) };
+         let set = self.allocate_set(device, source, chain.level_info(level));
+         let constants = BloomConstants { radius, karis_average: (level == 0) as u32, scale: 1.0 };
+         self.cmd_dispatch(device, command_buffer, self.downsample, set, &constants, chain.level_extent(level));
+      }
+      for level in (0..levels - 1).rev() {
+         let set = self.allocate_set(device, chain.level_info(level + 1), chain.level_info(level));
+         let constants = BloomConstants { radius, karis_average: 0, scale: 1.0 };
+         self.cmd_dispatch(device, command_buffer, self.upsample, set, &constants, chain.level_extent(level));
+      }
+
+      // The top level holds the sum of all levels
+      let set = self.allocate_set(device, chain.level_info(0), chain.level_info(0));
+      let constants = BloomConstants { radius, karis_average: 0, scale: 1.0 / levels as f32 };
+      cmd_begin_fullscreen(device, command_buffer, self.render_pass, self.framebuffer, scene_color.extent());
+      unsafe {
+         device.cmd_set_blend_constants(command_buffer, &[0.0, 0.0, 0.0, self.settings.intensity.min(1.0)]);
+         device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.composite);
+         device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, layout, 0, &[set], &[]);
+         self.layout.cmd_push(device, command_buffer, &constants);
+         device.cmd_draw(command_buffer, 3, 1, 0, 0);
+         device.cmd_end_render_pass(command_buffer);
+      }
+      self.chain = Some(chain);
+   }
+
+   /// Records a dispatch of the pipeline over a level, followed by a barrier making its writes
+   /// visible to the next pass
+   fn cmd_dispatch(
+      &self,
+      device: &ash::Device,
+      command_buffer: vk::CommandBuffer,
+      pipeline: vk::Pipeline,
+      set: vk::DescriptorSet,
+      constants: &BloomConstants,
+      extent: vk::Extent2D,
+   ) {
+      let barrier = vk::MemoryBarrier {
+         src_access_mask: vk::AccessFlags::SHADER_WRITE,
+         dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+         ..Default::default()
+      };
+      unsafe {
+         device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+         device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.layout.layout(),
+            0,
+            &[set],
+            &[],
+         );
+         self.layout.cmd_push(device, command_buffer, constants);
+         device.cmd_dispatch(
+            command_buffer,
+            extent.width.div_ceil(GROUP_SIZE),
+            extent.height.div_ceil(GROUP_SIZE),
+            1,
+         );
+         device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[],
+            &[],
+         );
+      }
+   }
+}
+
+impl VulkanDrop for Bloom {
+   fn drop(self, device: &ash::Device) {
+      unsafe {
+         device.destroy_pipeline(self.downsample, None);
+         device.destroy_pipeline(self.upsample, None);
+         device.destroy_pipeline(self.composite, None);
+         device.destroy_sampler(self.sampler, None);
+         device.destroy_framebuffer(self.framebuffer, None);
+      }
+      self.layout.drop(device);
+      self.descriptors.drop(device);
+      if let Some(chain) = self.chain {
+         chain.drop(device);
+      }
+      unsafe { device.destroy_render_pass(self.render_pass, None) };
+   }
+}
+
+/// Records the `Bloom` resource into the frame. Add it to `Stage::Render` after the systems
+/// rendering the scene and before `tonemap_system`
+pub fn bloom_system(device: ash::Device) -> System {
+   System::parallel("bloom", move |world| {
+      world.resource_mut::<Bloom>().render(&device, world);
+   })
+   .reads_resource::<SceneColor>()
+   .writes_resource::<Bloom>()
+   .writes_resource::<FrameTarget>()
+}
+
+/// The `SceneColor` blended over, from and back to the shader read layout
+fn create_render_pass(device: &ash::Device) -> vk::RenderPass {
+   let attachments = [vk::AttachmentDescription {
+      format: SceneColor::FORMAT,
+      samples: vk::SampleCountFlags::TYPE_1,
+      load_op: vk::AttachmentLoadOp::LOAD,
+      store_op: vk::AttachmentStoreOp::STORE,
+      initial_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+      final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+      ..Default::default()
+   }];
+   let color_attachment_refs = [vk::AttachmentReference {
+      attachment: 0,
+      layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+   }];
+   let read_stages = vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER;
+   let dependencies = [
+      // After the chain read the scene color
+      vk::SubpassDependency {
+         src_subpass: vk::SUBPASS_EXTERNAL,
+         src_stage_mask: read_stages,
+         dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+         dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+         ..Default::default()
+      },
+      vk::SubpassDependency {
+         src_subpass: 0,
+         dst_subpass: vk::SUBPASS_EXTERNAL,
+         src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+         src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+         dst_stage_mask: read_stages,
+         dst_access_mask: vk::AccessFlags::SHADER_READ,
+         ..Default::default()
+      },
+   ];
+   let subpass = vk::SubpassDescription::builder()
+      .color_attachments(&color_attachment_refs)
+      .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+   let create_info = vk::RenderPassCreateInfo::builder()
+      .attachments(&attachments)
+      .subpasses(std::slice::from_ref(&subpass))
+      .dependencies(&dependencies);
+   unsafe { device.create_render_pass(&create_info, None).unwrap() }
+}
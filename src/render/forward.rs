@@ -0,0 +1,664 @@
+use std::cmp::Ordering;
+use std::io::Cursor;
+use std::mem;
+
+use ash::vk;
+use cgmath::{InnerSpace, Matrix4};
+
+use crate::ecs::{System, World};
+use crate::platform::gpu::vulkan_buffer::VulkanBuffer;
+use crate::platform::gpu::vulkan_context::{FrameTarget, VulkanContext};
+use crate::platform::gpu::vulkan_descriptor::DescriptorAllocator;
+use crate::platform::gpu::vulkan_mesh::Mesh;
+use crate::platform::gpu::vulkan_pipeline::{Multisampling, VulkanPipelineLayout};
+use crate::platform::gpu::vulkan_shader::VulkanShader;
+use crate::platform::gpu::vulkan_texture::VulkanTexture;
+use crate::platform::gpu::VulkanDrop;
+use crate::scene::{
+   AmbientLight, Camera, CameraUniform, CastShadows, DirectionalLight, GlobalTransform, MeshInstance, PointLight,
+   Sky, SpotLight, Sun,
+};
+
+use super::{
+   GpuEnvironment, GpuMaterials, GpuMeshes, LightBuffer, MaterialPipelineDesc, MaterialPipelines, ObjectConstants,
+   SceneColor, ShadowMaps, ShadowSettings, SkyPass, MATERIAL_SET,
+};
+
+/// Index of the per frame descriptor set: binding 0 is the `CameraUniform`, binding 1 the lights
+/// of `LightBuffer`, binding 2 the `GpuShadow`s of `ShadowMaps`, binding 3 their atlas, binding 4
+/// the comparison sampler, binding 5 the cube array of point light shadows, binding 6 their
+/// `GpuPointShadow`s, bindings 7 to 10 the irradiance cube, prefiltered cube, BRDF lookup table
+/// and sampler of `GpuEnvironment`, binding 11 the `GpuSky` of `SkyPass` and binding 12 the
+/// environment cube
+pub const FRAME_SET: u32 = 0;
+
+/// Settings of `ForwardRenderer`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ForwardSettings {
+   /// Capacity of the light buffer, see `LightBuffer::write` for which lights are kept
+   pub max_lights: u32,
+   /// Depth convention of the cameras, reverse-Z by default like `Perspective`
+   pub reverse_z: bool,
+   pub shadows: ShadowSettings,
+   /// Samples per pixel requested, lowered to what the device supports, see
+   /// `VulkanContext::sample_count`. Above 1 the scene is drawn multisampled and resolved into
+   /// the `SceneColor`
+   pub samples: u32,
+   /// Whether multisampled pixels shade every sample rather than once, smoothing aliasing inside
+   /// triangles too. Ignored if the device doesn't support it
+   pub sample_shading: bool,
+}
+
+impl Default for ForwardSettings {
+   fn default() -> Self {
+      ForwardSettings {
+         max_lights: 256,
+         reverse_z: true,
+         shadows: ShadowSettings::default(),
+         samples: 1,
+         sample_shading: false,
+      }
+   }
+}
+
+impl ForwardSettings {
+   pub fn with_max_lights(mut self, max_lights: u32) -> Self {
+      self.max_lights = max_lights;
+      self
+   }
+
+   pub fn with_reverse_z(mut self, reverse_z: bool) -> Self {
+      self.reverse_z = reverse_z;
+      self
+   }
+
+   pub fn with_shadows(mut self, shadows: ShadowSettings) -> Self {
+      self.shadows = shadows;
+      self
+   }
+
+   pub fn with_samples(mut self, samples: u32) -> Self {
+      self.samples = samples;
+      self
+   }
+
+   pub fn with_sample_shading(mut self, sample_shading: bool) -> Self {
+      self.sample_shading = sample_shading;
+      self
+   }
+}
+
+/// Draws the `MeshInstance`s seen by the first camera into the HDR `SceneColor`, shading them with
+/// the metallic-roughness BRDF under the lights of the world, in front of the `Sky`. Opaque and
+/// masked instances cast the shadows of `CastShadows` lights, rendered first. With
+/// `ForwardSettings::samples`, it draws into multisampled images of its own, resolved into the
+/// scene color at the end of the pass. A world resource, together with `GpuMaterials`,
+/// `GpuMeshes` and `GpuEnvironment`, recorded by `forward_render_system`.
+pub struct ForwardRenderer {
+   render_pass: vk::RenderPass,
+   multisampling: Multisampling,
+   /// Of the context's depth image, and of the multisampled one
+   depth_format: vk::Format,
+   memory_properties: vk::PhysicalDeviceMemoryProperties,
+   /// Multisampled color and depth images of the swapchain generation, resolved into the
+   /// `SceneColor`. Empty without multisampling
+   multisampled_targets: Vec<VulkanTexture>,
+   /// Of the `SceneColor` and depth image, or the multisampled targets, of the swapchain
+   /// generation
+   framebuffer: vk::Framebuffer,
+   swapchain_generation: u64,
+   frame_descriptors: DescriptorAllocator,
+   frame_set_layout: vk::DescriptorSetLayout,
+   pipeline_layout: VulkanPipelineLayout,
+   pipelines: MaterialPipelines,
+   camera: VulkanBuffer,
+   lights: LightBuffer,
+   shadows: ShadowMaps,
+   sky: SkyPass,
+}
+
+impl ForwardRenderer {
+   pub fn new(context: &VulkanContext, materials: &GpuMaterials, settings: ForwardSettings) -> Self {
+      let device = &context.device;
+      let multisampling = Multisampling::new(context, settings.samples, settings.sample_shading);
+      let render_pass = create_render_pass(device, multisampling.samples, context.depth_format);
+
+      // Per frame sets are allocated again each frame, after the previous one finished
+      let mut frame_descriptors = DescriptorAllocator::new(1);
+      let frame_set_layout = frame_descriptors.create_layout(device, &[
+         vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 1,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 2,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 3,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 4,
+            descriptor_type: vk::DescriptorType::SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 5,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 6,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 7,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 8,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 9,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 10,
+            descriptor_type: vk::DescriptorType::SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 11,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 12,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         },
+      ]);
+      let pipeline_layout = VulkanPipelineLayout::builder(context)
+         .with_set_layout(frame_set_layout)
+         .with_set_layout(materials.set_layout())
+         .with_push_constants::<ObjectConstants>(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+         .build(device);
+
+      let vertex_spv = &include_bytes!("../../shader/pbr/vert.spv")[..];
+      let shader = VulkanShader::builder(device)
+         .with_vertex_shader(0, &mut Cursor::new(vertex_spv))
+         .with_fragment_shader(1, &mut Cursor::new(&include_bytes!("../../shader/pbr/frag.spv")[..]))
+         .build();
+      let masked_shader = VulkanShader::builder(device)
+         .with_vertex_shader(0, &mut Cursor::new(vertex_spv))
+         .with_fragment_shader(1, &mut Cursor::new(&include_bytes!("../../shader/pbr/frag_mask.spv")[..]))
+         .build();
+      let depth_compare_op = if settings.reverse_z {
+         vk::CompareOp::GREATER_OR_EQUAL
+      } else {
+         vk::CompareOp::LESS_OR_EQUAL
+      };
+      let pipelines = MaterialPipelines::new(device, &MaterialPipelineDesc {
+         render_pass,
+         layout: &pipeline_layout,
+         shader: &shader,
+         masked_shader: &masked_shader,
+         depth_compare_op,
+         multisampling,
+      });
+      shader.drop(device);
+      masked_shader.drop(device);
+      let sky = SkyPass::new(context, render_pass, &pipeline_layout, depth_compare_op, multisampling);
+
+      ForwardRenderer {
+         render_pass,
+         multisampling,
+         depth_format: context.depth_format,
+         memory_properties: context.device_memory_properties,
+         multisampled_targets: Vec::new(),
+         framebuffer: vk::Framebuffer::null(),
+         swapchain_generation: 0,
+         frame_descriptors,
+         frame_set_layout,
+         pipeline_layout,
+         pipelines,
+         camera: VulkanBuffer::new_host_visible(
+            device,
+            &context.device_memory_properties,
+            mem::size_of::<CameraUniform>() as vk::DeviceSize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+         ),
+         lights: LightBuffer::new(context, settings.max_lights),
+         shadows: ShadowMaps::new(context, settings.shadows),
+         sky,
+      }
+   }
+
+   pub fn render_pass(&self) -> vk::RenderPass {
+      self.render_pass
+   }
+
+   pub fn pipeline_layout(&self) -> &VulkanPipelineLayout {
+      &self.pipeline_layout
+   }
+
+   /// Of the render pass, for pipelines drawing in it
+   pub fn multisampling(&self) -> Multisampling {
+      self.multisampling
+   }
+
+   /// Remakes the framebuffer and multisampled targets after the swapchain is recreated, with
+   /// the scene color resized too
+   fn framebuffer(&mut self, device: &ash::Device, frame: &FrameTarget, color_view: vk::ImageView) -> vk::Framebuffer {
+      if frame.swapchain_changed(&mut self.swapchain_generation) {
+         unsafe { device.destroy_framebuffer(self.framebuffer, None) };
+         self.framebuffer = vk::Framebuffer::null();
+         for target in self.multisampled_targets.drain(..) {
+            target.drop(device);
+         }
+      }
+      if self.framebuffer == vk::Framebuffer::null() {
+         let attachments = if self.multisampling.is_multisampled() {
+            let samples = self.multisampling.samples;
+            let memory_properties = &self.memory_properties;
+            self.multisampled_targets = vec![
+               VulkanTexture::new_multisampled_target(
+                  device,
+                  memory_properties,
+                  frame.extent,
+                  SceneColor::FORMAT,
+                  samples,
+                  vk::ImageAspectFlags::COLOR,
+               ),
+               VulkanTexture::new_multisampled_target(
+                  device,
+                  memory_properties,
+                  frame.extent,
+                  self.depth_format,
+                  samples,
+                  vk::ImageAspectFlags::DEPTH,
+               ),
+            ];
+            vec![self.multisampled_targets[0].image_view(), self.multisampled_targets[1].image_view(), color_view]
+         } else {
+            vec![color_view, frame.depth_image_view]
+         };
+         let create_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(self.render_pass)
+            .attachments(&attachments)
+            .width(frame.extent.width)
+            .height(frame.extent.height)
+            .layers(1);
+         self.framebuffer = unsafe { device.create_framebuffer(&create_info, None).unwrap() };
+      }
+      self.framebuffer
+   }
+
+   /// Records the pass into the frame's command buffer, resizing the `SceneColor` as needed.
+   /// Draws nothing without a camera
+   pub fn render(&mut self, device: &ash::Device, world: &World) {
+      let mut cameras = world.query::<(&Camera, &GlobalTransform)>();
+      let Some((_, (camera, camera_transform))) = cameras.iter().next() else { return };
+      let materials = world.resource::<GpuMaterials>();
+      let meshes = world.resource::<GpuMeshes>();
+      let environment = world.resource::<GpuEnvironment>();
+      let viewer = camera_transform.translation();
+
+      let mut instances = world.query::<(&GlobalTransform, &MeshInstance)>();
+      let mut draws: Vec<_> = instances
+         .iter()
+         .filter_map(|(_, (global, instance))| {
+            let distance = (global.translation() - viewer).magnitude2();
+            Some((materials.get(instance.material)?, meshes.get(instance.mesh)?, global.matrix(), distance))
+         })
+         .collect();
+      // Fewer pipeline switches, and blended objects last, back to front
+      draws.sort_by(|a, b| {
+         a.0.key.cmp(&b.0.key).then_with(|| {
+            if a.0.key.is_transparent() { b.3.total_cmp(&a.3) } else { Ordering::Equal }
+         })
+      });
+
+      // The previous frame has finished, so the buffers and sets aren't in use by the GPU
+      self.camera.write(device, &[CameraUniform::new(camera, camera_transform)]);
+      let shadows = self.shadows.prepare(device, world, camera, camera_transform);
+      self.lights.write(device, world, viewer, &shadows);
+      let draw_sky = self.sky.prepare(device, world, camera, camera_transform);
+      self.frame_descriptors.reset(device);
+      let frame_set = self.frame_descriptors.allocate(device, self.frame_set_layout);
+      let camera_info = [self.camera.descriptor_info()];
+      let lights_info = [self.lights.descriptor_info()];
+      let shadows_info = [self.shadows.shadows_descriptor_info()];
+      let atlas_info = [self.shadows.atlas_descriptor_info()];
+      let sampler_info = [self.shadows.sampler_descriptor_info()];
+      let cubes_info = [self.shadows.cubes_descriptor_info()];
+      let point_shadows_info = [self.shadows.point_shadows_descriptor_info()];
+      let irradiance_info = [environment.irradiance_descriptor_info()];
+      let prefiltered_info = [environment.prefiltered_descriptor_info()];
+      let brdf_lut_info = [environment.brdf_lut_descriptor_info()];
+      let environment_sampler_info = [environment.sampler_descriptor_info()];
+      let sky_info = [self.sky.descriptor_info()];
+      let environment_info = [environment.environment_descriptor_info()];
+      let writes = [
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(&camera_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&lights_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(2)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&shadows_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(3)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&atlas_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(4)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(&sampler_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(5)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&cubes_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(6)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&point_shadows_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(7)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&irradiance_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(8)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&prefiltered_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(9)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&brdf_lut_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(10)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(&environment_sampler_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(11)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(&sky_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(frame_set)
+            .dst_binding(12)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&environment_info)
+            .build(),
+      ];
+
+      let frame = world.resource::<FrameTarget>();
+      let color_view = world.resource_mut::<SceneColor>().prepare(device, &frame).image_view();
+      let framebuffer = self.framebuffer(device, &frame, color_view);
+      let render_area: vk::Rect2D = frame.extent.into();
+      let viewport = vk::Viewport {
+         x: 0.0,
+         y: 0.0,
+         width: frame.extent.width as f32,
+         height: frame.extent.height as f32,
+         min_depth: 0.0,
+         max_depth: 1.0,
+      };
+      let clear_values = [
+         vk::ClearValue { color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 0.0] } },
+         vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue { depth: camera.clear_depth(), stencil: 0 },
+         },
+      ];
+      let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+         .render_pass(self.render_pass)
+         .framebuffer(framebuffer)
+         .render_area(render_area)
+         .clear_values(&clear_values);
+
+      let command_buffer = frame.command_buffer;
+      let casters: Vec<(&Mesh, Matrix4<f32>)> = draws
+         .iter()
+         .filter(|(material, ..)| !material.key.is_transparent())
+         .map(|&(_, mesh, model, _)| (mesh, model))
+         .collect();
+      self.shadows.render(device, command_buffer, &casters);
+
+      let layout = self.pipeline_layout.layout();
+      unsafe {
+         device.update_descriptor_sets(&writes, &[]);
+         device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+         device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+         device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+         device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, layout, FRAME_SET, &[frame_set], &[]);
+         let mut bound_key = None;
+         // The sky fills what opaque objects left at the far depth, under blended ones
+         let blended = draws.partition_point(|(material, ..)| !material.key.is_transparent());
+         for (index, (material, mesh, model, _)) in draws.iter().enumerate() {
+            if draw_sky && index == blended {
+               self.sky.draw(device, command_buffer);
+               bound_key = None;
+            }
+            if bound_key != Some(material.key) {
+               device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipelines.get(material.key));
+               bound_key = Some(material.key);
+            }
+            device.cmd_bind_descriptor_sets(
+               command_buffer,
+               vk::PipelineBindPoint::GRAPHICS,
+               layout,
+               MATERIAL_SET,
+               &[material.descriptor_set],
+               &[],
+            );
+            mesh.bind(device, command_buffer);
+            mesh.draw_object(device, command_buffer, &self.pipeline_layout, &ObjectConstants::new(*model, material.id));
+         }
+         if draw_sky && blended == draws.len() {
+            self.sky.draw(device, command_buffer);
+         }
+         device.cmd_end_render_pass(command_buffer);
+      }
+   }
+}
+
+impl VulkanDrop for ForwardRenderer {
+   fn drop(self, device: &ash::Device) {
+      self.pipelines.drop(device);
+      self.pipeline_layout.drop(device);
+      self.frame_descriptors.drop(device);
+      self.camera.drop(device);
+      self.lights.drop(device);
+      self.shadows.drop(device);
+      self.sky.drop(device);
+      for target in self.multisampled_targets {
+         target.drop(device);
+      }
+      unsafe {
+         device.destroy_framebuffer(self.framebuffer, None);
+         device.destroy_render_pass(self.render_pass, None);
+      }
+   }
+}
+
+/// Records the `ForwardRenderer` resource into the frame. Add it to `Stage::Render`
+pub fn forward_render_system(device: ash::Device) -> System {
+   System::parallel("forward_render", move |world| {
+      world.resource_mut::<ForwardRenderer>().render(&device, world);
+   })
+   .with_query::<(&GlobalTransform, &MeshInstance)>()
+   .with_query::<&Camera>()
+   .reads::<DirectionalLight>()
+   .reads::<PointLight>()
+   .reads::<SpotLight>()
+   .reads::<CastShadows>()
+   .reads::<Sun>()
+   .reads_resource::<Sky>()
+   .reads_resource::<AmbientLight>()
+   .reads_resource::<GpuEnvironment>()
+   .reads_resource::<GpuMaterials>()
+   .reads_resource::<GpuMeshes>()
+   .writes_resource::<ForwardRenderer>()
+   .writes_resource::<SceneColor>()
+   .writes_resource::<FrameTarget>()
+}
+
+/// Color attachment of the `SceneColor`, sampled afterwards, and the context's depth image. When
+/// multisampled, multisampled color and depth images instead, the color resolved into the
+/// `SceneColor`
+fn create_render_pass(device: &ash::Device, samples: vk::SampleCountFlags, depth_format: vk::Format) -> vk::RenderPass {
+   let multisampled = samples != vk::SampleCountFlags::TYPE_1;
+   let scene_color = vk::AttachmentDescription {
+      format: SceneColor::FORMAT,
+      samples: vk::SampleCountFlags::TYPE_1,
+      load_op: if multisampled { vk::AttachmentLoadOp::DONT_CARE } else { vk::AttachmentLoadOp::CLEAR },
+      store_op: vk::AttachmentStoreOp::STORE,
+      final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+      ..Default::default()
+   };
+   let attachments = if multisampled {
+      vec![
+         vk::AttachmentDescription {
+            format: SceneColor::FORMAT,
+            samples,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ..Default::default()
+         },
+         vk::AttachmentDescription {
+            format: depth_format,
+            samples,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ..Default::default()
+         },
+         scene_color,
+      ]
+   } else {
+      vec![
+         scene_color,
+         vk::AttachmentDescription {
+            format: depth_format,
+            samples,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            initial_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ..Default::default()
+         },
+      ]
+   };
+   let color_attachment_refs = [vk::AttachmentReference {
+      attachment: 0,
+      layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+   }];
+   let depth_attachment_ref = vk::AttachmentReference {
+      attachment: 1,
+      layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+   };
+   // The scene color is read by the passes after, of the previous frame and of this one
+   let read_stages = vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER;
+   let dependencies = [
+      vk::SubpassDependency {
+         src_subpass: vk::SUBPASS_EXTERNAL,
+         src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | read_stages,
+         dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+         dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+         ..Default::default()
+      },
+      vk::SubpassDependency {
+         src_subpass: 0,
+         dst_subpass: vk::SUBPASS_EXTERNAL,
+         src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+         src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+         dst_stage_mask: read_stages,
+         dst_access_mask: vk::AccessFlags::SHADER_READ,
+         ..Default::default()
+      },
+   ];
+   let resolve_attachment_refs = [vk::AttachmentReference {
+      attachment: 2,
+      layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+   }];
+   let mut subpass = vk::SubpassDescription::builder()
+      .color_attachments(&color_attachment_refs)
+      .depth_stencil_attachment(&depth_attachment_ref)
+      .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+   if multisampled {
+      subpass = subpass.resolve_attachments(&resolve_attachment_refs);
+   }
+   let create_info = vk::RenderPassCreateInfo::builder()
+      .attachments(&attachments)
+      .subpasses(std::slice::from_ref(&subpass))
+      .dependencies(&dependencies);
+   unsafe { device.create_render_pass(&create_info, None).unwrap() }
+}
@@ -0,0 +1,562 @@
+use std::io::Cursor;
+use std::mem;
+
+use ash::vk;
+
+use crate::asset::{Assets, Handle, HdrImage};
+use crate::ecs::World;
+use crate::platform::gpu::vulkan_buffer::VulkanBuffer;
+use crate::platform::gpu::vulkan_context::{record_submit_commandbuffer, VulkanContext};
+use crate::platform::gpu::vulkan_descriptor::DescriptorAllocator;
+use crate::platform::gpu::vulkan_pipeline::VulkanPipelineLayout;
+use crate::platform::gpu::vulkan_shader::VulkanShader;
+use crate::platform::gpu::vulkan_texture::VulkanTexture;
+use crate::platform::gpu::VulkanDrop;
+use crate::platform::time::Time;
+use crate::scene::{EnvironmentLight, EnvironmentSource};
+
+use super::GpuAtmosphere;
+
+/// Invocations along X and Y of every baking shader
+const GROUP_SIZE: u32 = 8;
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Settings of `GpuEnvironment`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IblSettings {
+   /// Width and height of the cube faces the environment map is resampled into
+   pub environment_size: u32,
+   /// Width and height of the faces of the diffuse irradiance cube
+   pub irradiance_size: u32,
+   /// Width and height of the top level of the prefiltered specular cube
+   pub prefiltered_size: u32,
+   /// Levels of the prefiltered cube, from smooth at level 0 to fully rough at the last
+   pub prefiltered_mips: u32,
+   /// Width and height of the BRDF lookup table
+   pub brdf_lut_size: u32,
+   /// Samples per texel when integrating the environment and the BRDF
+   pub sample_count: u32,
+   /// Seconds of `Time` between bakes of a changing sky. A moving sun changes it every frame, and
+   /// each bake waits for the GPU
+   pub sky_rebake_interval: f32,
+}
+
+impl Default for IblSettings {
+   fn default() -> Self {
+      IblSettings {
+         environment_size: 512,
+         irradiance_size: 32,
+         prefiltered_size: 256,
+         prefiltered_mips: 6,
+         brdf_lut_size: 128,
+         sample_count: 512,
+         sky_rebake_interval: 0.5,
+      }
+   }
+}
+
+impl IblSettings {
+   pub fn with_environment_size(mut self, environment_size: u32) -> Self {
+      self.environment_size = environment_size;
+      self
+   }
+
+   pub fn with_irradiance_size(mut self, irradiance_size: u32) -> Self {
+      self.irradiance_size = irradiance_size;
+      self
+   }
+
+   pub fn with_prefiltered(mut self, prefiltered_size: u32, prefiltered_mips: u32) -> Self {
+      self.prefiltered_size = prefiltered_size;
+      self.prefiltered_mips = prefiltered_mips;
+      self
+   }
+
+   pub fn with_brdf_lut_size(mut self, brdf_lut_size: u32) -> Self {
+      self.brdf_lut_size = brdf_lut_size;
+      self
+   }
+
+   pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+      self.sample_count = sample_count;
+      self
+   }
+
+   pub fn with_sky_rebake_interval(mut self, sky_rebake_interval: f32) -> Self {
+      self.sky_rebake_interval = sky_rebake_interval;
+      self
+   }
+}
+
+/// Push constants of the baking shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct BakeConstants {
+   /// Perceptual roughness the prefiltered level is integrated for
+   roughness: f32,
+   sample_count: u32,
+}
+
+/// What the cubes were baked from
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Baked {
+   Map(Handle<HdrImage>),
+   Atmosphere(GpuAtmosphere),
+}
+
+/// Descriptor of a baking shader
+enum BakeBinding {
+   Image(vk::DescriptorType, vk::DescriptorImageInfo),
+   Uniform(vk::DescriptorBufferInfo),
+}
+
+/// Compute pipeline with the set layout of its shader
+struct BakePipeline {
+   set_layout: vk::DescriptorSetLayout,
+   layout: VulkanPipelineLayout,
+   pipeline: vk::Pipeline,
+}
+
+impl BakePipeline {
+   fn new(
+      context: &VulkanContext,
+      descriptors: &mut DescriptorAllocator,
+      bindings: &[vk::DescriptorType],
+      spv: &[u8],
+   ) -> Self {
+      let device = &context.device;
+      let bindings: Vec<vk::DescriptorSetLayoutBinding> = bindings
+         .iter()
+         .enumerate()
+         .map(|(binding, &descriptor_type)| vk::DescriptorSetLayoutBinding {
+            binding: binding as u32,
+            descriptor_type,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            ..Default::default()
+         })
+         .collect();
+      let set_layout = descriptors.create_layout(device, &bindings);
+      let layout = VulkanPipelineLayout::builder(context)
+         .with_set_layout(set_layout)
+         .with_push_constants::<BakeConstants>(vk::ShaderStageFlags::COMPUTE)
+         .build(device);
+      let shader = VulkanShader::builder(device)
+         .with_compute_shader(0, &mut Cursor::new(spv))
+         .build();
+      let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+         .stage(shader.shader_stage_create_infos()[0])
+         .layout(layout.layout());
+      let pipeline = unsafe {
+         device
+            .create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info.build()], None)
+            .expect("Unable to create compute pipeline")[0]
+      };
+      shader.drop(device);
+      BakePipeline { set_layout, layout, pipeline }
+   }
+
+   /// Allocates a set with the bindings, in order, and writes it
+   fn allocate_set(
+      &self,
+      device: &ash::Device,
+      descriptors: &mut DescriptorAllocator,
+      bindings: &[BakeBinding],
+   ) -> vk::DescriptorSet {
+      let set = descriptors.allocate(device, self.set_layout);
+      let writes: Vec<vk::WriteDescriptorSet> = bindings
+         .iter()
+         .enumerate()
+         .map(|(binding, descriptor)| {
+            let write = vk::WriteDescriptorSet::builder().dst_set(set).dst_binding(binding as u32);
+            match descriptor {
+               BakeBinding::Image(descriptor_type, info) => {
+                  write.descriptor_type(*descriptor_type).image_info(std::slice::from_ref(info)).build()
+               }
+               BakeBinding::Uniform(info) => write
+                  .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                  .buffer_info(std::slice::from_ref(info))
+                  .build(),
+            }
+         })
+         .collect();
+      unsafe { device.update_descriptor_sets(&writes, &[]) };
+      set
+   }
+
+   /// Records a dispatch covering `size` texels square on each of `layers` layers
+   fn cmd_dispatch(
+      &self,
+      device: &ash::Device,
+      command_buffer: vk::CommandBuffer,
+      set: vk::DescriptorSet,
+      constants: &BakeConstants,
+      size: u32,
+      layers: u32,
+   ) {
+      let groups = size.div_ceil(GROUP_SIZE);
+      unsafe {
+         device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+         device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.layout.layout(),
+            0,
+            &[set],
+            &[],
+         );
+         self.layout.cmd_push(device, command_buffer, constants);
+         device.cmd_dispatch(command_buffer, groups, groups, layers);
+      }
+   }
+}
+
+impl VulkanDrop for BakePipeline {
+   fn drop(self, device: &ash::Device) {
+      unsafe { device.destroy_pipeline(self.pipeline, None) };
+      self.layout.drop(device);
+   }
+}
+
+/// Image based lighting baked from the `EnvironmentLight` resource, a world resource read by
+/// `ForwardRenderer`. Compute shaders resample the equirectangular image, or render the
+/// atmosphere of the `Sky`, into a cube, integrate
+/// it into a diffuse irradiance cube and a specular cube prefiltered with GGX lobes of growing
+/// roughness along its levels, and precompute the BRDF lookup table of the split sum
+/// approximation. Without an environment the cubes are black and contribute nothing.
+pub struct GpuEnvironment {
+   settings: IblSettings,
+   environment: VulkanTexture,
+   irradiance: VulkanTexture,
+   prefiltered: VulkanTexture,
+   brdf_lut: VulkanTexture,
+   sampler: vk::Sampler,
+   descriptors: DescriptorAllocator,
+   equirect_to_cube: BakePipeline,
+   atmosphere_to_cube: BakePipeline,
+   irradiance_pipeline: BakePipeline,
+   prefilter: BakePipeline,
+   /// `GpuAtmosphere` rendered by `atmosphere_to_cube`
+   atmosphere: VulkanBuffer,
+   /// Source of the environment in the cubes, with its intensity
+   baked: Option<(Baked, f32)>,
+   /// `Time::total_time` of the latest bake
+   baked_at: f32,
+}
+
+impl GpuEnvironment {
+   /// Creates black cubes and bakes the BRDF lookup table. Waits until the bake finishes
+   pub fn new(context: &VulkanContext, settings: IblSettings) -> Self {
+      let environment_mips = u32::BITS - settings.environment_size.leading_zeros();
+      let prefiltered_chain = u32::BITS - settings.prefiltered_size.leading_zeros();
+      assert!(
+         (1..=prefiltered_chain).contains(&settings.prefiltered_mips),
+         "Prefiltered cube of size {} can't have {} levels",
+         settings.prefiltered_size,
+         settings.prefiltered_mips
+      );
+      assert!(settings.sample_count > 0, "Baking needs at least one sample");
+      let device = &context.device;
+      let environment = VulkanTexture::new_storage_cube(context, settings.environment_size, FORMAT, environment_mips);
+      let irradiance = VulkanTexture::new_storage_cube(context, settings.irradiance_size, FORMAT, 1);
+      let prefiltered =
+         VulkanTexture::new_storage_cube(context, settings.prefiltered_size, FORMAT, settings.prefiltered_mips);
+      let lut_extent = vk::Extent2D { width: settings.brdf_lut_size, height: settings.brdf_lut_size };
+      let brdf_lut = VulkanTexture::new_storage(context, lut_extent, FORMAT);
+
+      let sampler_info = vk::SamplerCreateInfo::builder()
+         .mag_filter(vk::Filter::LINEAR)
+         .min_filter(vk::Filter::LINEAR)
+         .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+         .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+         .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+         .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+         .max_lod(vk::LOD_CLAMP_NONE);
+      let sampler = unsafe { device.create_sampler(&sampler_info, None).unwrap() };
+
+      // A bake allocates a set per prefiltered level, and frees them all before the next one
+      let mut descriptors = DescriptorAllocator::new(settings.prefiltered_mips + 1);
+      let equirect_to_cube = BakePipeline::new(
+         context,
+         &mut descriptors,
+         &[vk::DescriptorType::SAMPLED_IMAGE, vk::DescriptorType::STORAGE_IMAGE],
+         &include_bytes!("../../shader/ibl/equirect_to_cube.spv")[..],
+      );
+      let atmosphere_to_cube = BakePipeline::new(
+         context,
+         &mut descriptors,
+         &[vk::DescriptorType::UNIFORM_BUFFER, vk::DescriptorType::STORAGE_IMAGE],
+         &include_bytes!("../../shader/ibl/atmosphere_to_cube.spv")[..],
+      );
+      let cube_bindings =
+         [vk::DescriptorType::SAMPLED_IMAGE, vk::DescriptorType::SAMPLER, vk::DescriptorType::STORAGE_IMAGE];
+      let irradiance_pipeline = BakePipeline::new(
+         context,
+         &mut descriptors,
+         &cube_bindings,
+         &include_bytes!("../../shader/ibl/irradiance.spv")[..],
+      );
+      let prefilter = BakePipeline::new(
+         context,
+         &mut descriptors,
+         &cube_bindings,
+         &include_bytes!("../../shader/ibl/prefilter.spv")[..],
+      );
+
+      let brdf_pipeline = BakePipeline::new(
+         context,
+         &mut descriptors,
+         &[vk::DescriptorType::STORAGE_IMAGE],
+         &include_bytes!("../../shader/ibl/brdf_lut.spv")[..],
+      );
+      let lut_set = brdf_pipeline.allocate_set(device, &mut descriptors, &[BakeBinding::Image(
+         vk::DescriptorType::STORAGE_IMAGE,
+         brdf_lut.storage_descriptor_info(brdf_lut.image_view()),
+      )]);
+      let constants = BakeConstants { roughness: 0.0, sample_count: settings.sample_count };
+      submit_and_wait(context, |device, command_buffer| {
+         brdf_pipeline.cmd_dispatch(device, command_buffer, lut_set, &constants, settings.brdf_lut_size, 1);
+         cmd_compute_to_fragment(device, command_buffer);
+      });
+      descriptors.reset(device);
+      brdf_pipeline.drop(device);
+
+      GpuEnvironment {
+         settings,
+         environment,
+         irradiance,
+         prefiltered,
+         brdf_lut,
+         sampler,
+         descriptors,
+         equirect_to_cube,
+         atmosphere_to_cube,
+         irradiance_pipeline,
+         prefilter,
+         atmosphere: VulkanBuffer::new_host_visible(
+            device,
+            &context.device_memory_properties,
+            mem::size_of::<GpuAtmosphere>() as vk::DeviceSize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+         ),
+         baked: None,
+         baked_at: 0.0,
+      }
+   }
+
+   pub fn settings(&self) -> &IblSettings {
+      &self.settings
+   }
+
+   /// Bakes the source of the world's `EnvironmentLight` into the cubes when it changed since
+   /// the last call, which for the sky is whenever the atmosphere or the sun moves, at most once
+   /// per `IblSettings::sky_rebake_interval`. Waits until the bake finishes. The GPU must not be
+   /// using the cubes
+   pub fn prepare(&mut self, context: &VulkanContext, world: &World) {
+      let Some(light) = world.get_resource::<EnvironmentLight>().map(|light| *light) else {
+         self.baked = None;
+         return;
+      };
+      let source = match light.source {
+         EnvironmentSource::Map(map) => Baked::Map(map),
+         EnvironmentSource::Sky => match GpuAtmosphere::from_world(world) {
+            Some(atmosphere) => Baked::Atmosphere(atmosphere),
+            None => {
+               self.baked = None;
+               return;
+            }
+         },
+      };
+      let now = world.get_resource::<Time>().map(|time| time.total_time);
+      if let Some((baked, intensity)) = &mut self.baked {
+         let sky_is_fresh = matches!((*baked, source), (Baked::Atmosphere(_), Baked::Atmosphere(_)))
+            && now.is_some_and(|now| now - self.baked_at < self.settings.sky_rebake_interval);
+         if *baked == source || sky_is_fresh {
+            *intensity = light.intensity;
+            return;
+         }
+      }
+      match source {
+         Baked::Map(map) => {
+            let Some(images) = world.get_resource::<Assets<HdrImage>>() else { return };
+            let Some(image) = images.get(map) else { return };
+            let equirectangular = VulkanTexture::new_rgba32f(context, image.extent(), &image.pixels);
+            self.bake(context, Some(&equirectangular));
+            equirectangular.drop(&context.device);
+         }
+         Baked::Atmosphere(atmosphere) => {
+            self.atmosphere.write(&context.device, &[atmosphere]);
+            self.bake(context, None);
+         }
+      }
+      self.baked = Some((source, light.intensity));
+      self.baked_at = now.unwrap_or(0.0);
+   }
+
+   /// Resamples the equirectangular texture, or renders the atmosphere without one, into the
+   /// environment cube and integrates it
+   fn bake(&mut self, context: &VulkanContext, equirectangular: Option<&VulkanTexture>) {
+      let device = &context.device;
+      // Shaders write a level of a cube as an array of its faces
+      let cube_level = |texture: &VulkanTexture, level: u32| {
+         texture.subresource_view(device, vk::ImageViewType::TYPE_2D_ARRAY, level..level + 1, 0..6)
+      };
+      let storage = |texture: &VulkanTexture, view: vk::ImageView| {
+         BakeBinding::Image(vk::DescriptorType::STORAGE_IMAGE, texture.storage_descriptor_info(view))
+      };
+      let environment_target = cube_level(&self.environment, 0);
+      let irradiance_target = cube_level(&self.irradiance, 0);
+      let prefiltered_targets: Vec<vk::ImageView> = (0..self.settings.prefiltered_mips)
+         .map(|level| cube_level(&self.prefiltered, level))
+         .collect();
+
+      let sampler_info = vk::DescriptorImageInfo { sampler: self.sampler, ..Default::default() };
+      let environment_binding = storage(&self.environment, environment_target);
+      let (source_pipeline, source_set) = match equirectangular {
+         Some(equirectangular) => (
+            &self.equirect_to_cube,
+            self.equirect_to_cube.allocate_set(device, &mut self.descriptors, &[
+               BakeBinding::Image(vk::DescriptorType::SAMPLED_IMAGE, equirectangular.descriptor_info()),
+               environment_binding,
+            ]),
+         ),
+         None => (
+            &self.atmosphere_to_cube,
+            self.atmosphere_to_cube.allocate_set(device, &mut self.descriptors, &[
+               BakeBinding::Uniform(self.atmosphere.descriptor_info()),
+               environment_binding,
+            ]),
+         ),
+      };
+      let irradiance_set = self.irradiance_pipeline.allocate_set(device, &mut self.descriptors, &[
+         BakeBinding::Image(vk::DescriptorType::SAMPLED_IMAGE, self.environment.descriptor_info()),
+         BakeBinding::Image(vk::DescriptorType::SAMPLER, sampler_info),
+         storage(&self.irradiance, irradiance_target),
+      ]);
+      let prefilter_sets: Vec<vk::DescriptorSet> = prefiltered_targets
+         .iter()
+         .map(|&target| {
+            self.prefilter.allocate_set(device, &mut self.descriptors, &[
+               BakeBinding::Image(vk::DescriptorType::SAMPLED_IMAGE, self.environment.descriptor_info()),
+               BakeBinding::Image(vk::DescriptorType::SAMPLER, sampler_info),
+               storage(&self.prefiltered, target),
+            ])
+         })
+         .collect();
+
+      let settings = self.settings;
+      let constants = BakeConstants { roughness: 0.0, sample_count: settings.sample_count };
+      submit_and_wait(context, |device, command_buffer| {
+         source_pipeline.cmd_dispatch(device, command_buffer, source_set, &constants, settings.environment_size, 6);
+         // Samples of the integrals read blurrier levels of the environment the wider they spread
+         self.environment.cmd_generate_mips(device, command_buffer);
+         self.irradiance_pipeline
+            .cmd_dispatch(device, command_buffer, irradiance_set, &constants, settings.irradiance_size, 6);
+         let last_level = (settings.prefiltered_mips - 1).max(1) as f32;
+         for (level, &set) in prefilter_sets.iter().enumerate() {
+            let constants = BakeConstants { roughness: level as f32 / last_level, ..constants };
+            let size = (settings.prefiltered_size >> level).max(1);
+            self.prefilter.cmd_dispatch(device, command_buffer, set, &constants, size, 6);
+         }
+         cmd_compute_to_fragment(device, command_buffer);
+      });
+
+      self.descriptors.reset(device);
+      unsafe {
+         for view in prefiltered_targets.into_iter().chain([environment_target, irradiance_target]) {
+            device.destroy_image_view(view, None);
+         }
+      }
+   }
+
+   /// Scale of the baked environment's light, zero without one
+   pub fn intensity(&self) -> f32 {
+      self.baked.map_or(0.0, |(_, intensity)| intensity)
+   }
+
+   /// Level of the prefiltered cube holding the fully rough lobe
+   pub fn prefiltered_max_lod(&self) -> f32 {
+      (self.settings.prefiltered_mips - 1) as f32
+   }
+
+   /// Info for the `SAMPLED_IMAGE` descriptor of the environment cube, the skybox of `Sky::Environment`
+   pub fn environment_descriptor_info(&self) -> vk::DescriptorImageInfo {
+      self.environment.descriptor_info()
+   }
+
+   /// Info for the `SAMPLED_IMAGE` descriptor of the irradiance cube
+   pub fn irradiance_descriptor_info(&self) -> vk::DescriptorImageInfo {
+      self.irradiance.descriptor_info()
+   }
+
+   /// Info for the `SAMPLED_IMAGE` descriptor of the prefiltered specular cube
+   pub fn prefiltered_descriptor_info(&self) -> vk::DescriptorImageInfo {
+      self.prefiltered.descriptor_info()
+   }
+
+   /// Info for the `SAMPLED_IMAGE` descriptor of the BRDF lookup table
+   pub fn brdf_lut_descriptor_info(&self) -> vk::DescriptorImageInfo {
+      self.brdf_lut.descriptor_info()
+   }
+
+   /// Info for the `SAMPLER` descriptor of the linear, clamped sampler of the cubes and table
+   pub fn sampler_descriptor_info(&self) -> vk::DescriptorImageInfo {
+      vk::DescriptorImageInfo { sampler: self.sampler, ..Default::default() }
+   }
+}
+
+impl VulkanDrop for GpuEnvironment {
+   fn drop(self, device: &ash::Device) {
+      self.equirect_to_cube.drop(device);
+      self.atmosphere_to_cube.drop(device);
+      self.irradiance_pipeline.drop(device);
+      self.prefilter.drop(device);
+      self.descriptors.drop(device);
+      self.atmosphere.drop(device);
+      unsafe { device.destroy_sampler(self.sampler, None) };
+      self.environment.drop(device);
+      self.irradiance.drop(device);
+      self.prefiltered.drop(device);
+      self.brdf_lut.drop(device);
+   }
+}
+
+/// Records commands into the setup command buffer, submits them and waits until they finish
+fn submit_and_wait(context: &VulkanContext, f: impl FnOnce(&ash::Device, vk::CommandBuffer)) {
+   record_submit_commandbuffer(
+      &context.device,
+      context.setup_command_buffer,
+      context.setup_commands_reuse_fence,
+      context.present_queue,
+      &[],
+      &[],
+      &[],
+      f,
+   );
+   unsafe {
+      context
+         .device
+         .wait_for_fences(&[context.setup_commands_reuse_fence], true, u64::MAX)
+         .expect("Wait for fence failed.");
+   }
+}
+
+/// Makes the images written by compute shaders visible to fragment shaders
+fn cmd_compute_to_fragment(device: &ash::Device, command_buffer: vk::CommandBuffer) {
+   let barrier = vk::MemoryBarrier {
+      src_access_mask: vk::AccessFlags::SHADER_WRITE,
+      dst_access_mask: vk::AccessFlags::SHADER_READ,
+      ..Default::default()
+   };
+   unsafe {
+      device.cmd_pipeline_barrier(
+         command_buffer,
+         vk::PipelineStageFlags::COMPUTE_SHADER,
+         vk::PipelineStageFlags::FRAGMENT_SHADER,
+         vk::DependencyFlags::empty(),
+         &[barrier],
+         &[],
+         &[],
+      );
+   }
+}
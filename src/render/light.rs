@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Range;
+
+use ash::vk;
+use cgmath::{InnerSpace, Vector3, Zero};
+
+use crate::ecs::{Entity, World};
+use crate::platform::gpu::vulkan_buffer::VulkanBuffer;
+use crate::platform::gpu::vulkan_context::VulkanContext;
+use crate::platform::gpu::VulkanDrop;
+use crate::scene::{AmbientLight, DirectionalLight, GlobalTransform, PointLight, SpotLight};
+
+use super::GpuEnvironment;
+
+const DIRECTIONAL: u32 = 0;
+const POINT: u32 = 1;
+const SPOT: u32 = 2;
+
+/// Light as laid out in the std430 `Light` struct of the shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpuLight {
+   pub position: Vector3<f32>,
+   /// Zero for lights without a range
+   pub range: f32,
+   /// Direction the light travels in
+   pub direction: Vector3<f32>,
+   /// 0 for directional, 1 for point and 2 for spot lights
+   pub kind: u32,
+   /// Color multiplied by the intensity
+   pub radiance: Vector3<f32>,
+   /// Spot cone falloff is `clamp(cos(angle) * scale + offset, 0, 1)`, squared
+   pub spot_scale: f32,
+   pub spot_offset: f32,
+   /// Index of the light's first `GpuShadow`, or of its `GpuPointShadow` for point lights
+   pub shadow: u32,
+   /// Number of consecutive shadows, the cascades of directional lights. Zero without shadows
+   pub shadow_count: u32,
+   _padding: u32,
+}
+
+impl GpuLight {
+   pub fn directional(light: &DirectionalLight, global: &GlobalTransform) -> Self {
+      GpuLight {
+         kind: DIRECTIONAL,
+         direction: forward(global),
+         radiance: light.color * light.intensity,
+         ..Self::empty()
+      }
+   }
+
+   pub fn point(light: &PointLight, global: &GlobalTransform) -> Self {
+      GpuLight {
+         kind: POINT,
+         position: global.translation(),
+         range: light.range.unwrap_or(0.0),
+         radiance: light.color * light.intensity,
+         ..Self::empty()
+      }
+   }
+
+   pub fn spot(light: &SpotLight, global: &GlobalTransform) -> Self {
+      let cos_outer = light.outer_cone_angle.0.cos();
+      let cos_inner = light.inner_cone_angle.0.cos();
+      let spot_scale = 1.0 / (cos_inner - cos_outer).max(1e-3);
+      GpuLight {
+         kind: SPOT,
+         position: global.translation(),
+         range: light.range.unwrap_or(0.0),
+         direction: forward(global),
+         radiance: light.color * light.intensity,
+         spot_scale,
+         spot_offset: -cos_outer * spot_scale,
+         ..Self::empty()
+      }
+   }
+
+   pub fn with_shadows(mut self, shadows: Range<u32>) -> Self {
+      self.shadow = shadows.start;
+      self.shadow_count = shadows.len() as u32;
+      self
+   }
+
+   fn empty() -> Self {
+      GpuLight {
+         position: Vector3::zero(),
+         range: 0.0,
+         direction: -Vector3::unit_z(),
+         kind: DIRECTIONAL,
+         radiance: Vector3::zero(),
+         spot_scale: 0.0,
+         spot_offset: 0.0,
+         shadow: 0,
+         shadow_count: 0,
+         _padding: 0,
+      }
+   }
+}
+
+/// Local -Z of the entity in world space
+fn forward(global: &GlobalTransform) -> Vector3<f32> {
+   let direction = -global.matrix().z.truncate();
+   if direction.is_zero() { -Vector3::unit_z() } else { direction.normalize() }
+}
+
+/// Start of the light buffer, followed by the lights.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct LightsHeader {
+   ambient: Vector3<f32>,
+   count: u32,
+   /// Scale of the irradiance and prefiltered cubes of `GpuEnvironment`, zero without them
+   environment_intensity: f32,
+   prefiltered_max_lod: f32,
+   _padding: [u32; 2],
+}
+
+/// Storage buffer with the lights of the world, rewritten each frame. Holds the `AmbientLight`
+/// resource, the intensity of the `GpuEnvironment` and up to `max_lights` punctual lights.
+pub struct LightBuffer {
+   buffer: VulkanBuffer,
+   max_lights: u32,
+}
+
+impl LightBuffer {
+   pub fn new(context: &VulkanContext, max_lights: u32) -> Self {
+      let size = mem::size_of::<LightsHeader>() + max_lights as usize * mem::size_of::<GpuLight>();
+      LightBuffer {
+         buffer: VulkanBuffer::new_host_visible(
+            &context.device,
+            &context.device_memory_properties,
+            size as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+         ),
+         max_lights,
+      }
+   }
+
+   pub fn max_lights(&self) -> u32 {
+      self.max_lights
+   }
+
+   pub fn descriptor_info(&self) -> vk::DescriptorBufferInfo {
+      self.buffer.descriptor_info()
+   }
+
+   /// Packs the lights of the world into the buffer. Past `max_lights`, directional lights are
+   /// kept first, then the lights nearest to the viewer. `shadows` are the ranges of `GpuShadow`s
+   /// of the lights casting them. The GPU must not be using the buffer
+   pub fn write(&self, device: &ash::Device, world: &World, viewer: Vector3<f32>, shadows: &HashMap<Entity, Range<u32>>) {
+      let lights = gather_lights(world, viewer, self.max_lights as usize, shadows);
+      let ambient = world.get_resource::<AmbientLight>().map(|ambient| *ambient).unwrap_or_default();
+      let environment = world.get_resource::<GpuEnvironment>();
+      let header = LightsHeader {
+         ambient: ambient.color * ambient.intensity,
+         count: lights.len() as u32,
+         environment_intensity: environment.as_ref().map_or(0.0, |environment| environment.intensity()),
+         prefiltered_max_lod: environment.as_ref().map_or(0.0, |environment| environment.prefiltered_max_lod()),
+         _padding: [0; 2],
+      };
+      self.buffer.write(device, &[header]);
+      self.buffer.write_at(device, mem::size_of::<LightsHeader>() as vk::DeviceSize, &lights);
+   }
+}
+
+impl VulkanDrop for LightBuffer {
+   fn drop(self, device: &ash::Device) {
+      self.buffer.drop(device);
+   }
+}
+
+fn gather_lights(
+   world: &World,
+   viewer: Vector3<f32>,
+   max_lights: usize,
+   shadows: &HashMap<Entity, Range<u32>>,
+) -> Vec<GpuLight> {
+   let with_shadows = |entity: Entity, light: GpuLight| match shadows.get(&entity) {
+      Some(range) => light.with_shadows(range.clone()),
+      None => light,
+   };
+   let mut lights: Vec<GpuLight> = world
+      .query::<(&DirectionalLight, &GlobalTransform)>()
+      .iter()
+      .map(|(entity, (light, global))| with_shadows(entity, GpuLight::directional(light, global)))
+      .collect();
+   let mut local: Vec<GpuLight> = world
+      .query::<(&PointLight, &GlobalTransform)>()
+      .iter()
+      .map(|(entity, (light, global))| with_shadows(entity, GpuLight::point(light, global)))
+      .chain(
+         world
+            .query::<(&SpotLight, &GlobalTransform)>()
+            .iter()
+            .map(|(entity, (light, global))| with_shadows(entity, GpuLight::spot(light, global))),
+      )
+      .collect();
+   if lights.len() + local.len() > max_lights {
+      local.sort_by(|a, b| (a.position - viewer).magnitude2().total_cmp(&(b.position - viewer).magnitude2()));
+   }
+   lights.append(&mut local);
+   lights.truncate(max_lights);
+   lights
+}
@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+use std::mem;
+
+use ash::vk;
+use cgmath::{Vector3, Vector4};
+
+use crate::asset::{AlphaMode, Assets, Handle, Image, Material, SamplerDesc, Texture, TextureSlot, Vertex};
+use crate::ecs::World;
+use crate::platform::gpu::vulkan_buffer::VulkanBuffer;
+use crate::platform::gpu::vulkan_context::VulkanContext;
+use crate::platform::gpu::vulkan_descriptor::DescriptorAllocator;
+use crate::platform::gpu::vulkan_mesh::VertexLayout;
+use crate::platform::gpu::vulkan_pipeline::{Multisampling, VulkanPipelineLayout};
+use crate::platform::gpu::vulkan_shader::VulkanShader;
+use crate::platform::gpu::vulkan_texture::VulkanTexture;
+use crate::platform::gpu::VulkanDrop;
+
+use super::ObjectConstants;
+
+/// Index of the material descriptor set in pipeline layouts of `MaterialPipelines`
+pub const MATERIAL_SET: u32 = 1;
+
+/// Descriptor sets per pool of `GpuMaterials`
+const MATERIALS_PER_POOL: u32 = 64;
+
+/// Texture slots of a material, in the order of their bindings in the material set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MaterialTexture {
+   BaseColor,
+   MetallicRoughness,
+   Normal,
+   Occlusion,
+   Emissive,
+}
+
+impl MaterialTexture {
+   pub const ALL: [MaterialTexture; 5] = [
+      MaterialTexture::BaseColor,
+      MaterialTexture::MetallicRoughness,
+      MaterialTexture::Normal,
+      MaterialTexture::Occlusion,
+      MaterialTexture::Emissive,
+   ];
+
+   /// Binding of the `texture2D`, binding 0 is the `MaterialUniform`
+   pub fn binding(self) -> u32 {
+      1 + self as u32
+   }
+
+   /// Binding of the `sampler`, after all textures
+   pub fn sampler_binding(self) -> u32 {
+      1 + Self::ALL.len() as u32 + self as u32
+   }
+
+   /// Color textures are sRGB encoded, the rest hold linear data
+   pub fn is_srgb(self) -> bool {
+      matches!(self, MaterialTexture::BaseColor | MaterialTexture::Emissive)
+   }
+
+   pub fn slot(self, material: &Material) -> Option<TextureSlot> {
+      match self {
+         MaterialTexture::BaseColor => material.base_color_texture,
+         MaterialTexture::MetallicRoughness => material.metallic_roughness_texture,
+         MaterialTexture::Normal => material.normal_texture,
+         MaterialTexture::Occlusion => material.occlusion_texture,
+         MaterialTexture::Emissive => material.emissive_texture,
+      }
+   }
+}
+
+/// Material factors as laid out in the std140 uniform block at binding 0 of the material set.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MaterialUniform {
+   pub base_color: Vector4<f32>,
+   pub emissive: Vector3<f32>,
+   pub metallic: f32,
+   pub roughness: f32,
+   pub normal_scale: f32,
+   pub occlusion_strength: f32,
+   /// Zero unless the alpha mode is `Mask`
+   pub alpha_cutoff: f32,
+   /// Bit N is set if the texture of binding N + 1 samples `Vertex::uv1`
+   pub uv1_mask: u32,
+   _padding: [u32; 3],
+}
+
+impl From<&Material> for MaterialUniform {
+   fn from(material: &Material) -> Self {
+      let uv1_mask = MaterialTexture::ALL
+         .iter()
+         .filter(|slot| slot.slot(material).is_some_and(|slot| slot.uv_set == 1))
+         .fold(0, |mask, &slot| mask | 1 << slot as u32);
+      MaterialUniform {
+         base_color: material.base_color,
+         emissive: material.emissive,
+         metallic: material.metallic,
+         roughness: material.roughness,
+         normal_scale: material.normal_scale,
+         occlusion_strength: material.occlusion_strength,
+         alpha_cutoff: match material.alpha_mode {
+            AlphaMode::Mask { cutoff } => cutoff,
+            _ => 0.0,
+         },
+         uv1_mask,
+         _padding: [0; 3],
+      }
+   }
+}
+
+/// Alpha handling of a pipeline variant. Ordered the way draws should be, blended ones last.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AlphaKey {
+   Opaque,
+   Mask,
+   Blend,
+}
+
+/// Parts of a material that need their own pipeline, materials with equal keys share one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MaterialKey {
+   pub alpha: AlphaKey,
+   pub double_sided: bool,
+}
+
+impl MaterialKey {
+   pub fn new(material: &Material) -> Self {
+      let alpha = match material.alpha_mode {
+         AlphaMode::Opaque => AlphaKey::Opaque,
+         AlphaMode::Mask { .. } => AlphaKey::Mask,
+         AlphaMode::Blend => AlphaKey::Blend,
+      };
+      MaterialKey { alpha, double_sided: material.double_sided }
+   }
+
+   /// Every possible key
+   pub fn all() -> impl Iterator<Item = MaterialKey> {
+      [AlphaKey::Opaque, AlphaKey::Mask, AlphaKey::Blend]
+         .into_iter()
+         .flat_map(|alpha| [false, true].map(|double_sided| MaterialKey { alpha, double_sided }))
+   }
+
+   /// Blended materials must be drawn after the rest, back to front
+   pub fn is_transparent(self) -> bool {
+      self.alpha == AlphaKey::Blend
+   }
+
+   pub fn cull_mode(self) -> vk::CullModeFlags {
+      if self.double_sided {
+         vk::CullModeFlags::NONE
+      } else {
+         vk::CullModeFlags::BACK
+      }
+   }
+
+   /// Blended surfaces don't hide what's drawn after them
+   pub fn depth_write(self) -> bool {
+      !self.is_transparent()
+   }
+
+   pub fn color_blend_attachment(self) -> vk::PipelineColorBlendAttachmentState {
+      vk::PipelineColorBlendAttachmentState {
+         blend_enable: self.is_transparent().into(),
+         src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+         dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+         color_blend_op: vk::BlendOp::ADD,
+         src_alpha_blend_factor: vk::BlendFactor::ONE,
+         dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+         alpha_blend_op: vk::BlendOp::ADD,
+         color_write_mask: vk::ColorComponentFlags::RGBA,
+      }
+   }
+}
+
+/// Uploaded material, bind `descriptor_set` at `MATERIAL_SET` with the pipeline of `key`.
+pub struct GpuMaterial {
+   pub key: MaterialKey,
+   /// Index of the material in upload order, pushed in `ObjectConstants::material`
+   pub id: u32,
+   pub descriptor_set: vk::DescriptorSet,
+   uniform: VulkanBuffer,
+   /// Material the set was written for, to notice changes
+   source: Material,
+}
+
+/// Uniforms, textures and descriptor sets of the `Material` assets. Each material has a set with
+/// its `MaterialUniform` at binding 0, then a `texture2D` and a `sampler` per slot, see
+/// `MaterialTexture::binding`.
+///
+/// Texture slots without a texture are bound to a white texture, or a flat normal map for
+/// normals, so shaders sample every slot unconditionally.
+pub struct GpuMaterials {
+   set_layout: vk::DescriptorSetLayout,
+   allocator: DescriptorAllocator,
+   materials: HashMap<Handle<Material>, GpuMaterial>,
+   /// Images may be sampled both as sRGB and linear, each gets its own texture
+   textures: HashMap<(Handle<Image>, bool), VulkanTexture>,
+   samplers: HashMap<SamplerDesc, vk::Sampler>,
+   white: VulkanTexture,
+   flat_normal: VulkanTexture,
+}
+
+impl GpuMaterials {
+   pub fn new(context: &VulkanContext) -> Self {
+      let mut bindings = vec![vk::DescriptorSetLayoutBinding {
+         binding: 0,
+         descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+         descriptor_count: 1,
+         stage_flags: vk::ShaderStageFlags::FRAGMENT,
+         ..Default::default()
+      }];
+      for slot in MaterialTexture::ALL {
+         bindings.push(vk::DescriptorSetLayoutBinding {
+            binding: slot.binding(),
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         });
+         bindings.push(vk::DescriptorSetLayoutBinding {
+            binding: slot.sampler_binding(),
+            descriptor_type: vk::DescriptorType::SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+         });
+      }
+      let mut allocator = DescriptorAllocator::new(MATERIALS_PER_POOL);
+      let set_layout = allocator.create_layout(&context.device, &bindings);
+      let white = Image::solid([255, 255, 255, 255]);
+      let flat_normal = Image::solid([128, 128, 255, 255]);
+      GpuMaterials {
+         set_layout,
+         allocator,
+         materials: HashMap::new(),
+         textures: HashMap::new(),
+         samplers: HashMap::new(),
+         white: VulkanTexture::new_rgba8(context, white.extent(), vk::Format::R8G8B8A8_UNORM, &white.pixels),
+         flat_normal: VulkanTexture::new_rgba8(
+            context,
+            flat_normal.extent(),
+            vk::Format::R8G8B8A8_UNORM,
+            &flat_normal.pixels,
+         ),
+      }
+   }
+
+   /// Layout of the set at `MATERIAL_SET`
+   pub fn set_layout(&self) -> vk::DescriptorSetLayout {
+      self.set_layout
+   }
+
+   pub fn get(&self, handle: Handle<Material>) -> Option<&GpuMaterial> {
+      self.materials.get(&handle)
+   }
+
+   /// Keys of the uploaded materials
+   pub fn keys(&self) -> impl Iterator<Item = MaterialKey> + '_ {
+      self.materials.values().map(|material| material.key)
+   }
+
+   /// Uploads the materials added to the world's `Assets<Material>` since the last call together
+   /// with their textures, and rewrites the ones that changed. The GPU must not be using changed
+   /// materials
+   pub fn prepare(&mut self, context: &VulkanContext, world: &World) {
+      let Some(materials) = world.get_resource::<Assets<Material>>() else { return };
+      for (handle, material) in materials.iter() {
+         if self.materials.get(&handle).is_some_and(|gpu| gpu.source == *material) {
+            continue;
+         }
+         let textures = MaterialTexture::ALL.map(|slot| self.slot_texture(context, world, material, slot));
+         let gpu = match self.materials.remove(&handle) {
+            Some(gpu) => gpu,
+            None => self.allocate(context),
+         };
+         let gpu = GpuMaterial { key: MaterialKey::new(material), source: material.clone(), ..gpu };
+         gpu.uniform.write(&context.device, &[MaterialUniform::from(material)]);
+         self.write_textures(&context.device, &gpu, &textures);
+         self.materials.insert(handle, gpu);
+      }
+   }
+
+   fn allocate(&mut self, context: &VulkanContext) -> GpuMaterial {
+      let id = self.materials.len() as u32;
+      GpuMaterial {
+         key: MaterialKey { alpha: AlphaKey::Opaque, double_sided: false },
+         id,
+         descriptor_set: self.allocator.allocate(&context.device, self.set_layout),
+         uniform: VulkanBuffer::new_host_visible(
+            &context.device,
+            &context.device_memory_properties,
+            mem::size_of::<MaterialUniform>() as vk::DeviceSize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+         ),
+         source: Material::default(),
+      }
+   }
+
+   /// View and sampler bound to a slot, uploading the image on first use
+   fn slot_texture(
+      &mut self,
+      context: &VulkanContext,
+      world: &World,
+      material: &Material,
+      slot: MaterialTexture,
+   ) -> (vk::ImageView, vk::Sampler) {
+      let Some(texture_slot) = slot.slot(material) else {
+         let default = match slot {
+            MaterialTexture::Normal => &self.flat_normal,
+            _ => &self.white,
+         };
+         let view = default.image_view();
+         return (view, self.sampler(&context.device, SamplerDesc::default()));
+      };
+      let texture = world.resource::<Assets<Texture>>()[texture_slot.texture];
+      let srgb = slot.is_srgb();
+      let view = self
+         .textures
+         .entry((texture.image, srgb))
+         .or_insert_with(|| {
+            let image = &world.resource::<Assets<Image>>()[texture.image];
+            let format = if srgb { vk::Format::R8G8B8A8_SRGB } else { vk::Format::R8G8B8A8_UNORM };
+            VulkanTexture::new_rgba8(context, image.extent(), format, &image.pixels)
+         })
+         .image_view();
+      (view, self.sampler(&context.device, texture.sampler))
+   }
+
+   fn sampler(&mut self, device: &ash::Device, desc: SamplerDesc) -> vk::Sampler {
+      *self.samplers.entry(desc).or_insert_with(|| {
+         let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(desc.mag_filter)
+            .min_filter(desc.min_filter)
+            .mipmap_mode(desc.mipmap_mode)
+            .address_mode_u(desc.address_mode_u)
+            .address_mode_v(desc.address_mode_v)
+            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .max_anisotropy(1.0)
+            .max_lod(vk::LOD_CLAMP_NONE);
+         unsafe { device.create_sampler(&sampler_info, None).unwrap() }
+      })
+   }
+
+   /// Writes the uniform buffer and the textures of the material
+   fn write_textures(&self, device: &ash::Device, material: &GpuMaterial, textures: &[(vk::ImageView, vk::Sampler)]) {
+      let image_infos: Vec<[vk::DescriptorImageInfo; 2]> = textures
+         .iter()
+         .map(|&(image_view, sampler)| {
+            [
+               vk::DescriptorImageInfo {
+                  sampler: vk::Sampler::null(),
+                  image_view,
+                  image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+               },
+               vk::DescriptorImageInfo { sampler, ..Default::default() },
+            ]
+         })
+         .collect();
+      let uniform_info = [material.uniform.descriptor_info()];
+      let mut writes = vec![vk::WriteDescriptorSet::builder()
+         .dst_set(material.descriptor_set)
+         .dst_binding(0)
+         .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+         .buffer_info(&uniform_info)
+         .build()];
+      for (slot, [image, sampler]) in MaterialTexture::ALL.into_iter().zip(&image_infos) {
+         writes.push(
+            vk::WriteDescriptorSet::builder()
+               .dst_set(material.descriptor_set)
+               .dst_binding(slot.binding())
+               .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+               .image_info(std::slice::from_ref(image))
+               .build(),
+         );
+         writes.push(
+            vk::WriteDescriptorSet::builder()
+               .dst_set(material.descriptor_set)
+               .dst_binding(slot.sampler_binding())
+               .descriptor_type(vk::DescriptorType::SAMPLER)
+               .image_info(std::slice::from_ref(sampler))
+               .build(),
+         );
+      }
+      unsafe { device.update_descriptor_sets(&writes, &[]) };
+   }
+}
+
+impl VulkanDrop for GpuMaterials {
+   fn drop(self, device: &ash::Device) {
+      for (_, material) in self.materials {
+         material.uniform.drop(device);
+      }
+      for (_, texture) in self.textures {
+         texture.drop(device);
+      }
+      for (_, sampler) in self.samplers {
+         unsafe { device.destroy_sampler(sampler, None) };
+      }
+      self.white.drop(device);
+      self.flat_normal.drop(device);
+      self.allocator.drop(device);
+   }
+}
+
+/// What `MaterialPipelines` needs to build the variants for `asset::Vertex` meshes.
+pub struct MaterialPipelineDesc<'a> {
+   pub render_pass: vk::RenderPass,
+   /// Must have the layout of `GpuMaterials` at `MATERIAL_SET`, and `ObjectConstants` push
+   /// constants
+   pub layout: &'a VulkanPipelineLayout,
+   /// Stages for opaque and blended materials
+   pub shader: &'a VulkanShader,
+   /// Stages for masked materials, the fragment shader discards below the alpha cutoff
+   pub masked_shader: &'a VulkanShader,
+   pub depth_compare_op: vk::CompareOp,
+   /// Of the render pass attachments
+   pub multisampling: Multisampling,
+}
+
+/// One graphics pipeline per `MaterialKey`. Viewport and scissor are dynamic, and front faces
+/// wind counter-clockwise.
+pub struct MaterialPipelines {
+   pipelines: HashMap<MaterialKey, vk::Pipeline>,
+}
+
+impl MaterialPipelines {
+   pub fn new(device: &ash::Device, desc: &MaterialPipelineDesc) -> Self {
+      assert!(
+         desc.layout.push_constant_range::<ObjectConstants>().is_some(),
+         "Material pipelines need ObjectConstants push constants"
+      );
+      let keys: Vec<MaterialKey> = MaterialKey::all().collect();
+      let vertex_bindings = [Vertex::binding_description(0)];
+      let vertex_attributes = Vertex::attribute_descriptions(0);
+      let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+         .vertex_binding_descriptions(&vertex_bindings)
+         .vertex_attribute_descriptions(&vertex_attributes);
+      let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+         .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+      let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+         .viewport_count(1)
+         .scissor_count(1);
+      let multisample_state = desc.multisampling.state_info();
+      let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+      let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+      let rasterization_states: Vec<_> = keys
+         .iter()
+         .map(|key| {
+            vk::PipelineRasterizationStateCreateInfo::builder()
+               .polygon_mode(vk::PolygonMode::FILL)
+               .cull_mode(key.cull_mode())
+               .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+               .line_width(1.0)
+               .build()
+         })
+         .collect();
+      let depth_stencil_states: Vec<_> = keys
+         .iter()
+         .map(|key| {
+            vk::PipelineDepthStencilStateCreateInfo::builder()
+               .depth_test_enable(true)
+               .depth_write_enable(key.depth_write())
+               .depth_compare_op(desc.depth_compare_op)
+               .max_depth_bounds(1.0)
+               .build()
+         })
+         .collect();
+      let blend_attachments: Vec<_> = keys.iter().map(|key| [key.color_blend_attachment()]).collect();
+      let color_blend_states: Vec<_> = blend_attachments
+         .iter()
+         .map(|attachments| vk::PipelineColorBlendStateCreateInfo::builder().attachments(attachments).build())
+         .collect();
+
+      let pipeline_infos: Vec<_> = keys
+         .iter()
+         .enumerate()
+         .map(|(index, key)| {
+            let shader = if key.alpha == AlphaKey::Mask { desc.masked_shader } else { desc.shader };
+            vk::GraphicsPipelineCreateInfo::builder()
+               .stages(shader.shader_stage_create_infos())
+               .vertex_input_state(&vertex_input_state)
+               .input_assembly_state(&input_assembly_state)
+               .viewport_state(&viewport_state)
+               .rasterization_state(&rasterization_states[index])
+               .multisample_state(&multisample_state)
+               .depth_stencil_state(&depth_stencil_states[index])
+               .color_blend_state(&color_blend_states[index])
+               .dynamic_state(&dynamic_state)
+               .layout(desc.layout.layout())
+               .render_pass(desc.render_pass)
+               .build()
+         })
+         .collect();
+      let pipelines = unsafe {
+         device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &pipeline_infos, None)
+            .expect("Unable to create material pipelines")
+      };
+      MaterialPipelines { pipelines: keys.into_iter().zip(pipelines).collect() }
+   }
+
+   pub fn get(&self, key: MaterialKey) -> vk::Pipeline {
+      self.pipelines[&key]
+   }
+}
+
+impl VulkanDrop for MaterialPipelines {
+   fn drop(self, device: &ash::Device) {
+      for (_, pipeline) in self.pipelines {
+         unsafe { device.destroy_pipeline(pipeline, None) };
+      }
+   }
+}
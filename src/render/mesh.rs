@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use cgmath::{Matrix, Matrix3, Matrix4, SquareMatrix, Vector4};
+
+use crate::asset::{Assets, Handle, MeshData};
+use crate::ecs::World;
+use crate::platform::gpu::vulkan_context::VulkanContext;
+use crate::platform::gpu::vulkan_mesh::Mesh;
+use crate::platform::gpu::VulkanDrop;
+
+/// Per object data of a mesh draw, in the `push_constant` block of the vertex and fragment
+/// shaders. See `Mesh::draw_object`. At 128 bytes, it fits the push constants of any device.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ObjectConstants {
+   pub model: Matrix4<f32>,
+   /// Columns of the `mat3` transforming normals, padded like in std430
+   pub normal_matrix: [Vector4<f32>; 3],
+   /// `GpuMaterial::id` of the material
+   pub material: u32,
+   _padding: [u32; 3],
+}
+
+impl ObjectConstants {
+   pub fn new(model: Matrix4<f32>, material: u32) -> Self {
+      let linear = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+      // Inverse transpose keeps normals perpendicular under non-uniform scale
+      let normal_matrix = linear.invert().map_or(linear, |inverse| inverse.transpose());
+      ObjectConstants {
+         model,
+         normal_matrix: [normal_matrix.x.extend(0.0), normal_matrix.y.extend(0.0), normal_matrix.z.extend(0.0)],
+         material,
+         _padding: [0; 3],
+      }
+   }
+}
+
+/// Uploaded `MeshData` assets.
+#[derive(Default)]
+pub struct GpuMeshes {
+   meshes: HashMap<Handle<MeshData>, Mesh>,
+}
+
+impl GpuMeshes {
+   pub fn new() -> Self {
+      Default::default()
+   }
+
+   /// Uploads the meshes added to the world's `Assets<MeshData>` since the last call. Empty
+   /// meshes are skipped
+   pub fn prepare(&mut self, context: &VulkanContext, world: &World) {
+      let Some(meshes) = world.get_resource::<Assets<MeshData>>() else { return };
+      for (handle, mesh) in meshes.iter() {
+         if !self.meshes.contains_key(&handle) && !mesh.indices.is_empty() {
+            self.meshes.insert(handle, mesh.upload(context));
+         }
+      }
+   }
+
+   pub fn get(&self, handle: Handle<MeshData>) -> Option<&Mesh> {
+      self.meshes.get(&handle)
+   }
+}
+
+impl VulkanDrop for GpuMeshes {
+   fn drop(self, device: &ash::Device) {
+      for (_, mesh) in self.meshes {
+         mesh.drop(device);
+      }
+   }
+}
@@ -0,0 +1,37 @@
+//! GPU side of the assets, and the pipelines drawing them.
+//!
+//! Resources here are prepared from the world's `Assets<T>` before rendering:
+//!
+//! ```ignore
+//! world.resource_mut::<GpuMaterials>().prepare(&context, &world);
+//! let material = world.resource::<GpuMaterials>().get(instance.material).unwrap();
+//! device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipelines.get(material.key));
+//! ```
+
+mod bloom;
+mod forward;
+mod ibl;
+mod light;
+mod material;
+mod mesh;
+mod post;
+mod post_stack;
+mod shadow;
+mod sky;
+mod target;
+mod tonemap;
+
+pub use bloom::{bloom_system, Bloom, BloomSettings};
+pub use forward::{forward_render_system, ForwardRenderer, ForwardSettings, FRAME_SET};
+pub use ibl::{GpuEnvironment, IblSettings};
+pub use light::{GpuLight, LightBuffer};
+pub use material::{
+   AlphaKey, GpuMaterial, GpuMaterials, MaterialKey, MaterialPipelineDesc, MaterialPipelines, MaterialTexture,
+   MaterialUniform, MATERIAL_SET,
+};
+pub use mesh::{GpuMeshes, ObjectConstants};
+pub use post_stack::{post_stack_system, ColorLut, PostPass, PostStack};
+pub use shadow::{GpuPointShadow, GpuShadow, ShadowConstants, ShadowMaps, ShadowSettings};
+pub use sky::{GpuAtmosphere, GpuSky, SkyPass};
+pub use target::SceneColor;
+pub use tonemap::{exposure_system, tonemap_system, AutoExposure, Exposure, TonemapSettings, Tonemapper, Tonemapping};
@@ -0,0 +1,106 @@
+use std::io::Cursor;
+
+use ash::vk;
+
+use crate::platform::gpu::vulkan_pipeline::VulkanPipelineLayout;
+use crate::platform::gpu::vulkan_shader::VulkanShader;
+use crate::platform::gpu::VulkanDrop;
+
+/// Full screen triangle of shader/post/fullscreen.vert without vertex input or depth, shaded by
+/// the fragment shader. Blends with the blend state if given, with dynamic blend constants
+pub(super) fn create_fullscreen_pipeline(
+   device: &ash::Device,
+   render_pass: vk::RenderPass,
+   layout: &VulkanPipelineLayout,
+   fragment_spv: &[u8],
+   blend: Option<vk::PipelineColorBlendAttachmentState>,
+) -> vk::Pipeline {
+   let shader = VulkanShader::builder(device)
+      .with_vertex_shader(0, &mut Cursor::new(&include_bytes!("../../shader/post/vert.spv")[..]))
+      .with_fragment_shader(1, &mut Cursor::new(fragment_spv))
+      .build();
+   let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default();
+   let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+      .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+   let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+      .viewport_count(1)
+      .scissor_count(1);
+   let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+      .polygon_mode(vk::PolygonMode::FILL)
+      .cull_mode(vk::CullModeFlags::NONE)
+      .line_width(1.0);
+   let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+      .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+   let blend_attachments = [blend.unwrap_or(vk::PipelineColorBlendAttachmentState {
+      color_write_mask: vk::ColorComponentFlags::RGBA,
+      ..Default::default()
+   })];
+   let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder().attachments(&blend_attachments);
+   let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+   if blend.is_some() {
+      dynamic_states.push(vk::DynamicState::BLEND_CONSTANTS);
+   }
+   let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+   let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+      .stages(shader.shader_stage_create_infos())
+      .vertex_input_state(&vertex_input_state)
+      .input_assembly_state(&input_assembly_state)
+      .viewport_state(&viewport_state)
+      .rasterization_state(&rasterization_state)
+      .multisample_state(&multisample_state)
+      .color_blend_state(&color_blend_state)
+      .dynamic_state(&dynamic_state)
+      .layout(layout.layout())
+      .render_pass(render_pass);
+   let pipeline = unsafe {
+      device
+         .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info.build()], None)
+         .expect("Unable to create full screen pipeline")[0]
+   };
+   shader.drop(device);
+   pipeline
+}
+
+pub(super) fn create_compute_pipeline(device: &ash::Device, layout: &VulkanPipelineLayout, spv: &[u8]) -> vk::Pipeline {
+   let shader = VulkanShader::builder(device)
+      .with_compute_shader(0, &mut Cursor::new(spv))
+      .build();
+   let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+      .stage(shader.shader_stage_create_infos()[0])
+      .layout(layout.layout());
+   let pipeline = unsafe {
+      device
+         .create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info.build()], None)
+         .expect("Unable to create compute pipeline")[0]
+   };
+   shader.drop(device);
+   pipeline
+}
+
+/// Begins the render pass over the whole framebuffer, with the viewport and scissor set
+pub(super) fn cmd_begin_fullscreen(
+   device: &ash::Device,
+   command_buffer: vk::CommandBuffer,
+   render_pass: vk::RenderPass,
+   framebuffer: vk::Framebuffer,
+   extent: vk::Extent2D,
+) {
+   let render_area: vk::Rect2D = extent.into();
+   let viewport = vk::Viewport {
+      x: 0.0,
+      y: 0.0,
+      width: extent.width as f32,
+      height: extent.height as f32,
+      min_depth: 0.0,
+      max_depth: 1.0,
+   };
+   let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+      .render_pass(render_pass)
+      .framebuffer(framebuffer)
+      .render_area(render_area);
+   unsafe {
+      device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+      device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+      device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+   }
+}
@@ -0,0 +1,484 @@
+use std::borrow::Cow;
+
+use ash::vk;
+
+use crate::ecs::{System, World};
+use crate::platform::gpu::vulkan_context::{FrameTarget, VulkanContext};
+use crate::platform::gpu::vulkan_descriptor::DescriptorAllocator;
+use crate::platform::gpu::vulkan_pipeline::VulkanPipelineLayout;
+use crate::platform::gpu::vulkan_texture::VulkanTexture;
+use crate::platform::gpu::VulkanDrop;
+use crate::platform::time::Time;
+
+use super::post::{cmd_begin_fullscreen, create_fullscreen_pipeline};
+use super::{SceneColor, Tonemapper};
+
+const MIDDLE_GREY: f32 = 0.18;
+
+/// Push constants of every post-processing pass.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PostConstants {
+   /// `params0` and `params1` of the shader, `PostPass::params`
+   params: [f32; 8],
+   /// Size of a pixel of the source in texture coordinates
+   texel_size: [f32; 2],
+   /// Index of the frame, seeding noise
+   frame: u32,
+}
+
+/// 3D lookup table grading HDR colors for `PostPass::color_grading`. The table is indexed by
+/// log encoded color, mapping `stops` on either side of middle grey to 0 to 1, and holds log
+/// encoded graded colors, so even a table of 2 texels along each axis grades exactly.
+pub struct ColorLut {
+   texture: VulkanTexture,
+   size: u32,
+   stops: f32,
+}
+
+impl ColorLut {
+   pub const DEFAULT_STOPS: f32 = 10.0;
+
+   /// Bakes `grade` of linear exposed colors at `size` points along each axis. Colors outside
+   /// the stops are clamped to them
+   pub fn new(context: &VulkanContext, size: u32, stops: f32, grade: impl Fn([f32; 3]) -> [f32; 3]) -> Self {
+      assert!(size >= 2, "Lookup table must have at least 2 texels along each axis");
+      let decode = |texel: u32| MIDDLE_GREY * ((texel as f32 / (size - 1) as f32 - 0.5) * 2.0 * stops).exp2();
+      let encode = |channel: f32| {
+         let encoded = (channel.max(1e-6) / MIDDLE_GREY).log2() / (2.0 * stops) + 0.5;
+         (encoded.clamp(0.0, 1.0) * 1023.0).round() as u32
+      };
+      let mut texels = Vec::with_capacity((size * size * size) as usize);
+      for blue in 0..size {
+         for green in 0..size {
+            for red in 0..size {
+               let [r, g, b] = grade([decode(red), decode(green), decode(blue)]);
+               texels.push(encode(r) | encode(g) << 10 | encode(b) << 20 | 3 << 30);
+            }
+         }
+      }
+      ColorLut { texture: VulkanTexture::new_lut(context, size, &texels), size, stops }
+   }
+
+   /// Table keeping the colors as they are
+   pub fn identity(context: &VulkanContext) -> Self {
+      Self::new(context, 2, Self::DEFAULT_STOPS, |color| color)
+   }
+
+   pub fn size(&self) -> u32 {
+      self.size
+   }
+
+   pub fn stops(&self) -> f32 {
+      self.stops
+   }
+}
+
+impl VulkanDrop for ColorLut {
+   fn drop(self, device: &ash::Device) {
+      self.texture.drop(device);
+   }
+}
+
+/// Full screen pass of a `PostStack`, reading the output of the previous pass. The fragment
+/// shader declares bindings and push constants like shader/post/color_grading.frag, getting
+/// `params` as its `params0` and `params1`. Passes that depend on the brightness, unlike ones
+/// scaling the color, multiply it by the `scale` of the exposure buffer and divide it back.
+pub struct PostPass {
+   /// Finds the pass in `PostStack::pass_mut`
+   pub name: &'static str,
+   /// Disabled passes are skipped, the stack passing the image on
+   pub enabled: bool,
+   pub params: [f32; 8],
+   fragment_spv: Cow<'static, [u8]>,
+   lut: Option<ColorLut>,
+}
+
+impl PostPass {
+   pub fn new(name: &'static str, fragment_spv: impl Into<Cow<'static, [u8]>>) -> Self {
+      PostPass { name, enabled: true, params: [0.0; 8], fragment_spv: fragment_spv.into(), lut: None }
+   }
+
+   pub fn with_params(mut self, params: [f32; 8]) -> Self {
+      self.params = params;
+      self
+   }
+
+   /// Bound as the 3D `lut` texture, which is otherwise `ColorLut::identity`
+   pub fn with_lut(mut self, lut: ColorLut) -> Self {
+      self.lut = Some(lut);
+      self
+   }
+
+   pub fn lut(&self) -> Option<&ColorLut> {
+      self.lut.as_ref()
+   }
+
+   /// FXAA, blurring along aliased edges. Params: the longest blur in texels, the blur
+   /// reduction by luma, and the least reduction
+   pub fn fxaa() -> Self {
+      Self::new("fxaa", &include_bytes!("../../shader/post/fxaa.spv")[..])
+         .with_params([8.0, 1.0 / 8.0, 1.0 / 128.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+   }
+
+   /// Darkens the corners by `intensity` from 0 to 1. The darkening starts at `radius` from the
+   /// center and spreads over `smoothness`, both in units of the screen height
+   pub fn vignette(intensity: f32, radius: f32, smoothness: f32) -> Self {
+      Self::new("vignette", &include_bytes!("../../shader/post/vignette.spv")[..])
+         .with_params([intensity, radius, smoothness, 0.0, 0.0, 0.0, 0.0, 0.0])
+   }
+
+   /// Splits red and blue apart toward the corners by `strength` in units of the screen
+   pub fn chromatic_aberration(strength: f32) -> Self {
+      Self::new(
+         "chromatic_aberration",
+         &include_bytes!("../../shader/post/chromatic_aberration.spv")[..],
+      )
+      .with_params([strength, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+   }
+
+   /// Noise changing every frame, scaling the color by up to half the `intensity` either way
+   pub fn film_grain(intensity: f32) -> Self {
+      Self::new("film_grain", &include_bytes!("../../shader/post/film_grain.spv")[..])
+         .with_params([intensity, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+   }
+
+   /// Grades with the table, mixing `strength` from 0 to 1 of the graded color in. Params: the
+   /// stops and size of the table, and the strength
+   pub fn color_grading(lut: ColorLut, strength: f32) -> Self {
+      let params = [lut.stops(), lut.size() as f32, strength, 0.0, 0.0, 0.0, 0.0, 0.0];
+      Self::new("color_grading", &include_bytes!("../../shader/post/color_grading.spv")[..])
+         .with_params(params)
+         .with_lut(lut)
+   }
+
+   /// Sharpens by `strength`, 0 keeping the image as it is
+   pub fn sharpen(strength: f32) -> Self {
+      Self::new("sharpen", &include_bytes!("../../shader/post/sharpen.spv")[..])
+         .with_params([strength, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+   }
+}
+
+/// Ordered list of full screen passes over the HDR `SceneColor`, before tonemapping so they
+/// work the same for SDR and HDR displays. The first pass reads the scene color, the passes
+/// between ping-pong through two images sized like it, and the last one writes the scene color
+/// back. The images are remade after the swapchain is recreated.
+///
+/// The frame goes through bloom, the exposure measurement of the `Tonemapper`, the stack and
+/// the tonemapping, in that order. The passes get the exposure of the frame, while the scene
+/// color stays unexposed for the tonemapping, and the measurement doesn't see e.g. the vignette.
+///
+/// A post-processing node like `Bloom`, a world resource recorded by `post_stack_system`.
+pub struct PostStack {
+   passes: Vec<PostPass>,
+   /// Of each pass
+   pipelines: Vec<vk::Pipeline>,
+   /// Copies the scene color out for a single pass
+   copy_pipeline: vk::Pipeline,
+   /// Bound to passes without a table
+   identity_lut: ColorLut,
+   memory_properties: vk::PhysicalDeviceMemoryProperties,
+   render_pass: vk::RenderPass,
+   targets: Vec<VulkanTexture>,
+   /// Of the two targets and the scene color of the swapchain generation
+   framebuffers: Vec<vk::Framebuffer>,
+   swapchain_generation: u64,
+   sampler: vk::Sampler,
+   /// Sets are allocated again each frame, after the previous one finished
+   descriptors: DescriptorAllocator,
+   set_layout: vk::DescriptorSetLayout,
+   layout: VulkanPipelineLayout,
+}
+
+impl PostStack {
+   pub fn new(context: &VulkanContext, passes: Vec<PostPass>) -> Self {
+      let device = &context.device;
+      let render_pass = create_render_pass(device);
+      let sampler_info = vk::SamplerCreateInfo::builder()
+         .mag_filter(vk::Filter::LINEAR)
+         .min_filter(vk::Filter::LINEAR)
+         .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+         .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+         .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+         .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+      let sampler = unsafe { device.create_sampler(&sampler_info, None).unwrap() };
+
+      let mut descriptors = DescriptorAllocator::new(8);
+      let stage_flags = vk::ShaderStageFlags::FRAGMENT;
+      let set_layout = descriptors.create_layout(device, &[
+         vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 1,
+            descriptor_type: vk::DescriptorType::SAMPLER,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 2,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 3,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+         },
+      ]);
+      let layout = VulkanPipelineLayout::builder(context)
+         .with_set_layout(set_layout)
+         .with_push_constants::<PostConstants>(stage_flags)
+         .build(device);
+      let copy_pipeline =
+         create_fullscreen_pipeline(device, render_pass, &layout, include_bytes!("../../shader/post/copy.spv"), None);
+      let mut stack = PostStack {
+         passes: Vec::new(),
+         pipelines: Vec::new(),
+         copy_pipeline,
+         identity_lut: ColorLut::identity(context),
+         memory_properties: context.device_memory_properties,
+         render_pass,
+         targets: Vec::new(),
+         framebuffers: Vec::new(),
+         swapchain_generation: 0,
+         sampler,
+         descriptors,
+         set_layout,
+         layout,
+      };
+      for pass in passes {
+         stack.push(device, pass);
+      }
+      stack
+   }
+
+   /// Appends the pass, run after the ones before it
+   pub fn push(&mut self, device: &ash::Device, pass: PostPass) {
+      let pipeline = create_fullscreen_pipeline(device, self.render_pass, &self.layout, &pass.fragment_spv, None);
+      self.pipelines.push(pipeline);
+      self.passes.push(pass);
+   }
+
+   pub fn passes(&self) -> &[PostPass] {
+      &self.passes
+   }
+
+   /// The first pass of the name, to change its params or enable it
+   pub fn pass_mut(&mut self, name: &str) -> Option<&mut PostPass> {
+      self.passes.iter_mut().find(|pass| pass.name == name)
+   }
+
+   /// Remakes the targets and framebuffers after the swapchain is recreated, with the scene
+   /// color resized too
+   fn prepare(&mut self, device: &ash::Device, frame: &FrameTarget, scene_color: &VulkanTexture) {
+      if frame.swapchain_changed(&mut self.swapchain_generation) {
+         for framebuffer in self.framebuffers.drain(..) {
+            unsafe { device.destroy_framebuffer(framebuffer, None) };
+         }
+         for target in self.targets.drain(..) {
+            target.drop(device);
+         }
+      }
+      if self.targets.is_empty() {
+         let extent = scene_color.extent();
+         self.targets = (0..2)
+            .map(|_| VulkanTexture::new_color_target(device, &self.memory_properties, extent, SceneColor::FORMAT))
+            .collect();
+         let views = [self.targets[0].image_view(), self.targets[1].image_view(), scene_color.image_view()];
+         self.framebuffers = views
+            .iter()
+            .map(|view| {
+               let attachments = [*view];
+               let create_info = vk::FramebufferCreateInfo::builder()
+                  .render_pass(self.render_pass)
+                  .attachments(&attachments)
+                  .width(extent.width)
+                  .height(extent.height)
+                  .layers(1);
+               unsafe { device.create_framebuffer(&create_info, None).unwrap() }
+            })
+            .collect();
+      }
+   }
+
+   /// Records the enabled passes over the scene color, exposed like the `Tonemapper` resource
+   /// measured. Does nothing before the scene is first rendered, or without enabled passes
+   pub fn render(&mut self, device: &ash::Device, world: &World) {
+      let scene_color = world.resource::<SceneColor>();
+      let Some(scene_color) = scene_color.texture() else { return };
+      // Passes by index, `None` for the copy
+      let mut steps: Vec<Option<usize>> = (0..self.passes.len())
+         .filter(|&index| self.passes[index].enabled)
+         .map(Some)
+         .collect();
+      match steps.len() {
+         0 => return,
+         1 => steps.insert(0, None),
+         _ => {}
+      }
+      let frame = world.resource::<FrameTarget>();
+      let command_buffer = frame.command_buffer;
+      self.prepare(device, &frame, scene_color);
+      self.descriptors.reset(device);
+      let extent = scene_color.extent();
+      let texel_size = [1.0 / extent.width as f32, 1.0 / extent.height as f32];
+      let frame_index = world.resource::<Time>().frame_index as u32;
+      let exposure_info = [world.resource::<Tonemapper>().exposure_info()];
+
+      let last = steps.len() - 1;
+      for (i, step) in steps.into_iter().enumerate() {
+         let source = if i == 0 { scene_color.descriptor_info() } else { self.targets[(i - 1) % 2].descriptor_info() };
+         let framebuffer = if i == last { self.framebuffers[2] } else { self.framebuffers[i % 2] };
+         let (pipeline, params, lut) = match step {
+            Some(index) => {
+               let pass = &self.passes[index];
+               (self.pipelines[index], pass.params, pass.lut.as_ref().unwrap_or(&self.identity_lut))
+            }
+            None => (self.copy_pipeline, [0.0; 8], &self.identity_lut),
+         };
+         let set = self.descriptors.allocate(device, self.set_layout);
+         let source_info = [source];
+         let sampler_info = [vk::DescriptorImageInfo { sampler: self.sampler, ..Default::default() }];
+         let lut_info = [lut.texture.descriptor_info()];
+         let writes = [
+            vk::WriteDescriptorSet::builder()
+               .dst_set(set)
+               .dst_binding(0)
+               .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+               .image_info(&source_info)
+               .build(),
+            vk::WriteDescriptorSet::builder()
+               .dst_set(set)
+               .dst_binding(1)
+               .descriptor_type(vk::DescriptorType::SAMPLER)
+               .image_info(&sampler_info)
+               .build(),
+            vk::WriteDescriptorSet::builder()
+               .dst_set(set)
+               .dst_binding(2)
+               .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+               .image_info(&lut_info)
+               .build(),
+            vk::WriteDescriptorSet::builder()
+               .dst_set(set)
+               .dst_binding(3)
+               .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+               .buffer_info(&exposure_info)
+               .build(),
+         ];
+         let constants = PostConstants { params, texel_size, frame: frame_index };
+         cmd_begin_fullscreen(device, command_buffer, self.render_pass, framebuffer, extent);
+         unsafe {
+            device.update_descriptor_sets(&writes, &[]);
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+               command_buffer,
+               vk::PipelineBindPoint::GRAPHICS,
+               self.layout.layout(),
+               0,
+               &[set],
+               &[],
+            );
+            self.layout.cmd_push(device, command_buffer, &constants);
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            device.cmd_end_render_pass(command_buffer);
+         }
+      }
+   }
+}
+
+impl VulkanDrop for PostStack {
+   fn drop(self, device: &ash::Device) {
+      unsafe {
+         for pipeline in self.pipelines {
+            device.destroy_pipeline(pipeline, None);
+         }
+         device.destroy_pipeline(self.copy_pipeline, None);
+         for framebuffer in self.framebuffers {
+            device.destroy_framebuffer(framebuffer, None);
+         }
+         device.destroy_sampler(self.sampler, None);
+      }
+      for pass in self.passes {
+         if let Some(lut) = pass.lut {
+            lut.drop(device);
+         }
+      }
+      self.identity_lut.drop(device);
+      for target in self.targets {
+         target.drop(device);
+      }
+      self.layout.drop(device);
+      self.descriptors.drop(device);
+      unsafe { device.destroy_render_pass(self.render_pass, None) };
+   }
+}
+
+/// Records the `PostStack` resource into the frame. Add it to `Stage::Render` after
+/// `exposure_system` and before `tonemap_system`
+pub fn post_stack_system(device: ash::Device) -> System {
+   System::parallel("post_stack", move |world| {
+      world.resource_mut::<PostStack>().render(&device, world);
+   })
+   .reads_resource::<SceneColor>()
+   .reads_resource::<Time>()
+   .reads_resource::<Tonemapper>()
+   .writes_resource::<PostStack>()
+   .writes_resource::<FrameTarget>()
+}
+
+/// A target or the `SceneColor` overwritten by a pass, ending in the shader read layout
+fn create_render_pass(device: &ash::Device) -> vk::RenderPass {
+   let attachments = [vk::AttachmentDescription {
+      format: SceneColor::FORMAT,
+      samples: vk::SampleCountFlags::TYPE_1,
+      load_op: vk::AttachmentLoadOp::DONT_CARE,
+      store_op: vk::AttachmentStoreOp::STORE,
+      initial_layout: vk::ImageLayout::UNDEFINED,
+      final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+      ..Default::default()
+   }];
+   let color_attachment_refs = [vk::AttachmentReference {
+      attachment: 0,
+      layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+   }];
+   let read_stages = vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER;
+   let dependencies = [
+      // After the image was last read and written, by the passes before
+      vk::SubpassDependency {
+         src_subpass: vk::SUBPASS_EXTERNAL,
+         src_stage_mask: read_stages | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+         src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+         dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+         dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+         ..Default::default()
+      },
+      vk::SubpassDependency {
+         src_subpass: 0,
+         dst_subpass: vk::SUBPASS_EXTERNAL,
+         src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+         src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+         dst_stage_mask: read_stages,
+         dst_access_mask: vk::AccessFlags::SHADER_READ,
+         ..Default::default()
+      },
+   ];
+   let subpass = vk::SubpassDescription::builder()
+      .color_attachments(&color_attachment_refs)
+      .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+   let create_info = vk::RenderPassCreateInfo::builder()
+      .attachments(&attachments)
+      .subpasses(std::slice::from_ref(&subpass))
+      .dependencies(&dependencies);
+   unsafe { device.create_render_pass(&create_info, None).unwrap() }
+}
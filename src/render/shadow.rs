@@ -0,0 +1,723 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::mem;
+use std::ops::Range;
+
+use ash::vk;
+use cgmath::{Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, Transform as _, Vector3, Vector4};
+
+use crate::asset::Vertex;
+use crate::ecs::{Entity, World};
+use crate::platform::gpu::vulkan_buffer::VulkanBuffer;
+use crate::platform::gpu::vulkan_context::VulkanContext;
+use crate::platform::gpu::vulkan_mesh::{Mesh, VertexLayout};
+use crate::platform::gpu::vulkan_pipeline::VulkanPipelineLayout;
+use crate::platform::gpu::vulkan_shader::VulkanShader;
+use crate::platform::gpu::vulkan_texture::{find_depth_format, VulkanTexture};
+use crate::platform::gpu::VulkanDrop;
+use crate::scene::{
+   Camera, CastShadows, DirectionalLight, GlobalTransform, Orthographic, Perspective, PointLight, Projection,
+   SpotLight,
+};
+
+/// Settings of the shadow maps of `ForwardRenderer`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowSettings {
+   /// Width and height of the depth atlas holding every shadow map, a power of two
+   pub atlas_size: u32,
+   /// Capacity of the shadow buffer. Lights whose shadows don't fit cast none
+   pub max_shadows: u32,
+   /// Shadow maps per directional light, each covering a farther slice of the view
+   pub cascade_count: u32,
+   /// View distance the cascades cover, and the far plane of spot lights without a range
+   pub max_distance: f32,
+   /// Blend between uniform (0) and logarithmic (1) cascade splits
+   pub split_lambda: f32,
+   /// Distance toward the light past a cascade's slice where objects still cast into it
+   pub caster_margin: f32,
+   /// Layers of the cube array of point light shadows. Lights past it, the farthest from the
+   /// camera first, cast none
+   pub max_point_shadows: u32,
+   /// Width and height of each cube face, the same for every point light
+   pub cube_size: u32,
+}
+
+impl Default for ShadowSettings {
+   fn default() -> Self {
+      ShadowSettings {
+         atlas_size: 4096,
+         max_shadows: 32,
+         cascade_count: 4,
+         max_distance: 50.0,
+         split_lambda: 0.75,
+         caster_margin: 50.0,
+         max_point_shadows: 4,
+         cube_size: 512,
+      }
+   }
+}
+
+impl ShadowSettings {
+   pub fn with_atlas_size(mut self, atlas_size: u32) -> Self {
+      self.atlas_size = atlas_size;
+      self
+   }
+
+   pub fn with_max_shadows(mut self, max_shadows: u32) -> Self {
+      self.max_shadows = max_shadows;
+      self
+   }
+
+   pub fn with_cascades(mut self, cascade_count: u32, max_distance: f32) -> Self {
+      self.cascade_count = cascade_count;
+      self.max_distance = max_distance;
+      self
+   }
+
+   pub fn with_split_lambda(mut self, split_lambda: f32) -> Self {
+      self.split_lambda = split_lambda;
+      self
+   }
+
+   pub fn with_caster_margin(mut self, caster_margin: f32) -> Self {
+      self.caster_margin = caster_margin;
+      self
+   }
+
+   pub fn with_point_shadows(mut self, max_point_shadows: u32, cube_size: u32) -> Self {
+      self.max_point_shadows = max_point_shadows;
+      self.cube_size = cube_size;
+      self
+   }
+}
+
+/// Shadow map as laid out in the std430 `Shadow` struct of the shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpuShadow {
+   /// World space to the shadow map's clip space, with depth in [0, 1]
+   pub view_projection: Matrix4<f32>,
+   /// Offset and size of the shadow map in atlas UVs
+   pub atlas_rect: Vector4<f32>,
+   /// View depth where the cascade ends, the largest float for spot lights
+   pub split_depth: f32,
+   /// World space offset of the receiver along its normal. Per unit of distance for spot lights
+   pub normal_offset: f32,
+   /// Radius of the percentage closer filter in atlas UVs
+   pub filter_radius: f32,
+   /// Size of an atlas texel in UVs
+   pub atlas_texel: f32,
+}
+
+/// Point light shadow as laid out in the std430 `PointShadow` struct of the shaders. Its cube is
+/// the one at the same index in the cube array.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpuPointShadow {
+   /// The depth stored for a point at distance `d` along the major axis of its cube face is
+   /// `depth_scale + depth_offset / d`
+   pub depth_scale: f32,
+   pub depth_offset: f32,
+   /// Offset of the receiver along its normal, per unit of distance to the light
+   pub normal_offset: f32,
+   /// Radius of the percentage closer filter, per unit of distance to the light
+   pub filter_radius: f32,
+}
+
+/// Push constants of the shadow pass.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowConstants {
+   pub clip_from_model: Matrix4<f32>,
+}
+
+/// Shadow map rendered this frame
+struct ShadowView {
+   view_projection: Matrix4<f32>,
+   viewport: vk::Rect2D,
+   depth_bias: f32,
+   slope_bias: f32,
+}
+
+/// Shadow map of a light before it has a place in the atlas
+struct ShadowProjection {
+   view_projection: Matrix4<f32>,
+   split_depth: f32,
+   /// World size of a texel, at unit distance for spot lights
+   texel_size: f32,
+}
+
+/// Depth atlas with the shadow maps of the `CastShadows` directional and spot lights, and the
+/// storage buffer of their `GpuShadow`s. Directional lights get cascades fitted to bounding
+/// spheres of the view slices, moved in whole texels, so their shadows don't shimmer as the
+/// camera moves. Point lights render their six faces into a cube array, compared against the
+/// depth of the fragment's distance along the face's axis. Both are sampled with a comparison
+/// sampler for percentage closer filtering.
+pub struct ShadowMaps {
+   settings: ShadowSettings,
+   atlas: VulkanTexture,
+   sampler: vk::Sampler,
+   render_pass: vk::RenderPass,
+   framebuffer: vk::Framebuffer,
+   pipeline_layout: VulkanPipelineLayout,
+   pipeline: vk::Pipeline,
+   shadows: VulkanBuffer,
+   views: Vec<ShadowView>,
+   cubes: VulkanTexture,
+   /// View and framebuffer of each cube face
+   cube_faces: Vec<(vk::ImageView, vk::Framebuffer)>,
+   point_shadows: VulkanBuffer,
+   /// Framebuffer of each cube face rendered this frame
+   face_views: Vec<(vk::Framebuffer, ShadowView)>,
+}
+
+impl ShadowMaps {
+   pub fn new(context: &VulkanContext, settings: ShadowSettings) -> Self {
+      assert!(settings.atlas_size.is_power_of_two(), "Shadow atlas size must be a power of two");
+      assert!(settings.cascade_count > 0, "Directional lights need at least one cascade");
+      assert!(context.image_cube_array, "Point light shadows need the imageCubeArray device feature");
+      let device = &context.device;
+      let format = find_depth_format(
+         context,
+         &[vk::Format::D32_SFLOAT, vk::Format::D16_UNORM],
+         vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+      )
+      .expect("No filterable depth format for shadow maps");
+      let extent = vk::Extent2D { width: settings.atlas_size, height: settings.atlas_size };
+      let atlas = VulkanTexture::new_depth(context, extent, format);
+
+      // Filtered comparisons blend four texel tests, softening PCF for free
+      let sampler_info = vk::SamplerCreateInfo::builder()
+         .mag_filter(vk::Filter::LINEAR)
+         .min_filter(vk::Filter::LINEAR)
+         .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+         .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+         .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+         .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+         .compare_enable(true)
+         .compare_op(vk::CompareOp::LESS_OR_EQUAL)
+         .max_lod(0.0);
+      let sampler = unsafe { device.create_sampler(&sampler_info, None).unwrap() };
+
+      let render_pass = create_render_pass(device, format);
+      let attachments = [atlas.image_view()];
+      let framebuffer_info = vk::FramebufferCreateInfo::builder()
+         .render_pass(render_pass)
+         .attachments(&attachments)
+         .width(extent.width)
+         .height(extent.height)
+         .layers(1);
+      let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() };
+
+      // Cube arrays can't be empty
+      let cubes = VulkanTexture::new_depth_cube_array(context, settings.cube_size, format, settings.max_point_shadows.max(1));
+      let cube_faces = (0..cubes.layers())
+         .map(|layer| {
+            let view = cubes.layer_view(device, layer);
+            let attachments = [view];
+            let framebuffer_info = vk::FramebufferCreateInfo::builder()
+               .render_pass(render_pass)
+               .attachments(&attachments)
+               .width(settings.cube_size)
+               .height(settings.cube_size)
+               .layers(1);
+            (view, unsafe { device.create_framebuffer(&framebuffer_info, None).unwrap() })
+         })
+         .collect();
+
+      let pipeline_layout = VulkanPipelineLayout::builder(context)
+         .with_push_constants::<ShadowConstants>(vk::ShaderStageFlags::VERTEX)
+         .build(device);
+      let pipeline = create_pipeline(device, render_pass, &pipeline_layout);
+
+      ShadowMaps {
+         settings,
+         atlas,
+         sampler,
+         render_pass,
+         framebuffer,
+         pipeline_layout,
+         pipeline,
+         shadows: VulkanBuffer::new_host_visible(
+            device,
+            &context.device_memory_properties,
+            (settings.max_shadows.max(1) as usize * mem::size_of::<GpuShadow>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+         ),
+         views: Vec::new(),
+         cubes,
+         cube_faces,
+         point_shadows: VulkanBuffer::new_host_visible(
+            device,
+            &context.device_memory_properties,
+            (settings.max_point_shadows.max(1) as usize * mem::size_of::<GpuPointShadow>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+         ),
+         face_views: Vec::new(),
+      }
+   }
+
+   pub fn settings(&self) -> &ShadowSettings {
+      &self.settings
+   }
+
+   /// Info for the `STORAGE_BUFFER` descriptor of the `GpuShadow`s
+   pub fn shadows_descriptor_info(&self) -> vk::DescriptorBufferInfo {
+      self.shadows.descriptor_info()
+   }
+
+   /// Info for the `SAMPLED_IMAGE` descriptor of the atlas
+   pub fn atlas_descriptor_info(&self) -> vk::DescriptorImageInfo {
+      self.atlas.descriptor_info()
+   }
+
+   /// Info for the `STORAGE_BUFFER` descriptor of the `GpuPointShadow`s
+   pub fn point_shadows_descriptor_info(&self) -> vk::DescriptorBufferInfo {
+      self.point_shadows.descriptor_info()
+   }
+
+   /// Info for the `SAMPLED_IMAGE` descriptor of the cube array
+   pub fn cubes_descriptor_info(&self) -> vk::DescriptorImageInfo {
+      self.cubes.descriptor_info()
+   }
+
+   /// Info for the `SAMPLER` descriptor of the depth comparison sampler
+   pub fn sampler_descriptor_info(&self) -> vk::DescriptorImageInfo {
+      vk::DescriptorImageInfo { sampler: self.sampler, ..Default::default() }
+   }
+
+   /// Places the shadow maps of the world's lights in the atlas, fitting cascades to the camera,
+   /// and gives the nearest point lights cubes. Writes their `GpuShadow`s and `GpuPointShadow`s,
+   /// and returns the range of shadows of each light casting them, indices of `GpuPointShadow`s
+   /// for point lights. The GPU must not be using the buffers
+   pub fn prepare(
+      &mut self,
+      device: &ash::Device,
+      world: &World,
+      camera: &Camera,
+      camera_global: &GlobalTransform,
+   ) -> HashMap<Entity, Range<u32>> {
+      let settings = self.settings;
+      let mut casters: Vec<(Entity, CastShadows, Vec<ShadowProjection>)> = Vec::new();
+      for (entity, (_, global, cast)) in world.query::<(&DirectionalLight, &GlobalTransform, &CastShadows)>().iter() {
+         let resolution = map_resolution(cast, settings.atlas_size);
+         let projections = cascades(&settings, camera, camera_global, light_direction(global), resolution);
+         casters.push((entity, *cast, projections));
+      }
+      for (entity, (light, global, cast)) in world.query::<(&SpotLight, &GlobalTransform, &CastShadows)>().iter() {
+         let resolution = map_resolution(cast, settings.atlas_size);
+         casters.push((entity, *cast, vec![spot_projection(&settings, light, global, resolution)]));
+      }
+
+      let sizes: Vec<u32> = casters
+         .iter()
+         .flat_map(|(_, cast, projections)| projections.iter().map(|_| map_resolution(cast, settings.atlas_size)))
+         .collect();
+      let placements = pack_tiles(settings.atlas_size, &sizes);
+
+      let atlas_texel = 1.0 / settings.atlas_size as f32;
+      let mut ranges = HashMap::new();
+      let mut shadows = Vec::new();
+      self.views.clear();
+      let mut placements = placements.into_iter();
+      for (entity, cast, projections) in casters {
+         let tiles: Vec<Option<vk::Rect2D>> = placements.by_ref().take(projections.len()).collect();
+         let fits = shadows.len() + projections.len() <= settings.max_shadows as usize;
+         if !fits || tiles.iter().any(Option::is_none) {
+            continue;
+         }
+         let start = shadows.len() as u32;
+         for (projection, tile) in projections.into_iter().zip(tiles.into_iter().flatten()) {
+            shadows.push(GpuShadow {
+               view_projection: projection.view_projection,
+               atlas_rect: Vector4::new(
+                  tile.offset.x as f32 * atlas_texel,
+                  tile.offset.y as f32 * atlas_texel,
+                  tile.extent.width as f32 * atlas_texel,
+                  tile.extent.height as f32 * atlas_texel,
+               ),
+               split_depth: projection.split_depth,
+               normal_offset: cast.normal_bias * projection.texel_size,
+               filter_radius: cast.filter_radius * atlas_texel,
+               atlas_texel,
+            });
+            self.views.push(ShadowView {
+               view_projection: projection.view_projection,
+               viewport: tile,
+               depth_bias: cast.depth_bias,
+               slope_bias: cast.slope_bias,
+            });
+         }
+         ranges.insert(entity, start..shadows.len() as u32);
+      }
+      self.shadows.write(device, &shadows);
+
+      let viewer = camera_global.translation();
+      let mut point_lights: Vec<_> = world
+         .query::<(&PointLight, &GlobalTransform, &CastShadows)>()
+         .iter()
+         .map(|(entity, (light, global, cast))| (entity, *light, global.translation(), *cast))
+         .collect();
+      point_lights.sort_by(|a, b| (a.2 - viewer).magnitude2().total_cmp(&(b.2 - viewer).magnitude2()));
+      point_lights.truncate(settings.max_point_shadows as usize);
+      self.face_views.clear();
+      let mut point_shadows = Vec::new();
+      for (index, (entity, light, position, cast)) in point_lights.into_iter().enumerate() {
+         let far = light.range.unwrap_or(settings.max_distance);
+         let near = (far * 1e-3).max(0.01);
+         let texel_size = 2.0 / settings.cube_size as f32;
+         point_shadows.push(GpuPointShadow {
+            depth_scale: far / (far - near),
+            depth_offset: -near * far / (far - near),
+            normal_offset: cast.normal_bias * texel_size,
+            filter_radius: cast.filter_radius * texel_size,
+         });
+         let viewport = vk::Rect2D::from(vk::Extent2D { width: settings.cube_size, height: settings.cube_size });
+         for (face, view_projection) in cube_face_projections(position, near, far).into_iter().enumerate() {
+            let (_, framebuffer) = self.cube_faces[index * 6 + face];
+            self.face_views.push((framebuffer, ShadowView {
+               view_projection,
+               viewport,
+               depth_bias: cast.depth_bias,
+               slope_bias: cast.slope_bias,
+            }));
+         }
+         ranges.insert(entity, index as u32..index as u32 + 1);
+      }
+      self.point_shadows.write(device, &point_shadows);
+      ranges
+   }
+
+   /// Records the shadow passes rendering the casters into the prepared shadow maps. Always
+   /// clears the atlas, which moves it to the layout it's sampled in
+   pub fn render(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, casters: &[(&Mesh, Matrix4<f32>)]) {
+      self.record_pass(device, command_buffer, self.framebuffer, self.atlas.extent(), &self.views, casters);
+      let face_extent = vk::Extent2D { width: self.settings.cube_size, height: self.settings.cube_size };
+      for (framebuffer, view) in &self.face_views {
+         self.record_pass(device, command_buffer, *framebuffer, face_extent, std::slice::from_ref(view), casters);
+      }
+   }
+
+   fn record_pass(
+      &self,
+      device: &ash::Device,
+      command_buffer: vk::CommandBuffer,
+      framebuffer: vk::Framebuffer,
+      extent: vk::Extent2D,
+      views: &[ShadowView],
+      casters: &[(&Mesh, Matrix4<f32>)],
+   ) {
+      let clear_values = [vk::ClearValue {
+         depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+      }];
+      let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+         .render_pass(self.render_pass)
+         .framebuffer(framebuffer)
+         .render_area(extent.into())
+         .clear_values(&clear_values);
+      unsafe {
+         device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+         device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+         for view in views {
+            let viewport = vk::Viewport {
+               x: view.viewport.offset.x as f32,
+               y: view.viewport.offset.y as f32,
+               width: view.viewport.extent.width as f32,
+               height: view.viewport.extent.height as f32,
+               min_depth: 0.0,
+               max_depth: 1.0,
+            };
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[view.viewport]);
+            device.cmd_set_depth_bias(command_buffer, view.depth_bias, 0.0, view.slope_bias);
+            for (mesh, model) in casters {
+               let constants = ShadowConstants { clip_from_model: view.view_projection * model };
+               mesh.bind(device, command_buffer);
+               mesh.draw_object(device, command_buffer, &self.pipeline_layout, &constants);
+            }
+         }
+         device.cmd_end_render_pass(command_buffer);
+      }
+   }
+}
+
+impl VulkanDrop for ShadowMaps {
+   fn drop(self, device: &ash::Device) {
+      unsafe {
+         device.destroy_pipeline(self.pipeline, None);
+         device.destroy_framebuffer(self.framebuffer, None);
+         for (view, framebuffer) in self.cube_faces {
+            device.destroy_framebuffer(framebuffer, None);
+            device.destroy_image_view(view, None);
+         }
+         device.destroy_render_pass(self.render_pass, None);
+         device.destroy_sampler(self.sampler, None);
+      }
+      self.pipeline_layout.drop(device);
+      self.shadows.drop(device);
+      self.atlas.drop(device);
+      self.point_shadows.drop(device);
+      self.cubes.drop(device);
+   }
+}
+
+fn map_resolution(cast: &CastShadows, atlas_size: u32) -> u32 {
+   cast.resolution.max(1).next_power_of_two().min(atlas_size)
+}
+
+/// Places square tiles, largest first, in rows from the top left. The tiles are powers of two,
+/// so rows of smaller tiles line up without gaps. Placements are in the order of `sizes`
+fn pack_tiles(atlas_size: u32, sizes: &[u32]) -> Vec<Option<vk::Rect2D>> {
+   let mut order: Vec<usize> = (0..sizes.len()).collect();
+   order.sort_by_key(|&index| std::cmp::Reverse(sizes[index]));
+   let mut placements = vec![None; sizes.len()];
+   let (mut x, mut y, mut row_height) = (0, 0, 0);
+   for index in order {
+      let size = sizes[index];
+      if x + size > atlas_size {
+         x = 0;
+         y += row_height;
+         row_height = 0;
+      }
+      if y + size > atlas_size {
+         continue;
+      }
+      placements[index] = Some(vk::Rect2D {
+         offset: vk::Offset2D { x: x as i32, y: y as i32 },
+         extent: vk::Extent2D { width: size, height: size },
+      });
+      x += size;
+      row_height = row_height.max(size);
+   }
+   placements
+}
+
+/// Local -Z of the light in world space
+fn light_direction(global: &GlobalTransform) -> Vector3<f32> {
+   (-global.matrix().z.truncate()).normalize()
+}
+
+/// View matrix looking along the direction, with any up vector not parallel to it
+fn look_to(eye: Point3<f32>, direction: Vector3<f32>) -> Matrix4<f32> {
+   let up = if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+   Matrix4::look_to_rh(eye, direction, up)
+}
+
+/// Cascades of a directional light, splitting the view between the camera's near plane and
+/// `max_distance` into slices, each fitted with an orthographic projection
+fn cascades(
+   settings: &ShadowSettings,
+   camera: &Camera,
+   camera_global: &GlobalTransform,
+   direction: Vector3<f32>,
+   resolution: u32,
+) -> Vec<ShadowProjection> {
+   let (near, far) = match camera.projection {
+      Projection::Perspective(perspective) => (perspective.near, perspective.far),
+      Projection::Orthographic(orthographic) => (orthographic.near, Some(orthographic.far)),
+   };
+   let far = far.map_or(settings.max_distance, |far| far.min(settings.max_distance));
+   let near = near.max(1e-3).min(far);
+   let count = settings.cascade_count;
+   let mut start = near;
+   (1..=count)
+      .map(|index| {
+         let fraction = index as f32 / count as f32;
+         let uniform = near + (far - near) * fraction;
+         let logarithmic = near * (far / near).powf(fraction);
+         let end = uniform + (logarithmic - uniform) * settings.split_lambda;
+         let corners = frustum_slice(camera, camera_global, start, end);
+         start = end;
+         cascade_projection(&corners, direction, resolution, settings.caster_margin, end)
+      })
+      .collect()
+}
+
+/// World space corners of the camera's view between two depths
+fn frustum_slice(camera: &Camera, global: &GlobalTransform, near: f32, far: f32) -> [Point3<f32>; 8] {
+   let half_height = |depth: f32| match camera.projection {
+      Projection::Perspective(perspective) => (perspective.fov_y.0 * 0.5).tan() * depth,
+      Projection::Orthographic(orthographic) => orthographic.height * 0.5,
+   };
+   let matrix = global.matrix();
+   let mut corners = [Point3::origin(); 8];
+   for (index, corner) in corners.iter_mut().enumerate() {
+      let depth = if index < 4 { near } else { far };
+      let y = half_height(depth) * if index & 1 == 0 { -1.0 } else { 1.0 };
+      let x = y.abs() * camera.aspect_ratio * if index & 2 == 0 { -1.0 } else { 1.0 };
+      *corner = matrix.transform_point(Point3::new(x, y, -depth));
+   }
+   corners
+}
+
+/// Orthographic projection around the bounding sphere of the slice. The sphere's size doesn't
+/// change as the camera turns, and snapping its center to texels keeps the map's texels in
+/// place as the camera moves
+fn cascade_projection(
+   corners: &[Point3<f32>; 8],
+   direction: Vector3<f32>,
+   resolution: u32,
+   caster_margin: f32,
+   split_depth: f32,
+) -> ShadowProjection {
+   let center = Point3::centroid(corners);
+   let radius = corners.iter().map(|corner| (corner - center).magnitude()).fold(0.0, f32::max);
+   // Rounded up, so float noise doesn't resize the sphere
+   let radius = (radius * 16.0).ceil() / 16.0;
+   let texel_size = 2.0 * radius / resolution as f32;
+
+   let light_view = look_to(Point3::origin(), direction);
+   let light_center = light_view.transform_point(center);
+   let snap = |value: f32| (value / texel_size).floor() * texel_size;
+   let near_plane = light_center.z + radius + caster_margin;
+   let view = Matrix4::from_translation(Vector3::new(-snap(light_center.x), -snap(light_center.y), -near_plane)) * light_view;
+   let projection = Camera::orthographic(Orthographic {
+      height: 2.0 * radius,
+      near: 0.0,
+      far: 2.0 * radius + caster_margin,
+   })
+   .projection_matrix();
+   ShadowProjection { view_projection: projection * view, split_depth, texel_size }
+}
+
+fn spot_projection(settings: &ShadowSettings, light: &SpotLight, global: &GlobalTransform, resolution: u32) -> ShadowProjection {
+   let fov_y = Rad((light.outer_cone_angle.0 * 2.0).min(Rad::from(Deg(170.0)).0));
+   let far = light.range.unwrap_or(settings.max_distance);
+   let view = look_to(Point3::from_vec(global.translation()), light_direction(global));
+   let projection = Camera::perspective(Perspective {
+      fov_y,
+      near: (far * 1e-3).max(0.01),
+      far: Some(far),
+      reverse_z: false,
+   })
+   .projection_matrix();
+   ShadowProjection {
+      view_projection: projection * view,
+      split_depth: f32::MAX,
+      texel_size: 2.0 * (fov_y.0 * 0.5).tan() / resolution as f32,
+   }
+}
+
+/// View projections of the faces of a cube around the position, in the order of cube array
+/// layers. Projected X is mirrored, as cube faces are seen from the inside: sampling a face's
+/// texel at UV (u, v) must find what was rendered to framebuffer pixel (u, v)
+fn cube_face_projections(position: Vector3<f32>, near: f32, far: f32) -> [Matrix4<f32>; 6] {
+   let projection = Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0)
+      * Camera::perspective(Perspective {
+         fov_y: Deg(90.0).into(),
+         near,
+         far: Some(far),
+         reverse_z: false,
+      })
+      .projection_matrix();
+   let eye = Point3::from_vec(position);
+   let faces = [
+      (Vector3::unit_x(), Vector3::unit_y()),
+      (-Vector3::unit_x(), Vector3::unit_y()),
+      (Vector3::unit_y(), -Vector3::unit_z()),
+      (-Vector3::unit_y(), Vector3::unit_z()),
+      (Vector3::unit_z(), Vector3::unit_y()),
+      (-Vector3::unit_z(), Vector3::unit_y()),
+   ];
+   faces.map(|(direction, up)| projection * Matrix4::look_to_rh(eye, direction, up))
+}
+
+/// Depth only pass into the atlas or a cube face, leaving it ready to be sampled by fragment shaders
+fn create_render_pass(device: &ash::Device, format: vk::Format) -> vk::RenderPass {
+   let attachments = [vk::AttachmentDescription {
+      format,
+      samples: vk::SampleCountFlags::TYPE_1,
+      load_op: vk::AttachmentLoadOp::CLEAR,
+      store_op: vk::AttachmentStoreOp::STORE,
+      final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+      ..Default::default()
+   }];
+   let depth_attachment_ref = vk::AttachmentReference {
+      attachment: 0,
+      layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+   };
+   let dependencies = [
+      // Reads of the previous frame finish before the clear
+      vk::SubpassDependency {
+         src_subpass: vk::SUBPASS_EXTERNAL,
+         dst_subpass: 0,
+         src_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+         dst_stage_mask: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+         src_access_mask: vk::AccessFlags::SHADER_READ,
+         dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+         ..Default::default()
+      },
+      vk::SubpassDependency {
+         src_subpass: 0,
+         dst_subpass: vk::SUBPASS_EXTERNAL,
+         src_stage_mask: vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+         dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+         src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+         dst_access_mask: vk::AccessFlags::SHADER_READ,
+         ..Default::default()
+      },
+   ];
+   let subpass = vk::SubpassDescription::builder()
+      .depth_stencil_attachment(&depth_attachment_ref)
+      .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+   let create_info = vk::RenderPassCreateInfo::builder()
+      .attachments(&attachments)
+      .subpasses(std::slice::from_ref(&subpass))
+      .dependencies(&dependencies);
+   unsafe { device.create_render_pass(&create_info, None).unwrap() }
+}
+
+/// Vertex only pipeline with dynamic viewport, scissor and depth bias. Nothing is culled, so
+/// single sided geometry casts from both sides
+fn create_pipeline(device: &ash::Device, render_pass: vk::RenderPass, layout: &VulkanPipelineLayout) -> vk::Pipeline {
+   let shader = VulkanShader::builder(device)
+      .with_vertex_shader(0, &mut Cursor::new(&include_bytes!("../../shader/shadow/vert.spv")[..]))
+      .build();
+   let vertex_bindings = [Vertex::binding_description(0)];
+   let vertex_attributes = Vertex::attribute_descriptions(0);
+   let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+      .vertex_binding_descriptions(&vertex_bindings)
+      .vertex_attribute_descriptions(&vertex_attributes);
+   let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+      .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+   let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+      .viewport_count(1)
+      .scissor_count(1);
+   let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+      .polygon_mode(vk::PolygonMode::FILL)
+      .cull_mode(vk::CullModeFlags::NONE)
+      .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+      .depth_bias_enable(true)
+      .line_width(1.0);
+   let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+      .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+   let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+      .depth_test_enable(true)
+      .depth_write_enable(true)
+      .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+      .max_depth_bounds(1.0);
+   let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder();
+   let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR, vk::DynamicState::DEPTH_BIAS];
+   let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+   let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+      .stages(shader.shader_stage_create_infos())
+      .vertex_input_state(&vertex_input_state)
+      .input_assembly_state(&input_assembly_state)
+      .viewport_state(&viewport_state)
+      .rasterization_state(&rasterization_state)
+      .multisample_state(&multisample_state)
+      .depth_stencil_state(&depth_stencil_state)
+      .color_blend_state(&color_blend_state)
+      .dynamic_state(&dynamic_state)
+      .layout(layout.layout())
+      .render_pass(render_pass);
+   let pipeline = unsafe {
+      device
+         .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info.build()], None)
+         .expect("Unable to create the shadow pipeline")[0]
+   };
+   shader.drop(device);
+   pipeline
+}
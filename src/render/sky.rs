@@ -0,0 +1,222 @@
+use std::io::Cursor;
+use std::mem;
+
+use ash::vk;
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Zero};
+
+use crate::ecs::World;
+use crate::platform::gpu::vulkan_buffer::VulkanBuffer;
+use crate::platform::gpu::vulkan_context::VulkanContext;
+use crate::platform::gpu::vulkan_pipeline::{Multisampling, VulkanPipelineLayout};
+use crate::platform::gpu::vulkan_shader::VulkanShader;
+use crate::platform::gpu::VulkanDrop;
+use crate::scene::{Atmosphere, Camera, GlobalTransform, Sky, Sun};
+
+use super::GpuEnvironment;
+
+const ENVIRONMENT: u32 = 1;
+const ATMOSPHERE: u32 = 2;
+
+/// `Atmosphere` lit by the `Sun`, as laid out in the std140 `Atmosphere` struct of the shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpuAtmosphere {
+   /// Unit vector toward the sun
+   pub sun_direction: Vector3<f32>,
+   pub sun_intensity: f32,
+   pub rayleigh_scattering: Vector3<f32>,
+   pub rayleigh_scale_height: f32,
+   pub mie_scattering: f32,
+   pub mie_scale_height: f32,
+   pub mie_anisotropy: f32,
+   pub planet_radius: f32,
+   pub atmosphere_radius: f32,
+   pub observer_altitude: f32,
+   _padding: [u32; 2],
+}
+
+impl GpuAtmosphere {
+   pub fn new(atmosphere: &Atmosphere, sun_direction: Vector3<f32>) -> Self {
+      GpuAtmosphere {
+         sun_direction: sun_direction.normalize(),
+         sun_intensity: atmosphere.sun_intensity,
+         rayleigh_scattering: atmosphere.rayleigh_scattering,
+         rayleigh_scale_height: atmosphere.rayleigh_scale_height,
+         mie_scattering: atmosphere.mie_scattering,
+         mie_scale_height: atmosphere.mie_scale_height,
+         mie_anisotropy: atmosphere.mie_anisotropy,
+         planet_radius: atmosphere.planet_radius,
+         atmosphere_radius: atmosphere.atmosphere_radius,
+         observer_altitude: atmosphere.observer_altitude,
+         _padding: [0; 2],
+      }
+   }
+
+   /// The `Sky::Atmosphere` resource of the world, with the sun along the local +Z of the first
+   /// `Sun`, or overhead without one
+   pub fn from_world(world: &World) -> Option<Self> {
+      let Sky::Atmosphere(atmosphere) = *world.get_resource::<Sky>()? else { return None };
+      let sun_direction = world
+         .query::<(&Sun, &GlobalTransform)>()
+         .iter()
+         .map(|(_, (_, global))| global.matrix().z.truncate())
+         .find(|direction| !direction.is_zero())
+         .unwrap_or(Vector3::unit_y());
+      Some(Self::new(&atmosphere, sun_direction))
+   }
+}
+
+/// Sky uniform as laid out in the std140 `Sky` block of the sky shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpuSky {
+   /// Clip space to world space directions, the inverse of the projection times the view's
+   /// rotation
+   pub world_from_clip: Matrix4<f32>,
+   pub atmosphere: GpuAtmosphere,
+   /// 0 draws nothing, 1 the environment cube and 2 the atmosphere
+   pub mode: u32,
+   /// Scale of the sky's color
+   pub intensity: f32,
+   /// Depth of the far plane, where the sky is drawn
+   pub depth: f32,
+   _padding: u32,
+}
+
+/// Draws the `Sky` resource behind the scene inside the pass of `ForwardRenderer`, from the
+/// uniform buffer and environment cube of its frame set.
+pub struct SkyPass {
+   uniform: VulkanBuffer,
+   pipeline: vk::Pipeline,
+}
+
+impl SkyPass {
+   /// `layout` is the one of the forward pipelines, the sky only reads the frame set
+   pub fn new(
+      context: &VulkanContext,
+      render_pass: vk::RenderPass,
+      layout: &VulkanPipelineLayout,
+      depth_compare_op: vk::CompareOp,
+      multisampling: Multisampling,
+   ) -> Self {
+      let device = &context.device;
+      SkyPass {
+         uniform: VulkanBuffer::new_host_visible(
+            device,
+            &context.device_memory_properties,
+            mem::size_of::<GpuSky>() as vk::DeviceSize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+         ),
+         pipeline: create_pipeline(device, render_pass, layout, depth_compare_op, multisampling),
+      }
+   }
+
+   /// Info for the `UNIFORM_BUFFER` descriptor of the `GpuSky`
+   pub fn descriptor_info(&self) -> vk::DescriptorBufferInfo {
+      self.uniform.descriptor_info()
+   }
+
+   /// Writes the sky seen by the camera, returning whether there is one to draw. The GPU must
+   /// not be using the buffer
+   pub fn prepare(&self, device: &ash::Device, world: &World, camera: &Camera, camera_global: &GlobalTransform) -> bool {
+      let (mode, intensity) = match world.get_resource::<Sky>().map(|sky| *sky) {
+         None => (0, 0.0),
+         Some(Sky::Environment) => {
+            (ENVIRONMENT, world.get_resource::<GpuEnvironment>().map_or(0.0, |environment| environment.intensity()))
+         }
+         Some(Sky::Atmosphere(_)) => (ATMOSPHERE, 1.0),
+      };
+      if mode == 0 {
+         return false;
+      }
+      let mut view = Camera::view_matrix(camera_global);
+      view.w = Vector3::zero().extend(1.0);
+      let world_from_clip = (camera.projection_matrix() * view)
+         .invert()
+         .expect("Camera projection must be invertible");
+      let atmosphere = GpuAtmosphere::from_world(world)
+         .unwrap_or_else(|| GpuAtmosphere::new(&Atmosphere::default(), Vector3::unit_y()));
+      let sky = GpuSky {
+         world_from_clip,
+         atmosphere,
+         mode,
+         intensity,
+         depth: camera.clear_depth(),
+         _padding: 0,
+      };
+      self.uniform.write(device, &[sky]);
+      true
+   }
+
+   /// Records the draw into the render pass, with the frame set bound
+   pub fn draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+      unsafe {
+         device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+         device.cmd_draw(command_buffer, 3, 1, 0, 0);
+      }
+   }
+}
+
+impl VulkanDrop for SkyPass {
+   fn drop(self, device: &ash::Device) {
+      unsafe { device.destroy_pipeline(self.pipeline, None) };
+      self.uniform.drop(device);
+   }
+}
+
+/// Full screen triangle without vertex input, tested against the depth of the scene but not
+/// writing it
+fn create_pipeline(
+   device: &ash::Device,
+   render_pass: vk::RenderPass,
+   layout: &VulkanPipelineLayout,
+   depth_compare_op: vk::CompareOp,
+   multisampling: Multisampling,
+) -> vk::Pipeline {
+   let shader = VulkanShader::builder(device)
+      .with_vertex_shader(0, &mut Cursor::new(&include_bytes!("../../shader/sky/vert.spv")[..]))
+      .with_fragment_shader(1, &mut Cursor::new(&include_bytes!("../../shader/sky/frag.spv")[..]))
+      .build();
+   let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default();
+   let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+      .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+   let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+      .viewport_count(1)
+      .scissor_count(1);
+   let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+      .polygon_mode(vk::PolygonMode::FILL)
+      .cull_mode(vk::CullModeFlags::NONE)
+      .line_width(1.0);
+   let multisample_state = multisampling.state_info();
+   let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+      .depth_test_enable(true)
+      .depth_write_enable(false)
+      .depth_compare_op(depth_compare_op)
+      .max_depth_bounds(1.0);
+   let blend_attachments = [vk::PipelineColorBlendAttachmentState {
+      color_write_mask: vk::ColorComponentFlags::RGBA,
+      ..Default::default()
+   }];
+   let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder().attachments(&blend_attachments);
+   let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+   let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+   let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+      .stages(shader.shader_stage_create_infos())
+      .vertex_input_state(&vertex_input_state)
+      .input_assembly_state(&input_assembly_state)
+      .viewport_state(&viewport_state)
+      .rasterization_state(&rasterization_state)
+      .multisample_state(&multisample_state)
+      .depth_stencil_state(&depth_stencil_state)
+      .color_blend_state(&color_blend_state)
+      .dynamic_state(&dynamic_state)
+      .layout(layout.layout())
+      .render_pass(render_pass);
+   let pipeline = unsafe {
+      device
+         .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info.build()], None)
+         .expect("Unable to create sky pipeline")[0]
+   };
+   shader.drop(device);
+   pipeline
+}
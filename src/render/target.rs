@@ -0,0 +1,51 @@
+use ash::vk;
+
+use crate::platform::gpu::vulkan_context::{FrameTarget, VulkanContext};
+use crate::platform::gpu::vulkan_texture::VulkanTexture;
+use crate::platform::gpu::VulkanDrop;
+
+/// HDR color image the scene is rendered into, sized like the swapchain and read by the passes
+/// after it, like `Tonemapper`. A world resource, resized by `ForwardRenderer` when it renders.
+pub struct SceneColor {
+   memory_properties: vk::PhysicalDeviceMemoryProperties,
+   texture: Option<VulkanTexture>,
+   swapchain_generation: u64,
+}
+
+impl SceneColor {
+   pub const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+   /// The image is made by the first `prepare`
+   pub fn new(context: &VulkanContext) -> Self {
+      SceneColor {
+         memory_properties: context.device_memory_properties,
+         texture: None,
+         swapchain_generation: 0,
+      }
+   }
+
+   /// Remakes the image for the frame's swapchain if it was recreated
+   pub fn prepare(&mut self, device: &ash::Device, frame: &FrameTarget) -> &VulkanTexture {
+      if frame.swapchain_changed(&mut self.swapchain_generation) {
+         if let Some(texture) = self.texture.take() {
+            texture.drop(device);
+         }
+      }
+      self.texture.get_or_insert_with(|| {
+         VulkanTexture::new_color_target(device, &self.memory_properties, frame.extent, Self::FORMAT)
+      })
+   }
+
+   /// The image in the shader read layout, `None` until the scene is first rendered
+   pub fn texture(&self) -> Option<&VulkanTexture> {
+      self.texture.as_ref()
+   }
+}
+
+impl VulkanDrop for SceneColor {
+   fn drop(self, device: &ash::Device) {
+      if let Some(texture) = self.texture {
+         texture.drop(device);
+      }
+   }
+}
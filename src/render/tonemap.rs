@@ -0,0 +1,512 @@
+use ash::vk;
+
+use crate::ecs::{System, World};
+use crate::platform::gpu::vulkan_buffer::VulkanBuffer;
+use crate::platform::gpu::vulkan_context::{is_srgb_format, DisplayOutput, FrameTarget, VulkanContext};
+use crate::platform::gpu::vulkan_descriptor::DescriptorAllocator;
+use crate::platform::gpu::vulkan_framebuffer::SwapchainFramebuffers;
+use crate::platform::gpu::vulkan_pipeline::VulkanPipelineLayout;
+use crate::platform::gpu::VulkanDrop;
+use crate::platform::time::Time;
+
+use super::post::{cmd_begin_fullscreen, create_compute_pipeline, create_fullscreen_pipeline};
+use super::SceneColor;
+
+/// Invocations along X and Y of the histogram shader
+const HISTOGRAM_GROUP_SIZE: u32 = 16;
+const HISTOGRAM_BINS: usize = 256;
+/// Luminance the average of the scene is exposed to, middle grey
+const MIDDLE_GREY: f32 = 0.18;
+
+/// Curve mapping the exposed HDR color into the SDR display range. HDR outputs keep colors up to
+/// paper white instead, rolling highlights off toward the peak of `TonemapSettings`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tonemapping {
+   /// Divides by one plus the luminance, keeping hues but washing out highlights
+   Reinhard,
+   /// Fit of the ACES filmic curve, contrasty and saturated
+   #[default]
+   Aces,
+   /// AgX with its base look, desaturating highlights toward white
+   AgX,
+}
+
+/// Scale of the scene color before tonemapping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exposure {
+   /// Fixed exposure in stops, scaling the scene color by 2^stops
+   Manual(f32),
+   /// Exposure adapting to the average luminance of the scene
+   Auto(AutoExposure),
+}
+
+impl Default for Exposure {
+   fn default() -> Self {
+      Exposure::Manual(0.0)
+   }
+}
+
+/// Settings of `Exposure::Auto`. The scene is exposed so its average luminance, measured with a
+/// histogram of log2 luminance, ends up at middle grey.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoExposure {
+   /// Lower end of the log2 luminance range of the histogram, darker pixels are ignored
+   pub min_log_luminance: f32,
+   /// Upper end of the log2 luminance range of the histogram, brighter pixels count as it
+   pub max_log_luminance: f32,
+   /// Rate at which the exposure follows the scene, per second
+   pub adaptation_speed: f32,
+   /// Stops added to the measured exposure
+   pub compensation: f32,
+}
+
+impl Default for AutoExposure {
+   fn default() -> Self {
+      AutoExposure {
+         min_log_luminance: -8.0,
+         max_log_luminance: 4.0,
+         adaptation_speed: 1.5,
+         compensation: 0.0,
+      }
+   }
+}
+
+impl AutoExposure {
+   pub fn with_log_luminance_range(mut self, min_log_luminance: f32, max_log_luminance: f32) -> Self {
+      self.min_log_luminance = min_log_luminance;
+      self.max_log_luminance = max_log_luminance;
+      self
+   }
+
+   pub fn with_adaptation_speed(mut self, adaptation_speed: f32) -> Self {
+      self.adaptation_speed = adaptation_speed;
+      self
+   }
+
+   pub fn with_compensation(mut self, compensation: f32) -> Self {
+      self.compensation = compensation;
+      self
+   }
+}
+
+/// Settings of `Tonemapper`, changeable between frames through `Tonemapper::settings_mut`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TonemapSettings {
+   pub tonemapping: Tonemapping,
+   pub exposure: Exposure,
+   /// Brightness of a color of 1 on HDR outputs, like SDR white on the display
+   pub paper_white_nits: f32,
+   /// Brightest the display shows on HDR outputs, highlights approach it
+   pub peak_nits: f32,
+}
+
+impl Default for TonemapSettings {
+   fn default() -> Self {
+      TonemapSettings {
+         tonemapping: Tonemapping::default(),
+         exposure: Exposure::default(),
+         paper_white_nits: 203.0,
+         peak_nits: 1000.0,
+      }
+   }
+}
+
+impl TonemapSettings {
+   pub fn with_tonemapping(mut self, tonemapping: Tonemapping) -> Self {
+      self.tonemapping = tonemapping;
+      self
+   }
+
+   pub fn with_exposure(mut self, exposure: Exposure) -> Self {
+      self.exposure = exposure;
+      self
+   }
+
+   pub fn with_nits(mut self, paper_white_nits: f32, peak_nits: f32) -> Self {
+      self.paper_white_nits = paper_white_nits;
+      self.peak_nits = peak_nits;
+      self
+   }
+}
+
+/// Encoding of the swapchain images, the `output_encoding` of the tonemapping shader
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Output {
+   /// sRGB encoded by the format on write
+   Sdr,
+   /// sRGB encoded by the shader
+   SdrSrgb,
+   Hdr10,
+   ScRgb,
+}
+
+/// Storage buffer of the exposure shaders, `Exposure` in the shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct GpuExposure {
+   /// Adapted average luminance
+   luminance: f32,
+   /// Scale applied to the scene color under `Exposure::Auto`
+   scale: f32,
+   histogram: [u32; HISTOGRAM_BINS],
+}
+
+/// Push constants of the histogram and exposure shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ExposureConstants {
+   min_log_luminance: f32,
+   log_luminance_range: f32,
+   /// Fraction of the way from the adapted luminance to the measured one covered this frame
+   adaptation: f32,
+   /// Luminance the adapted one is exposed to
+   key: f32,
+}
+
+/// Push constants of the tonemapping shader.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TonemapConstants {
+   tonemapping: u32,
+   output_encoding: u32,
+   auto_exposure: u32,
+   manual_exposure: f32,
+   paper_white_nits: f32,
+   peak_nits: f32,
+}
+
+/// Draws the HDR `SceneColor` into the swapchain image, exposed and tonemapped, and presents it.
+/// Encodes for the `DisplayOutput` of the context, HDR10 or scRGB when it selected one.
+/// `Exposure::Auto` first measures the scene with compute shaders, building a luminance
+/// histogram and adapting the exposure on the GPU. A world resource recorded by
+/// `tonemap_system`, after the scene is rendered.
+///
+/// The exposure of the frame is kept in a buffer, which a `PostStack` reads to work on the
+/// exposed color. With a stack, `exposure_system` measures the scene before it, so the passes
+/// don't feed back into the exposure.
+pub struct Tonemapper {
+   settings: TonemapSettings,
+   output: Output,
+   render_pass: vk::RenderPass,
+   framebuffers: SwapchainFramebuffers,
+   /// The set is allocated again each frame, after the previous one finished
+   descriptors: DescriptorAllocator,
+   set_layout: vk::DescriptorSetLayout,
+   layout: VulkanPipelineLayout,
+   pipeline: vk::Pipeline,
+   exposure_layout: VulkanPipelineLayout,
+   histogram_pipeline: vk::Pipeline,
+   exposure_pipeline: vk::Pipeline,
+   /// `GpuExposure`, kept across frames for the adaptation
+   exposure: VulkanBuffer,
+   /// Set of the frame, allocated by `measure`
+   set: vk::DescriptorSet,
+   /// Frame index of the latest `measure`
+   measured_frame: Option<u64>,
+}
+
+impl Tonemapper {
+   pub fn new(context: &VulkanContext, settings: TonemapSettings) -> Self {
+      let device = &context.device;
+      let surface_format = context.surface_format.format;
+      let render_pass = create_render_pass(device, surface_format);
+      let mut descriptors = DescriptorAllocator::new(1);
+      let stage_flags = vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE;
+      let set_layout = descriptors.create_layout(device, &[
+         vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+         },
+         vk::DescriptorSetLayoutBinding {
+            binding: 1,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+         },
+      ]);
+      let layout = VulkanPipelineLayout::builder(context)
+         .with_set_layout(set_layout)
+         .with_push_constants::<TonemapConstants>(vk::ShaderStageFlags::FRAGMENT)
+         .build(device);
+      let exposure_layout = VulkanPipelineLayout::builder(context)
+         .with_set_layout(set_layout)
+         .with_push_constants::<ExposureConstants>(vk::ShaderStageFlags::COMPUTE)
+         .build(device);
+      let histogram_pipeline =
+         create_compute_pipeline(device, &exposure_layout, include_bytes!("../../shader/post/histogram.spv"));
+      let exposure_pipeline =
+         create_compute_pipeline(device, &exposure_layout, include_bytes!("../../shader/post/exposure.spv"));
+      // Starts exposed at 1, adapting from there
+      let initial = GpuExposure { luminance: MIDDLE_GREY, scale: 1.0, histogram: [0; HISTOGRAM_BINS] };
+      Tonemapper {
+         settings,
+         output: match context.display_output {
+            DisplayOutput::Sdr if is_srgb_format(surface_format) => Output::Sdr,
+            DisplayOutput::Sdr => Output::SdrSrgb,
+            DisplayOutput::Hdr10 => Output::Hdr10,
+            DisplayOutput::ScRgb => Output::ScRgb,
+         },
+         render_pass,
+         framebuffers: SwapchainFramebuffers::color_only(render_pass),
+         descriptors,
+         set_layout,
+         pipeline: create_fullscreen_pipeline(
+            device,
+            render_pass,
+            &layout,
+            include_bytes!("../../shader/post/tonemap.spv"),
+            None,
+         ),
+         layout,
+         exposure_layout,
+         histogram_pipeline,
+         exposure_pipeline,
+         exposure: VulkanBuffer::new_device_local(context, vk::BufferUsageFlags::STORAGE_BUFFER, &[initial]),
+         set: vk::DescriptorSet::null(),
+         measured_frame: None,
+      }
+   }
+
+   pub fn settings(&self) -> &TonemapSettings {
+      &self.settings
+   }
+
+   pub fn settings_mut(&mut self) -> &mut TonemapSettings {
+      &mut self.settings
+   }
+
+   pub fn render_pass(&self) -> vk::RenderPass {
+      self.render_pass
+   }
+
+   /// `GpuExposure` of the frame, its `scale` is the exposure applied by `render`
+   pub(super) fn exposure_info(&self) -> vk::DescriptorBufferInfo {
+      self.exposure.descriptor_info()
+   }
+
+   /// Records the exposure of the frame into the exposure buffer, measured under `Exposure::Auto`.
+   /// Happens once per frame, `render` calls it unless `exposure_system` did earlier
+   pub fn measure(&mut self, device: &ash::Device, world: &World) {
+      let frame_index = world.resource::<Time>().frame_index;
+      if self.measured_frame == Some(frame_index) {
+         return;
+      }
+      let scene_color = world.resource::<SceneColor>();
+      let Some(scene_color) = scene_color.texture() else { return };
+      let command_buffer = world.resource::<FrameTarget>().command_buffer;
+      self.measured_frame = Some(frame_index);
+
+      self.descriptors.reset(device);
+      self.set = self.descriptors.allocate(device, self.set_layout);
+      let scene_color_info = [scene_color.descriptor_info()];
+      let exposure_info = [self.exposure.descriptor_info()];
+      let writes = [
+         vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&scene_color_info)
+            .build(),
+         vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&exposure_info)
+            .build(),
+      ];
+      unsafe { device.update_descriptor_sets(&writes, &[]) };
+
+      match self.settings.exposure {
+         Exposure::Manual(stops) => unsafe {
+            let scale_offset = std::mem::offset_of!(GpuExposure, scale) as vk::DeviceSize;
+            let scale = stops.exp2().to_ne_bytes();
+            device.cmd_update_buffer(command_buffer, self.exposure.buffer(), scale_offset, &scale);
+            cmd_buffer_barrier(
+               device,
+               command_buffer,
+               (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+               vk::PipelineStageFlags::FRAGMENT_SHADER,
+            );
+         },
+         Exposure::Auto(auto) => {
+            let delta_time = world.resource::<Time>().delta_time;
+            let constants = ExposureConstants {
+               min_log_luminance: auto.min_log_luminance,
+               log_luminance_range: (auto.max_log_luminance - auto.min_log_luminance).max(f32::EPSILON),
+               adaptation: 1.0 - (-delta_time * auto.adaptation_speed).exp(),
+               key: MIDDLE_GREY * auto.compensation.exp2(),
+            };
+            self.cmd_measure(device, command_buffer, &constants, scene_color.extent());
+         }
+      }
+   }
+
+   /// Records the exposure and tonemapping of the scene color into the frame's command buffer.
+   /// Draws nothing before the scene is first rendered
+   pub fn render(&mut self, device: &ash::Device, world: &World) {
+      self.measure(device, world);
+      let scene_color = world.resource::<SceneColor>();
+      if scene_color.texture().is_none() {
+         return;
+      }
+      let frame = world.resource::<FrameTarget>();
+      let command_buffer = frame.command_buffer;
+      let (auto_exposure, manual_exposure) = match self.settings.exposure {
+         Exposure::Manual(stops) => (false, stops.exp2()),
+         Exposure::Auto(_) => (true, 1.0),
+      };
+      let constants = TonemapConstants {
+         tonemapping: self.settings.tonemapping as u32,
+         output_encoding: self.output as u32,
+         auto_exposure: auto_exposure as u32,
+         manual_exposure,
+         paper_white_nits: self.settings.paper_white_nits,
+         peak_nits: self.settings.peak_nits,
+      };
+
+      let framebuffer = self.framebuffers.get(device, &frame);
+      cmd_begin_fullscreen(device, command_buffer, self.render_pass, framebuffer, frame.extent);
+      unsafe {
+         device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+         device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.layout.layout(),
+            0,
+            &[self.set],
+            &[],
+         );
+         self.layout.cmd_push(device, command_buffer, &constants);
+         device.cmd_draw(command_buffer, 3, 1, 0, 0);
+         device.cmd_end_render_pass(command_buffer);
+      }
+   }
+
+   /// Records the histogram of the scene color and the adaptation of the exposure to it
+   fn cmd_measure(
+      &self,
+      device: &ash::Device,
+      command_buffer: vk::CommandBuffer,
+      constants: &ExposureConstants,
+      extent: vk::Extent2D,
+   ) {
+      let layout = self.exposure_layout.layout();
+      unsafe {
+         device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::COMPUTE, layout, 0, &[self.set], &[]);
+         self.exposure_layout.cmd_push(device, command_buffer, constants);
+         device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.histogram_pipeline);
+         device.cmd_dispatch(
+            command_buffer,
+            extent.width.div_ceil(HISTOGRAM_GROUP_SIZE),
+            extent.height.div_ceil(HISTOGRAM_GROUP_SIZE),
+            1,
+         );
+         let compute_write = (vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE);
+         cmd_buffer_barrier(device, command_buffer, compute_write, vk::PipelineStageFlags::COMPUTE_SHADER);
+         device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.exposure_pipeline);
+         device.cmd_dispatch(command_buffer, 1, 1, 1);
+         cmd_buffer_barrier(device, command_buffer, compute_write, vk::PipelineStageFlags::FRAGMENT_SHADER);
+      }
+   }
+}
+
+impl VulkanDrop for Tonemapper {
+   fn drop(self, device: &ash::Device) {
+      unsafe {
+         device.destroy_pipeline(self.pipeline, None);
+         device.destroy_pipeline(self.histogram_pipeline, None);
+         device.destroy_pipeline(self.exposure_pipeline, None);
+      }
+      self.layout.drop(device);
+      self.exposure_layout.drop(device);
+      self.descriptors.drop(device);
+      self.exposure.drop(device);
+      self.framebuffers.drop(device);
+      unsafe { device.destroy_render_pass(self.render_pass, None) };
+   }
+}
+
+/// Records the exposure of the `Tonemapper` resource into the frame. Add it to `Stage::Render`
+/// before `post_stack_system`, after the systems rendering the scene and bloom
+pub fn exposure_system(device: ash::Device) -> System {
+   System::parallel("exposure", move |world| {
+      world.resource_mut::<Tonemapper>().measure(&device, world);
+   })
+   .reads_resource::<SceneColor>()
+   .reads_resource::<Time>()
+   .writes_resource::<Tonemapper>()
+   .writes_resource::<FrameTarget>()
+}
+
+/// Records the `Tonemapper` resource into the frame. Add it to `Stage::Render` after the
+/// systems rendering the scene
+pub fn tonemap_system(device: ash::Device) -> System {
+   System::parallel("tonemap", move |world| {
+      world.resource_mut::<Tonemapper>().render(&device, world);
+   })
+   .reads_resource::<SceneColor>()
+   .reads_resource::<Time>()
+   .writes_resource::<Tonemapper>()
+   .writes_resource::<FrameTarget>()
+}
+
+/// Makes the writes of the exposure buffer by the stage and access visible to the shaders of
+/// the stage
+fn cmd_buffer_barrier(
+   device: &ash::Device,
+   command_buffer: vk::CommandBuffer,
+   (src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+   dst_stage: vk::PipelineStageFlags,
+) {
+   let barrier = vk::MemoryBarrier {
+      src_access_mask: src_access,
+      dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+      ..Default::default()
+   };
+   unsafe {
+      device.cmd_pipeline_barrier(
+         command_buffer,
+         src_stage,
+         dst_stage,
+         vk::DependencyFlags::empty(),
+         &[barrier],
+         &[],
+         &[],
+      );
+   }
+}
+
+/// Color attachment in the swapchain format, overwritten entirely and presented afterwards
+fn create_render_pass(device: &ash::Device, color_format: vk::Format) -> vk::RenderPass {
+   let attachments = [vk::AttachmentDescription {
+      format: color_format,
+      samples: vk::SampleCountFlags::TYPE_1,
+      load_op: vk::AttachmentLoadOp::DONT_CARE,
+      store_op: vk::AttachmentStoreOp::STORE,
+      final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+      ..Default::default()
+   }];
+   let color_attachment_refs = [vk::AttachmentReference {
+      attachment: 0,
+      layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+   }];
+   let dependencies = [vk::SubpassDependency {
+      src_subpass: vk::SUBPASS_EXTERNAL,
+      src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+      dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+      dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+      ..Default::default()
+   }];
+   let subpass = vk::SubpassDescription::builder()
+      .color_attachments(&color_attachment_refs)
+      .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+   let create_info = vk::RenderPassCreateInfo::builder()
+      .attachments(&attachments)
+      .subpasses(std::slice::from_ref(&subpass))
+      .dependencies(&dependencies);
+   unsafe { device.create_render_pass(&create_info, None).unwrap() }
+}
@@ -0,0 +1,291 @@
+use ash::vk;
+use cgmath::{Deg, Matrix4, Rad, SquareMatrix, Vector4};
+
+use crate::ecs::System;
+use crate::platform::gpu::vulkan_context::FrameTarget;
+
+use super::GlobalTransform;
+
+/// Perspective projection. Reverse-Z maps the near plane to depth 1 and the far plane to 0, which
+/// spreads floating point depth precision much more evenly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Perspective {
+   /// Vertical field of view
+   pub fov_y: Rad<f32>,
+   pub near: f32,
+   /// `None` for the infinitely far plane
+   pub far: Option<f32>,
+   pub reverse_z: bool,
+}
+
+impl Default for Perspective {
+   fn default() -> Self {
+      Perspective {
+         fov_y: Deg(60.0).into(),
+         near: 0.1,
+         far: None,
+         reverse_z: true,
+      }
+   }
+}
+
+/// Orthographic projection of a box `height` units tall, centered on the camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Orthographic {
+   pub height: f32,
+   pub near: f32,
+   pub far: f32,
+}
+
+impl Default for Orthographic {
+   fn default() -> Self {
+      Orthographic {
+         height: 2.0,
+         near: 0.0,
+         far: 1000.0,
+      }
+   }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+   Perspective(Perspective),
+   Orthographic(Orthographic),
+}
+
+/// Makes the entity's `GlobalTransform` a point of view. The camera looks along its local -Z,
+/// with +Y up, like glTF cameras.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+   pub projection: Projection,
+   /// Width over height of the render target, kept up to date by `camera_aspect_system`
+   pub aspect_ratio: f32,
+}
+
+impl Camera {
+   pub fn perspective(perspective: Perspective) -> Self {
+      Camera {
+         projection: Projection::Perspective(perspective),
+         aspect_ratio: 1.0,
+      }
+   }
+
+   pub fn orthographic(orthographic: Orthographic) -> Self {
+      Camera {
+         projection: Projection::Orthographic(orthographic),
+         aspect_ratio: 1.0,
+      }
+   }
+
+   /// Projection to Vulkan clip space: Y points down and depth is in [0, 1]
+   pub fn projection_matrix(&self) -> Matrix4<f32> {
+      match self.projection {
+         Projection::Perspective(perspective) => perspective_matrix(perspective, self.aspect_ratio),
+         Projection::Orthographic(orthographic) => orthographic_matrix(orthographic, self.aspect_ratio),
+      }
+   }
+
+   pub fn view_matrix(global: &GlobalTransform) -> Matrix4<f32> {
+      global.matrix().invert().expect("Camera transform must be invertible")
+   }
+
+   pub fn is_reverse_z(&self) -> bool {
+      matches!(self.projection, Projection::Perspective(Perspective { reverse_z: true, .. }))
+   }
+
+   /// Depth the depth attachment should be cleared to, the farthest one
+   pub fn clear_depth(&self) -> f32 {
+      if self.is_reverse_z() { 0.0 } else { 1.0 }
+   }
+
+   /// Depth test that passes for closer fragments
+   pub fn depth_compare_op(&self) -> vk::CompareOp {
+      if self.is_reverse_z() {
+         vk::CompareOp::GREATER_OR_EQUAL
+      } else {
+         vk::CompareOp::LESS_OR_EQUAL
+      }
+   }
+}
+
+impl Default for Camera {
+   fn default() -> Self {
+      Self::perspective(Perspective::default())
+   }
+}
+
+fn perspective_matrix(perspective: Perspective, aspect_ratio: f32) -> Matrix4<f32> {
+   let Perspective { fov_y, near, far, reverse_z } = perspective;
+   let focal_length = 1.0 / (fov_y.0 * 0.5).tan();
+   // Clip space Z is `depth_scale * z + depth_offset`, and W is `-z`
+   let (depth_scale, depth_offset) = match (far, reverse_z) {
+      (Some(far), false) => (far / (near - far), near * far / (near - far)),
+      (Some(far), true) => (near / (far - near), near * far / (far - near)),
+      (None, false) => (-1.0, -near),
+      (None, true) => (0.0, near),
+   };
+   Matrix4::new(
+      focal_length / aspect_ratio, 0.0, 0.0, 0.0,
+      0.0, -focal_length, 0.0, 0.0,
+      0.0, 0.0, depth_scale, -1.0,
+      0.0, 0.0, depth_offset, 0.0,
+   )
+}
+
+fn orthographic_matrix(orthographic: Orthographic, aspect_ratio: f32) -> Matrix4<f32> {
+   let Orthographic { height, near, far } = orthographic;
+   let width = height * aspect_ratio;
+   Matrix4::new(
+      2.0 / width, 0.0, 0.0, 0.0,
+      0.0, -2.0 / height, 0.0, 0.0,
+      0.0, 0.0, 1.0 / (near - far), 0.0,
+      0.0, 0.0, near / (near - far), 1.0,
+   )
+}
+
+/// Camera matrices as laid out in a std140 uniform block:
+/// `mat4 view; mat4 projection; mat4 view_projection; vec4 position;`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CameraUniform {
+   pub view: Matrix4<f32>,
+   pub projection: Matrix4<f32>,
+   pub view_projection: Matrix4<f32>,
+   /// World space position, w is 1
+   pub position: Vector4<f32>,
+}
+
+impl CameraUniform {
+   pub fn new(camera: &Camera, global: &GlobalTransform) -> Self {
+      let view = Camera::view_matrix(global);
+      let projection = camera.projection_matrix();
+      CameraUniform {
+         view,
+         projection,
+         view_projection: projection * view,
+         position: global.translation().extend(1.0),
+      }
+   }
+}
+
+/// Sets the aspect ratio of all cameras to the one of the swapchain, so it follows window resizes.
+/// Add it to `Stage::Input`, so later stages see the up to date projection
+pub fn camera_aspect_system() -> System {
+   System::parallel("update_camera_aspect", |world| {
+      let extent = world.resource::<FrameTarget>().extent;
+      let aspect_ratio = extent.width as f32 / extent.height.max(1) as f32;
+      for (_, camera) in world.query::<&mut Camera>().iter() {
+         camera.aspect_ratio = aspect_ratio;
+      }
+   })
+   .with_query::<&mut Camera>()
+   .reads_resource::<FrameTarget>()
+}
+
+#[cfg(test)]
+mod tests {
+   use cgmath::Vector3;
+
+   use super::*;
+
+   const EPSILON: f32 = 1e-5;
+
+   /// Projects a view space point to normalized device coordinates
+   fn ndc(camera: &Camera, point: Vector3<f32>) -> Vector3<f32> {
+      let clip = camera.projection_matrix() * point.extend(1.0);
+      assert!(clip.w > 0.0, "{:?} is behind the camera", point);
+      clip.truncate() / clip.w
+   }
+
+   fn assert_near(actual: f32, expected: f32) {
+      assert!((actual - expected).abs() < EPSILON, "{} != {}", actual, expected);
+   }
+
+   fn perspective(far: Option<f32>, reverse_z: bool) -> Camera {
+      Camera {
+         aspect_ratio: 2.0,
+         ..Camera::perspective(Perspective { fov_y: Deg(90.0).into(), near: 0.5, far, reverse_z })
+      }
+   }
+
+   #[test]
+   fn perspective_flips_y_and_scales_x_by_aspect() {
+      for (far, reverse_z) in [(Some(100.0), false), (Some(100.0), true), (None, false), (None, true)] {
+         let camera = perspective(far, reverse_z);
+         // With a 90 degree fov, the top edge at distance d is d units up
+         let top_right = ndc(&camera, Vector3::new(8.0, 4.0, -4.0));
+         assert_near(top_right.x, 1.0);
+         assert_near(top_right.y, -1.0);
+         let bottom = ndc(&camera, Vector3::new(0.0, -1.0, -2.0));
+         assert_near(bottom.y, 0.5);
+      }
+   }
+
+   #[test]
+   fn perspective_maps_near_and_far_to_unit_depth() {
+      let camera = perspective(Some(100.0), false);
+      assert_near(ndc(&camera, Vector3::new(0.0, 0.0, -0.5)).z, 0.0);
+      assert_near(ndc(&camera, Vector3::new(1.0, 1.0, -100.0)).z, 1.0);
+      let middle = ndc(&camera, Vector3::new(0.0, 0.0, -10.0)).z;
+      assert!(middle > 0.0 && middle < 1.0);
+      assert_eq!(camera.clear_depth(), 1.0);
+      assert_eq!(camera.depth_compare_op(), vk::CompareOp::LESS_OR_EQUAL);
+   }
+
+   #[test]
+   fn reverse_z_maps_near_to_one_and_far_to_zero() {
+      let camera = perspective(Some(100.0), true);
+      assert_near(ndc(&camera, Vector3::new(0.0, 0.0, -0.5)).z, 1.0);
+      assert_near(ndc(&camera, Vector3::new(1.0, 1.0, -100.0)).z, 0.0);
+      let middle = ndc(&camera, Vector3::new(0.0, 0.0, -10.0)).z;
+      assert!(middle > 0.0 && middle < 1.0);
+      assert_eq!(camera.clear_depth(), 0.0);
+      assert_eq!(camera.depth_compare_op(), vk::CompareOp::GREATER_OR_EQUAL);
+   }
+
+   #[test]
+   fn infinite_far_plane_approaches_but_never_reaches_the_far_depth() {
+      let camera = perspective(None, false);
+      assert_near(ndc(&camera, Vector3::new(0.0, 0.0, -0.5)).z, 0.0);
+      let far = ndc(&camera, Vector3::new(0.0, 0.0, -1.0e5)).z;
+      assert!(far < 1.0 && far > 1.0 - 1.0e-4, "{}", far);
+
+      let camera = perspective(None, true);
+      assert_near(ndc(&camera, Vector3::new(0.0, 0.0, -0.5)).z, 1.0);
+      let far = ndc(&camera, Vector3::new(0.0, 0.0, -1.0e5)).z;
+      assert!(far > 0.0 && far < 1.0e-4, "{}", far);
+   }
+
+   #[test]
+   fn closer_points_pass_the_depth_test() {
+      for (far, reverse_z) in [(Some(100.0), false), (Some(100.0), true), (None, false), (None, true)] {
+         let camera = perspective(far, reverse_z);
+         let near = ndc(&camera, Vector3::new(0.0, 0.0, -1.0)).z;
+         let far = ndc(&camera, Vector3::new(0.0, 0.0, -2.0)).z;
+         let passes = match camera.depth_compare_op() {
+            vk::CompareOp::LESS_OR_EQUAL => near < far,
+            _ => near > far,
+         };
+         assert!(passes, "reverse_z: {}, near {} far {}", reverse_z, near, far);
+      }
+   }
+
+   #[test]
+   fn orthographic_maps_the_box_to_clip_space() {
+      let camera = Camera {
+         aspect_ratio: 2.0,
+         ..Camera::orthographic(Orthographic { height: 4.0, near: 1.0, far: 11.0 })
+      };
+      let near_corner = ndc(&camera, Vector3::new(4.0, 2.0, -1.0));
+      assert_near(near_corner.x, 1.0);
+      assert_near(near_corner.y, -1.0);
+      assert_near(near_corner.z, 0.0);
+      let far_corner = ndc(&camera, Vector3::new(-4.0, -2.0, -11.0));
+      assert_near(far_corner.x, -1.0);
+      assert_near(far_corner.y, 1.0);
+      assert_near(far_corner.z, 1.0);
+      // Depth is linear in distance
+      assert_near(ndc(&camera, Vector3::new(0.0, 0.0, -6.0)).z, 0.5);
+      assert!(!camera.is_reverse_z());
+   }
+}
@@ -0,0 +1,221 @@
+//! Camera controllers driven by the actions of `assets/input.toml`.
+
+use cgmath::{vec3, Deg, InnerSpace, Quaternion, Rad, Rotation, Rotation3, Vector2, Vector3, Zero};
+
+use crate::ecs::System;
+use crate::platform::gpu::vulkan_context::FrameTarget;
+use crate::platform::input::Input;
+use crate::platform::time::Time;
+
+use super::{Camera, Projection, Transform};
+
+/// Pitch is kept a bit away from the poles, where yaw becomes ambiguous
+const MAX_PITCH: Deg<f32> = Deg(89.0);
+
+fn orientation(yaw: Rad<f32>, pitch: Rad<f32>) -> Quaternion<f32> {
+   Quaternion::from_angle_y(yaw) * Quaternion::from_angle_x(pitch)
+}
+
+fn clamp_pitch(pitch: Rad<f32>) -> Rad<f32> {
+   let max_pitch: Rad<f32> = MAX_PITCH.into();
+   Rad(pitch.0.clamp(-max_pitch.0, max_pitch.0))
+}
+
+/// Free flying camera. Moves with `move_*` actions relative to where it looks, `move_fast` and
+/// `move_slow` scale the speed. Looks around with the mouse while `look` is held, scrolling
+/// changes the base speed.
+#[derive(Clone, Copy, Debug)]
+pub struct FlyController {
+   /// Units per second
+   pub speed: f32,
+   pub fast_multiplier: f32,
+   pub slow_multiplier: f32,
+   /// Radians per pixel of mouse motion
+   pub sensitivity: f32,
+   pub yaw: Rad<f32>,
+   pub pitch: Rad<f32>,
+}
+
+impl Default for FlyController {
+   fn default() -> Self {
+      FlyController {
+         speed: 2.0,
+         fast_multiplier: 4.0,
+         slow_multiplier: 0.25,
+         sensitivity: 0.003,
+         yaw: Rad(0.0),
+         pitch: Rad(0.0),
+      }
+   }
+}
+
+impl FlyController {
+   pub fn with_speed(mut self, speed: f32) -> Self {
+      self.speed = speed;
+      self
+   }
+
+   pub fn with_yaw_pitch(mut self, yaw: impl Into<Rad<f32>>, pitch: impl Into<Rad<f32>>) -> Self {
+      self.yaw = yaw.into();
+      self.pitch = clamp_pitch(pitch.into());
+      self
+   }
+
+   pub fn update(&mut self, transform: &mut Transform, input: &Input, delta_time: f32) {
+      if input.action_held("look") {
+         let motion = input.mouse_motion() * self.sensitivity;
+         self.yaw -= Rad(motion.x);
+         self.pitch = clamp_pitch(self.pitch - Rad(motion.y));
+      }
+      // Scrolling a few lines doubles or halves the speed
+      self.speed *= 2.0_f32.powf(input.scroll_delta().y / 60.0);
+      transform.rotation = orientation(self.yaw, self.pitch);
+
+      let local_direction = vec3(
+         input.action_axis("move_left", "move_right"),
+         input.action_axis("move_down", "move_up"),
+         input.action_axis("move_forward", "move_backward"),
+      );
+      if local_direction.is_zero() {
+         return;
+      }
+      let mut speed = self.speed;
+      if input.action_held("move_fast") {
+         speed *= self.fast_multiplier;
+      }
+      if input.action_held("move_slow") {
+         speed *= self.slow_multiplier;
+      }
+      let direction = transform.rotation.rotate_vector(local_direction.normalize());
+      transform.translation += direction * speed * delta_time;
+   }
+}
+
+/// Camera circling around a target, for inspecting models. Rotates while `orbit` is held,
+/// moves the target in the view plane while `pan` is held, and zooms towards the point under
+/// the cursor when scrolling.
+#[derive(Clone, Copy, Debug)]
+pub struct OrbitController {
+   pub target: Vector3<f32>,
+   pub distance: f32,
+   pub min_distance: f32,
+   pub max_distance: f32,
+   pub yaw: Rad<f32>,
+   pub pitch: Rad<f32>,
+   /// Radians per pixel of mouse motion
+   pub rotate_sensitivity: f32,
+   /// Fraction of the distance zoomed per scrolled pixel
+   pub zoom_sensitivity: f32,
+}
+
+impl Default for OrbitController {
+   fn default() -> Self {
+      OrbitController {
+         target: Vector3::zero(),
+         distance: 5.0,
+         min_distance: 0.05,
+         max_distance: 1000.0,
+         yaw: Rad(0.0),
+         pitch: Rad(0.0),
+         rotate_sensitivity: 0.005,
+         zoom_sensitivity: 0.002,
+      }
+   }
+}
+
+impl OrbitController {
+   pub fn with_target(mut self, target: Vector3<f32>, distance: f32) -> Self {
+      self.target = target;
+      self.distance = distance;
+      self
+   }
+
+   pub fn with_yaw_pitch(mut self, yaw: impl Into<Rad<f32>>, pitch: impl Into<Rad<f32>>) -> Self {
+      self.yaw = yaw.into();
+      self.pitch = clamp_pitch(pitch.into());
+      self
+   }
+
+   /// `viewport_size` in pixels is needed to find the point under the cursor
+   pub fn update(
+      &mut self,
+      transform: &mut Transform,
+      camera: Option<&mut Camera>,
+      input: &Input,
+      viewport_size: Vector2<f32>,
+   ) {
+      let rotation = orientation(self.yaw, self.pitch);
+      if input.action_held("orbit") {
+         let motion = input.mouse_motion() * self.rotate_sensitivity;
+         self.yaw -= Rad(motion.x);
+         self.pitch = clamp_pitch(self.pitch - Rad(motion.y));
+      } else if input.action_held("pan") {
+         // The point under the cursor follows the cursor
+         let pixel_size = camera
+            .as_deref()
+            .map_or(0.0, |camera| view_plane_height(camera, self.distance) / viewport_size.y.max(1.0));
+         let delta = input.cursor_delta() * pixel_size;
+         self.target += rotation.rotate_vector(vec3(-delta.x, delta.y, 0.0));
+      }
+
+      let scroll = input.scroll_delta().y;
+      if scroll != 0.0 {
+         let scale = (-scroll * self.zoom_sensitivity).exp();
+         let new_distance = (self.distance * scale).clamp(self.min_distance, self.max_distance);
+         let scale = new_distance / self.distance;
+         if let (Some(camera), Some(cursor)) = (camera, input.cursor_position()) {
+            // Scaling the scene around the point under the cursor keeps that point in place on the screen
+            let ndc = Vector2::new(cursor.x / viewport_size.x, cursor.y / viewport_size.y) * 2.0 - Vector2::new(1.0, 1.0);
+            let half_height = view_plane_height(camera, self.distance) * 0.5;
+            let half_width = half_height * camera.aspect_ratio;
+            let cursor_offset = rotation.rotate_vector(vec3(ndc.x * half_width, -ndc.y * half_height, 0.0));
+            self.target += cursor_offset * (1.0 - scale);
+            if let Projection::Orthographic(orthographic) = &mut camera.projection {
+               orthographic.height *= scale;
+            }
+         }
+         self.distance = new_distance;
+      }
+
+      transform.rotation = orientation(self.yaw, self.pitch);
+      transform.translation = self.target + transform.rotation.rotate_vector(vec3(0.0, 0.0, self.distance));
+   }
+}
+
+/// Height of the visible area at the given distance in front of the camera
+fn view_plane_height(camera: &Camera, distance: f32) -> f32 {
+   match camera.projection {
+      Projection::Perspective(perspective) => 2.0 * distance * (perspective.fov_y.0 * 0.5).tan(),
+      Projection::Orthographic(orthographic) => orthographic.height,
+   }
+}
+
+/// Updates transforms of entities with `FlyController`, add it to `Stage::Update`
+pub fn fly_controller_system() -> System {
+   System::parallel("fly_controller", |world| {
+      let input = world.resource::<Input>();
+      let delta_time = world.resource::<Time>().delta_time;
+      for (_, (controller, transform)) in world.query::<(&mut FlyController, &mut Transform)>().iter() {
+         controller.update(transform, &input, delta_time);
+      }
+   })
+   .with_query::<(&mut FlyController, &mut Transform)>()
+   .reads_resource::<Input>()
+   .reads_resource::<Time>()
+}
+
+/// Updates transforms of entities with `OrbitController`, add it to `Stage::Update`
+pub fn orbit_controller_system() -> System {
+   System::parallel("orbit_controller", |world| {
+      let input = world.resource::<Input>();
+      let extent = world.resource::<FrameTarget>().extent;
+      let viewport_size = Vector2::new(extent.width as f32, extent.height as f32);
+      let mut controllers = world.query::<(&mut OrbitController, &mut Transform, Option<&mut Camera>)>();
+      for (_, (controller, transform, camera)) in controllers.iter() {
+         controller.update(transform, camera, &input, viewport_size);
+      }
+   })
+   .with_query::<(&mut OrbitController, &mut Transform, Option<&mut Camera>)>()
+   .reads_resource::<Input>()
+   .reads_resource::<FrameTarget>()
+}
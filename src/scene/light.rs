@@ -0,0 +1,148 @@
+use cgmath::{Deg, Rad, Vector3};
+
+use crate::asset::{HdrImage, Handle};
+
+/// Light infinitely far away, shining along the entity's local -Z, like the sun.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirectionalLight {
+   /// Linear RGB
+   pub color: Vector3<f32>,
+   /// Illuminance in lux
+   pub intensity: f32,
+}
+
+impl Default for DirectionalLight {
+   fn default() -> Self {
+      DirectionalLight {
+         color: Vector3::new(1.0, 1.0, 1.0),
+         intensity: 1.0,
+      }
+   }
+}
+
+/// Light shining from the entity's position in all directions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointLight {
+   /// Linear RGB
+   pub color: Vector3<f32>,
+   /// Luminous intensity in candela
+   pub intensity: f32,
+   /// Distance where the light fades out completely, `None` for the inverse square falloff only
+   pub range: Option<f32>,
+}
+
+impl Default for PointLight {
+   fn default() -> Self {
+      PointLight {
+         color: Vector3::new(1.0, 1.0, 1.0),
+         intensity: 1.0,
+         range: None,
+      }
+   }
+}
+
+/// Light shining from the entity's position in a cone around its local -Z.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpotLight {
+   /// Linear RGB
+   pub color: Vector3<f32>,
+   /// Luminous intensity in candela
+   pub intensity: f32,
+   /// Distance where the light fades out completely, `None` for the inverse square falloff only
+   pub range: Option<f32>,
+   /// Angle from the axis where the falloff starts
+   pub inner_cone_angle: Rad<f32>,
+   /// Angle from the axis where the light ends
+   pub outer_cone_angle: Rad<f32>,
+}
+
+impl Default for SpotLight {
+   fn default() -> Self {
+      SpotLight {
+         color: Vector3::new(1.0, 1.0, 1.0),
+         intensity: 1.0,
+         range: None,
+         inner_cone_angle: Rad(0.0),
+         outer_cone_angle: Deg(45.0).into(),
+      }
+   }
+}
+
+/// Light reaching every surface evenly from all directions, a world resource. A crude stand-in
+/// for light bounced around the scene.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmbientLight {
+   /// Linear RGB
+   pub color: Vector3<f32>,
+   pub intensity: f32,
+}
+
+impl Default for AmbientLight {
+   fn default() -> Self {
+      AmbientLight {
+         color: Vector3::new(1.0, 1.0, 1.0),
+         intensity: 0.1,
+      }
+   }
+}
+
+/// Light reaching surfaces from their surroundings, a world resource. Replaces the flat
+/// `AmbientLight` with diffuse and specular light baked by `GpuEnvironment`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EnvironmentLight {
+   pub source: EnvironmentSource,
+   /// Scale of the environment's linear RGB
+   pub intensity: f32,
+}
+
+/// What `EnvironmentLight` bakes its light from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnvironmentSource {
+   /// Equirectangular image in `Assets<HdrImage>`, its center looking along -Z
+   Map(Handle<HdrImage>),
+   /// The `Sky::Atmosphere` resource, baked again when it or its `Sun` changes
+   Sky,
+}
+
+impl EnvironmentLight {
+   pub fn new(map: Handle<HdrImage>) -> Self {
+      EnvironmentLight { source: EnvironmentSource::Map(map), intensity: 1.0 }
+   }
+
+   pub fn from_sky() -> Self {
+      EnvironmentLight { source: EnvironmentSource::Sky, intensity: 1.0 }
+   }
+
+   pub fn with_intensity(mut self, intensity: f32) -> Self {
+      self.intensity = intensity;
+      self
+   }
+}
+
+/// Makes the light of the entity cast shadows. Directional lights get one shadow map per cascade,
+/// each `resolution` texels wide, and point lights a cube of the renderer's fixed size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CastShadows {
+   /// Width and height of a shadow map, rounded up to a power of two. Unused by point lights
+   pub resolution: u32,
+   /// Constant depth bias, in units of the smallest depth difference
+   pub depth_bias: f32,
+   /// Depth bias scaled by the depth slope of the caster's triangles
+   pub slope_bias: f32,
+   /// Offset of the receiving point along its normal, in shadow map texels
+   pub normal_bias: f32,
+   /// Radius of the percentage closer filter, in shadow map texels
+   pub filter_radius: f32,
+}
+
+impl Default for CastShadows {
+   fn default() -> Self {
+      CastShadows {
+         resolution: 1024,
+         depth_bias: 1.25,
+         slope_bias: 1.75,
+         normal_bias: 1.0,
+         filter_radius: 1.5,
+      }
+   }
+}
@@ -0,0 +1,8 @@
+use crate::asset::{Handle, Material, MeshData};
+
+/// Draws a mesh with a material at the entity's `GlobalTransform`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshInstance {
+   pub mesh: Handle<MeshData>,
+   pub material: Handle<Material>,
+}
@@ -0,0 +1,20 @@
+//! Components and systems describing the scene, built on top of `ecs`.
+
+mod camera;
+mod controller;
+mod light;
+mod mesh;
+mod sky;
+mod transform;
+
+pub use camera::{camera_aspect_system, Camera, CameraUniform, Orthographic, Perspective, Projection};
+pub use controller::{fly_controller_system, orbit_controller_system, FlyController, OrbitController};
+pub use light::{
+   AmbientLight, CastShadows, DirectionalLight, EnvironmentLight, EnvironmentSource, PointLight, SpotLight,
+};
+pub use mesh::MeshInstance;
+pub use sky::{Atmosphere, Sky, Sun};
+pub use transform::{
+   despawn_recursive, propagate_transforms, remove_parent, set_parent, transform_propagation_system,
+   Children, GlobalTransform, Name, Parent, Transform,
+};
@@ -0,0 +1,79 @@
+use cgmath::Vector3;
+
+/// Marks the `DirectionalLight` standing for the sun of the `Sky::Atmosphere`. The sun lies
+/// opposite to the direction the light travels in, along the entity's local +Z.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sun;
+
+/// Background drawn behind the scene, a world resource. Without it the background stays black.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sky {
+   /// Skybox of the environment cube baked from the `EnvironmentLight`, at its intensity
+   Environment,
+   /// Sky scattering the light of the `Sun`
+   Atmosphere(Atmosphere),
+}
+
+/// Single scattering atmosphere around a spherical planet centered below the origin, with +Y up.
+/// Lengths are in meters. The defaults are those of the Earth.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Atmosphere {
+   /// Radiance scale of the sunlight entering the atmosphere
+   pub sun_intensity: f32,
+   pub planet_radius: f32,
+   /// Radius of the top of the atmosphere, above which there is no air
+   pub atmosphere_radius: f32,
+   /// Height of the origin above the ground
+   pub observer_altitude: f32,
+   /// Rayleigh scattering coefficients of red, green and blue light at sea level, per meter
+   pub rayleigh_scattering: Vector3<f32>,
+   /// Altitude over which the density of air molecules falls by a factor of e
+   pub rayleigh_scale_height: f32,
+   /// Mie scattering coefficient of aerosols at sea level, per meter
+   pub mie_scattering: f32,
+   /// Altitude over which the density of aerosols falls by a factor of e
+   pub mie_scale_height: f32,
+   /// Anisotropy of the Mie phase function, toward 1 for a tighter halo around the sun
+   pub mie_anisotropy: f32,
+}
+
+impl Default for Atmosphere {
+   fn default() -> Self {
+      Atmosphere {
+         sun_intensity: 22.0,
+         planet_radius: 6_371e3,
+         atmosphere_radius: 6_471e3,
+         observer_altitude: 1.0,
+         rayleigh_scattering: Vector3::new(5.5e-6, 13.0e-6, 22.4e-6),
+         rayleigh_scale_height: 8e3,
+         mie_scattering: 21e-6,
+         mie_scale_height: 1.2e3,
+         mie_anisotropy: 0.758,
+      }
+   }
+}
+
+impl Atmosphere {
+   pub fn with_sun_intensity(mut self, sun_intensity: f32) -> Self {
+      self.sun_intensity = sun_intensity;
+      self
+   }
+
+   pub fn with_observer_altitude(mut self, observer_altitude: f32) -> Self {
+      self.observer_altitude = observer_altitude;
+      self
+   }
+
+   pub fn with_rayleigh(mut self, scattering: Vector3<f32>, scale_height: f32) -> Self {
+      self.rayleigh_scattering = scattering;
+      self.rayleigh_scale_height = scale_height;
+      self
+   }
+
+   pub fn with_mie(mut self, scattering: f32, scale_height: f32, anisotropy: f32) -> Self {
+      self.mie_scattering = scattering;
+      self.mie_scale_height = scale_height;
+      self.mie_anisotropy = anisotropy;
+      self
+   }
+}
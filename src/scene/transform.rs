@@ -0,0 +1,327 @@
+use cgmath::{Matrix4, One, Quaternion, Vector3};
+
+use crate::ecs::{Entity, System, With, Without, World};
+
+/// Position, orientation and size of an entity relative to its parent, or to the world if it has none.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+   pub translation: Vector3<f32>,
+   pub rotation: Quaternion<f32>,
+   pub scale: Vector3<f32>,
+}
+
+impl Transform {
+   pub const IDENTITY: Transform = Transform {
+      translation: Vector3::new(0.0, 0.0, 0.0),
+      rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+      scale: Vector3::new(1.0, 1.0, 1.0),
+   };
+
+   pub fn from_translation(translation: Vector3<f32>) -> Self {
+      Transform { translation, ..Self::IDENTITY }
+   }
+
+   pub fn from_rotation(rotation: Quaternion<f32>) -> Self {
+      Transform { rotation, ..Self::IDENTITY }
+   }
+
+   pub fn from_scale(scale: Vector3<f32>) -> Self {
+      Transform { scale, ..Self::IDENTITY }
+   }
+
+   pub fn with_translation(mut self, translation: Vector3<f32>) -> Self {
+      self.translation = translation;
+      self
+   }
+
+   pub fn with_rotation(mut self, rotation: Quaternion<f32>) -> Self {
+      self.rotation = rotation;
+      self
+   }
+
+   pub fn with_scale(mut self, scale: Vector3<f32>) -> Self {
+      self.scale = scale;
+      self
+   }
+
+   /// Scales first, then rotates, then translates
+   pub fn matrix(&self) -> Matrix4<f32> {
+      Matrix4::from_translation(self.translation)
+         * Matrix4::from(self.rotation)
+         * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+   }
+}
+
+impl Default for Transform {
+   fn default() -> Self {
+      Self::IDENTITY
+   }
+}
+
+/// Transform relative to the world, computed by `propagate_transforms`. Inserted automatically
+/// for entities that have a `Transform`.
+#[derive(Clone, Copy, Debug)]
+pub struct GlobalTransform {
+   matrix: Matrix4<f32>,
+   /// The local transform the matrix was computed from, `None` forces recomputation
+   computed_from: Option<Transform>,
+}
+
+impl GlobalTransform {
+   pub fn matrix(&self) -> Matrix4<f32> {
+      self.matrix
+   }
+
+   pub fn translation(&self) -> Vector3<f32> {
+      self.matrix.w.truncate()
+   }
+}
+
+impl Default for GlobalTransform {
+   fn default() -> Self {
+      GlobalTransform {
+         matrix: Matrix4::one(),
+         computed_from: None,
+      }
+   }
+}
+
+/// Entity whose transform this entity's transform is relative to. Use `set_parent` to change it,
+/// so `Children` of both parents stay in sync.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parent(pub Entity);
+
+/// Human readable name of the entity, e.g. the node name of an imported scene
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Name(pub String);
+
+/// Entities that have this entity as `Parent`
+#[derive(Clone, Debug, Default)]
+pub struct Children(Vec<Entity>);
+
+impl Children {
+   pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+      self.0.iter().copied()
+   }
+
+   pub fn len(&self) -> usize {
+      self.0.len()
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.0.is_empty()
+   }
+}
+
+/// Attaches the child to the parent, detaching it from its previous parent if any
+pub fn set_parent(world: &mut World, child: Entity, parent: Entity) {
+   let mut ancestor = Some(parent);
+   while let Some(entity) = ancestor {
+      assert_ne!(entity, child, "{:?} can't be a descendant of itself", child);
+      ancestor = world.get::<Parent>(entity).map(|parent| parent.0);
+   }
+   remove_parent(world, child);
+   world.insert_one(child, Parent(parent));
+   // A former root has no parent to remove, but its matrix is stale all the same
+   if let Some(global) = world.get_mut::<GlobalTransform>(child) {
+      global.computed_from = None;
+   }
+   match world.get_mut::<Children>(parent) {
+      Some(children) => children.0.push(child),
+      None => {
+         world.insert_one(parent, Children(vec![child]));
+      }
+   }
+}
+
+/// Makes the entity a root of its own hierarchy. Returns the previous parent if any
+pub fn remove_parent(world: &mut World, child: Entity) -> Option<Entity> {
+   let Parent(parent) = world.remove::<Parent>(child)?;
+   if let Some(children) = world.get_mut::<Children>(parent) {
+      children.0.retain(|&other| other != child);
+   }
+   if let Some(global) = world.get_mut::<GlobalTransform>(child) {
+      global.computed_from = None;
+   }
+   Some(parent)
+}
+
+/// Despawns the entity along with all its descendants
+pub fn despawn_recursive(world: &mut World, entity: Entity) {
+   remove_parent(world, entity);
+   let mut stack = vec![entity];
+   while let Some(entity) = stack.pop() {
+      if let Some(children) = world.remove::<Children>(entity) {
+         stack.extend(children.0);
+      }
+      world.despawn(entity);
+   }
+}
+
+/// Recomputes `GlobalTransform`s of entities whose `Transform` or any ancestor's `Transform`
+/// has changed since the previous call. Subtrees that didn't change are visited, but not recomputed
+pub fn propagate_transforms(world: &mut World) {
+   let missing: Vec<Entity> = world
+      .query_filtered::<Entity, (With<Transform>, Without<GlobalTransform>)>()
+      .iter()
+      .map(|(entity, _)| entity)
+      .collect();
+   for entity in missing {
+      world.insert_one(entity, GlobalTransform::default());
+   }
+
+   let world: &World = world;
+   let mut transforms = world.query::<&Transform>();
+   let mut globals = world.query::<&mut GlobalTransform>();
+   let mut children = world.query::<&Children>();
+   let mut roots = world.query_filtered::<(Entity, Option<&Parent>), With<Transform>>();
+
+   let mut stack: Vec<(Entity, Matrix4<f32>, bool)> = roots
+      .iter()
+      .filter_map(|(entity, (_, parent))| match parent {
+         None => Some((entity, Matrix4::one(), false)),
+         // Entities whose parent was despawned or has no transform are roots as well. Their matrix may still
+         // include the former parent's, so they are always recomputed
+         Some(&Parent(parent)) if transforms.get(parent).is_none() => Some((entity, Matrix4::one(), true)),
+         Some(_) => None,
+      })
+      .collect();
+   while let Some((entity, parent_matrix, parent_changed)) = stack.pop() {
+      let Some(&transform) = transforms.get(entity) else { continue };
+      let global = globals.get(entity).unwrap();
+      let changed = parent_changed || global.computed_from != Some(transform);
+      if changed {
+         global.matrix = parent_matrix * transform.matrix();
+         global.computed_from = Some(transform);
+      }
+      let matrix = global.matrix;
+      if let Some(children) = children.get(entity) {
+         stack.extend(children.iter().map(|child| (child, matrix, changed)));
+      }
+   }
+}
+
+/// `propagate_transforms` as a system of the `TransformPropagation` stage
+pub fn transform_propagation_system() -> System {
+   System::exclusive("propagate_transforms", propagate_transforms)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn translation(x: f32) -> Transform {
+      Transform::from_translation(Vector3::new(x, 0.0, 0.0))
+   }
+
+   fn global_x(world: &World, entity: Entity) -> f32 {
+      world.get::<GlobalTransform>(entity).unwrap().translation().x
+   }
+
+   /// Spawns root → child → grandchild, translated by 100, 10 and 1 along x, and propagates them
+   fn spawn_chain(world: &mut World) -> (Entity, Entity, Entity) {
+      let root = world.spawn((translation(100.0),));
+      let child = world.spawn((translation(10.0),));
+      let grandchild = world.spawn((translation(1.0),));
+      set_parent(world, child, root);
+      set_parent(world, grandchild, child);
+      propagate_transforms(world);
+      (root, child, grandchild)
+   }
+
+   #[test]
+   fn propagates_down_the_hierarchy() {
+      let mut world = World::new();
+      let (root, child, grandchild) = spawn_chain(&mut world);
+      assert_eq!(global_x(&world, root), 100.0);
+      assert_eq!(global_x(&world, child), 110.0);
+      assert_eq!(global_x(&world, grandchild), 111.0);
+   }
+
+   #[test]
+   fn dirty_parent_recomputes_its_descendants() {
+      let mut world = World::new();
+      let (root, child, grandchild) = spawn_chain(&mut world);
+      *world.get_mut::<Transform>(root).unwrap() = translation(200.0);
+      propagate_transforms(&mut world);
+      assert_eq!(global_x(&world, child), 210.0);
+      assert_eq!(global_x(&world, grandchild), 211.0);
+   }
+
+   #[test]
+   fn clean_subtree_is_left_alone() {
+      let mut world = World::new();
+      let (root, child, grandchild) = spawn_chain(&mut world);
+      let sibling = world.spawn((translation(20.0),));
+      set_parent(&mut world, sibling, root);
+      let other_root = world.spawn((translation(5.0),));
+      propagate_transforms(&mut world);
+
+      // A recomputation would overwrite the marker
+      let marker = Matrix4::from_scale(3.0);
+      world.get_mut::<GlobalTransform>(grandchild).unwrap().matrix = marker;
+      world.get_mut::<GlobalTransform>(other_root).unwrap().matrix = marker;
+      *world.get_mut::<Transform>(sibling).unwrap() = translation(30.0);
+      propagate_transforms(&mut world);
+      assert_eq!(world.get::<GlobalTransform>(grandchild).unwrap().matrix(), marker);
+      assert_eq!(world.get::<GlobalTransform>(other_root).unwrap().matrix(), marker);
+      assert_eq!(global_x(&world, child), 110.0);
+      assert_eq!(global_x(&world, sibling), 130.0);
+   }
+
+   #[test]
+   fn remove_parent_makes_a_root() {
+      let mut world = World::new();
+      let (root, child, grandchild) = spawn_chain(&mut world);
+      assert_eq!(remove_parent(&mut world, child), Some(root));
+      assert_eq!(remove_parent(&mut world, child), None);
+      assert!(world.get::<Children>(root).unwrap().is_empty());
+      propagate_transforms(&mut world);
+      assert_eq!(global_x(&world, child), 10.0);
+      assert_eq!(global_x(&world, grandchild), 11.0);
+   }
+
+   #[test]
+   fn despawn_recursive_despawns_descendants_only() {
+      let mut world = World::new();
+      let (root, child, grandchild) = spawn_chain(&mut world);
+      despawn_recursive(&mut world, child);
+      assert!(world.contains(root));
+      assert!(!world.contains(child));
+      assert!(!world.contains(grandchild));
+      assert!(world.get::<Children>(root).unwrap().is_empty());
+   }
+
+   #[test]
+   fn dangling_parent_is_treated_as_root() {
+      let mut world = World::new();
+      let (root, child, grandchild) = spawn_chain(&mut world);
+      world.despawn(root);
+      propagate_transforms(&mut world);
+      assert_eq!(global_x(&world, child), 10.0);
+      assert_eq!(global_x(&world, grandchild), 11.0);
+   }
+
+   #[test]
+   fn parent_without_transform_is_treated_as_root() {
+      let mut world = World::new();
+      let (root, child, grandchild) = spawn_chain(&mut world);
+      world.remove::<Transform>(root);
+      propagate_transforms(&mut world);
+      assert_eq!(global_x(&world, child), 10.0);
+      assert_eq!(global_x(&world, grandchild), 11.0);
+   }
+
+   #[test]
+   fn set_parent_recomputes_former_root() {
+      let mut world = World::new();
+      let parent = world.spawn((Transform::from_translation(Vector3::new(10.0, 0.0, 0.0)),));
+      let child = world.spawn((Transform::from_translation(Vector3::new(1.0, 0.0, 0.0)),));
+      propagate_transforms(&mut world);
+      assert_eq!(world.get::<GlobalTransform>(child).unwrap().translation(), Vector3::new(1.0, 0.0, 0.0));
+
+      set_parent(&mut world, child, parent);
+      propagate_transforms(&mut world);
+      assert_eq!(world.get::<GlobalTransform>(child).unwrap().translation(), Vector3::new(11.0, 0.0, 0.0));
+   }
+}